@@ -1,83 +1,368 @@
-use std::time::Instant;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use winit::{
     dpi::PhysicalSize,
     event::{DeviceEvent, ElementState, Event, KeyEvent, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     keyboard::{Key, KeyCode, NamedKey, PhysicalKey},
+    platform::pump_events::EventLoopExtPumpEvents,
     window::{Window, WindowBuilder},
 };
 
-use renderer::{Error as RendererError, Renderer};
+use renderer::{
+    BuildPreference, DepthFormat, Error as RendererError, Ipd, Renderer, TextureLoading,
+};
 
 use crate::{
-    data::camera_controller::{AxisMovement, CameraController},
+    data::{
+        camera_controller::{AxisMovement, CameraController},
+        camera_path::CameraPath,
+        frame_stats::FrameStats,
+    },
     input,
 };
 
 pub mod conf {
     pub const FRAME_RESOLUTION: (u32, u32) = (1600, 1200);
     pub const FOV_DEGREES: f32 = 45.;
+    pub const CAMERA_PATH_FILE: &str = "camera_path.json";
+    pub const PLAYBACK_STEPS_PER_KEYFRAME: usize = 60;
+    pub const PLAYBACK_SAMPLES_PER_STEP: u32 = 64;
+    pub const OUTPUT_SAMPLES: u32 = 256;
+    pub const PLAYBACK_OUTPUT_DIR: &str = "./camera_path_frames/";
+    // edge length of the ground plane relative to the largest extent of the scene
+    pub const GROUND_PLANE_SCALE: f32 = 10.;
+    pub const GROUND_PLANE_COLOR: f32 = 0.5;
+    // how often the compiled shaders are checked for changes to reload
+    pub const SHADER_RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+    // how long window events are waited on between checks for loaded scenes
+    pub const LOADING_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
 }
 
 pub struct App {
     renderer: Renderer,
+    scene_file: PathBuf,
+    // played back in the window, renders to files stay on the first keyframe
+    animation: scene::animation::Animation,
 
     // state
     last_frame: Instant,
+    last_shader_check: Instant,
+    animation_start: Instant,
     inputs: input::State,
     camera_controller: CameraController,
+    // as of the last update, the motion blur is cleared once the camera comes to rest
+    camera_moving: bool,
+    camera_path: CameraPath,
+    // None unless frame times are being logged
+    frame_stats: Option<FrameStats>,
     needs_resizing: bool,
+    // as of the last resize event
+    window_size: (u32, u32),
 }
 
 impl App {
-    pub fn new(window: &Window, scene_file: &str) -> Self {
-        firestorm::profile_method!(new);
+    // Loads the scenes on worker threads while the window stays responsive and says it is
+    // loading. The scenes are merged into the first, None if the window was closed before then
+    pub fn load_scenes(
+        window: &Window,
+        event_loop: &mut EventLoop<()>,
+        scene_files: &[String],
+    ) -> Option<scene::Scene> {
+        firestorm::profile_method!(load_scenes);
+
+        let title = window.title();
+        window.set_title(&format!("{title} - Loading"));
+
+        let mut loading = scene_files
+            .iter()
+            .map(scene::io::load_async)
+            .collect::<Vec<_>>();
+        let mut scenes = scene_files.iter().map(|_| None).collect::<Vec<_>>();
+
+        let mut closed = false;
+        while !closed && scenes.iter().any(Option::is_none) {
+            event_loop.pump_events(Some(conf::LOADING_POLL_INTERVAL), |event, _| {
+                if let Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    ..
+                } = event
+                {
+                    closed = true;
+                }
+            });
+
+            for (loading, scene) in loading.iter_mut().zip(&mut scenes) {
+                if scene.is_none() {
+                    *scene = loading.poll();
+                }
+            }
+        }
+
+        window.set_title(&title);
 
-        let scene = scene::io::load(scene_file);
+        let mut scenes = scenes.into_iter().collect::<Option<Vec<_>>>()?.into_iter();
+        let mut scene = scenes.next()?;
+        scenes.for_each(|other| scene.merge(other));
+        (!closed).then_some(scene)
+    }
+
+    // the scene file provides the camera and is where it gets exported to
+    pub fn new(
+        window: &Window,
+        scene_file: &str,
+        mut scene: scene::Scene,
+        resolution: (u32, u32),
+        ground_plane: bool,
+        texture_loading: TextureLoading,
+    ) -> Self {
+        firestorm::profile_method!(new);
 
-        let camera_controller = CameraController::new(
-            scene.info.bounding_box.size() * 1.2 + scene.info.bounding_box.center(),
-            scene.info.bounding_box.center(),
-            conf::FRAME_RESOLUTION,
-            conf::FOV_DEGREES,
+        let camera_controller = scene.camera.map_or_else(
+            || {
+                CameraController::new(
+                    scene.info.bounding_box.size() * 1.2 + scene.info.bounding_box.center(),
+                    scene.info.bounding_box.center(),
+                    resolution,
+                    conf::FOV_DEGREES,
+                )
+            },
+            |camera| {
+                CameraController::from_camera(
+                    &camera,
+                    scene.info.bounding_box.size().length(),
+                    resolution,
+                )
+            },
         );
 
+        // added after framing the camera so that the view stays on the scene itself
+        if ground_plane {
+            let size =
+                scene.info.bounding_box.size().max_element().max(1.) * conf::GROUND_PLANE_SCALE;
+            scene.add_ground_plane(size, Self::ground_plane_material());
+        }
+
         let inputs = input::State::default();
+        let animation = std::mem::take(&mut scene.animation);
 
         let renderer = Renderer::create(
             &window.title(),
             window,
             scene,
-            conf::FRAME_RESOLUTION,
+            resolution,
             camera_controller.camera(),
-        );
+            BuildPreference::default(),
+            texture_loading,
+            DepthFormat::default(),
+        )
+        .unwrap_or_else(|err| panic!("Unable to render {scene_file}: {err}"));
 
         Self {
             renderer,
+            scene_file: PathBuf::from(scene_file),
+            animation,
 
             last_frame: Instant::now(),
+            last_shader_check: Instant::now(),
+            animation_start: Instant::now(),
             inputs,
             camera_controller,
+            camera_moving: false,
+            camera_path: CameraPath::default(),
+            frame_stats: None,
             needs_resizing: false,
+            window_size: resolution,
+        }
+    }
+
+    const fn ground_plane_material() -> scene::Material {
+        scene::Material {
+            color: glam::Vec3::splat(conf::GROUND_PLANE_COLOR),
+            color_texture: -1,
+            emittance: glam::Vec3::ZERO,
+            emittance_texture: -1,
+            metallic: 0.,
+            roughness: 1.,
+            metallic_roughness_texture: -1,
+            double_sided: 1,
+            transmission: 0.,
+            ior: 1.5,
+            alpha_mode: scene::alpha_mode::OPAQUE,
+            alpha_cutoff: 0.5,
+            color_tex_coord: 0,
+            emittance_tex_coord: 0,
+            metallic_roughness_tex_coord: 0,
+            pad: 0,
         }
     }
 
     fn render(&mut self) {
         firestorm::profile_method!(render);
 
+        let frame_time = self.last_frame.elapsed();
         self.update();
 
         if self.needs_resizing {
-            if self.renderer.recreate() {
+            if self.renderer.resize(self.window_size) {
                 self.needs_resizing = false;
             } else {
                 return;
             }
         }
 
-        if matches!(self.renderer.render(), Err(RendererError::NeedsRecreating)) {
-            self.needs_resizing = true;
+        match self.renderer.render() {
+            Ok(()) => {
+                if let Some(frame_stats) = &mut self.frame_stats {
+                    frame_stats.record(frame_time, || self.renderer.stats());
+                }
+            }
+            Err(RendererError::NeedsRecreating) => self.needs_resizing = true,
+            // the device may still be busy so the renderer can't be cleaned up
+            Err(err) => {
+                eprintln!("Rendering failed: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn set_use_pathtracer(&mut self, use_pathtracer: bool) {
+        self.renderer.set_use_pathtracer(use_pathtracer);
+    }
+
+    pub fn set_max_sample_luminance(&mut self, max_sample_luminance: Option<f32>) {
+        self.renderer.set_max_sample_luminance(max_sample_luminance);
+    }
+
+    pub fn set_shutter_time(&mut self, shutter_time: f32) {
+        self.renderer.set_shutter_time(shutter_time);
+    }
+
+    pub fn set_tile_size(&mut self, tile_size: Option<u32>) {
+        self.renderer.set_tile_size(tile_size);
+    }
+
+    pub fn set_stereo(&mut self, ipd: Option<Ipd>) {
+        self.renderer.set_stereo(ipd);
+    }
+
+    pub fn set_hdr_output(&mut self, hdr: bool) {
+        self.renderer.set_hdr_output(hdr);
+    }
+
+    // Only frames rendered to the window are logged
+    pub fn set_frame_stats(&mut self, frame_stats: Option<FrameStats>) {
+        self.frame_stats = frame_stats;
+    }
+
+    // Accumulates `samples` for the scene's camera and saves the image without ever presenting it
+    // With a maximum relative error the samples are only an upper bound
+    // The reference file gets the unclamped radiance, see `Renderer::save_reference`, and the EXR file
+    // the radiance before tonemapping, see `Renderer::save_exr`
+    pub fn render_to_file(
+        mut self,
+        samples: u32,
+        max_error: Option<f32>,
+        file: &Path,
+        reference_file: Option<&Path>,
+        exr_file: Option<&Path>,
+    ) {
+        firestorm::profile_method!(render_to_file);
+
+        match max_error {
+            Some(max_error) => self.accumulate_until_converged(max_error, samples),
+            None => self.accumulate(samples, "Rendering"),
+        }
+        self.renderer
+            .render_to_file(file)
+            .expect("Failed to save rendered image");
+        println!("Saved {}", file.display());
+
+        if let Some(reference_file) = reference_file {
+            self.renderer
+                .save_reference(reference_file)
+                .expect("Failed to save reference image");
+            println!("Saved {}", reference_file.display());
+        }
+
+        if let Some(exr_file) = exr_file {
+            self.renderer
+                .save_exr(exr_file)
+                .expect("Failed to save EXR image");
+            println!("Saved {}", exr_file.display());
+        }
+    }
+
+    // Accumulates each camera along the path and saves it as a numbered image
+    pub fn render_camera_path(mut self, camera_path: &CameraPath, samples: u32) {
+        firestorm::profile_method!(render_camera_path);
+
+        let output_dir = Path::new(conf::PLAYBACK_OUTPUT_DIR);
+        fs::create_dir_all(output_dir).expect("Failed to create camera path output directory");
+
+        let cameras = camera_path
+            .playback(conf::PLAYBACK_STEPS_PER_KEYFRAME)
+            .collect::<Vec<_>>();
+        for (idx, &camera) in cameras.iter().enumerate() {
+            self.renderer.update_camera(camera);
+            // the path starts where it starts, not with a move from the scene's camera
+            if idx == 0 {
+                self.renderer.reset_shutter();
+            }
+            self.accumulate(samples, &format!("Frame {}/{}", idx + 1, cameras.len()));
+
+            let file = output_dir.join(format!("{idx:04}.png"));
+            self.renderer
+                .render_to_file(&file)
+                .expect("Failed to save camera path frame");
+            println!("Saved {}", file.display());
+        }
+    }
+
+    fn accumulate(&mut self, num_samples: u32, label: &str) {
+        self.renderer
+            .render_samples(num_samples, |samples| {
+                let percent = 100 * samples / num_samples;
+                print!("\r{label}: {percent:3}%");
+                io::stdout().flush().expect("Failed to flush stdout");
+                true
+            })
+            .unwrap_or_else(|err| {
+                eprintln!("\nRendering failed: {err}");
+                std::process::exit(1);
+            });
+        println!();
+    }
+
+    fn accumulate_until_converged(&mut self, max_error: f32, max_samples: u32) {
+        let (samples, error) = self
+            .renderer
+            .render_until_converged(max_error, max_samples)
+            .unwrap_or_else(|err| {
+                eprintln!("Rendering failed: {err}");
+                std::process::exit(1);
+            });
+        println!("Rendered {samples} samples, relative error {error:.4}");
+    }
+
+    fn record_keyframe(&mut self) {
+        self.camera_path.record(self.camera_controller.camera());
+        println!("Recorded camera keyframe {}", self.camera_path.len());
+    }
+
+    fn export_camera(&self) {
+        self.renderer.export_camera(&self.scene_file);
+    }
+
+    fn save_camera_path(&self) {
+        if !self.camera_path.is_empty() {
+            self.camera_path.save(conf::CAMERA_PATH_FILE);
+            println!("Saved camera path to {}", conf::CAMERA_PATH_FILE);
         }
     }
 
@@ -87,6 +372,13 @@ impl App {
         let now = Instant::now();
         let delta_us = (now - self.last_frame).as_micros();
 
+        if now - self.last_shader_check >= conf::SHADER_RELOAD_INTERVAL {
+            self.last_shader_check = now;
+            if self.renderer.reload_shaders() {
+                println!("Reloaded shaders");
+            }
+        }
+
         let moves = [
             AxisMovement::new(
                 self.inputs.key_pressed(KeyCode::KeyW),
@@ -115,15 +407,24 @@ impl App {
             self.camera_controller.pan(mouse_delta, delta_us);
         }
 
-        if has_movement || has_rotation {
+        let camera_moving = has_movement || has_rotation;
+        if camera_moving {
             self.renderer.update_camera(self.camera_controller.camera());
+        } else if self.camera_moving {
+            self.renderer.reset_shutter();
+        }
+        self.camera_moving = camera_moving;
+
+        if !self.animation.is_empty() {
+            let time = (now - self.animation_start).as_secs_f32();
+            self.renderer.set_pose(&self.animation.sample(time));
         }
 
         self.last_frame = now;
     }
 
-    pub fn window_builder() -> WindowBuilder {
-        WindowBuilder::new().with_inner_size(PhysicalSize::<u32>::from(conf::FRAME_RESOLUTION))
+    pub fn window_builder(resolution: (u32, u32)) -> WindowBuilder {
+        WindowBuilder::new().with_inner_size(PhysicalSize::<u32>::from(resolution))
     }
 
     pub fn run(mut self, event_loop: EventLoop<()>) {
@@ -134,9 +435,11 @@ impl App {
             .run(move |event, elwt| match event {
                 Event::AboutToWait => self.render(),
                 Event::WindowEvent { ref event, .. } => match event {
-                    WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                    WindowEvent::Resized(size) => {
+                        self.window_size = (size.width, size.height);
                         self.needs_resizing = true;
                     }
+                    WindowEvent::ScaleFactorChanged { .. } => self.needs_resizing = true,
                     WindowEvent::CloseRequested
                     | WindowEvent::KeyboardInput {
                         event:
@@ -147,7 +450,10 @@ impl App {
                                 ..
                             },
                         ..
-                    } => elwt.exit(),
+                    } => {
+                        self.save_camera_path();
+                        elwt.exit();
+                    }
                     WindowEvent::KeyboardInput {
                         event:
                             KeyEvent {
@@ -158,6 +464,26 @@ impl App {
                             },
                         ..
                     } => self.renderer.toggle_renderer(),
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => self.record_keyframe(),
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(KeyCode::KeyV),
+                                state: ElementState::Pressed,
+                                repeat: false,
+                                ..
+                            },
+                        ..
+                    } => self.export_camera(),
                     WindowEvent::KeyboardInput {
                         event:
                             KeyEvent {
@@ -39,6 +39,18 @@ impl CameraController {
         }
     }
 
+    // Picks up from an existing camera, `scale` sets the distance covered by movements
+    pub fn from_camera(camera: &inputs::Camera, scale: f32, resolution: (u32, u32)) -> Self {
+        let placement = camera.view.inverse;
+        Self {
+            position: placement.w_axis.truncate(),
+            direction: -placement.z_axis.truncate().normalize(),
+            aspect_ratio: resolution.0 as f32 / resolution.1 as f32,
+            fov: 2. * (1. / camera.proj.forward.y_axis.y.abs()).atan(),
+            scale,
+        }
+    }
+
     pub fn move_in_direction(&mut self, axes: &[AxisMovement; 3], slow: bool, delta_us: u128) {
         let final_direction = axes[0].factor() * self.direction
             + axes[1].factor() * self.right_axis()
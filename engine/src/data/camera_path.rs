@@ -0,0 +1,65 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use shared::inputs;
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct CameraPath {
+    keyframes: Vec<inputs::Camera>,
+}
+
+impl CameraPath {
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        firestorm::profile_method!(load);
+
+        let contents = fs::read_to_string(path).expect("Failed to read camera path file");
+        serde_json::from_str(&contents).expect("Failed to parse camera path")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) {
+        firestorm::profile_method!(save);
+
+        let contents = serde_json::to_string_pretty(self).expect("Failed to serialize camera path");
+        fs::write(path, contents).expect("Failed to write camera path file");
+    }
+
+    pub fn record(&mut self, camera: inputs::Camera) {
+        self.keyframes.push(camera);
+    }
+
+    pub const fn len(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    // Every keyframe followed by `steps - 1` cameras interpolated towards the next one
+    pub fn playback(&self, steps: usize) -> impl Iterator<Item = inputs::Camera> + '_ {
+        self.keyframes
+            .windows(2)
+            .flat_map(move |pair| {
+                (0..steps)
+                    .map(move |step| interpolate(&pair[0], &pair[1], step as f32 / steps as f32))
+            })
+            .chain(self.keyframes.last().copied())
+    }
+}
+
+// The projection is kept from the first camera, only the placement is blended
+fn interpolate(from: &inputs::Camera, to: &inputs::Camera, t: f32) -> inputs::Camera {
+    let (_, from_rotation, from_position) = from.view.inverse.to_scale_rotation_translation();
+    let (_, to_rotation, to_position) = to.view.inverse.to_scale_rotation_translation();
+
+    let placement = glam::Mat4::from_rotation_translation(
+        from_rotation.slerp(to_rotation, t),
+        from_position.lerp(to_position, t),
+    );
+
+    inputs::Camera {
+        view: inputs::Transform::new(placement.inverse()),
+        proj: from.proj,
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use renderer::RenderStats;
+
+mod conf {
+    // frame times are averaged over this long before being logged
+    pub const LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+    pub const CSV_HEADER: &str = "time_s,frames,avg_frame_ms,max_frame_ms,samples,renderer";
+}
+
+// Logs averaged frame times for tracking performance, along with what was being rendered so that
+// the numbers can be compared
+pub struct FrameStats {
+    output: Box<dyn Write>,
+    csv: bool,
+    start: Instant,
+    interval_start: Instant,
+    frames: u32,
+    total_frame_time: Duration,
+    max_frame_time: Duration,
+}
+
+impl FrameStats {
+    pub fn to_stdout() -> Self {
+        Self::new(Box::new(io::stdout()), false)
+    }
+
+    pub fn to_csv(path: impl AsRef<Path>) -> Self {
+        let file = File::create(path).expect("Failed to create frame stats file");
+        let mut stats = Self::new(Box::new(BufWriter::new(file)), true);
+        stats.write_line(conf::CSV_HEADER);
+        stats
+    }
+
+    fn new(output: Box<dyn Write>, csv: bool) -> Self {
+        Self {
+            output,
+            csv,
+            start: Instant::now(),
+            interval_start: Instant::now(),
+            frames: 0,
+            total_frame_time: Duration::ZERO,
+            max_frame_time: Duration::ZERO,
+        }
+    }
+
+    // The render stats are only queried once per logged interval
+    pub fn record(&mut self, frame_time: Duration, stats: impl FnOnce() -> RenderStats) {
+        self.frames += 1;
+        self.total_frame_time += frame_time;
+        self.max_frame_time = self.max_frame_time.max(frame_time);
+
+        if self.interval_start.elapsed() < conf::LOG_INTERVAL {
+            return;
+        }
+
+        let stats = stats();
+        let time = self.start.elapsed().as_secs_f64();
+        let avg_frame_ms = self.total_frame_time.as_secs_f64() * 1e3 / f64::from(self.frames);
+        let max_frame_ms = self.max_frame_time.as_secs_f64() * 1e3;
+        let renderer = if stats.use_pathtracer {
+            "pathtracer"
+        } else {
+            "rasterizer"
+        };
+
+        let line = if self.csv {
+            format!(
+                "{time:.3},{},{avg_frame_ms:.3},{max_frame_ms:.3},{},{renderer}",
+                self.frames, stats.accumulated_samples
+            )
+        } else {
+            format!(
+                "{time:7.1}s: {avg_frame_ms:7.2} ms avg, {max_frame_ms:7.2} ms max over {} frames, \
+                 {} spp, {renderer}",
+                self.frames, stats.accumulated_samples
+            )
+        };
+        self.write_line(&line);
+
+        self.interval_start = Instant::now();
+        self.frames = 0;
+        self.total_frame_time = Duration::ZERO;
+        self.max_frame_time = Duration::ZERO;
+    }
+
+    // flushed right away so that the log is complete however the engine exits
+    fn write_line(&mut self, line: &str) {
+        writeln!(self.output, "{line}")
+            .and_then(|()| self.output.flush())
+            .expect("Failed to write frame stats");
+    }
+}
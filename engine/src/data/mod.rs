@@ -1 +1,3 @@
 pub mod camera_controller;
+pub mod camera_path;
+pub mod frame_stats;
@@ -2,26 +2,262 @@ mod app;
 mod data;
 mod input;
 
-use std::env;
+use std::{env, path::Path};
 
 use winit::event_loop::EventLoop;
 
-use app::App;
+use renderer::{Ipd, TextureLoading};
+
+use app::{conf, App};
+use data::{camera_path::CameraPath, frame_stats::FrameStats};
+
+// Command line options, see `Args::parse`
+#[derive(Default)]
+struct Args {
+    scene_files: Vec<String>,
+    camera_path_file: Option<String>,
+    output_file: Option<String>,
+    reference_file: Option<String>,
+    exr_file: Option<String>,
+    max_sample_luminance: Option<f32>,
+    resolution: (u32, u32),
+    use_pathtracer: bool,
+    hdr_output: bool,
+    samples: Option<u32>,
+    max_error: Option<f32>,
+    ground_plane: bool,
+    shutter_time: f32,
+    texture_loading: TextureLoading,
+    frame_stats: Option<FrameStats>,
+    ipd: Option<Ipd>,
+    tile_size: Option<u32>,
+}
 
 fn main() {
-    let scene_file = env::args().nth(1).expect("Please specify a scene file");
+    let args = Args::parse();
+    args.validate();
+    let Args {
+        scene_files,
+        camera_path_file,
+        output_file,
+        reference_file,
+        exr_file,
+        max_sample_luminance,
+        resolution,
+        use_pathtracer,
+        hdr_output,
+        samples,
+        max_error,
+        ground_plane,
+        shutter_time,
+        mut texture_loading,
+        frame_stats,
+        ipd,
+        tile_size,
+    } = args;
+
+    // textures only stream in while rendering to the window
+    let headless = camera_path_file.is_some() || output_file.is_some();
+    if headless {
+        texture_loading = TextureLoading::Eager;
+    }
 
-    let event_loop = EventLoop::new().expect("Failed to create event loop");
+    let mut event_loop = EventLoop::new().expect("Failed to create event loop");
 
-    let window = App::window_builder()
+    // playback and offline renders go straight to files so the window is never shown
+    let window = App::window_builder(resolution)
+        .with_visible(!headless)
         .build(&event_loop)
         .expect("Failed to create window");
 
-    let app = App::new(&window, &scene_file);
+    let Some(scene) = App::load_scenes(&window, &mut event_loop, &scene_files) else {
+        return;
+    };
 
-    app.run(event_loop);
+    let mut app = App::new(
+        &window,
+        &scene_files[0],
+        scene,
+        resolution,
+        ground_plane,
+        texture_loading,
+    );
+    app.set_use_pathtracer(use_pathtracer);
+    app.set_shutter_time(shutter_time);
+    app.set_max_sample_luminance(max_sample_luminance);
+    app.set_frame_stats(frame_stats);
+    app.set_stereo(ipd);
+    app.set_tile_size(tile_size);
+    // only what is presented is affected, files are always saved as SDR
+    if hdr_output && !headless {
+        app.set_hdr_output(true);
+    }
+
+    // files are always path traced, whichever renderer is shown in the window
+    match (camera_path_file, output_file) {
+        (Some(camera_path_file), _) => app.render_camera_path(
+            &CameraPath::load(camera_path_file),
+            samples.unwrap_or(conf::PLAYBACK_SAMPLES_PER_STEP),
+        ),
+        (None, Some(output_file)) => app.render_to_file(
+            samples.unwrap_or(conf::OUTPUT_SAMPLES),
+            max_error,
+            Path::new(&output_file),
+            reference_file.as_deref().map(Path::new),
+            exr_file.as_deref().map(Path::new),
+        ),
+        (None, None) => app.run(event_loop),
+    }
 
     if firestorm::enabled() {
         firestorm::save("./profiling_results/").expect("Failed to save profiling results");
     }
 }
+
+impl Args {
+    fn parse() -> Self {
+        let mut args = env::args().skip(1);
+        let mut parsed = Self {
+            resolution: conf::FRAME_RESOLUTION,
+            use_pathtracer: true,
+            ..Self::default()
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--camera-path" => {
+                    parsed.camera_path_file =
+                        Some(args.next().expect("Please specify a camera path file"));
+                }
+                "--output" => {
+                    parsed.output_file =
+                        Some(args.next().expect("Please specify an output image file"));
+                }
+                // the unclamped radiance, saved alongside the output
+                "--reference" => {
+                    parsed.reference_file =
+                        Some(args.next().expect("Please specify a reference image file"));
+                }
+                // the radiance before tonemapping, saved alongside the output
+                "--exr" => {
+                    parsed.exr_file = Some(args.next().expect("Please specify an EXR image file"));
+                }
+                "--clamp-fireflies" => {
+                    parsed.max_sample_luminance = Some(parse_arg(
+                        args.next(),
+                        |&luminance| luminance > 0.,
+                        "Please specify a positive luminance to clamp samples to",
+                    ));
+                }
+                "--resolution" => {
+                    parsed.resolution = args
+                        .next()
+                        .as_deref()
+                        .and_then(parse_resolution)
+                        .expect("Please specify a resolution as WIDTHxHEIGHT");
+                }
+                "--pathtracer" => parsed.use_pathtracer = true,
+                "--rasterizer" => parsed.use_pathtracer = false,
+                "--hdr" => parsed.hdr_output = true,
+                "--samples" => {
+                    parsed.samples = Some(parse_arg(
+                        args.next(),
+                        |&samples| samples > 0,
+                        "Please specify a positive number of samples",
+                    ));
+                }
+                "--max-error" => {
+                    parsed.max_error = Some(parse_arg(
+                        args.next(),
+                        |&max_error| max_error > 0.,
+                        "Please specify a positive relative error",
+                    ));
+                }
+                "--ground-plane" => parsed.ground_plane = true,
+                // motion blur across the steps of a camera path, as a fraction of each step
+                "--shutter-time" => {
+                    parsed.shutter_time = parse_arg(
+                        args.next(),
+                        |shutter_time| (0. ..=1.).contains(shutter_time),
+                        "Please specify a shutter time between 0 and 1",
+                    );
+                }
+                // side by side views for cardboard style viewing, the eyes this far apart in world
+                // units
+                "--stereo" => {
+                    parsed.ipd = Some(Ipd(parse_arg(
+                        args.next(),
+                        |&ipd| ipd > 0.,
+                        "Please specify a positive distance between the eyes",
+                    )));
+                }
+                // path traced in tiles this many pixels a side, for long renders that would
+                // otherwise trip the OS GPU timeout
+                "--tile-size" => {
+                    parsed.tile_size = Some(parse_arg(
+                        args.next(),
+                        |&tile_size| tile_size > 0,
+                        "Please specify a positive tile size in pixels",
+                    ));
+                }
+                "--stream-textures" => {
+                    parsed.texture_loading = TextureLoading::Streamed { budget: None };
+                }
+                "--texture-budget" => {
+                    let budget_mib: u64 = parse_arg(
+                        args.next(),
+                        |_| true,
+                        "Please specify a texture budget in MiB",
+                    );
+                    parsed.texture_loading = TextureLoading::Streamed {
+                        budget: Some(budget_mib << 20),
+                    };
+                }
+                // frame times averaged over each second
+                "--stats" => parsed.frame_stats = Some(FrameStats::to_stdout()),
+                "--stats-csv" => {
+                    parsed.frame_stats = Some(FrameStats::to_csv(
+                        args.next().expect("Please specify a frame stats file"),
+                    ));
+                }
+                arg if arg.starts_with("--") => panic!("Unknown argument: {arg}"),
+                _ => parsed.scene_files.push(arg),
+            }
+        }
+        parsed
+    }
+
+    // Panics on combinations of options that can't be honored
+    fn validate(&self) {
+        assert!(!self.scene_files.is_empty(), "Please specify a scene file");
+        assert!(
+            self.camera_path_file.is_none() || self.output_file.is_none(),
+            "--camera-path and --output can't be combined"
+        );
+        assert!(
+            self.reference_file.is_none() || self.output_file.is_some(),
+            "--reference is only saved along with --output"
+        );
+        assert!(
+            self.exr_file.is_none() || self.output_file.is_some(),
+            "--exr is only saved along with --output"
+        );
+    }
+}
+
+// The value of an option, which panics with the message when it is missing or invalid
+fn parse_arg<T: std::str::FromStr>(
+    arg: Option<String>,
+    valid: impl Fn(&T) -> bool,
+    message: &str,
+) -> T {
+    arg.and_then(|arg| arg.parse().ok())
+        .filter(valid)
+        .expect(message)
+}
+
+// e.g. 1920x1080
+fn parse_resolution(resolution: &str) -> Option<(u32, u32)> {
+    let (width, height) = resolution.split_once('x')?;
+    let resolution = (width.parse().ok()?, height.parse().ok()?);
+    (resolution.0 > 0 && resolution.1 > 0).then_some(resolution)
+}
@@ -2,15 +2,40 @@ pub use glsl_derive::GlslStruct;
 
 pub trait Glsl {
     const NAME: &'static str;
+
+    fn layout(packing: Packing) -> Layout {
+        Layout::of(Self::NAME, packing)
+    }
 }
 
 pub struct GlslField {
     pub name: &'static str,
     pub ty: &'static str,
+    pub offset: usize,
+    pub layout: fn(Packing) -> Layout,
+}
+
+// Rules that GLSL lays out the members of a block with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Packing {
+    // uniform blocks
+    Std140,
+    // push constants and storage blocks
+    Std430,
+    // blocks declared `layout(scalar)`, such as the buffer references
+    Scalar,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub align: usize,
+    pub size: usize,
 }
 
 pub trait GlslStruct: Glsl {
     const FIELDS: &'static [GlslField];
+    // `size_of` the host struct
+    const SIZE: usize;
 
     fn glsl_struct_definition() -> String {
         let mut def = String::from("struct ");
@@ -26,8 +51,182 @@ pub trait GlslStruct: Glsl {
         def.push_str("};\n");
         def
     }
+
+    // Emits explicit padding members so that the std140 layout of the struct
+    // matches the offsets of the host `#[repr(C)]` struct
+    fn glsl_struct_definition_std140() -> String {
+        let mut def = String::from("struct ");
+        def.push_str(Self::NAME);
+        def.push_str(" {\n");
+
+        let mut offset = 0;
+        let mut num_pads = 0;
+        for field in Self::FIELDS {
+            let layout = (field.layout)(Packing::Std140);
+
+            while offset + Layout::PADDING.size <= field.offset {
+                def.push_str("  uint _pad");
+                def.push_str(&num_pads.to_string());
+                def.push_str(";\n");
+                offset += Layout::PADDING.size;
+                num_pads += 1;
+            }
+
+            assert!(
+                offset == field.offset && offset % layout.align == 0,
+                "`{}::{}` at offset {} cannot satisfy std140 alignment of {}",
+                Self::NAME,
+                field.name,
+                field.offset,
+                layout.align,
+            );
+
+            def.push_str("  ");
+            def.push_str(field.ty);
+            def.push(' ');
+            def.push_str(field.name);
+            def.push_str(";\n");
+            offset += layout.size;
+        }
+        def.push_str("};\n");
+        def
+    }
+
+    fn struct_layout(packing: Packing) -> Layout {
+        let align = Self::FIELDS
+            .iter()
+            .map(|field| (field.layout)(packing).align)
+            .max()
+            .unwrap_or_default();
+        let align = if packing == Packing::Std140 {
+            align.next_multiple_of(Layout::STD140_STRUCT_ALIGN)
+        } else {
+            align
+        };
+        let size = Self::FIELDS
+            .iter()
+            .zip(Self::glsl_offsets(packing))
+            .map(|(field, offset)| offset + (field.layout)(packing).size)
+            .max()
+            .unwrap_or_default()
+            .next_multiple_of(align);
+        Layout { align, size }
+    }
+
+    // Offsets that GLSL places the members of the generated definition at
+    fn glsl_offsets(packing: Packing) -> Vec<usize> {
+        let mut offset = 0_usize;
+        Self::FIELDS
+            .iter()
+            .map(|field| {
+                let layout = (field.layout)(packing);
+                let field_offset = offset.next_multiple_of(layout.align);
+                offset = field_offset + layout.size;
+                field_offset
+            })
+            .collect()
+    }
+
+    // Compares the host `#[repr(C)]` struct against the generated definition laid out with
+    // `packing`, listing every member that GLSL would read from elsewhere
+    fn validate_layout(packing: Packing) -> Result<(), LayoutError> {
+        let mismatches = Self::FIELDS
+            .iter()
+            .zip(Self::glsl_offsets(packing))
+            .filter(|&(field, glsl_offset)| field.offset != glsl_offset)
+            .map(|(field, glsl_offset)| FieldMismatch {
+                name: field.name,
+                host_offset: field.offset,
+                glsl_offset,
+            })
+            .collect::<Vec<_>>();
+        let glsl_size = Self::struct_layout(packing).size;
+
+        if mismatches.is_empty() && glsl_size == Self::SIZE {
+            Ok(())
+        } else {
+            Err(LayoutError {
+                name: Self::NAME,
+                packing,
+                host_size: Self::SIZE,
+                glsl_size,
+                mismatches,
+            })
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LayoutError {
+    pub name: &'static str,
+    pub packing: Packing,
+    pub host_size: usize,
+    pub glsl_size: usize,
+    pub mismatches: Vec<FieldMismatch>,
 }
 
+#[derive(Debug)]
+pub struct FieldMismatch {
+    pub name: &'static str,
+    pub host_offset: usize,
+    pub glsl_offset: usize,
+}
+
+impl Layout {
+    const PADDING: Self = Self::new(4, 4);
+    const STD140_STRUCT_ALIGN: usize = 16;
+
+    pub const fn new(align: usize, size: usize) -> Self {
+        Self { align, size }
+    }
+
+    // std140 and std430 only differ for arrays and structs
+    pub fn of(name: &str, packing: Packing) -> Self {
+        match (name, packing) {
+            ("float" | "int" | "uint", _) => Self::new(4, 4),
+            ("vec2" | "uvec2", Packing::Scalar) => Self::new(4, 8),
+            ("double" | "int64_t" | "uint64_t" | "vec2" | "uvec2", _) => Self::new(8, 8),
+            ("vec3", Packing::Scalar) => Self::new(4, 12),
+            ("vec3", _) => Self::new(16, 12),
+            ("vec4", Packing::Scalar) => Self::new(4, 16),
+            ("vec4", _) => Self::new(16, 16),
+            ("mat4", Packing::Scalar) => Self::new(4, 64),
+            ("mat4", _) => Self::new(16, 64),
+            _ => panic!("No {packing} layout known for `{name}`"),
+        }
+    }
+}
+
+impl std::fmt::Display for Packing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Std140 => write!(f, "std140"),
+            Self::Std430 => write!(f, "std430"),
+            Self::Scalar => write!(f, "scalar"),
+        }
+    }
+}
+
+impl std::fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` is {} bytes on the host and {} bytes in {}",
+            self.name, self.host_size, self.glsl_size, self.packing
+        )?;
+        for mismatch in &self.mismatches {
+            write!(
+                f,
+                ", `{}` is at offset {} on the host and {} in GLSL",
+                mismatch.name, mismatch.host_offset, mismatch.glsl_offset
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
 macro_rules! impl_glsl {
     ($type:ty => $name:expr) => {
         impl Glsl for $type {
@@ -47,4 +246,6 @@ impl_glsl!(glam::Vec2 => "vec2");
 impl_glsl!(glam::Vec3 => "vec3");
 impl_glsl!(glam::Vec4 => "vec4");
 
+impl_glsl!(glam::UVec2 => "uvec2");
+
 impl_glsl!(glam::Mat4 => "mat4");
@@ -15,13 +15,16 @@ pub fn derive_macro_glsl(input: TokenStream) -> TokenStream {
             fields: Fields::Named(ref fields),
             ..
         }) => fields.named.iter().map(|field| {
-            let field_name_str = field.ident.as_ref().unwrap().to_string();
+            let field_name = field.ident.as_ref().unwrap();
+            let field_name_str = field_name.to_string();
             let field_type = &field.ty;
 
             quote! {
                 ::glsl::GlslField {
                     name: #field_name_str,
                     ty: <#field_type as ::glsl::Glsl>::NAME,
+                    offset: ::core::mem::offset_of!(Self, #field_name),
+                    layout: <#field_type as ::glsl::Glsl>::layout,
                 }
             }
         }),
@@ -31,12 +34,17 @@ pub fn derive_macro_glsl(input: TokenStream) -> TokenStream {
     quote! {
         impl #impl_generics ::glsl::Glsl for #name #ty_generics #where_clause {
             const NAME: &'static str = #name_str;
+
+            fn layout(packing: ::glsl::Packing) -> ::glsl::Layout {
+                <Self as ::glsl::GlslStruct>::struct_layout(packing)
+            }
         }
 
         impl #impl_generics ::glsl::GlslStruct for #name #ty_generics #where_clause {
             const FIELDS: &'static [::glsl::GlslField] = &[
                 #( #fields, )*
             ];
+            const SIZE: usize = ::core::mem::size_of::<Self>();
         }
     }
     .into()
@@ -40,6 +40,16 @@ impl Compiler {
         options.set_optimization_level(shaderc::OptimizationLevel::Performance);
         options.set_generate_debug_info();
         options.set_warnings_as_errors();
+        // image format qualifier of the intermediate render targets
+        let intermediate_format = if std::env::var_os("CARGO_FEATURE_HDR16").is_some() {
+            "rgba16f"
+        } else {
+            "rgba32f"
+        };
+        options.add_macro_definition("INTERMEDIATE_FORMAT", Some(intermediate_format));
+        if std::env::var_os("CARGO_FEATURE_RAY_QUERY_AO").is_some() {
+            options.add_macro_definition("RAY_QUERY_AO", None);
+        }
         options.set_include_callback(|source, _, _, _| {
             self.sources.get(source).map_or_else(
                 || Err(format!("Unable to resolve source {source}")),
@@ -100,6 +110,8 @@ impl Compiler {
             Some("rgen") => Some(shaderc::ShaderKind::RayGeneration),
             Some("rmiss") => Some(shaderc::ShaderKind::Miss),
             Some("rchit") => Some(shaderc::ShaderKind::ClosestHit),
+            Some("rahit") => Some(shaderc::ShaderKind::AnyHit),
+            Some("comp") => Some(shaderc::ShaderKind::Compute),
             _ => None,
         })
     }
@@ -1,21 +1,49 @@
-use std::{ops::Deref, slice};
+use std::{
+    ops::Deref,
+    slice,
+    time::{Duration, Instant},
+};
 
 use ash::vk;
 
 use crate::{
-    buffer::Buffer, commands::Commands, context::Context, memory, query_pool::QueryPool,
-    scope::Scope, world, Destroy,
+    buffer::Buffer,
+    commands::Commands,
+    context::{Context, Resource},
+    memory,
+    query_pool::QueryPool,
+    scope::Scope,
+    stats::AsMemoryReport,
+    world, Destroy,
 };
 
+// Preferring fast traces with compaction gives the smallest and quickest to traverse structures,
+// which suits static scenes. Preferring fast builds and skipping compaction (which needs a round
+// trip to the host for the compacted sizes) gets frequently rebuilt scenes on screen sooner at the
+// cost of slower traversal and more memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildPreference {
+    pub fast_build: bool,
+    pub compact: bool,
+}
+
 pub struct AccelerationStructures {
     blases: Vec<AccelerationStructure>,
     pub tlas: AccelerationStructure,
+    instances: InstancesInfo, // kept around for updating the TLAS in place
+    // updates have to be built with the same flags as the original TLAS
+    preference: BuildPreference,
+    // wall clock time of the initial BLAS and TLAS builds, including waiting on the GPU
+    pub build_time: Duration,
+    // total size of the BLASes as originally built, before any compaction
+    uncompacted_blas_size: vk::DeviceSize,
 }
 
 pub struct AccelerationStructure {
     accel: vk::AccelerationStructureKHR,
     address: vk::DeviceAddress,
     buffer: Buffer,
+    size: vk::DeviceSize,
 }
 
 #[derive(Debug)]
@@ -42,75 +70,276 @@ unsafe impl bytemuck::Zeroable for Instance {}
 unsafe impl bytemuck::Pod for Instance {}
 
 impl AccelerationStructures {
-    pub fn build(ctx: &Context, scene_info: &world::SceneInfo) -> Self {
+    // The materials decide the opacity of instances with a material override
+    pub fn build(
+        ctx: &Context,
+        scene_info: &world::SceneInfo,
+        materials: &[scene::Material],
+        preference: BuildPreference,
+    ) -> Self {
         firestorm::profile_method!(build);
 
+        let start = Instant::now();
+
         let mut scope = Scope::new(Commands::begin_on_queue(
             ctx,
             "Acceleration Structures - Initialization".to_owned(),
             ctx.queues.compute(),
         ));
 
-        let blases = Self::build_blases(ctx, &mut scope, scene_info);
-        let tlas = Self::build_tlas(ctx, &mut scope, &scene_info.host.instances, &blases);
+        let (blases, uncompacted_blas_size) =
+            Self::build_blases(ctx, &mut scope, scene_info, preference);
+        let (tlas, instances) = Self::build_tlas(
+            ctx,
+            &mut scope,
+            &scene_info.host,
+            materials,
+            &blases,
+            preference,
+            None,
+        );
 
         scope.finish(ctx);
 
-        Self { blases, tlas }
+        Self {
+            blases,
+            tlas,
+            instances,
+            preference,
+            build_time: start.elapsed(),
+            uncompacted_blas_size,
+        }
+    }
+
+    pub fn memory_report(&self) -> AsMemoryReport {
+        AsMemoryReport {
+            blas_bytes: self.blases.iter().map(|blas| blas.size).sum(),
+            tlas_bytes: self.tlas.size,
+            uncompacted_blas_bytes: self.uncompacted_blas_size,
+        }
     }
 
     fn build_tlas(
         ctx: &Context,
         scope: &mut Scope,
-        instances: &[scene::Instance],
+        scene_info: &scene::Info,
+        materials: &[scene::Material],
         blases: &[AccelerationStructure],
-    ) -> AccelerationStructure {
+        preference: BuildPreference,
+        visible: Option<&[bool]>,
+    ) -> (AccelerationStructure, InstancesInfo) {
         firestorm::profile_method!(build_tlas);
 
-        let instances_info =
-            InstancesInfo::for_instances(ctx, scope.commands.buffer, instances, blases);
+        let instances_info = InstancesInfo::for_instances(
+            ctx,
+            scope.commands.buffer,
+            scene_info,
+            materials,
+            blases,
+            visible,
+        );
         let geometry_info = GeometryInfo::for_instances(ctx, &instances_info);
-        let mut build_info = BuildInfo::for_geometry(ctx, false, &geometry_info);
-        scope.add_resource(instances_info);
+        let mut build_info = BuildInfo::for_geometry(ctx, false, preference, &geometry_info);
+
+        let tlas =
+            AccelerationStructure::build(ctx, scope, "Top Level".to_owned(), &mut build_info, None);
+
+        (tlas, instances_info)
+    }
+
+    // Builds a new TLAS from only the visible instances, or all of them without a mask. Any
+    // descriptors referring to the old TLAS must be rewritten, and the GPU must be done with it
+    pub fn rebuild_tlas(
+        &mut self,
+        ctx: &Context,
+        scene_info: &scene::Info,
+        materials: &[scene::Material],
+        visible: Option<&[bool]>,
+    ) {
+        firestorm::profile_method!(rebuild_tlas);
+
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "Acceleration Structures - Rebuild".to_owned(),
+            ctx.queues.compute(),
+        ));
+
+        let (tlas, instances) = Self::build_tlas(
+            ctx,
+            &mut scope,
+            scene_info,
+            materials,
+            &self.blases,
+            self.preference,
+            visible,
+        );
+
+        scope.finish(ctx);
+
+        unsafe {
+            self.tlas.destroy_with(ctx);
+            self.instances.destroy_with(ctx);
+        }
+        self.tlas = tlas;
+        self.instances = instances;
+    }
+
+    // Refits the TLAS to the instance's new transform, mask or material override, the BLASes are
+    // left untouched
+    pub fn update_instance(
+        &mut self,
+        ctx: &Context,
+        scene_info: &scene::Info,
+        materials: &[scene::Material],
+        index: usize,
+        visible: Option<&[bool]>,
+    ) {
+        firestorm::profile_method!(update_instance);
+
+        // hidden instances are left out of the TLAS and the others keep their order
+        let slot = match visible {
+            Some(visible) if !visible[index] => return,
+            Some(visible) => visible[..index].iter().filter(|&&visible| visible).count(),
+            None => index,
+        };
+
+        let instance = &scene_info.instances[index];
+        self.instances.instances[slot] = Instance::for_instance(
+            instance,
+            &scene_info.meshes[instance.blas_index],
+            materials,
+            &self.blases,
+        );
+        self.refit_tlas(ctx);
+    }
+
+    // Builds the BLASes of the meshes again from the current vertices, eg. once they were skinned,
+    // and refits the TLAS onto them and the current instance transforms
+    pub fn rebuild_meshes(
+        &mut self,
+        ctx: &Context,
+        scene_info: &world::SceneInfo,
+        materials: &[scene::Material],
+        meshes: &[usize],
+        visible: Option<&[bool]>,
+    ) {
+        firestorm::profile_method!(rebuild_meshes);
+
+        if !meshes.is_empty() {
+            let mut scope = Scope::new(Commands::begin_on_queue(
+                ctx,
+                "Acceleration Structures - Rebuild Meshes".to_owned(),
+                ctx.queues.compute(),
+            ));
+
+            // not worth the round trip to the host for compaction since they are rebuilt again
+            let preference = BuildPreference {
+                compact: false,
+                ..self.preference
+            };
+            let geometry_infos = meshes
+                .iter()
+                .map(|&mesh| GeometryInfo::for_mesh(scene_info, &scene_info.host.meshes[mesh]))
+                .collect::<Vec<_>>();
+            let mut build_infos = BuildInfo::for_geometries(ctx, true, preference, &geometry_infos);
+
+            let blases = meshes
+                .iter()
+                .zip(&mut build_infos)
+                .map(|(mesh, build_info)| {
+                    AccelerationStructure::build(
+                        ctx,
+                        &mut scope,
+                        mesh.to_string(),
+                        build_info,
+                        None,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            scope.finish(ctx);
+
+            for (&mesh, blas) in meshes.iter().zip(blases) {
+                let mut old = std::mem::replace(&mut self.blases[mesh], blas);
+                unsafe {
+                    old.destroy_with(ctx);
+                }
+            }
+        }
 
-        AccelerationStructure::build(ctx, scope, "Top Level".to_owned(), &mut build_info, None)
+        self.instances.instances =
+            Instance::for_instances(&scene_info.host, materials, &self.blases, visible);
+        self.refit_tlas(ctx);
+    }
+
+    // Updates the TLAS in place from the instances as they are on the host
+    fn refit_tlas(&self, ctx: &Context) {
+        self.instances
+            .buffer
+            .fill_from(ctx, bytemuck::cast_slice(&self.instances.instances));
+
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "Acceleration Structures - Update".to_owned(),
+            ctx.queues.compute(),
+        ));
+
+        let geometry_info = GeometryInfo::for_instances(ctx, &self.instances);
+        let mut build_info = BuildInfo::for_geometry(ctx, false, self.preference, &geometry_info);
+        build_info.geometry = build_info
+            .geometry
+            .mode(vk::BuildAccelerationStructureModeKHR::UPDATE)
+            .src_acceleration_structure(self.tlas.accel)
+            .dst_acceleration_structure(self.tlas.accel);
+        build_info.geometry.scratch_data.device_address = AccelerationStructure::create_scratch(
+            ctx,
+            &mut scope,
+            "Top Level Update".to_owned(),
+            build_info.sizes.update_scratch_size,
+        );
+
+        AccelerationStructure::cmd_build(ctx, scope.commands.buffer, &build_info);
+
+        scope.finish(ctx);
     }
 
     pub fn build_blases(
         ctx: &Context,
         scope: &mut Scope,
         scene_info: &world::SceneInfo,
-    ) -> Vec<AccelerationStructure> {
+        preference: BuildPreference,
+    ) -> (Vec<AccelerationStructure>, vk::DeviceSize) {
         firestorm::profile_method!(build_blases);
 
-        let geometry_infos = GeometryInfo::for_primitives(scene_info);
-        let mut build_infos = BuildInfo::for_geometries(ctx, true, &geometry_infos);
+        let geometry_infos = GeometryInfo::for_meshes(scene_info);
+        let mut build_infos = BuildInfo::for_geometries(ctx, true, preference, &geometry_infos);
 
-        let max_scratch_size = build_infos
+        // each build gets its own region of a shared scratch buffer so that the builds can overlap
+        let scratch_alignment = AccelerationStructure::scratch_alignment(ctx);
+        let scratch_sizes = build_infos
             .iter()
-            .map(|build_info| build_info.sizes.build_scratch_size)
-            .max()
-            .unwrap();
+            .map(|build_info| {
+                memory::align_to(build_info.sizes.build_scratch_size as _, scratch_alignment)
+            })
+            .collect::<Vec<_>>();
 
         let scratch_address = AccelerationStructure::create_scratch(
             ctx,
             scope,
             "Bottom Level".to_owned(),
-            max_scratch_size,
+            scratch_sizes.iter().sum::<usize>() as _,
         );
-
-        let query_type = vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR;
-        let query_pool = QueryPool::create(
-            ctx,
-            "Acceleration Structure Compacted Size".to_owned(),
-            query_type,
-            build_infos.len() as _,
-        );
-        query_pool.reset(ctx, scope.commands.buffer);
+        let scratch_addresses = scratch_sizes.iter().scan(scratch_address, |address, size| {
+            let current = *address;
+            *address += *size as vk::DeviceAddress;
+            Some(current)
+        });
 
         let mut uncompacted = Vec::with_capacity(build_infos.len());
 
-        for (idx, build_info) in build_infos.iter_mut().enumerate() {
+        for ((idx, build_info), scratch_address) in
+            build_infos.iter_mut().enumerate().zip(scratch_addresses)
+        {
             uncompacted.push(AccelerationStructure::build(
                 ctx,
                 scope,
@@ -118,30 +347,53 @@ impl AccelerationStructures {
                 build_info,
                 Some(scratch_address),
             ));
+        }
 
-            unsafe {
-                ctx.cmd_pipeline_barrier(
-                    scope.commands.buffer,
-                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-                    vk::DependencyFlags::empty(),
-                    slice::from_ref(
-                        &vk::MemoryBarrier::default()
-                            .src_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
-                            .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR),
-                    ),
-                    &[],
-                    &[],
-                );
-
-                ctx.ext.accel.cmd_write_acceleration_structures_properties(
-                    scope.commands.buffer,
-                    slice::from_ref(&build_info.geometry.dst_acceleration_structure),
-                    query_type,
-                    *query_pool,
-                    idx as _,
-                );
-            }
+        // the size queries and the TLAS build both read the finished BLASes
+        unsafe {
+            ctx.cmd_pipeline_barrier(
+                scope.commands.buffer,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::DependencyFlags::empty(),
+                slice::from_ref(
+                    &vk::MemoryBarrier::default()
+                        .src_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
+                        .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR),
+                ),
+                &[],
+                &[],
+            );
+        }
+
+        let uncompacted_size = uncompacted.iter().map(|accel| accel.size).sum();
+
+        if !preference.compact {
+            return (uncompacted, uncompacted_size);
+        }
+
+        let query_type = vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR;
+        let query_pool = QueryPool::create(
+            ctx,
+            "Acceleration Structure Compacted Size".to_owned(),
+            query_type,
+            build_infos.len() as _,
+        );
+        query_pool.reset(ctx, scope.commands.buffer);
+
+        let uncompacted_accels = uncompacted
+            .iter()
+            .map(|accel| accel.accel)
+            .collect::<Vec<_>>();
+
+        unsafe {
+            ctx.ext.accel.cmd_write_acceleration_structures_properties(
+                scope.commands.buffer,
+                &uncompacted_accels,
+                query_type,
+                *query_pool,
+                0,
+            );
         }
 
         scope.commands.flush(ctx);
@@ -173,7 +425,7 @@ impl AccelerationStructures {
         scope.add_resource(uncompacted);
         scope.add_resource(query_pool);
 
-        compacted
+        (compacted, uncompacted_size)
     }
 }
 
@@ -208,6 +460,7 @@ impl AccelerationStructure {
                 .expect("Failed to create acceleration structure")
         };
         ctx.set_debug_name(accel, &object_name);
+        ctx.track_created(Resource::AccelerationStructure);
 
         let address = unsafe {
             let info = vk::AccelerationStructureDeviceAddressInfoKHR::default()
@@ -221,6 +474,7 @@ impl AccelerationStructure {
             accel,
             address,
             buffer,
+            size: build_info.sizes.acceleration_structure_size,
         }
     }
 
@@ -245,15 +499,19 @@ impl AccelerationStructure {
         let accel = Self::init(ctx, name, build_info);
         build_info.geometry.dst_acceleration_structure = accel.accel;
 
+        Self::cmd_build(ctx, scope.commands.buffer, build_info);
+
+        accel
+    }
+
+    fn cmd_build(ctx: &Context, command_buffer: vk::CommandBuffer, build_info: &BuildInfo) {
         unsafe {
             ctx.ext.accel.cmd_build_acceleration_structures(
-                scope.commands.buffer,
+                command_buffer,
                 slice::from_ref(&build_info.geometry),
                 slice::from_ref(&build_info.ranges.as_slice()),
             );
         }
-
-        accel
     }
 
     fn create_scratch(
@@ -264,11 +522,7 @@ impl AccelerationStructure {
     ) -> vk::DeviceAddress {
         firestorm::profile_method!(create_scratch);
 
-        let min_alignment = ctx
-            .physical_device
-            .properties
-            .acceleration_structure
-            .min_acceleration_structure_scratch_offset_alignment as _;
+        let min_alignment = Self::scratch_alignment(ctx);
 
         let scratch = Buffer::create(
             ctx,
@@ -286,10 +540,31 @@ impl AccelerationStructure {
         scope.add_resource(scratch);
         address
     }
+
+    const fn scratch_alignment(ctx: &Context) -> usize {
+        ctx.physical_device
+            .properties
+            .acceleration_structure
+            .min_acceleration_structure_scratch_offset_alignment as _
+    }
+}
+
+impl Default for BuildPreference {
+    fn default() -> Self {
+        Self {
+            fast_build: false,
+            compact: true,
+        }
+    }
 }
 
 impl<'a> BuildInfo<'a> {
-    fn for_geometry(ctx: &Context, bottom_level: bool, geometry_info: &'a GeometryInfo) -> Self {
+    fn for_geometry(
+        ctx: &Context,
+        bottom_level: bool,
+        preference: BuildPreference,
+        geometry_info: &'a GeometryInfo,
+    ) -> Self {
         let ranges = geometry_info.ranges.clone();
 
         let ty = if bottom_level {
@@ -298,16 +573,25 @@ impl<'a> BuildInfo<'a> {
             vk::AccelerationStructureTypeKHR::TOP_LEVEL
         };
 
-        let compaction_flag = if bottom_level {
+        // the TLAS is refit when instances move, the BLASes are only ever rebuilt
+        let compaction_or_update_flag = if !bottom_level {
+            vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE
+        } else if preference.compact {
             vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION
         } else {
             vk::BuildAccelerationStructureFlagsKHR::empty()
         };
 
+        let preference_flag = if preference.fast_build {
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD
+        } else {
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+        };
+
         let geometry = vk::AccelerationStructureBuildGeometryInfoKHR::default()
             .ty(ty)
             .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE | compaction_flag)
+            .flags(preference_flag | compaction_or_update_flag)
             .geometries(&geometry_info.geometries);
 
         let primitive_counts = ranges
@@ -336,11 +620,12 @@ impl<'a> BuildInfo<'a> {
     fn for_geometries(
         ctx: &Context,
         bottom_level: bool,
+        preference: BuildPreference,
         geometry_infos: &'a [GeometryInfo],
     ) -> Vec<Self> {
         geometry_infos
             .iter()
-            .map(|geometry_info| Self::for_geometry(ctx, bottom_level, geometry_info))
+            .map(|geometry_info| Self::for_geometry(ctx, bottom_level, preference, geometry_info))
             .collect()
     }
 }
@@ -376,7 +661,11 @@ impl<'a> GeometryInfo<'a> {
         scene_info: &'a world::SceneInfo,
         primitive_info: &scene::PrimitiveInfo,
         primitive_size: &scene::PrimitiveSize,
-    ) -> Self {
+        is_opaque: bool,
+    ) -> (
+        vk::AccelerationStructureGeometryKHR<'a>,
+        vk::AccelerationStructureBuildRangeInfoKHR,
+    ) {
         let triangles = vk::AccelerationStructureGeometryTrianglesDataKHR::default()
             .vertex_format(vk::Format::R32G32B32_SFLOAT)
             .vertex_stride(std::mem::size_of::<scene::Vertex>() as _)
@@ -390,9 +679,16 @@ impl<'a> GeometryInfo<'a> {
                 device_address: scene_info.device.indices_address,
             });
 
+        // alpha tested geometry has to invoke the any-hit shader
+        let flags = if is_opaque {
+            vk::GeometryFlagsKHR::OPAQUE
+        } else {
+            vk::GeometryFlagsKHR::NO_DUPLICATE_ANY_HIT_INVOCATION
+        };
+
         let geometry = vk::AccelerationStructureGeometryKHR::default()
             .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
-            .flags(vk::GeometryFlagsKHR::OPAQUE)
+            .flags(flags)
             .geometry(vk::AccelerationStructureGeometryDataKHR { triangles });
 
         let range = vk::AccelerationStructureBuildRangeInfoKHR::default()
@@ -400,18 +696,32 @@ impl<'a> GeometryInfo<'a> {
             .primitive_offset(primitive_info.indices_offset * std::mem::size_of::<u32>() as u32)
             .first_vertex(primitive_info.vertices_offset);
 
-        Self::new(geometry, range)
+        (geometry, range)
     }
 
-    fn for_primitives(scene_info: &'a world::SceneInfo) -> Vec<Self> {
+    fn for_mesh(scene_info: &'a world::SceneInfo, mesh: &scene::Mesh) -> Self {
+        let (geometries, ranges) = mesh
+            .primitives
+            .clone()
+            .map(|primitive_index| {
+                Self::for_primitive(
+                    scene_info,
+                    &scene_info.host.primitive_infos[primitive_index],
+                    &scene_info.host.primitive_sizes[primitive_index],
+                    scene_info.host.primitive_opacities[primitive_index],
+                )
+            })
+            .unzip();
+
+        Self { geometries, ranges }
+    }
+
+    fn for_meshes(scene_info: &'a world::SceneInfo) -> Vec<Self> {
         scene_info
             .host
-            .primitive_infos
+            .meshes
             .iter()
-            .zip(scene_info.host.primitive_sizes.iter())
-            .map(|(primitive_info, primitive_size)| {
-                Self::for_primitive(scene_info, primitive_info, primitive_size)
-            })
+            .map(|mesh| Self::for_mesh(scene_info, mesh))
             .collect()
     }
 }
@@ -420,12 +730,14 @@ impl InstancesInfo {
     fn for_instances(
         ctx: &Context,
         command_buffer: vk::CommandBuffer,
-        instances: &[scene::Instance],
+        scene_info: &scene::Info,
+        materials: &[scene::Material],
         blases: &[AccelerationStructure],
+        visible: Option<&[bool]>,
     ) -> Self {
         firestorm::profile_method!(for_instances);
 
-        let instances = Instance::for_instances(instances, blases);
+        let instances = Instance::for_instances(scene_info, materials, blases, visible);
 
         let buffer = Buffer::create_with_data(
             ctx,
@@ -459,7 +771,24 @@ impl InstancesInfo {
 }
 
 impl Instance {
-    fn for_instance(instance: &scene::Instance, blases: &[AccelerationStructure]) -> Self {
+    fn for_instance(
+        instance: &scene::Instance,
+        mesh: &scene::Mesh,
+        materials: &[scene::Material],
+        blases: &[AccelerationStructure],
+    ) -> Self {
+        // gltf triangles are wound counter-clockwise, backfaces are handled per material
+        let mut flags = vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
+            | vk::GeometryInstanceFlagsKHR::TRIANGLE_FRONT_COUNTERCLOCKWISE;
+        // geometry is opaque by the materials of its primitives, which an override goes over
+        if let Some(material) = instance.material {
+            flags |= if materials[material as usize].alpha_mode == scene::alpha_mode::MASK {
+                vk::GeometryInstanceFlagsKHR::FORCE_NO_OPAQUE
+            } else {
+                vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE
+            };
+        }
+
         let t = &instance.transform;
         Self(vk::AccelerationStructureInstanceKHR {
             transform: vk::TransformMatrixKHR {
@@ -470,23 +799,37 @@ impl Instance {
                 ],
             },
             acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
-                device_handle: blases[instance.primitive_index].address,
+                device_handle: blases[instance.blas_index].address,
             },
+            // offset into the primitives, indexed in the shader by the geometry index
             instance_custom_index_and_mask: vk::Packed24_8::new(
-                instance.primitive_index as _,
-                0xff,
+                mesh.primitives.start as _,
+                instance.visibility_mask,
             ),
             instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
                 0,
-                vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as _,
+                flags.as_raw() as _,
             ),
         })
     }
 
-    fn for_instances(instances: &[scene::Instance], blases: &[AccelerationStructure]) -> Vec<Self> {
-        instances
+    // The custom index points at the primitives of the mesh, not at the instance, so it stays valid
+    // with hidden instances left out
+    fn for_instances(
+        scene_info: &scene::Info,
+        materials: &[scene::Material],
+        blases: &[AccelerationStructure],
+        visible: Option<&[bool]>,
+    ) -> Vec<Self> {
+        scene_info
+            .instances
             .iter()
-            .map(|instance| Self::for_instance(instance, blases))
+            .enumerate()
+            .filter(|&(index, _)| visible.is_none_or(|visible| visible[index]))
+            .map(|(_, instance)| {
+                let mesh = &scene_info.meshes[instance.blas_index];
+                Self::for_instance(instance, mesh, materials, blases)
+            })
             .collect()
     }
 }
@@ -495,6 +838,7 @@ impl Destroy<Context> for AccelerationStructures {
     unsafe fn destroy_with(&mut self, ctx: &Context) {
         firestorm::profile_method!(destroy_with);
 
+        self.instances.destroy_with(ctx);
         self.tlas.destroy_with(ctx);
         self.blases.destroy_with(ctx);
     }
@@ -508,6 +852,7 @@ impl Destroy<Context> for AccelerationStructure {
             .accel
             .destroy_acceleration_structure(self.accel, None);
         self.buffer.destroy_with(ctx);
+        ctx.track_destroyed(Resource::AccelerationStructure);
     }
 }
 
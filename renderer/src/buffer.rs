@@ -3,11 +3,19 @@ use std::{ops::Deref, slice};
 use ash::vk;
 use vk_mem::Alloc;
 
-use crate::{context::Context, memory, scope::Scope, Destroy};
+use crate::{
+    context::{Context, Resource},
+    memory,
+    scope::Scope,
+    Destroy,
+};
 
 pub struct Buffer {
     buffer: vk::Buffer,
     allocation: vk_mem::Allocation,
+    name: String,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
 }
 
 impl Buffer {
@@ -19,14 +27,25 @@ impl Buffer {
     ) -> Self {
         firestorm::profile_method!(create);
 
+        let create_info = create_info
+            .sharing_mode(ctx.queues.sharing_mode())
+            .queue_family_indices(ctx.queues.unique_family_indices());
+
         let (buffer, allocation) = unsafe {
             ctx.allocator
                 .create_buffer(&create_info, alloc_info)
                 .expect("Failed to create buffer with allocated memory")
         };
-        ctx.set_debug_name(buffer, &(name + " - Buffer"));
+        ctx.set_debug_name(buffer, &(name.clone() + " - Buffer"));
+        ctx.track_created(Resource::Buffer);
 
-        Self { buffer, allocation }
+        Self {
+            buffer,
+            allocation,
+            name,
+            size: create_info.size,
+            usage: create_info.usage,
+        }
     }
 
     pub fn create_with_data(
@@ -53,29 +72,91 @@ impl Buffer {
     ) -> Self {
         firestorm::profile_method!(create_with_staged_data);
 
+        // also a transfer source so that the buffer can later be resized
+        info.usage |= vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST;
+        info.size = std::mem::size_of_val(data) as _;
+        let buffer = Self::create(
+            ctx,
+            name,
+            info,
+            &memory::purpose::device_local(memory_priority),
+        );
+        buffer.cmd_fill_staged(ctx, scope, data);
+        buffer
+    }
+
+    pub fn cmd_fill_staged(&self, ctx: &Context, scope: &mut Scope, data: &[u8]) {
+        self.cmd_fill_staged_at(ctx, scope, 0, data);
+    }
+
+    // Overwrites only the range starting at the offset, in bytes
+    pub fn cmd_fill_staged_at(
+        &self,
+        ctx: &Context,
+        scope: &mut Scope,
+        offset: vk::DeviceSize,
+        data: &[u8],
+    ) {
+        firestorm::profile_method!(cmd_fill_staged_at);
+
         let staging = Self::create_with_data(
             ctx,
-            name.clone() + " - Staging",
-            vk::BufferCreateInfo {
-                usage: vk::BufferUsageFlags::TRANSFER_SRC,
-                ..info
-            },
+            self.name.clone() + " - Staging",
+            vk::BufferCreateInfo::default().usage(vk::BufferUsageFlags::TRANSFER_SRC),
             data,
         );
 
-        info.usage |= vk::BufferUsageFlags::TRANSFER_DST;
-        info.size = std::mem::size_of_val(data) as _;
+        self.cmd_copy_from(
+            ctx,
+            scope.commands.buffer,
+            &staging,
+            offset,
+            std::mem::size_of_val(data) as _,
+        );
+
+        scope.add_resource(staging);
+    }
+
+    // Grows a device local buffer while preserving its contents. Anything that refers to the old
+    // buffer handle or device address (descriptors, scene desc, acceleration structures) must be
+    // re-pointed from `on_resize`
+    pub fn resize(
+        &mut self,
+        ctx: &Context,
+        scope: &mut Scope,
+        new_size: vk::DeviceSize,
+        memory_priority: memory::Priority,
+        on_resize: impl FnOnce(&Self),
+    ) {
+        firestorm::profile_method!(resize);
+
+        if new_size <= self.size {
+            return;
+        }
+
+        assert!(
+            self.usage.contains(vk::BufferUsageFlags::TRANSFER_SRC),
+            "Buffer {} needs TRANSFER_SRC usage to be resized",
+            self.name
+        );
+
+        let info = vk::BufferCreateInfo::default()
+            .size(new_size)
+            .usage(self.usage | vk::BufferUsageFlags::TRANSFER_DST);
         let buffer = Self::create(
             ctx,
-            name,
+            self.name.clone(),
             info,
             &memory::purpose::device_local(memory_priority),
         );
-        buffer.cmd_copy_from(ctx, scope.commands.buffer, &staging, info.size);
 
-        scope.add_resource(staging);
+        let old = std::mem::replace(self, buffer);
+        self.cmd_copy_from(ctx, scope.commands.buffer, &old, 0, old.size);
 
-        buffer
+        // in use by the copy until the scope is finished
+        scope.add_resource(old);
+
+        on_resize(self);
     }
 
     pub fn fill_with<T: bytemuck::Pod>(&self, ctx: &Context, data: &T) {
@@ -95,16 +176,30 @@ impl Buffer {
         }
     }
 
+    pub fn read_into(&self, ctx: &Context, data: &mut [u8]) {
+        firestorm::profile_method!(read_into);
+
+        let mapped_ptr = ctx
+            .allocator
+            .get_allocation_info(&self.allocation)
+            .mapped_data;
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(mapped_ptr.cast(), data.as_mut_ptr(), data.len());
+        }
+    }
+
     fn cmd_copy_from(
         &self,
         ctx: &Context,
         command_buffer: vk::CommandBuffer,
         src: &Self,
+        dst_offset: vk::DeviceSize,
         size: u64,
     ) {
         firestorm::profile_method!(cmd_copy_from);
 
-        let copy_info = vk::BufferCopy::default().size(size);
+        let copy_info = vk::BufferCopy::default().dst_offset(dst_offset).size(size);
 
         unsafe {
             ctx.cmd_copy_buffer(command_buffer, **src, **self, slice::from_ref(&copy_info));
@@ -128,6 +223,7 @@ impl Destroy<Context> for Buffer {
 
         ctx.allocator
             .destroy_buffer(self.buffer, &mut self.allocation);
+        ctx.track_destroyed(Resource::Buffer);
     }
 }
 
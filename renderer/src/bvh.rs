@@ -0,0 +1,414 @@
+use std::time::{Duration, Instant};
+
+use ash::vk;
+
+use crate::{buffer::Buffer, commands::Commands, context::Context, memory, scope::Scope, Destroy};
+
+mod conf {
+    pub const MAX_LEAF_SIZE: usize = 4;
+}
+
+// Software counterpart of the acceleration structures, traversed by the compute path tracer
+pub struct Bvh {
+    // every instanced triangle in object space, kept around for rebuilding when instances move
+    object_triangles: Vec<scene::BvhTriangle>,
+    // the vertices of each object triangle within its primitive, for reposing them
+    object_indices: Vec<[u32; 3]>,
+    nodes: Buffer,
+    triangles: Buffer,
+    transforms: Buffer,
+    // instances are skipped by rays whose cull mask has none of their bits
+    masks: Buffer,
+    // wall clock time of the last build, including the upload
+    pub build_time: Duration,
+}
+
+impl Bvh {
+    pub fn build(ctx: &Context, scene_info: &scene::Info, scene_data: &scene::Data) -> Self {
+        firestorm::profile_method!(build);
+
+        let start = Instant::now();
+
+        let (object_triangles, object_indices) = Self::object_triangles(scene_info, scene_data);
+        let (nodes, triangles, transforms, masks) =
+            Self::upload(ctx, scene_info, &object_triangles, None);
+
+        Self {
+            object_triangles,
+            object_indices,
+            nodes,
+            triangles,
+            transforms,
+            masks,
+            build_time: start.elapsed(),
+        }
+    }
+
+    // The whole hierarchy is rebuilt, from a world space copy of the object space triangles (as last
+    // reposed by `repose_primitive`) made on every upload. Without a mask every instance is visible
+    pub fn rebuild(&mut self, ctx: &Context, scene_info: &scene::Info, visible: Option<&[bool]>) {
+        firestorm::profile_method!(rebuild);
+
+        let start = Instant::now();
+
+        let (nodes, triangles, transforms, masks) =
+            Self::upload(ctx, scene_info, &self.object_triangles, visible);
+        unsafe {
+            self.destroy_buffers(ctx);
+        }
+        self.nodes = nodes;
+        self.triangles = triangles;
+        self.transforms = transforms;
+        self.masks = masks;
+
+        self.build_time = start.elapsed();
+    }
+
+    // Moves the triangles of the primitive onto its new vertices, in effect from the next rebuild
+    pub fn repose_primitive(&mut self, primitive: usize, vertices: &[scene::Vertex]) {
+        let position = |index: u32| vertices[index as usize].position.truncate();
+        for (tri, indices) in self.object_triangles.iter_mut().zip(&self.object_indices) {
+            if tri.primitive as usize == primitive {
+                tri.v0 = position(indices[0]);
+                tri.v1 = position(indices[1]);
+                tri.v2 = position(indices[2]);
+            }
+        }
+    }
+
+    pub fn fill_scene_desc(&self, ctx: &Context, scene_desc: &mut scene::SceneDesc) {
+        scene_desc.bvh_nodes_address = self.nodes.get_device_address(ctx);
+        scene_desc.bvh_triangles_address = self.triangles.get_device_address(ctx);
+        scene_desc.instance_transforms_address = self.transforms.get_device_address(ctx);
+        scene_desc.instance_masks_address = self.masks.get_device_address(ctx);
+    }
+
+    fn object_triangles(
+        scene_info: &scene::Info,
+        scene_data: &scene::Data,
+    ) -> (Vec<scene::BvhTriangle>, Vec<[u32; 3]>) {
+        firestorm::profile_method!(object_triangles);
+
+        let mut triangles = Vec::new();
+        let mut triangle_indices = Vec::new();
+
+        for (instance_index, instance) in scene_info.instances.iter().enumerate() {
+            for primitive in scene_info.meshes[instance.blas_index].primitives.clone() {
+                let info = &scene_info.primitive_infos[primitive];
+                let size = &scene_info.primitive_sizes[primitive];
+
+                let indices = &scene_data.indices[info.indices_offset as usize..]
+                    [..size.indices_size as usize];
+                let position = |index: u32| {
+                    scene_data.vertices[(info.vertices_offset + index) as usize]
+                        .position
+                        .truncate()
+                };
+
+                triangles.extend(indices.chunks_exact(3).zip(0..).map(|(tri, triangle)| {
+                    scene::BvhTriangle {
+                        v0: position(tri[0]),
+                        instance: instance_index as _,
+                        v1: position(tri[1]),
+                        primitive: primitive as _,
+                        v2: position(tri[2]),
+                        triangle,
+                    }
+                }));
+                triangle_indices
+                    .extend(indices.chunks_exact(3).map(|tri| [tri[0], tri[1], tri[2]]));
+            }
+        }
+
+        (triangles, triangle_indices)
+    }
+
+    fn upload(
+        ctx: &Context,
+        scene_info: &scene::Info,
+        object_triangles: &[scene::BvhTriangle],
+        visible: Option<&[bool]>,
+    ) -> (Buffer, Buffer, Buffer, Buffer) {
+        firestorm::profile_method!(upload);
+
+        let mut transforms = scene_info
+            .instances
+            .iter()
+            .map(|instance| instance.transform)
+            .collect::<Vec<_>>();
+        let mut masks = scene_info
+            .instances
+            .iter()
+            .map(|instance| u32::from(instance.visibility_mask))
+            .collect::<Vec<_>>();
+        // stand in for the instance of the placeholder triangle of an empty scene, see `build_nodes`
+        if transforms.is_empty() {
+            transforms.push(glam::Mat4::IDENTITY);
+            masks.push(0);
+        }
+
+        // the transforms and masks stay indexed by instance, hidden or not
+        let mut triangles = object_triangles
+            .iter()
+            .filter(|tri| visible.is_none_or(|visible| visible[tri.instance as usize]))
+            .map(|tri| {
+                let transform = &transforms[tri.instance as usize];
+                scene::BvhTriangle {
+                    v0: transform.transform_point3(tri.v0),
+                    v1: transform.transform_point3(tri.v1),
+                    v2: transform.transform_point3(tri.v2),
+                    ..*tri
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let nodes = Self::build_nodes(&mut triangles);
+
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "BVH - Initialization".to_owned(),
+            ctx.queues.transfer(),
+        ));
+
+        let create_info = vk::BufferCreateInfo::default().usage(
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        );
+        let mut create_buffer = |name: &str, data: &[u8]| {
+            Buffer::create_with_staged_data(
+                ctx,
+                &mut scope,
+                format!("BVH {name}"),
+                create_info,
+                data,
+                memory::Priority::Medium,
+            )
+        };
+
+        let buffers = (
+            create_buffer("Nodes", bytemuck::cast_slice(&nodes)),
+            create_buffer("Triangles", bytemuck::cast_slice(&triangles)),
+            create_buffer("Transforms", bytemuck::cast_slice(&transforms)),
+            create_buffer("Masks", bytemuck::cast_slice(&masks)),
+        );
+
+        scope.finish(ctx);
+
+        buffers
+    }
+
+    // Splits at the median centroid along the widest axis until the leaves are small enough. Interior
+    // nodes have a count of zero, so without triangles the root is made a leaf of a placeholder
+    // triangle with no area, which no ray hits
+    fn build_nodes(triangles: &mut Vec<scene::BvhTriangle>) -> Vec<scene::BvhNode> {
+        firestorm::profile_method!(build_nodes);
+
+        if triangles.is_empty() {
+            triangles.push(scene::BvhTriangle::default());
+        }
+
+        let mut nodes = vec![Self::leaf(triangles, 0)];
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = nodes[node_index];
+            let (first, count) = (node.left_or_first as usize, node.count as usize);
+            if count <= conf::MAX_LEAF_SIZE {
+                continue;
+            }
+
+            let node_triangles = &mut triangles[first..first + count];
+
+            let (centroids_min, centroids_max) = node_triangles.iter().map(centroid).fold(
+                (glam::Vec3::INFINITY, glam::Vec3::NEG_INFINITY),
+                |(min, max), c| (min.min(c), max.max(c)),
+            );
+            let extent = centroids_max - centroids_min;
+            if extent.max_element() <= 0. {
+                continue;
+            }
+            let axis = if extent.x >= extent.y && extent.x >= extent.z {
+                0
+            } else if extent.y >= extent.z {
+                1
+            } else {
+                2
+            };
+
+            let mid = count / 2;
+            node_triangles.select_nth_unstable_by(mid, |a, b| {
+                centroid(a)[axis].total_cmp(&centroid(b)[axis])
+            });
+
+            let left = nodes.len();
+            nodes.push(Self::leaf(&triangles[first..first + mid], first));
+            nodes.push(Self::leaf(
+                &triangles[first + mid..first + count],
+                first + mid,
+            ));
+
+            nodes[node_index].left_or_first = left as _;
+            nodes[node_index].count = 0;
+
+            stack.extend([left, left + 1]);
+        }
+
+        nodes
+    }
+
+    fn leaf(triangles: &[scene::BvhTriangle], first: usize) -> scene::BvhNode {
+        let (aabb_min, aabb_max) = triangles
+            .iter()
+            .flat_map(|tri| [tri.v0, tri.v1, tri.v2])
+            .fold(
+                (glam::Vec3::INFINITY, glam::Vec3::NEG_INFINITY),
+                |(min, max), v| (min.min(v), max.max(v)),
+            );
+
+        scene::BvhNode {
+            aabb_min,
+            left_or_first: first as _,
+            aabb_max,
+            count: triangles.len() as _,
+        }
+    }
+
+    unsafe fn destroy_buffers(&mut self, ctx: &Context) {
+        self.masks.destroy_with(ctx);
+        self.transforms.destroy_with(ctx);
+        self.triangles.destroy_with(ctx);
+        self.nodes.destroy_with(ctx);
+    }
+}
+
+fn centroid(triangle: &scene::BvhTriangle) -> glam::Vec3 {
+    (triangle.v0 + triangle.v1 + triangle.v2) / 3.
+}
+
+impl Destroy<Context> for Bvh {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.destroy_buffers(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Distinct centroids for up to 2431 triangles, since the offsets along each axis only repeat
+    // after as many
+    fn triangles(count: u32) -> Vec<scene::BvhTriangle> {
+        (0..count)
+            .map(|index| {
+                let base = glam::vec3(
+                    (index * 7 % 13) as f32,
+                    (index * 5 % 11) as f32,
+                    (index * 3 % 17) as f32,
+                );
+                scene::BvhTriangle {
+                    v0: base,
+                    v1: base + glam::Vec3::X,
+                    v2: base + glam::Vec3::Y,
+                    triangle: index,
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    fn leaves(nodes: &[scene::BvhNode]) -> Vec<scene::BvhNode> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = nodes[index];
+            if node.count > 0 {
+                leaves.push(node);
+            } else {
+                let left = node.left_or_first as usize;
+                assert!(left > index && left + 1 < nodes.len());
+                stack.extend([left, left + 1]);
+            }
+        }
+        leaves
+    }
+
+    fn contains(outer: &scene::BvhNode, inner_min: glam::Vec3, inner_max: glam::Vec3) -> bool {
+        outer.aabb_min.cmple(inner_min).all() && inner_max.cmple(outer.aabb_max).all()
+    }
+
+    #[test]
+    fn leaves_cover_every_triangle_once() {
+        let mut tris = triangles(100);
+        let nodes = Bvh::build_nodes(&mut tris);
+
+        let mut covered = vec![0; tris.len()];
+        for leaf in leaves(&nodes) {
+            let first = leaf.left_or_first as usize;
+            for count in &mut covered[first..first + leaf.count as usize] {
+                *count += 1;
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+
+        let mut ids = tris.iter().map(|tri| tri.triangle).collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert!(ids.into_iter().eq(0..100));
+    }
+
+    #[test]
+    fn children_lie_within_their_parents() {
+        let mut tris = triangles(100);
+        let nodes = Bvh::build_nodes(&mut tris);
+
+        for node in &nodes {
+            if node.count > 0 {
+                let first = node.left_or_first as usize;
+                for tri in &tris[first..first + node.count as usize] {
+                    for v in [tri.v0, tri.v1, tri.v2] {
+                        assert!(contains(node, v, v));
+                    }
+                }
+            } else {
+                for child in &nodes[node.left_or_first as usize..][..2] {
+                    assert!(contains(node, child.aabb_min, child.aabb_max));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_respect_the_max_size() {
+        let mut tris = triangles(100);
+        let nodes = Bvh::build_nodes(&mut tris);
+
+        assert!(leaves(&nodes)
+            .iter()
+            .all(|leaf| leaf.count as usize <= conf::MAX_LEAF_SIZE));
+    }
+
+    #[test]
+    fn coincident_centroids_stay_in_one_leaf() {
+        let mut tris = vec![triangles(1)[0]; 3 * conf::MAX_LEAF_SIZE];
+        let nodes = Bvh::build_nodes(&mut tris);
+
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].count as usize, tris.len());
+    }
+
+    #[test]
+    fn empty_root_is_a_leaf() {
+        let mut tris = Vec::new();
+        let nodes = Bvh::build_nodes(&mut tris);
+
+        // a count of zero would send the shader to children that don't exist
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].left_or_first, 0);
+        assert_eq!(nodes[0].count, 1);
+        assert_eq!(tris.len(), 1);
+
+        // has no area, so the triangle test rejects it
+        let tri = tris[0];
+        assert_eq!((tri.v1 - tri.v0).cross(tri.v2 - tri.v0), glam::Vec3::ZERO);
+    }
+}
@@ -11,6 +11,8 @@ pub struct Commands {
     queue: vk::Queue,
     pool: vk::CommandPool,
     pub buffer: vk::CommandBuffer,
+    // waited on with a timeout for submissions without a fence of their own
+    fence: vk::Fence,
 }
 
 impl Commands {
@@ -40,12 +42,15 @@ impl Commands {
             })
             .expect("Failed to allocate command buffer")[0]
         };
-        ctx.set_debug_name(buffer, &(name + " - Command Buffer"));
+        ctx.set_debug_name(buffer, &(name.clone() + " - Command Buffer"));
+
+        let fence = ctx.create_fence(&(name + " - Fence"), false);
 
         Self {
             queue: **queue,
             pool,
             buffer,
+            fence,
         }
     }
 
@@ -79,19 +84,26 @@ impl Commands {
             ..*submit_info
         };
 
-        unsafe {
-            self.finish_recording(ctx);
+        self.finish_recording(ctx);
+
+        // a hung or lost device never gets to the commands
+        if ctx.failure().is_some() {
+            return;
+        }
 
-            ctx.queue_submit(
+        unsafe {
+            if let Err(err) = ctx.queue_submit(
                 self.queue,
                 slice::from_ref(&submit_info),
-                fence.unwrap_or_else(vk::Fence::null),
-            )
-            .expect("Failed to submit commands to queue");
+                fence.unwrap_or(self.fence),
+            ) {
+                ctx.fail(err);
+                return;
+            }
 
-            if fence.is_none() {
-                ctx.queue_wait_idle(self.queue)
-                    .expect("Failed to wait for queue to idle");
+            if fence.is_none() && ctx.wait_for_fences_or_fail(slice::from_ref(&self.fence)) {
+                ctx.reset_fences(slice::from_ref(&self.fence))
+                    .expect("Failed to reset fence");
             }
         }
     }
@@ -126,6 +138,7 @@ impl Destroy<Context> for Commands {
     unsafe fn destroy_with(&mut self, ctx: &Context) {
         firestorm::profile_method!(destroy_with);
 
+        ctx.destroy_fence(self.fence, None);
         ctx.destroy_command_pool(self.pool, None);
     }
 }
@@ -1,7 +1,9 @@
 use std::{
+    cell::Cell,
     fs::File,
     mem::ManuallyDrop,
     ops::{Deref, DerefMut},
+    time::Duration,
 };
 
 use ash::vk;
@@ -18,6 +20,23 @@ pub struct Device {
     pub ext: extensions::Handles,
     pub queues: Queues,
     pub allocator: ManuallyDrop<vk_mem::Allocator>,
+    // waits on the device that take longer than this consider it hung
+    pub timeout: Cell<Duration>,
+    // timed out or lost, nothing is submitted to the device anymore once set
+    failure: Cell<Option<vk::Result>>,
+    // objects of each kind created but not yet destroyed, only counted in debug builds where any left
+    // over are reported on drop
+    live_resources: Cell<[isize; Resource::ALL.len()]>,
+}
+
+// Kinds of objects that debug builds keep count of to catch those never destroyed
+#[derive(Clone, Copy, Debug)]
+pub enum Resource {
+    Buffer,
+    Image,
+    Sampler,
+    AccelerationStructure,
+    Descriptors,
 }
 
 impl Device {
@@ -29,7 +48,11 @@ impl Device {
         firestorm::profile_method!(create);
 
         let device = {
-            let (required_features, mut additional_required_features) = features::required();
+            let (required_features, mut additional_required_features) = features::required(
+                physical_device.supports_ray_tracing,
+                physical_device.supports_ray_query,
+                physical_device.supports_anisotropy,
+            );
             let mut required_features = additional_required_features
                 .iter_mut()
                 .fold(required_features, |acc_features, f| {
@@ -38,8 +61,16 @@ impl Device {
 
             let queue_create_infos = Queues::create_infos(families);
 
+            let mut extension_names = extensions::REQUIRED_FOR_DEVICE.to_vec();
+            if physical_device.supports_ray_tracing {
+                extension_names.extend_from_slice(extensions::RAY_TRACING);
+            }
+            if physical_device.supports_ray_query {
+                extension_names.extend_from_slice(extensions::RAY_QUERY);
+            }
+
             let create_info = vk::DeviceCreateInfo::default()
-                .enabled_extension_names(extensions::REQUIRED_FOR_DEVICE)
+                .enabled_extension_names(&extension_names)
                 .push_next(&mut required_features)
                 .queue_create_infos(&queue_create_infos);
 
@@ -61,6 +92,7 @@ impl Device {
             create_info.flags = vk_mem::AllocatorCreateFlags::KHR_DEDICATED_ALLOCATION
                 | vk_mem::AllocatorCreateFlags::KHR_BIND_MEMORY2
                 | vk_mem::AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS
+                | vk_mem::AllocatorCreateFlags::EXT_MEMORY_BUDGET
                 | vk_mem::AllocatorCreateFlags::EXT_MEMORY_PRIORITY;
 
             ManuallyDrop::new(
@@ -73,9 +105,75 @@ impl Device {
             ext,
             queues,
             allocator,
+            timeout: Cell::new(crate::conf::DEFAULT_GPU_TIMEOUT),
+            failure: Cell::new(None),
+            live_resources: Cell::new([0; Resource::ALL.len()]),
+        }
+    }
+
+    pub fn track_created(&self, resource: Resource) {
+        self.track(resource, 1);
+    }
+
+    pub fn track_destroyed(&self, resource: Resource) {
+        self.track(resource, -1);
+    }
+
+    fn track(&self, resource: Resource, delta: isize) {
+        if cfg!(debug_assertions) {
+            let mut live_resources = self.live_resources.get();
+            live_resources[resource as usize] += delta;
+            self.live_resources.set(live_resources);
+        }
+    }
+
+    fn check_leaks(&self) {
+        let leaked = Resource::ALL
+            .into_iter()
+            .zip(self.live_resources.get())
+            .filter(|&(_, count)| count != 0)
+            .map(|(resource, count)| format!("{resource:?} x{count}"))
+            .collect::<Vec<_>>();
+        if leaked.is_empty() {
+            return;
+        }
+
+        let message = format!("Vulkan objects not destroyed: {}", leaked.join(", "));
+        // panicking again while unwinding would abort and hide the original panic
+        if std::thread::panicking() {
+            eprintln!("{message}");
+        } else {
+            panic!("{message}");
         }
     }
 
+    // Only a hung or lost device can be reported to the application, anything else is a bug
+    pub fn fail(&self, result: vk::Result) {
+        assert!(
+            matches!(result, vk::Result::TIMEOUT | vk::Result::ERROR_DEVICE_LOST),
+            "Device error: {result}"
+        );
+        self.failure.set(Some(result));
+    }
+
+    pub const fn failure(&self) -> Option<vk::Result> {
+        self.failure.get()
+    }
+
+    // Returns false if the fences didn't all signal within the timeout or the device was lost
+    pub fn wait_for_fences_or_fail(&self, fences: &[vk::Fence]) -> bool {
+        firestorm::profile_method!(wait_for_fences_or_fail);
+
+        if self.failure().is_some() {
+            return false;
+        }
+
+        let timeout = u64::try_from(self.timeout.get().as_nanos()).unwrap_or(u64::MAX);
+        unsafe { self.device.wait_for_fences(fences, true, timeout) }
+            .map_err(|err| self.fail(err))
+            .is_ok()
+    }
+
     pub fn create_semaphore(&self, name: &str) -> vk::Semaphore {
         firestorm::profile_method!(create_semaphore);
 
@@ -128,11 +226,17 @@ impl Device {
         }
     }
 
+    // A hung device would never go idle, so this returns straight away once a wait has timed out
     pub unsafe fn wait_idle(&self) {
         firestorm::profile_method!(wait_idle);
 
-        self.device_wait_idle()
-            .expect("Failed to wait for device to idle");
+        if self.failure() == Some(vk::Result::TIMEOUT) {
+            return;
+        }
+
+        if let Err(err) = self.device_wait_idle() {
+            self.fail(err);
+        }
     }
 
     pub fn set_debug_name<H: vk::Handle>(&self, object: H, name: &str) {
@@ -154,6 +258,10 @@ impl Drop for Device {
     fn drop(&mut self) {
         firestorm::profile_method!(drop);
 
+        if cfg!(debug_assertions) {
+            self.check_leaks();
+        }
+
         unsafe {
             ManuallyDrop::drop(&mut self.allocator);
             self.device.destroy_device(None);
@@ -161,6 +269,16 @@ impl Drop for Device {
     }
 }
 
+impl Resource {
+    const ALL: [Self; 5] = [
+        Self::Buffer,
+        Self::Image,
+        Self::Sampler,
+        Self::AccelerationStructure,
+        Self::Descriptors,
+    ];
+}
+
 impl Deref for Device {
     type Target = ash::Device;
     fn deref(&self) -> &Self::Target {
@@ -10,19 +10,30 @@ pub const REQUIRED_FOR_INSTANCE: &[*const std::ffi::c_char] = &[
     khr::win32_surface::NAME.as_ptr(),
 ];
 
+// Optional, surfaces only report their HDR color spaces with it
+pub const SWAPCHAIN_COLOR_SPACE: &std::ffi::CStr = ash::vk::EXT_SWAPCHAIN_COLORSPACE_NAME;
+
 pub const REQUIRED_FOR_DEVICE: &[*const std::ffi::c_char] = &[
     // Core
     khr::swapchain::NAME.as_ptr(),
+    // Additional
+    ext::memory_budget::NAME.as_ptr(),
+    ext::memory_priority::NAME.as_ptr(),
+    ext::pageable_device_local_memory::NAME.as_ptr(),
+];
+
+// Optional, devices without them fall back to path tracing in a compute shader
+pub const RAY_TRACING: &[*const std::ffi::c_char] = &[
     // Acceleration Structure
     khr::acceleration_structure::NAME.as_ptr(),
     khr::deferred_host_operations::NAME.as_ptr(),
     // Ray Tracing
     khr::ray_tracing_pipeline::NAME.as_ptr(),
-    // Additional
-    ext::memory_priority::NAME.as_ptr(),
-    ext::pageable_device_local_memory::NAME.as_ptr(),
 ];
 
+// Optional on top of `RAY_TRACING`, for tracing rays from the rasterizer
+pub const RAY_QUERY: &[*const std::ffi::c_char] = &[khr::ray_query::NAME.as_ptr()];
+
 pub struct Handles {
     pub debug_utils: ext::debug_utils::Device,
     pub swapchain: khr::swapchain::Device,
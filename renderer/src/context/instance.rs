@@ -1,17 +1,37 @@
 use std::{
     collections::HashSet,
-    ffi::CString,
+    env,
+    ffi::{c_void, CStr, CString},
     ops::{Deref, DerefMut},
 };
 
-use ash::vk;
+use ash::{ext, vk};
 use raw_window_handle::HasWindowHandle;
 
 use super::{bytes_to_string, extensions, physical_device::PhysicalDevice, queue, surface};
 
+mod conf {
+    use ash::vk;
+
+    pub const VALIDATION_LAYER: &std::ffi::CStr = c"VK_LAYER_KHRONOS_validation";
+    // "1" or "0" to force validation on or off, defaults to on in debug builds
+    pub const VALIDATION_ENV_VAR: &str = "RENDERER_VALIDATION";
+    pub const MESSAGE_SEVERITIES: vk::DebugUtilsMessageSeverityFlagsEXT =
+        vk::DebugUtilsMessageSeverityFlagsEXT::from_raw(
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING.as_raw()
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR.as_raw(),
+        );
+}
+
 pub struct Instance {
     pub entry: ash::Entry,
     instance: ash::Instance,
+    debug_messenger: Option<DebugMessenger>,
+}
+
+struct DebugMessenger {
+    loader: ext::debug_utils::Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
 }
 
 impl Instance {
@@ -25,9 +45,28 @@ impl Instance {
             .application_name(&app_name)
             .api_version(crate::conf::VK_API_VERSION);
 
-        let instance_create_info = vk::InstanceCreateInfo::default()
+        let validation = Self::validation_requested() && Self::has_validation_layer(&entry);
+        let layers = if validation {
+            vec![conf::VALIDATION_LAYER.as_ptr()]
+        } else {
+            vec![]
+        };
+
+        // also chained into the instance creation to catch messages from its creation
+        let mut messenger_create_info = DebugMessenger::create_info();
+
+        let mut instance_extensions = extensions::REQUIRED_FOR_INSTANCE.to_vec();
+        if Self::has_extension(&entry, extensions::SWAPCHAIN_COLOR_SPACE) {
+            instance_extensions.push(extensions::SWAPCHAIN_COLOR_SPACE.as_ptr());
+        }
+
+        let mut instance_create_info = vk::InstanceCreateInfo::default()
             .application_info(&app_info)
-            .enabled_extension_names(extensions::REQUIRED_FOR_INSTANCE);
+            .enabled_layer_names(&layers)
+            .enabled_extension_names(&instance_extensions);
+        if validation {
+            instance_create_info = instance_create_info.push_next(&mut messenger_create_info);
+        }
 
         let instance = unsafe {
             entry
@@ -35,7 +74,49 @@ impl Instance {
                 .expect("Failed to create Vulkan instance")
         };
 
-        Self { entry, instance }
+        let debug_messenger = validation.then(|| DebugMessenger::create(&entry, &instance));
+
+        Self {
+            entry,
+            instance,
+            debug_messenger,
+        }
+    }
+
+    fn validation_requested() -> bool {
+        env::var(conf::VALIDATION_ENV_VAR)
+            .map_or(cfg!(debug_assertions), |value| value.trim() != "0")
+    }
+
+    fn has_validation_layer(entry: &ash::Entry) -> bool {
+        let available = unsafe {
+            entry
+                .enumerate_instance_layer_properties()
+                .expect("Failed to get instance layer properties")
+        };
+
+        let found = available
+            .iter()
+            .any(|layer| layer.layer_name_as_c_str() == Ok(conf::VALIDATION_LAYER));
+        if !found {
+            eprintln!(
+                "Warning: validation requested but {} is not available",
+                conf::VALIDATION_LAYER.to_string_lossy()
+            );
+        }
+        found
+    }
+
+    fn has_extension(entry: &ash::Entry, name: &CStr) -> bool {
+        let available = unsafe {
+            entry
+                .enumerate_instance_extension_properties(None)
+                .expect("Failed to get instance extension properties")
+        };
+
+        available
+            .iter()
+            .any(|extension| extension.extension_name_as_c_str() == Ok(name))
     }
 
     pub fn create_surface_on(&self, window: &impl HasWindowHandle) -> surface::Handle {
@@ -60,7 +141,9 @@ impl Instance {
 
         let (physical_device, queue_families, surface_config_options) = all_devices
             .into_iter()
-            .filter(|&physical_device| self.has_required_device_extensions(physical_device))
+            .filter(|&physical_device| {
+                self.has_device_extensions(physical_device, extensions::REQUIRED_FOR_DEVICE)
+            })
             .filter_map(|physical_device| PhysicalDevice::new(self, physical_device))
             .filter_map(|physical_device| {
                 queue::Families::find(self, &physical_device, surface).map(|queue_families| {
@@ -68,7 +151,15 @@ impl Instance {
                     (physical_device, queue_families, surface_config_options)
                 })
             })
-            .find(|(_, _, surface_config_options)| Self::is_suitable(surface_config_options))
+            .filter(|(_, _, surface_config_options)| Self::is_suitable(surface_config_options))
+            // prefer the first device that supports hardware ray tracing, and ray queries when the
+            // rasterizer traces with them
+            .min_by_key(|(physical_device, _, _)| {
+                (
+                    cfg!(feature = "ray_query_ao") && !physical_device.supports_ray_query,
+                    !physical_device.supports_ray_tracing,
+                )
+            })
             .expect("Failed to find a suitable physical device");
 
         (
@@ -82,8 +173,12 @@ impl Instance {
         surface_config_options.has_some()
     }
 
-    fn has_required_device_extensions(&self, physical_device: vk::PhysicalDevice) -> bool {
-        firestorm::profile_method!(has_required_device_extensions);
+    pub fn has_device_extensions(
+        &self,
+        physical_device: vk::PhysicalDevice,
+        extensions: &[*const std::ffi::c_char],
+    ) -> bool {
+        firestorm::profile_method!(has_device_extensions);
 
         let available_extensions: HashSet<_> = unsafe {
             self.enumerate_device_extension_properties(physical_device)
@@ -93,7 +188,7 @@ impl Instance {
                 .collect()
         };
 
-        extensions::REQUIRED_FOR_DEVICE
+        extensions
             .iter()
             .copied()
             .map(|s| unsafe { bytes_to_string(s) })
@@ -106,11 +201,66 @@ impl Drop for Instance {
         firestorm::profile_method!(drop);
 
         unsafe {
+            if let Some(debug_messenger) = self.debug_messenger.take() {
+                debug_messenger
+                    .loader
+                    .destroy_debug_utils_messenger(debug_messenger.messenger, None);
+            }
             self.instance.destroy_instance(None);
         }
     }
 }
 
+impl DebugMessenger {
+    fn create_info() -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
+        vk::DebugUtilsMessengerCreateInfoEXT::default()
+            .message_severity(conf::MESSAGE_SEVERITIES)
+            .message_type(
+                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            )
+            .pfn_user_callback(Some(debug_callback))
+    }
+
+    fn create(entry: &ash::Entry, instance: &ash::Instance) -> Self {
+        firestorm::profile_method!(create);
+
+        let loader = ext::debug_utils::Instance::new(entry, instance);
+        let messenger = unsafe {
+            loader
+                .create_debug_utils_messenger(&Self::create_info(), None)
+                .expect("Failed to create debug messenger")
+        };
+
+        Self { loader, messenger }
+    }
+}
+
+unsafe extern "system" fn debug_callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    _user_data: *mut c_void,
+) -> vk::Bool32 {
+    let message = callback_data
+        .as_ref()
+        .and_then(|data| data.message_as_c_str())
+        .map_or_else(|| "<no message>".into(), CStr::to_string_lossy);
+
+    match severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+            eprintln!("Vulkan error ({message_type:?}): {message}");
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+            eprintln!("Vulkan warning ({message_type:?}): {message}");
+        }
+        _ => println!("Vulkan ({message_type:?}): {message}"),
+    }
+
+    vk::FALSE
+}
+
 impl Deref for Instance {
     type Target = ash::Instance;
     fn deref(&self) -> &Self::Target {
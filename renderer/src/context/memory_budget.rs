@@ -0,0 +1,62 @@
+use ash::vk;
+
+use super::{instance::Instance, physical_device::PhysicalDevice};
+
+pub struct MemoryBudget {
+    pub heaps: Vec<HeapBudget>,
+}
+
+pub struct HeapBudget {
+    pub device_local: bool,
+    pub size: vk::DeviceSize,
+    // as reported by the driver, for the whole process
+    pub budget: vk::DeviceSize,
+    pub usage: vk::DeviceSize,
+    // as tracked by the allocator
+    pub allocated_block_bytes: vk::DeviceSize,
+    pub allocation_bytes: vk::DeviceSize,
+}
+
+impl MemoryBudget {
+    pub fn query(
+        instance: &Instance,
+        physical_device: &PhysicalDevice,
+        allocator: &vk_mem::Allocator,
+    ) -> Self {
+        firestorm::profile_method!(query);
+
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties =
+            vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_properties);
+
+        unsafe {
+            instance.get_physical_device_memory_properties2(**physical_device, &mut properties);
+        }
+
+        let allocator_budgets = allocator
+            .get_heap_budgets()
+            .expect("Failed to get allocator heap budgets");
+
+        let memory_properties = properties.memory_properties;
+        let heaps = memory_properties
+            .memory_heaps_as_slice()
+            .iter()
+            .zip(allocator_budgets)
+            .enumerate()
+            .map(|(idx, (heap, allocator_budget))| HeapBudget {
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                size: heap.size,
+                budget: budget_properties.heap_budget[idx],
+                usage: budget_properties.heap_usage[idx],
+                allocated_block_bytes: allocator_budget.statistics.blockBytes,
+                allocation_bytes: allocator_budget.statistics.allocationBytes,
+            })
+            .collect();
+
+        Self { heaps }
+    }
+
+    pub fn device_local(&self) -> impl Iterator<Item = &HeapBudget> {
+        self.heaps.iter().filter(|heap| heap.device_local)
+    }
+}
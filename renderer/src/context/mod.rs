@@ -2,22 +2,29 @@ mod device;
 mod extensions;
 mod features;
 mod instance;
+pub mod memory_budget;
 mod physical_device;
 mod properties;
 pub mod queue;
 mod surface;
 
+pub use self::device::Resource;
+
 use std::ops::{Deref, DerefMut};
 
+use ash::vk;
 use raw_window_handle::HasWindowHandle;
 
-use self::{device::Device, instance::Instance, physical_device::PhysicalDevice, surface::Surface};
+use self::{
+    device::Device, instance::Instance, memory_budget::MemoryBudget,
+    physical_device::PhysicalDevice, surface::Surface,
+};
 
 pub struct Context {
     pub device: Device,
     pub surface: Surface,
     pub physical_device: PhysicalDevice,
-    _instance: Instance,
+    instance: Instance,
 }
 
 impl Context {
@@ -38,13 +45,47 @@ impl Context {
             device,
             surface,
             physical_device,
-            _instance: instance,
+            instance,
         }
     }
 
     pub fn refresh_surface_capabilities(&mut self) -> bool {
         self.surface.refresh_capabilities(&self.physical_device)
     }
+
+    pub fn memory_budget(&self) -> MemoryBudget {
+        MemoryBudget::query(&self.instance, &self.physical_device, &self.allocator)
+    }
+
+    // Whether optimally tiled images of the format can be blitted with linear filtering
+    pub fn supports_linear_blit(&self, format: vk::Format) -> bool {
+        self.supports_format_features(
+            format,
+            vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+    }
+
+    // Whether optimally tiled images of the format can be both written by shaders and rendered to
+    pub fn supports_render_target(&self, format: vk::Format) -> bool {
+        self.supports_format_features(
+            format,
+            vk::FormatFeatureFlags::STORAGE_IMAGE | vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+        )
+    }
+
+    fn supports_format_features(
+        &self,
+        format: vk::Format,
+        features: vk::FormatFeatureFlags,
+    ) -> bool {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(*self.physical_device, format)
+        };
+        properties.optimal_tiling_features.contains(features)
+    }
 }
 
 impl Deref for Context {
@@ -2,11 +2,28 @@ use std::ops::{Deref, DerefMut};
 
 use ash::vk;
 
-use super::{features, instance::Instance, properties::Properties};
+use super::{extensions, features, instance::Instance, properties::Properties};
+
+mod conf {
+    use ash::vk;
+
+    // from most to least precise
+    pub const DEPTH_FORMATS: &[vk::Format] = &[
+        vk::Format::D32_SFLOAT,
+        vk::Format::D24_UNORM_S8_UINT,
+        vk::Format::D16_UNORM,
+    ];
+}
 
 pub struct PhysicalDevice {
     physical_device: vk::PhysicalDevice,
     pub properties: Properties,
+    pub supports_ray_tracing: bool,
+    // only with ray tracing, which the TLAS queried against is built for
+    pub supports_ray_query: bool,
+    pub supports_anisotropy: bool,
+    // that can be both rendered to and sampled, in the order of `conf::DEPTH_FORMATS`
+    pub depth_formats: Vec<vk::Format>,
 }
 
 impl PhysicalDevice {
@@ -14,14 +31,47 @@ impl PhysicalDevice {
         firestorm::profile_method!(new);
 
         if features::supported_by(instance, physical_device) {
+            let supports_ray_tracing = instance
+                .has_device_extensions(physical_device, extensions::RAY_TRACING)
+                && features::ray_tracing_supported_by(instance, physical_device);
+            let supports_ray_query = supports_ray_tracing
+                && instance.has_device_extensions(physical_device, extensions::RAY_QUERY)
+                && features::ray_query_supported_by(instance, physical_device);
+
             Some(Self {
                 physical_device,
-                properties: Properties::get_supported(instance, physical_device),
+                properties: Properties::get_supported(
+                    instance,
+                    physical_device,
+                    supports_ray_tracing,
+                ),
+                supports_ray_tracing,
+                supports_ray_query,
+                supports_anisotropy: features::anisotropy_supported_by(instance, physical_device),
+                depth_formats: Self::supported_depth_formats(instance, physical_device),
             })
         } else {
             None
         }
     }
+
+    fn supported_depth_formats(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Vec<vk::Format> {
+        let features = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE;
+        conf::DEPTH_FORMATS
+            .iter()
+            .copied()
+            .filter(|&format| {
+                let properties = unsafe {
+                    instance.get_physical_device_format_properties(physical_device, format)
+                };
+                properties.optimal_tiling_features.contains(features)
+            })
+            .collect()
+    }
 }
 
 impl Deref for PhysicalDevice {
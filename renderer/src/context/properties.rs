@@ -13,6 +13,9 @@ pub struct V10Properties {
 }
 pub struct AccelerationStructureProperties {
     pub min_acceleration_structure_scratch_offset_alignment: u32,
+    pub max_geometry_count: u64,
+    pub max_instance_count: u64,
+    pub max_primitive_count: u64,
 }
 pub struct RayTracingPipelineProperties {
     pub shader_group_base_alignment: u32,
@@ -21,16 +24,24 @@ pub struct RayTracingPipelineProperties {
 }
 
 impl Properties {
-    pub fn get_supported(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self {
+    pub fn get_supported(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        ray_tracing: bool,
+    ) -> Self {
         firestorm::profile_method!(get_supported);
 
         let mut ray_tracing_pipeline = vk::PhysicalDeviceRayTracingPipelinePropertiesKHR::default();
         let mut acceleration_structure =
             vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
 
-        let mut v_1_0 = vk::PhysicalDeviceProperties2::default()
-            .push_next(&mut ray_tracing_pipeline)
-            .push_next(&mut acceleration_structure);
+        // left zeroed when the extensions are unavailable
+        let mut v_1_0 = vk::PhysicalDeviceProperties2::default();
+        if ray_tracing {
+            v_1_0 = v_1_0
+                .push_next(&mut ray_tracing_pipeline)
+                .push_next(&mut acceleration_structure);
+        }
 
         unsafe { instance.get_physical_device_properties2(physical_device, &mut v_1_0) };
 
@@ -55,6 +66,9 @@ impl From<vk::PhysicalDeviceAccelerationStructurePropertiesKHR<'_>>
         Self {
             min_acceleration_structure_scratch_offset_alignment: p
                 .min_acceleration_structure_scratch_offset_alignment,
+            max_geometry_count: p.max_geometry_count,
+            max_instance_count: p.max_instance_count,
+            max_primitive_count: p.max_primitive_count,
         }
     }
 }
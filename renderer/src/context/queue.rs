@@ -1,4 +1,4 @@
-use std::{collections::HashSet, ops::Deref};
+use std::ops::Deref;
 
 use ash::vk;
 
@@ -8,6 +8,7 @@ pub struct Queues {
     graphics: Queue,
     compute: Queue,
     transfer: Queue,
+    unique_family_indices: Vec<u32>,
 }
 
 pub struct Queue {
@@ -38,6 +39,7 @@ impl Queues {
             graphics,
             compute,
             transfer,
+            unique_family_indices: families.unique(),
         }
     }
 
@@ -46,13 +48,26 @@ impl Queues {
     }
 
     pub const fn compute(&self) -> &Queue {
-        &self.graphics
+        &self.compute
     }
 
     pub const fn transfer(&self) -> &Queue {
         &self.transfer
     }
 
+    // Resources are shared concurrently between the families instead of transferring ownership
+    pub const fn sharing_mode(&self) -> vk::SharingMode {
+        if self.unique_family_indices.len() > 1 {
+            vk::SharingMode::CONCURRENT
+        } else {
+            vk::SharingMode::EXCLUSIVE
+        }
+    }
+
+    pub fn unique_family_indices(&self) -> &[u32] {
+        &self.unique_family_indices
+    }
+
     pub fn create_infos(indices: &Families) -> Vec<vk::DeviceQueueCreateInfo> {
         indices
             .unique()
@@ -86,15 +101,24 @@ impl Families {
         let queue_families =
             unsafe { instance.get_physical_device_queue_family_properties(**physical_device) };
 
-        let valid_queue_families = queue_families
-            .into_iter()
-            .enumerate()
-            .filter(|(_, queue_family)| queue_family.queue_count > 0)
-            .collect::<Vec<_>>();
+        let families = Self::select(&queue_families, |idx| {
+            surface.is_supported_by(physical_device, idx)
+        })?;
+        families.assert_supported_by(&queue_families);
+        Some(families)
+    }
 
+    // Prefers dedicated compute and transfer families, falling back to the graphics family (which
+    // always supports both) on devices that only expose a single queue family
+    fn select(
+        queue_families: &[vk::QueueFamilyProperties],
+        supports_present: impl Fn(u32) -> bool,
+    ) -> Option<Self> {
         let mut found_indices = FamiliesInfo::default();
-        for (index, queue_family) in valid_queue_families {
-            let idx = index as u32;
+        for (idx, queue_family) in (0..).zip(queue_families) {
+            if queue_family.queue_count == 0 {
+                continue;
+            }
 
             let g = queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS);
             let c = queue_family.queue_flags.contains(vk::QueueFlags::COMPUTE);
@@ -107,11 +131,11 @@ impl Families {
                 .contains(vk::QueueFlags::VIDEO_ENCODE_KHR);
 
             if !g && !c && t && !vd && !ve {
-                found_indices.transfer = Some(idx);
+                found_indices.transfer.get_or_insert(idx);
             } else if !g && c {
-                found_indices.compute = Some(idx);
-            } else if g && c && surface.is_supported_by(physical_device, idx) {
-                // TODO: this should not be checking for the compute flag
+                found_indices.compute.get_or_insert(idx);
+            } else if g && c && found_indices.graphics.is_none() && supports_present(idx) {
+                // the compute passes are recorded on the graphics queue
                 found_indices.graphics = Some(idx);
             }
 
@@ -120,11 +144,47 @@ impl Families {
             }
         }
 
-        Self::try_from(found_indices).ok()
+        let graphics = found_indices.graphics?;
+        let compute = found_indices.compute.unwrap_or(graphics);
+        Some(Self {
+            graphics,
+            compute,
+            transfer: found_indices.transfer.unwrap_or(compute),
+        })
+    }
+
+    fn assert_supported_by(&self, queue_families: &[vk::QueueFamilyProperties]) {
+        let supports = |idx: u32, flags| {
+            let queue_flags = queue_families[idx as usize].queue_flags;
+            // graphics and compute families implicitly support transfer operations
+            queue_flags.intersects(flags)
+                || (flags == vk::QueueFlags::TRANSFER
+                    && queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE))
+        };
+
+        assert!(
+            supports(self.graphics, vk::QueueFlags::GRAPHICS)
+                && supports(self.graphics, vk::QueueFlags::COMPUTE),
+            "Graphics queue family {} does not support graphics and compute",
+            self.graphics
+        );
+        assert!(
+            supports(self.compute, vk::QueueFlags::COMPUTE),
+            "Compute queue family {} does not support compute",
+            self.compute
+        );
+        assert!(
+            supports(self.transfer, vk::QueueFlags::TRANSFER),
+            "Transfer queue family {} does not support transfer",
+            self.transfer
+        );
     }
 
-    fn unique(&self) -> HashSet<u32> {
-        HashSet::from([self.graphics, self.compute, self.transfer])
+    fn unique(&self) -> Vec<u32> {
+        let mut unique = vec![self.graphics, self.compute, self.transfer];
+        unique.sort_unstable();
+        unique.dedup();
+        unique
     }
 }
 
@@ -135,17 +195,6 @@ impl Deref for Queue {
     }
 }
 
-impl TryFrom<FamiliesInfo> for Families {
-    type Error = ();
-    fn try_from(value: FamiliesInfo) -> Result<Self, Self::Error> {
-        Ok(Self {
-            graphics: value.graphics.ok_or(())?,
-            compute: value.compute.ok_or(())?,
-            transfer: value.transfer.ok_or(())?,
-        })
-    }
-}
-
 impl FamiliesInfo {
     pub const fn is_complete(&self) -> bool {
         self.graphics.is_some() && self.compute.is_some() && self.transfer.is_some()
@@ -12,6 +12,17 @@ pub mod conf {
         format: vk::Format::B8G8R8A8_SRGB,
         color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
     };
+    // in order of preference, scRGB needs no encoding besides a scale
+    pub const HDR_SURFACE_FORMATS: &[vk::SurfaceFormatKHR] = &[
+        vk::SurfaceFormatKHR {
+            format: vk::Format::R16G16B16A16_SFLOAT,
+            color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        },
+        vk::SurfaceFormatKHR {
+            format: vk::Format::A2B10G10R10_UNORM_PACK32,
+            color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        },
+    ];
     pub const PREFERRED_PRESENT_MODE: vk::PresentModeKHR = vk::PresentModeKHR::FIFO_RELAXED;
     pub const FALLBACK_PRESENT_MODE: vk::PresentModeKHR = vk::PresentModeKHR::FIFO;
 }
@@ -31,6 +42,10 @@ pub struct Config {
     pub present_mode: vk::PresentModeKHR,
     pub extent: vk::Extent2D,
     pub image_count: u32,
+    // of the window, for surfaces that leave the extent to the swapchain
+    window_extent: Option<vk::Extent2D>,
+    supported_surface_formats: Vec<vk::SurfaceFormatKHR>,
+    supported_present_modes: Vec<vk::PresentModeKHR>,
 }
 
 pub struct ConfigurationOptions {
@@ -118,7 +133,7 @@ impl ConfigurationOptions {
 
     pub fn get_optimal(&self) -> Config {
         let surface_format = Self::choose_best_surface_format(&self.surface_formats);
-        let extent = Self::choose_extent(&self.capabilities);
+        let extent = Self::choose_extent(&self.capabilities, None);
         let image_count = Self::choose_image_count(&self.capabilities);
         let present_mode = Self::choose_best_present_mode(&self.present_modes);
 
@@ -127,6 +142,9 @@ impl ConfigurationOptions {
             present_mode,
             extent,
             image_count,
+            window_extent: None,
+            supported_surface_formats: self.surface_formats.clone(),
+            supported_present_modes: self.present_modes.clone(),
         }
     }
 
@@ -146,17 +164,21 @@ impl ConfigurationOptions {
             .unwrap_or(conf::FALLBACK_PRESENT_MODE)
     }
 
-    fn choose_extent(capabilities: &vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
+    fn choose_extent(
+        capabilities: &vk::SurfaceCapabilitiesKHR,
+        window_extent: Option<vk::Extent2D>,
+    ) -> vk::Extent2D {
         if capabilities.current_extent.width != u32::MAX {
             return capabilities.current_extent;
         }
 
+        let extent = window_extent.unwrap_or(capabilities.current_extent);
         vk::Extent2D {
-            width: capabilities.current_extent.width.clamp(
+            width: extent.width.clamp(
                 capabilities.min_image_extent.width,
                 capabilities.max_image_extent.width,
             ),
-            height: capabilities.current_extent.height.clamp(
+            height: extent.height.clamp(
                 capabilities.min_image_extent.height,
                 capabilities.max_image_extent.height,
             ),
@@ -175,7 +197,40 @@ impl ConfigurationOptions {
 
 impl Config {
     fn update_with(&mut self, surface_capabilities: &vk::SurfaceCapabilitiesKHR) {
-        self.extent = ConfigurationOptions::choose_extent(surface_capabilities);
+        self.extent = ConfigurationOptions::choose_extent(surface_capabilities, self.window_extent);
+    }
+
+    pub const fn set_window_extent(&mut self, window_extent: vk::Extent2D) {
+        self.window_extent = Some(window_extent);
+    }
+
+    pub fn select_present_mode(&mut self, present_mode: vk::PresentModeKHR) {
+        self.present_mode = if self.supported_present_modes.contains(&present_mode) {
+            present_mode
+        } else {
+            eprintln!(
+                "Warning: present mode {present_mode:?} is not supported, falling back to {:?}",
+                conf::FALLBACK_PRESENT_MODE
+            );
+            conf::FALLBACK_PRESENT_MODE
+        };
+    }
+
+    // Falls back to the SDR format if the surface supports none of the HDR ones, returns whether the
+    // output is HDR
+    pub fn select_hdr_output(&mut self, hdr: bool) -> bool {
+        let hdr_format = conf::HDR_SURFACE_FORMATS
+            .iter()
+            .copied()
+            .find(|format| hdr && self.supported_surface_formats.contains(format));
+        if hdr && hdr_format.is_none() {
+            eprintln!("Warning: HDR output is not supported by the surface, falling back to SDR");
+        }
+
+        self.surface_format = hdr_format.unwrap_or_else(|| {
+            ConfigurationOptions::choose_best_surface_format(&self.supported_surface_formats)
+        });
+        hdr_format.is_some()
     }
 
     const fn valid_extent(&self) -> bool {
@@ -1,6 +1,9 @@
 use ash::vk;
 
-use crate::{context::Context, Destroy};
+use crate::{
+    context::{Context, Resource},
+    Destroy,
+};
 
 pub struct Descriptors {
     pub layout: vk::DescriptorSetLayout,
@@ -8,11 +11,206 @@ pub struct Descriptors {
     pub sets: Vec<vk::DescriptorSet>,
 }
 
+// Describes the bindings of a layout once, the pool is sized from them for however many sets are
+// built so that the two can't drift apart
+#[derive(Default)]
+pub struct DescriptorSetBuilder {
+    bindings: Vec<vk::DescriptorSetLayoutBinding<'static>>,
+    flags: Vec<vk::DescriptorBindingFlags>,
+}
+
+impl Descriptors {
+    fn new(
+        ctx: &Context,
+        layout: vk::DescriptorSetLayout,
+        pool: vk::DescriptorPool,
+        sets: Vec<vk::DescriptorSet>,
+    ) -> Self {
+        ctx.track_created(Resource::Descriptors);
+        Self { layout, pool, sets }
+    }
+}
+
+impl DescriptorSetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn binding(
+        self,
+        binding: u32,
+        ty: vk::DescriptorType,
+        count: u32,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.binding_with_flags(
+            binding,
+            ty,
+            count,
+            stages,
+            vk::DescriptorBindingFlags::empty(),
+        )
+    }
+
+    // With VARIABLE_DESCRIPTOR_COUNT, which only the highest binding can have, the count is what
+    // every set is allocated with
+    pub fn binding_with_flags(
+        mut self,
+        binding: u32,
+        ty: vk::DescriptorType,
+        count: u32,
+        stages: vk::ShaderStageFlags,
+        flags: vk::DescriptorBindingFlags,
+    ) -> Self {
+        self.bindings.push(
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(ty)
+                .descriptor_count(count)
+                .stage_flags(stages),
+        );
+        self.flags.push(flags);
+        self
+    }
+
+    pub fn build(&self, ctx: &Context, num_sets: u32) -> Descriptors {
+        firestorm::profile_method!(build);
+
+        self.check_variable_count();
+
+        let layout = {
+            let mut binding_flags_info =
+                vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&self.flags);
+            let mut info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&self.bindings);
+            if self.flags.iter().any(|flags| !flags.is_empty()) {
+                info = info.push_next(&mut binding_flags_info);
+            }
+            unsafe {
+                ctx.create_descriptor_set_layout(&info, None)
+                    .expect("Failed to create descriptor set layout")
+            }
+        };
+
+        let pool = {
+            let sizes = self.pool_sizes(num_sets);
+            let info = vk::DescriptorPoolCreateInfo::default()
+                .pool_sizes(&sizes)
+                .max_sets(num_sets);
+            unsafe {
+                ctx.create_descriptor_pool(&info, None)
+                    .expect("Failed to create descriptor pool")
+            }
+        };
+
+        let sets = {
+            let layouts = vec![layout; num_sets as usize];
+            let variable_counts = self
+                .bindings
+                .iter()
+                .zip(&self.flags)
+                .find(|(_, flags)| {
+                    flags.contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT)
+                })
+                .map(|(binding, _)| vec![binding.descriptor_count; num_sets as usize]);
+            let mut set_counts = vk::DescriptorSetVariableDescriptorCountAllocateInfo::default()
+                .descriptor_counts(variable_counts.as_deref().unwrap_or_default());
+
+            let mut info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(pool)
+                .set_layouts(&layouts);
+            if variable_counts.is_some() {
+                info = info.push_next(&mut set_counts);
+            }
+            unsafe {
+                ctx.allocate_descriptor_sets(&info)
+                    .expect("Failed to allocate descriptor sets")
+            }
+        };
+
+        Descriptors::new(ctx, layout, pool, sets)
+    }
+
+    // Vulkan requires the binding with VARIABLE_DESCRIPTOR_COUNT to have the highest binding number
+    fn check_variable_count(&self) {
+        let variable = self.bindings.iter().zip(&self.flags).find(|(_, flags)| {
+            flags.contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT)
+        });
+        if let Some((variable, _)) = variable {
+            assert!(
+                self.bindings
+                    .iter()
+                    .all(|binding| binding.binding <= variable.binding),
+                "Binding {} has a variable descriptor count but isn't the highest binding",
+                variable.binding
+            );
+        }
+    }
+
+    // one size per type of descriptor, enough for every set
+    fn pool_sizes(&self, num_sets: u32) -> Vec<vk::DescriptorPoolSize> {
+        let mut sizes = Vec::<vk::DescriptorPoolSize>::new();
+        for binding in &self.bindings {
+            let count = binding.descriptor_count * num_sets;
+            match sizes
+                .iter_mut()
+                .find(|size| size.ty == binding.descriptor_type)
+            {
+                Some(size) => size.descriptor_count += count,
+                None => sizes.push(
+                    vk::DescriptorPoolSize::default()
+                        .ty(binding.descriptor_type)
+                        .descriptor_count(count),
+                ),
+            }
+        }
+        sizes
+    }
+}
+
 impl Destroy<Context> for Descriptors {
     unsafe fn destroy_with(&mut self, ctx: &Context) {
         firestorm::profile_method!(destroy_with);
 
         ctx.destroy_descriptor_pool(self.pool, None);
         ctx.destroy_descriptor_set_layout(self.layout, None);
+        ctx.track_destroyed(Resource::Descriptors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_variable_count(builder: DescriptorSetBuilder, binding: u32) -> DescriptorSetBuilder {
+        builder.binding_with_flags(
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            16,
+            vk::ShaderStageFlags::FRAGMENT,
+            vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+        )
+    }
+
+    fn storage_image(builder: DescriptorSetBuilder, binding: u32) -> DescriptorSetBuilder {
+        builder.binding(
+            binding,
+            vk::DescriptorType::STORAGE_IMAGE,
+            1,
+            vk::ShaderStageFlags::COMPUTE,
+        )
+    }
+
+    #[test]
+    fn variable_count_may_be_the_highest_binding() {
+        let builder = storage_image(DescriptorSetBuilder::new(), 0);
+        with_variable_count(builder, 1).check_variable_count();
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't the highest binding")]
+    fn variable_count_must_be_the_highest_binding() {
+        let builder = with_variable_count(DescriptorSetBuilder::new(), 1);
+        storage_image(builder, 2).check_variable_count();
     }
 }
@@ -3,21 +3,83 @@ use std::{marker::ConstParamTy, ops::Deref, slice};
 use ash::vk;
 use vk_mem::Alloc;
 
-use crate::{buffer::Buffer, context::Context, memory, scope::Scope, Destroy};
+use crate::{
+    buffer::Buffer,
+    commands::Commands,
+    context::{Context, Resource},
+    memory,
+    scope::Scope,
+    Destroy,
+};
 
 #[derive(PartialEq, Eq, ConstParamTy)]
 pub enum Format {
     Hdr,
+    // half the memory traffic of Hdr, but long accumulations lose precision once the running
+    // average is large relative to each new sample (fp16 has an 11 bit significand)
+    Hdr16,
     Color,
+    // textures from 16-bit sources, always holds linear values
+    Color16,
+    // 8-bit output that the shader encoded already, storage images have no sRGB formats
+    Encoded,
     Depth,
     Swapchain,
 }
 
+pub type Rgba16Image = image::ImageBuffer<image::Rgba<u16>, Vec<u16>>;
+
+// Encoding of the values stored in an image, sampling and attachment writes convert sRGB to and
+// from the linear values that all shading is done in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    Linear,
+    Srgb,
+}
+
+impl ColorSpace {
+    // That of the values stored in images of the format, as far as 8 bit color formats go
+    pub const fn of(format: vk::Format) -> Self {
+        match format {
+            vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::R8G8B8_SRGB
+            | vk::Format::B8G8R8_SRGB => Self::Srgb,
+            _ => Self::Linear,
+        }
+    }
+}
+
+impl Format {
+    pub const fn color_space(self) -> ColorSpace {
+        match self {
+            Self::Hdr | Self::Hdr16 | Self::Color16 | Self::Encoded | Self::Depth => {
+                ColorSpace::Linear
+            }
+            Self::Color | Self::Swapchain => ColorSpace::Srgb,
+        }
+    }
+
+    // Format to view the same texels with, images need MUTABLE_FORMAT for views that differ
+    pub fn view_format(self, color_space: ColorSpace) -> vk::Format {
+        match (self, color_space) {
+            (Self::Color, ColorSpace::Linear) => vk::Format::R8G8B8A8_UNORM,
+            (Self::Swapchain, ColorSpace::Linear) => vk::Format::B8G8R8A8_UNORM,
+            (format, _) => format.into(),
+        }
+    }
+}
+
 impl From<Format> for vk::Format {
     fn from(format: Format) -> Self {
         match format {
             Format::Hdr => Self::R32G32B32A32_SFLOAT,
+            Format::Hdr16 => Self::R16G16B16A16_SFLOAT,
             Format::Color => Self::R8G8B8A8_SRGB,
+            Format::Color16 => Self::R16G16B16A16_UNORM,
+            Format::Encoded => Self::R8G8B8A8_UNORM,
+            // supported everywhere, the rasterizer picks a more precise one where it can
             Format::Depth => Self::D16_UNORM,
             Format::Swapchain => Self::B8G8R8A8_SRGB,
         }
@@ -28,6 +90,8 @@ pub struct Image<const FORMAT: Format> {
     pub image: vk::Image,
     pub view: vk::ImageView,
     pub extent: vk::Extent2D,
+    // of the view, which can differ from the format of the image
+    pub format: vk::Format,
     allocation: Option<vk_mem::Allocation>, // None if memory is not managed by us (eg. swapchain)
 }
 
@@ -61,15 +125,29 @@ impl<const FORMAT: Format> Image<FORMAT> {
             }
         };
         ctx.set_debug_name(view, &(name + " - Image View"));
+        ctx.track_created(Resource::Image);
 
         Self {
             image,
             view,
             extent,
+            format,
             allocation,
         }
     }
 
+    // Another view of the same image, the memory stays owned by this one
+    pub fn view_as(&self, ctx: &Context, name: String, color_space: ColorSpace) -> Self {
+        Self::new_of_format(
+            ctx,
+            name,
+            self.image,
+            self.extent,
+            FORMAT.view_format(color_space),
+            None,
+        )
+    }
+
     pub fn new(
         ctx: &Context,
         name: String,
@@ -88,21 +166,44 @@ impl<const FORMAT: Format> Image<FORMAT> {
         memory_purpose: &vk_mem::AllocationCreateInfo,
         to: Option<&BarrierInfo>,
     ) -> Self {
-        firestorm::profile_method!(create);
+        Self::create_of_format(
+            ctx,
+            command_buffer,
+            name,
+            info,
+            memory_purpose,
+            to,
+            FORMAT.into(),
+        )
+    }
+
+    // For formats picked at runtime, which must be of the same kind as FORMAT
+    pub fn create_of_format(
+        ctx: &Context,
+        command_buffer: vk::CommandBuffer,
+        name: String,
+        info: &vk::ImageCreateInfo,
+        memory_purpose: &vk_mem::AllocationCreateInfo,
+        to: Option<&BarrierInfo>,
+        format: vk::Format,
+    ) -> Self {
+        firestorm::profile_method!(create_of_format);
 
         let name = name + " - Image";
 
         let image_info = vk::ImageCreateInfo {
             image_type: vk::ImageType::TYPE_2D,
-            mip_levels: 1,
+            mip_levels: info.mip_levels.max(1),
             array_layers: 1,
             samples: vk::SampleCountFlags::TYPE_1,
             initial_layout: vk::ImageLayout::UNDEFINED,
             tiling: vk::ImageTiling::OPTIMAL,
-            format: FORMAT.into(),
+            format,
             usage: Self::usage_flags() | info.usage,
             ..*info
-        };
+        }
+        .sharing_mode(ctx.queues.sharing_mode())
+        .queue_family_indices(ctx.queues.unique_family_indices());
 
         let (image, allocation) = unsafe {
             ctx.allocator
@@ -139,6 +240,21 @@ impl<const FORMAT: Format> Image<FORMAT> {
         }
     }
 
+    // The layout of depth and stencil can only change together without separateDepthStencilLayouts
+    fn barrier_subresource_range(&self) -> vk::ImageSubresourceRange {
+        let has_stencil = matches!(
+            self.format,
+            vk::Format::D16_UNORM_S8_UINT
+                | vk::Format::D24_UNORM_S8_UINT
+                | vk::Format::D32_SFLOAT_S8_UINT
+        );
+        let mut range = Self::subresource_range();
+        if has_stencil {
+            range.aspect_mask |= vk::ImageAspectFlags::STENCIL;
+        }
+        range
+    }
+
     pub fn transition_layout(
         &self,
         ctx: &Context,
@@ -156,7 +272,7 @@ impl<const FORMAT: Format> Image<FORMAT> {
             .dst_access_mask(to.access)
             .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
             .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .subresource_range(Self::subresource_range());
+            .subresource_range(self.barrier_subresource_range());
 
         unsafe {
             ctx.cmd_pipeline_barrier(
@@ -171,6 +287,61 @@ impl<const FORMAT: Format> Image<FORMAT> {
         }
     }
 
+    pub fn cmd_clear(
+        &self,
+        ctx: &Context,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+    ) {
+        firestorm::profile_method!(cmd_clear);
+
+        unsafe {
+            ctx.cmd_clear_color_image(
+                command_buffer,
+                self.image,
+                layout,
+                &vk::ClearColorValue::default(),
+                slice::from_ref(&Self::subresource_range()),
+            );
+        }
+    }
+
+    pub fn cmd_copy_to(
+        &self,
+        ctx: &Context,
+        command_buffer: vk::CommandBuffer,
+        dst: &Self,
+        layout: vk::ImageLayout,
+    ) {
+        firestorm::profile_method!(cmd_copy_to);
+
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: Self::aspect_flags(),
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let copy_info = vk::ImageCopy::default()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            });
+
+        unsafe {
+            ctx.cmd_copy_image(
+                command_buffer,
+                self.image,
+                layout,
+                dst.image,
+                layout,
+                slice::from_ref(&copy_info),
+            );
+        }
+    }
+
     const fn usage_flags() -> vk::ImageUsageFlags {
         match FORMAT {
             Format::Depth => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
@@ -193,22 +364,84 @@ impl Image<{ Format::Color }> {
         name: String,
         img: &image::RgbaImage,
     ) -> Self {
-        firestorm::profile_method!(create_from_image);
+        Self::create_from_texels(ctx, scope, name, img.dimensions(), img)
+    }
+}
+
+impl Image<{ Format::Color16 }> {
+    pub fn create_from_image(
+        ctx: &Context,
+        scope: &mut Scope,
+        name: String,
+        img: &Rgba16Image,
+    ) -> Self {
+        Self::create_from_texels(
+            ctx,
+            scope,
+            name,
+            img.dimensions(),
+            bytemuck::cast_slice(img),
+        )
+    }
+}
+
+impl Image<{ Format::Hdr }> {
+    pub fn create_from_image(
+        ctx: &Context,
+        scope: &mut Scope,
+        name: String,
+        img: &image::Rgba32FImage,
+    ) -> Self {
+        Self::create_from_texels(
+            ctx,
+            scope,
+            name,
+            img.dimensions(),
+            bytemuck::cast_slice(img),
+        )
+    }
+}
+
+impl<const FORMAT: Format> Image<FORMAT> {
+    // Texels are tightly packed rows of the format's size
+    fn create_from_texels(
+        ctx: &Context,
+        scope: &mut Scope,
+        name: String,
+        (width, height): (u32, u32),
+        texels: &[u8],
+    ) -> Self {
+        firestorm::profile_method!(create_from_texels);
 
         let staging = {
             let info = vk::BufferCreateInfo::default().usage(vk::BufferUsageFlags::TRANSFER_SRC);
-            Buffer::create_with_data(ctx, name.clone() + " - Staging", info, img)
+            Buffer::create_with_data(ctx, name.clone() + " - Staging", info, texels)
         };
 
         let extent = vk::Extent3D {
-            width: img.width(),
-            height: img.height(),
+            width,
+            height,
             depth: 1,
         };
 
+        // a full chain down to 1x1, unless the mips cannot be blitted
+        let mip_levels = if ctx.supports_linear_blit(FORMAT.into()) {
+            u32::BITS - width.max(height).leading_zeros()
+        } else {
+            1
+        };
+
+        // textures holding linear data are viewed with a non-sRGB format
+        let flags = if FORMAT == Format::Color {
+            vk::ImageCreateFlags::MUTABLE_FORMAT
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
         let info = vk::ImageCreateInfo::default()
+            .flags(flags)
             .extent(extent)
-            .usage(vk::ImageUsageFlags::TRANSFER_DST);
+            .mip_levels(mip_levels)
+            .usage(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST);
         let image = Self::create(
             ctx,
             scope.commands.buffer,
@@ -221,18 +454,102 @@ impl Image<{ Format::Color }> {
         // Copy data to image
         image.cmd_copy_from(ctx, scope.commands.buffer, &staging, extent);
 
-        image.transition_layout(
-            ctx,
-            scope.commands.buffer,
-            &BarrierInfo::TRANSFER_DST,
-            &BarrierInfo::SHADER_READ,
-        );
+        // leaves every level ready for sampling
+        image.cmd_generate_mipmaps(ctx, scope.commands.buffer, mip_levels);
 
         scope.add_resource(staging);
 
         image
     }
 
+    // Each level is blitted from the one above it, which is then done with and made shader readable
+    fn cmd_generate_mipmaps(
+        &self,
+        ctx: &Context,
+        command_buffer: vk::CommandBuffer,
+        mip_levels: u32,
+    ) {
+        firestorm::profile_method!(cmd_generate_mipmaps);
+
+        let transition_level = |level, from: &BarrierInfo, to: &BarrierInfo| {
+            let barrier = vk::ImageMemoryBarrier::default()
+                .image(self.image)
+                .old_layout(from.layout)
+                .new_layout(to.layout)
+                .src_access_mask(from.access)
+                .dst_access_mask(to.access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(vk::ImageSubresourceRange {
+                    base_mip_level: level,
+                    level_count: 1,
+                    ..Self::subresource_range()
+                });
+
+            unsafe {
+                ctx.cmd_pipeline_barrier(
+                    command_buffer,
+                    from.stage,
+                    to.stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    slice::from_ref(&barrier),
+                );
+            }
+        };
+
+        let subresource = |mip_level| vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let corner = |level: u32| vk::Offset3D {
+            x: (self.extent.width >> level).max(1) as _,
+            y: (self.extent.height >> level).max(1) as _,
+            z: 1,
+        };
+
+        for level in 1..mip_levels {
+            transition_level(
+                level - 1,
+                &BarrierInfo::TRANSFER_DST,
+                &BarrierInfo::TRANSFER_SRC,
+            );
+
+            let blit = vk::ImageBlit::default()
+                .src_subresource(subresource(level - 1))
+                .src_offsets([vk::Offset3D::default(), corner(level - 1)])
+                .dst_subresource(subresource(level))
+                .dst_offsets([vk::Offset3D::default(), corner(level)]);
+
+            unsafe {
+                ctx.cmd_blit_image(
+                    command_buffer,
+                    self.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    slice::from_ref(&blit),
+                    vk::Filter::LINEAR,
+                );
+            }
+
+            transition_level(
+                level - 1,
+                &BarrierInfo::TRANSFER_SRC,
+                &BarrierInfo::SHADER_READ,
+            );
+        }
+
+        transition_level(
+            mip_levels - 1,
+            &BarrierInfo::TRANSFER_DST,
+            &BarrierInfo::SHADER_READ,
+        );
+    }
+
     fn cmd_copy_from(
         &self,
         ctx: &Context,
@@ -263,6 +580,126 @@ impl Image<{ Format::Color }> {
     }
 }
 
+impl<const FORMAT: Format> Image<FORMAT> {
+    // Only implemented for the HDR formats, which are widened to 32-bit floats
+    pub fn read_back(&self, ctx: &Context) -> image::Rgba32FImage {
+        firestorm::profile_method!(read_back);
+
+        let num_values = 4 * self.extent.width as usize * self.extent.height as usize;
+
+        let values = match FORMAT {
+            Format::Hdr => {
+                let mut values = vec![0f32; num_values];
+                self.read_back_into(ctx, bytemuck::cast_slice_mut(&mut values));
+                values
+            }
+            Format::Hdr16 => {
+                let mut values = vec![0u16; num_values];
+                self.read_back_into(ctx, bytemuck::cast_slice_mut(&mut values));
+                values
+                    .into_iter()
+                    .map(|bits| f16::from_bits(bits) as f32)
+                    .collect()
+            }
+            _ => panic!("Readback is only supported for HDR images"),
+        };
+
+        image::Rgba32FImage::from_raw(self.extent.width, self.extent.height, values)
+            .expect("Readback size does not match the image extent")
+    }
+
+    // Copies the texels of a color image written by shaders into `data`, which must be sized to fit
+    fn read_back_into(&self, ctx: &Context, data: &mut [u8]) {
+        firestorm::profile_method!(read_back_into);
+
+        let mut buffer = Buffer::create(
+            ctx,
+            "Readback".to_owned(),
+            vk::BufferCreateInfo::default()
+                .size(data.len() as _)
+                .usage(vk::BufferUsageFlags::TRANSFER_DST),
+            &memory::purpose::readback(),
+        );
+
+        let commands = Commands::begin_on_queue(ctx, "Readback".to_owned(), ctx.queues.graphics());
+
+        self.transition_layout(
+            ctx,
+            commands.buffer,
+            &BarrierInfo {
+                layout: vk::ImageLayout::GENERAL,
+                stage: vk::PipelineStageFlags::ALL_COMMANDS,
+                access: vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            },
+            &BarrierInfo {
+                layout: vk::ImageLayout::GENERAL,
+                stage: vk::PipelineStageFlags::TRANSFER,
+                access: vk::AccessFlags::TRANSFER_READ,
+            },
+        );
+
+        // rows packed one after the other in `data`, whatever the pitch of the image itself
+        let copy_info = vk::BufferImageCopy::default()
+            .buffer_row_length(self.extent.width)
+            .buffer_image_height(self.extent.height)
+            .image_extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+
+        unsafe {
+            ctx.cmd_copy_image_to_buffer(
+                commands.buffer,
+                self.image,
+                vk::ImageLayout::GENERAL,
+                *buffer,
+                slice::from_ref(&copy_info),
+            );
+
+            ctx.cmd_pipeline_barrier(
+                commands.buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                slice::from_ref(
+                    &vk::MemoryBarrier::default()
+                        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .dst_access_mask(vk::AccessFlags::HOST_READ),
+                ),
+                &[],
+                &[],
+            );
+        }
+
+        commands.finish(ctx, &vk::SubmitInfo::default(), None);
+
+        buffer.read_into(ctx, data);
+
+        unsafe {
+            buffer.destroy_with(ctx);
+        }
+    }
+}
+
+impl Image<{ Format::Encoded }> {
+    pub fn read_back_encoded(&self, ctx: &Context) -> image::RgbaImage {
+        firestorm::profile_method!(read_back_encoded);
+
+        let mut values = vec![0u8; 4 * self.extent.width as usize * self.extent.height as usize];
+        self.read_back_into(ctx, &mut values);
+
+        image::RgbaImage::from_raw(self.extent.width, self.extent.height, values)
+            .expect("Readback size does not match the image extent")
+    }
+}
+
 impl Image<{ Format::Depth }> {
     pub const CLEAR_VALUE: vk::ClearValue = vk::ClearValue {
         depth_stencil: vk::ClearDepthStencilValue {
@@ -283,6 +720,25 @@ impl BarrierInfo {
         stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
         access: vk::AccessFlags::empty(),
     };
+    pub const CLEAR: Self = Self {
+        layout: vk::ImageLayout::GENERAL,
+        stage: vk::PipelineStageFlags::TRANSFER,
+        access: vk::AccessFlags::TRANSFER_WRITE,
+    };
+    pub const RAY_TRACING_STORAGE: Self = Self {
+        layout: vk::ImageLayout::GENERAL,
+        stage: vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+        access: vk::AccessFlags::from_raw(
+            vk::AccessFlags::SHADER_READ.as_raw() | vk::AccessFlags::SHADER_WRITE.as_raw(),
+        ),
+    };
+    pub const COMPUTE_STORAGE: Self = Self {
+        layout: vk::ImageLayout::GENERAL,
+        stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        access: vk::AccessFlags::from_raw(
+            vk::AccessFlags::SHADER_READ.as_raw() | vk::AccessFlags::SHADER_WRITE.as_raw(),
+        ),
+    };
     pub const COLOR_ATTACHMENT: Self = Self {
         layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
         stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
@@ -293,6 +749,11 @@ impl BarrierInfo {
         stage: vk::PipelineStageFlags::TRANSFER,
         access: vk::AccessFlags::TRANSFER_WRITE,
     };
+    pub const TRANSFER_SRC: Self = Self {
+        layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        stage: vk::PipelineStageFlags::TRANSFER,
+        access: vk::AccessFlags::TRANSFER_READ,
+    };
     pub const SHADER_READ: Self = Self {
         layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
         stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
@@ -318,6 +779,7 @@ impl<const FORMAT: Format> Destroy<Context> for Image<FORMAT> {
         if let Some(mut allocation) = self.allocation.take() {
             ctx.allocator.destroy_image(self.image, &mut allocation);
         }
+        ctx.track_destroyed(Resource::Image);
     }
 }
 
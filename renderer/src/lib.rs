@@ -1,7 +1,9 @@
 #![feature(adt_const_params)]
+#![feature(f16)]
 
 mod acceleration_structure;
 mod buffer;
+mod bvh;
 mod commands;
 mod context;
 mod descriptors;
@@ -13,6 +15,8 @@ mod query_pool;
 mod sampler;
 mod scope;
 mod shader_binding_table;
+mod stats;
+mod streaming;
 mod swapchain;
 mod sync_info;
 mod sync_state;
@@ -21,18 +25,77 @@ mod uniforms;
 mod util;
 mod world;
 
-use std::{ops::DerefMut, slice};
+use std::{
+    fs,
+    ops::DerefMut,
+    path::Path,
+    slice,
+    time::{Duration, SystemTime},
+};
 
 use raw_window_handle::HasWindowHandle;
 
-use shared::inputs;
+use shared::{inputs, tonemap};
 
-use {context::Context, swapchain::Swapchain, sync_info::SyncInfo, sync_state::SyncState};
+pub use acceleration_structure::BuildPreference;
+pub use context::memory_budget::{HeapBudget, MemoryBudget};
+pub use passes::{
+    pathtracer::{DebugView, PickResult, RenderMode, SamplingStrategy},
+    rasterizer::DepthFormat,
+    tonemap::TonemapMode,
+    tonemap_compute::ExternalImage,
+    upscale::{UpscaleImage, UpscaleInputs, Upscaler},
+};
+pub use stats::{AsMemoryReport, RenderStats};
+pub use streaming::TextureLoading;
+pub use swapchain::PresentMode;
+pub use sync_info::SyncInfo;
+pub use uniforms::{Ipd, Projection};
+pub use world::LightSettings;
+
+use {
+    context::Context,
+    swapchain::{Status, Swapchain},
+    sync_state::SyncState,
+};
 
 mod conf {
     pub const VK_API_VERSION: u32 = ash::vk::make_api_version(0, 1, 3, 0);
 
-    pub const INTERMEDIATE_FORMAT: super::image::Format = super::image::Format::Hdr;
+    // the shaders are compiled for the matching image format by the build script
+    pub const INTERMEDIATE_FORMAT: super::image::Format = if cfg!(feature = "hdr16") {
+        super::image::Format::Hdr16
+    } else {
+        super::image::Format::Hdr
+    };
+
+    pub const MEMORY_BUDGET_WARNING_FRACTION: f64 = 0.8;
+
+    pub const DEFAULT_GPU_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+    // times an out of date swapchain is recreated within a single render before giving up
+    pub const DEFAULT_PRESENT_RETRIES: u32 = 1;
+    pub const MAX_PRESENT_RETRIES: u32 = 4;
+
+    pub const DEFAULT_AO_RADIUS: f32 = 1.;
+    // in world units
+    pub const DEFAULT_RAY_EPSILON: f32 = 1e-4;
+    // no cap, so that the image converges to the same one as the reference
+    pub const DEFAULT_MAX_BOUNCES_AFTER_ROUGH: u32 = u32::MAX;
+
+    // frames between reading back the moments to estimate the error of a render until converged,
+    // also the fewest frames that the variance is estimated from
+    pub const CONVERGENCE_CHECK_INTERVAL: u32 = 16;
+
+    pub const DEFAULT_BLOOM_THRESHOLD: f32 = 1.;
+    pub const DEFAULT_BLOOM_INTENSITY: f32 = 0.05;
+
+    // nits that an output of 1 is shown at on HDR surfaces, the reference white of ITU-R BT.2408
+    pub const HDR_PAPER_WHITE: f32 = 203.;
+
+    // above 1 supersamples the render targets
+    pub const MIN_RENDER_SCALE: f32 = 0.1;
+    pub const MAX_RENDER_SCALE: f32 = 2.;
 }
 
 trait Destroy<C> {
@@ -44,74 +107,354 @@ pub struct Renderer {
     data: passes::Data<{ conf::INTERMEDIATE_FORMAT }>,
     pathtracer_pipeline: passes::pathtracer::Pipeline,
     rasterizer_pipeline: passes::rasterizer::Pipeline,
+    taa_pipeline: passes::taa::Pipeline<{ conf::INTERMEDIATE_FORMAT }>,
+    denoise_pipeline: passes::denoise::Pipeline<{ conf::INTERMEDIATE_FORMAT }>,
+    bloom_pipeline: passes::bloom::Pipeline<{ conf::INTERMEDIATE_FORMAT }>,
+    exposure_pipeline: passes::exposure::Pipeline,
+    upscale_pipeline: passes::upscale::Pipeline<{ conf::INTERMEDIATE_FORMAT }>,
     tonemap_pipeline:
         passes::tonemap::Pipeline<{ conf::INTERMEDIATE_FORMAT }, { image::Format::Swapchain }>,
+    // tonemaps the targets for saving, presented frames go through the graphics pass above
+    tonemap_compute_pipeline: passes::tonemap_compute::Pipeline,
+
+    // resolved from the preference at creation, see `passes::rasterizer::DepthFormat`
+    depth_format: ash::vk::Format,
 
     swapchain: Swapchain,
+    present_retries: u32,
+    // replaces the bilinear filtering of frames to the window size
+    upscaler: Option<Box<dyn Upscaler>>,
+    // compiled shaders modified after this are picked up by `reload_shaders`
+    shaders_loaded: SystemTime,
+
+    // resolution of the render targets at a render scale of 1
+    resolution: (u32, u32),
+    render_scale: f32,
 
     // state
     use_pathtracer: bool,
+    denoise: bool,
+    taa: bool,
+    instanced_draws: bool,
+    animate_path_tracing: bool,
+    realtime: bool,
+    // jittered frames resolved into the current TAA or upscaler history
+    taa_frame: u32,
+    debug_view: DebugView,
+    mode: RenderMode,
+    ao_radius: f32,
+    ray_epsilon: f32,
+    max_bounces_after_rough: u32,
+    sampling_strategy: SamplingStrategy,
+    // luminance that path traced samples are clamped to in the shown image
+    max_sample_luminance: Option<f32>,
+    tonemap_mode: TonemapMode,
+    bloom: bool,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    exposure: f32,
+    auto_exposure: bool,
+    // carried over between frames so that auto exposure adapts gradually
+    adapted_exposure: f32,
+    clear_color: glam::Vec3,
+    samples_per_frame: u32,
+    // only this region of the target is path traced, the rest keeps its last contents
+    crop: Option<ash::vk::Rect2D>,
+    // side in pixels of the tiles the path tracer is dispatched in, None for a single dispatch
+    tile_size: Option<u32>,
     frame: u32,
+    needs_clearing: bool,
     state: SyncState,
 
     ctx: Context,
 }
 
+#[derive(Debug)]
 pub enum Error {
     NeedsRecreating,
+    // the device didn't finish a frame within the GPU timeout, it can't be used anymore and may still
+    // be busy so the renderer should not be dropped
+    Timeout,
+    DeviceLost,
+    // the scene exceeds what the renderer or the device supports
+    TooManyTextures { count: usize, max: u32 },
+    TooManyInstances { count: usize, max: u64 },
+    TooManyPrimitives { mesh: usize, count: usize, max: u64 },
+    TooManyTriangles { mesh: usize, count: u64, max: u64 },
+    // a scene built in code refers to something that was never added to it
+    InvalidScene(scene::builder::Error),
+    // built with the `ray_query_ao` feature for a device that can't trace rays from the rasterizer
+    RayQueryUnsupported,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NeedsRecreating => write!(f, "the swapchain needs recreating"),
+            Self::Timeout => write!(f, "the device did not respond within the GPU timeout"),
+            Self::DeviceLost => write!(f, "the device was lost"),
+            Self::TooManyTextures { count, max } => write!(
+                f,
+                "the scene has {count} textures, at most {max} are supported"
+            ),
+            Self::TooManyInstances { count, max } => write!(
+                f,
+                "the scene has {count} instances, the device supports at most {max}"
+            ),
+            Self::TooManyPrimitives { mesh, count, max } => write!(
+                f,
+                "mesh {mesh} has {count} primitives, the device supports at most {max}"
+            ),
+            Self::TooManyTriangles { mesh, count, max } => write!(
+                f,
+                "mesh {mesh} has {count} triangles, the device supports at most {max}"
+            ),
+            Self::InvalidScene(err) => write!(f, "the scene is invalid: {err}"),
+            Self::RayQueryUnsupported => write!(
+                f,
+                "the device does not support the ray queries that ambient occlusion is traced with"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<scene::builder::Error> for Error {
+    fn from(err: scene::builder::Error) -> Self {
+        Self::InvalidScene(err)
+    }
 }
 
 impl Renderer {
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
         name: &str,
         window: &impl HasWindowHandle,
         scene: scene::Scene,
         resolution: (u32, u32),
         camera: inputs::Camera,
-    ) -> Self {
+        build_preference: BuildPreference,
+        texture_loading: TextureLoading,
+        depth_format: DepthFormat,
+    ) -> Result<Self, Error> {
         firestorm::profile_method!(create);
 
         let ctx = Context::init(name, window);
 
-        let data = passes::Data::create(&ctx, scene, resolution, camera);
+        assert!(
+            ctx.supports_render_target(conf::INTERMEDIATE_FORMAT.into()),
+            "Intermediate format not supported as a render target"
+        );
+
+        let data = passes::Data::create(
+            &ctx,
+            scene,
+            resolution,
+            camera,
+            build_preference,
+            texture_loading,
+        )?;
+
+        Self::check_memory_budget(&ctx);
 
         let pathtracer_pipeline = passes::pathtracer::Pipeline::create(&ctx, &data);
-        let rasterizer_pipeline = passes::rasterizer::Pipeline::create(&ctx, &data);
-        let tonemap_pipeline = passes::tonemap::Pipeline::create(&ctx, &data);
+        let depth_format = passes::rasterizer::Pipeline::select_depth_format(&ctx, depth_format);
+        let rasterizer_pipeline = passes::rasterizer::Pipeline::create(&ctx, &data, depth_format);
+        let taa_pipeline = passes::taa::Pipeline::create(&ctx, &data);
+        let denoise_pipeline = passes::denoise::Pipeline::create(&ctx, &data);
+        let bloom_pipeline = passes::bloom::Pipeline::create(&ctx, &data);
+        let exposure_pipeline = passes::exposure::Pipeline::create(&ctx, &data);
+        let upscale_pipeline = passes::upscale::Pipeline::create(&ctx);
+        let tonemap_pipeline = passes::tonemap::Pipeline::create(&ctx, &upscale_pipeline.output);
+        let tonemap_compute_pipeline = passes::tonemap_compute::Pipeline::create(&ctx, &data);
 
         let swapchain = Swapchain::create(&ctx);
 
         let state = SyncState::create(&ctx);
 
-        Self {
+        Ok(Self {
             data,
             pathtracer_pipeline,
             rasterizer_pipeline,
+            taa_pipeline,
+            denoise_pipeline,
+            bloom_pipeline,
+            exposure_pipeline,
+            upscale_pipeline,
             tonemap_pipeline,
+            tonemap_compute_pipeline,
 
+            depth_format,
             swapchain,
+            present_retries: conf::DEFAULT_PRESENT_RETRIES,
+            upscaler: None,
+            shaders_loaded: SystemTime::now(),
+
+            resolution,
+            render_scale: 1.,
 
             frame: 0,
+            needs_clearing: true,
             use_pathtracer: true,
+            denoise: false,
+            taa: false,
+            instanced_draws: true,
+            animate_path_tracing: false,
+            realtime: false,
+            taa_frame: 0,
+            debug_view: DebugView::default(),
+            mode: RenderMode::default(),
+            ao_radius: conf::DEFAULT_AO_RADIUS,
+            ray_epsilon: conf::DEFAULT_RAY_EPSILON,
+            max_bounces_after_rough: conf::DEFAULT_MAX_BOUNCES_AFTER_ROUGH,
+            sampling_strategy: SamplingStrategy::default(),
+            max_sample_luminance: None,
+            tonemap_mode: TonemapMode::default(),
+            bloom: false,
+            bloom_threshold: conf::DEFAULT_BLOOM_THRESHOLD,
+            bloom_intensity: conf::DEFAULT_BLOOM_INTENSITY,
+            exposure: 1.,
+            auto_exposure: false,
+            adapted_exposure: 1.,
+            clear_color: glam::Vec3::ZERO,
+            samples_per_frame: 1,
+            crop: None,
+            tile_size: None,
             state,
 
             ctx,
-        }
+        })
+    }
+
+    // For scenes assembled in code, fails without touching the device if the scene is inconsistent
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_scene_builder(
+        name: &str,
+        window: &impl HasWindowHandle,
+        builder: scene::builder::SceneBuilder,
+        resolution: (u32, u32),
+        camera: inputs::Camera,
+        build_preference: BuildPreference,
+        texture_loading: TextureLoading,
+        depth_format: DepthFormat,
+    ) -> Result<Self, Error> {
+        let scene = builder.build()?;
+        Self::create(
+            name,
+            window,
+            scene,
+            resolution,
+            camera,
+            build_preference,
+            texture_loading,
+            depth_format,
+        )
     }
 
     pub fn render(&mut self) -> Result<(), Error> {
         firestorm::profile_method!(render);
 
+        self.draw_frame(true)?;
+
+        let presented = self.present();
+
+        self.frame += 1;
+        self.state.advance();
+
+        self.check_device()?;
+        presented
+    }
+
+    // Like `render`, but tonemaps the frame into an image of the caller's instead of presenting it,
+    // for embedding the renderer in another application which presents it itself. The image is
+    // written once the semaphores to wait on are signaled and those to signal are once it is done.
+    // Frames are fenced by the renderer itself, so `sync_info` can't have a fence. The frame is
+    // scaled to the image with linear filtering instead of any upscaler, and SDR encoded
+    pub fn render_to_image(
+        &mut self,
+        target: &ExternalImage,
+        sync_info: &SyncInfo,
+    ) -> Result<(), Error> {
+        firestorm::profile_method!(render_to_image);
+
+        assert!(
+            sync_info.fence.is_none(),
+            "Frames rendered to external images are fenced by the renderer"
+        );
+
+        self.draw_frame(false)?;
+
         unsafe {
             self.ctx
-                .wait_for_fences(
-                    slice::from_ref(&self.state.in_flight_fence()),
-                    true,
-                    u64::MAX,
-                )
-                .expect("Failed to wait for fence");
+                .reset_fences(slice::from_ref(&self.state.in_flight_fence()))
+                .expect("Failed to reset fence");
         }
 
+        // blitting encodes into sRGB formats, the others get the encoded values as they are
+        let encoding = if image::ColorSpace::of(target.format) == image::ColorSpace::Srgb {
+            tonemap::encoding::SDR
+        } else {
+            tonemap::encoding::SRGB
+        };
+        self.tonemap_compute_pipeline.run_into(
+            &self.ctx,
+            self.state.current_frame,
+            inputs::TonemapConstants {
+                encoding,
+                ..self.tonemap_constants()
+            },
+            target,
+            &SyncInfo {
+                wait_on: sync_info.wait_on.clone(),
+                signal_to: sync_info.signal_to.clone(),
+                fence: Some(self.state.in_flight_fence()),
+            },
+        );
+
+        self.frame += 1;
+        self.state.advance();
+
+        self.check_device()
+    }
+
+    // Everything up to tonemapping, the frames are upscaled to the window only for presenting them
+    fn draw_frame(&mut self, upscale: bool) -> Result<(), Error> {
+        firestorm::profile_method!(draw_frame);
+
+        self.ctx
+            .wait_for_fences_or_fail(slice::from_ref(&self.state.in_flight_fence()));
+        self.check_device()?;
+
+        // the passes using the scene descriptors have all finished by now, only the tonemapping of an
+        // earlier frame (which has descriptors of its own) can still be in flight
+        if self.data.stream_textures(&self.ctx) {
+            self.invalidate_accumulation();
+        }
+
+        // the histogram of the frame that last used this fence is complete
+        if self.auto_exposure {
+            self.adapted_exposure = self.exposure_pipeline.adapt(
+                &self.ctx,
+                self.state.current_frame,
+                self.adapted_exposure,
+            );
+        }
+
+        let use_taa = self.taa && !self.use_pathtracer;
+        // temporal upscalers rely on the jitter as much as TAA does
+        let use_jitter = use_taa || (self.upscaler.is_some() && !self.use_pathtracer);
+        let jitter = if use_jitter {
+            passes::taa::jitter(self.taa_frame, self.data.target.extent)
+        } else {
+            glam::Vec2::ZERO
+        };
+        // the path traced scene is only static while time stands still
+        let advance_time = !self.use_pathtracer || self.animate_path_tracing;
+        if self.use_pathtracer && self.animate_path_tracing {
+            self.invalidate_accumulation();
+        }
+        self.data.uniforms.begin_frame(jitter, advance_time);
         self.data.uniforms.update(&self.ctx);
 
         let sync_info = SyncInfo {
@@ -120,61 +463,980 @@ impl Renderer {
             fence: None,
         };
         if self.use_pathtracer {
-            self.pathtracer_pipeline
-                .run(&self.ctx, &self.data, self.frame, &sync_info);
+            self.run_pathtracer(&sync_info);
         } else {
-            self.rasterizer_pipeline
-                .run(&self.ctx, &self.data, &sync_info);
+            self.rasterizer_pipeline.run(
+                &self.ctx,
+                &self.data,
+                self.clear_color,
+                self.instanced_draws,
+                passes::rasterizer::Occlusion {
+                    radius: self.ao_radius,
+                    ray_epsilon: self.ray_epsilon,
+                },
+                &sync_info,
+            );
+
+            if use_taa {
+                self.taa_pipeline
+                    .run(&self.ctx, &self.data, self.taa_frame, &sync_info);
+            }
+        }
+
+        self.post_process(&sync_info);
+
+        if upscale {
+            self.upscale_pipeline.run(
+                &self.ctx,
+                &self.data,
+                self.rasterizer_pipeline.depth(),
+                self.use_pathtracer || self.taa_frame == 0,
+                self.upscaler.as_deref_mut(),
+            );
+        }
+        if use_jitter {
+            self.taa_frame += 1;
+        }
+
+        Ok(())
+    }
+
+    // Recreates an out of date swapchain and tries again, a few times at most since the window can
+    // keep changing underneath. A suboptimal swapchain still gets the frame and is recreated after
+    fn present(&mut self) -> Result<(), Error> {
+        firestorm::profile_method!(present);
+
+        for attempt in 0..=self.present_retries {
+            if attempt > 0 && (self.ctx.failure().is_some() || !self.recreate()) {
+                break;
+            }
+
+            match self.try_present() {
+                Status::Optimal => return Ok(()),
+                Status::Suboptimal => {
+                    return self.recreate().then_some(()).ok_or(Error::NeedsRecreating);
+                }
+                Status::OutOfDate => {}
+            }
         }
 
-        let (image_index, needs_recreating) = self
+        Err(Error::NeedsRecreating)
+    }
+
+    fn try_present(&self) -> Status {
+        firestorm::profile_method!(try_present);
+
+        let (image_index, acquired) = self
             .swapchain
             .get_next_image(&self.ctx, self.state.frame_available_semaphore());
+        // the semaphore is left unsignaled and the fence untouched, so the frame can be retried
+        if acquired == Status::OutOfDate {
+            return acquired;
+        }
         let image_index = image_index as usize;
 
-        let needs_recreating = needs_recreating || {
-            unsafe {
-                self.ctx
-                    .reset_fences(slice::from_ref(&self.state.in_flight_fence()))
-                    .expect("Failed to reset fence");
+        unsafe {
+            self.ctx
+                .reset_fences(slice::from_ref(&self.state.in_flight_fence()))
+                .expect("Failed to reset fence");
+        }
+
+        self.tonemap_pipeline.run(
+            &self.ctx,
+            image_index,
+            self.tonemap_constants(),
+            &SyncInfo {
+                wait_on: vec![self.state.frame_available_semaphore()],
+                signal_to: vec![self.state.frame_ready_semaphore()],
+                fence: Some(self.state.in_flight_fence()),
+            },
+            &self.swapchain.images[image_index],
+        );
+
+        // a rejected present still waits on the semaphore, and recreating waits for the fence
+        let presented = self.swapchain.present_to_when(
+            &self.ctx,
+            image_index,
+            slice::from_ref(&self.state.frame_ready_semaphore()),
+        );
+        acquired.max(presented)
+    }
+
+    // Times `render` recreates an out of date swapchain on its own before returning
+    // `Error::NeedsRecreating`, 0 leaves it all to the caller
+    pub fn set_present_retries(&mut self, retries: u32) {
+        self.present_retries = retries.min(conf::MAX_PRESENT_RETRIES);
+    }
+
+    // Accumulates path traced frames without presenting them until `num_samples` more samples per
+    // pixel have been taken, rounded up to whole frames. `progress` is called with the number of
+    // samples taken so far after every frame and can return false to stop early. Either way the
+    // accumulated image is post processed so that it can be saved with `render_to_file`.
+    pub fn render_samples(
+        &mut self,
+        num_samples: u32,
+        mut progress: impl FnMut(u32) -> bool,
+    ) -> Result<(), Error> {
+        firestorm::profile_method!(render_samples);
+
+        unsafe {
+            self.ctx.wait_idle();
+        }
+
+        // without fences every submission waits for its queue to go idle
+        let sync_info = SyncInfo {
+            wait_on: vec![],
+            signal_to: vec![],
+            fence: None,
+        };
+
+        let mut samples = 0;
+        while samples < num_samples {
+            self.accumulate_frame(&sync_info)?;
+
+            samples += self.samples_per_frame;
+            if !progress(samples.min(num_samples)) {
+                break;
             }
+        }
 
-            self.tonemap_pipeline.run(
-                &self.ctx,
-                image_index,
-                &SyncInfo {
-                    wait_on: vec![self.state.frame_available_semaphore()],
-                    signal_to: vec![self.state.frame_ready_semaphore()],
-                    fence: Some(self.state.in_flight_fence()),
-                },
-                &self.swapchain.images[image_index],
-            );
+        self.post_process(&sync_info);
+        self.check_device()
+    }
 
-            self.swapchain.present_to_when(
-                &self.ctx,
-                image_index,
-                slice::from_ref(&self.state.frame_ready_semaphore()),
-            )
+    // Like `render_samples`, but stops as soon as the estimated relative error of the accumulated
+    // image falls below `threshold`, or at the latest after `max_samples` samples per pixel. The
+    // error is checked every few frames and returned along with the samples taken.
+    pub fn render_until_converged(
+        &mut self,
+        threshold: f32,
+        max_samples: u32,
+    ) -> Result<(u32, f32), Error> {
+        firestorm::profile_method!(render_until_converged);
+
+        unsafe {
+            self.ctx.wait_idle();
+        }
+
+        let sync_info = SyncInfo {
+            wait_on: vec![],
+            signal_to: vec![],
+            fence: None,
         };
 
+        let mut samples = 0;
+        let mut error = f32::INFINITY;
+        while samples < max_samples {
+            self.accumulate_frame(&sync_info)?;
+            samples += self.samples_per_frame;
+
+            // the last frame is checked too so that the error returned is that of the image
+            if self.frame.is_multiple_of(conf::CONVERGENCE_CHECK_INTERVAL) || samples >= max_samples
+            {
+                error = self.relative_error();
+                if error < threshold {
+                    break;
+                }
+            }
+        }
+
+        self.post_process(&sync_info);
+        self.check_device()?;
+        Ok((samples.min(max_samples), error))
+    }
+
+    fn accumulate_frame(&mut self, sync_info: &SyncInfo) -> Result<(), Error> {
+        self.data.uniforms.begin_frame(glam::Vec2::ZERO, false);
+        self.data.uniforms.update(&self.ctx);
+        self.run_pathtracer(sync_info);
         self.frame += 1;
-        self.state.advance();
+        self.check_device()
+    }
+
+    // Standard error of the accumulated luminance relative to it, over the whole image. Brighter
+    // pixels weigh more so that noise in nearly black ones does not dominate
+    fn relative_error(&self) -> f32 {
+        firestorm::profile_method!(relative_error);
+
+        if self.frame < 2 {
+            return f32::INFINITY;
+        }
+        let frames = f64::from(self.frame);
+
+        let moments = self.data.moments.read_back(&self.ctx);
+        let (error, mean) = moments.pixels().fold((0., 0.), |(error, mean), pixel| {
+            let [m1, m2, ..] = pixel.0.map(f64::from);
+            // unbiased variance of the frames divided by their number, that of their mean
+            let variance = m1.mul_add(-m1, m2).max(0.) / (frames - 1.);
+            (error + variance.sqrt(), mean + m1)
+        });
+
+        if mean > 0. {
+            (error / mean) as f32
+        } else {
+            0.
+        }
+    }
+
+    // Waits on the device that take longer than this report `Error::Timeout` instead of hanging, for
+    // example on an endless loop in a shader
+    pub fn set_gpu_timeout(&self, timeout: Duration) {
+        self.ctx.timeout.set(timeout);
+    }
+
+    fn check_device(&self) -> Result<(), Error> {
+        match self.ctx.failure() {
+            None => Ok(()),
+            Some(ash::vk::Result::TIMEOUT) => Err(Error::Timeout),
+            Some(_) => Err(Error::DeviceLost),
+        }
+    }
+
+    fn run_pathtracer(&mut self, sync_info: &SyncInfo) {
+        self.pathtracer_pipeline.run(
+            &self.ctx,
+            &self.data,
+            &self.pathtracer_constants(),
+            self.crop,
+            self.tile_size,
+            self.needs_clearing,
+            sync_info,
+        );
+        self.needs_clearing = false;
+    }
+
+    fn pathtracer_constants(&self) -> inputs::PathtracerConstants {
+        inputs::PathtracerConstants {
+            frame: self.frame,
+            debug_view: self.debug_view.into(),
+            samples_per_frame: self.samples_per_frame,
+            mode: self.mode.into(),
+            ao_radius: self.ao_radius,
+            ray_epsilon: self.ray_epsilon,
+            realtime: self.realtime.into(),
+            max_bounces_after_rough: self.max_bounces_after_rough,
+            sampling_strategy: self.sampling_strategy.into(),
+            max_sample_luminance: self.max_sample_luminance.unwrap_or_default(),
+            // filled in by the pass from the crop
+            ..Default::default()
+        }
+    }
 
-        (!needs_recreating)
-            .then_some(())
-            .ok_or(Error::NeedsRecreating)
+    // Everything between the rendered target and the tonemap input
+    fn post_process(&self, sync_info: &SyncInfo) {
+        // the auxiliary features are only produced by the path tracer
+        self.denoise_pipeline.run(
+            &self.ctx,
+            &self.data,
+            self.denoise && self.use_pathtracer,
+            sync_info,
+        );
+
+        if self.bloom {
+            self.bloom_pipeline.run(
+                &self.ctx,
+                &self.data,
+                self.bloom_threshold,
+                self.bloom_intensity,
+                sync_info,
+            );
+        }
+
+        if self.auto_exposure {
+            self.exposure_pipeline
+                .run(&self.ctx, &self.data, self.state.current_frame, sync_info);
+        }
+    }
+
+    fn tonemap_constants(&self) -> inputs::TonemapConstants {
+        // bounce counts are shown as a heatmap regardless of the selected tonemap
+        let mode = if self.use_pathtracer && self.debug_view == DebugView::Bounces {
+            TonemapMode::Heatmap
+        } else {
+            self.tonemap_mode
+        };
+
+        inputs::TonemapConstants {
+            mode: mode.into(),
+            exposure: if self.auto_exposure {
+                self.adapted_exposure
+            } else {
+                self.exposure
+            },
+            encoding: passes::tonemap::encoding(self.ctx.surface.config.surface_format.color_space),
+            paper_white: conf::HDR_PAPER_WHITE,
+        }
     }
 
     pub fn update_camera(&mut self, camera: inputs::Camera) {
         self.data.uniforms.update_camera(camera);
-        self.frame = 0;
+        self.invalidate_accumulation();
+    }
+
+    // Stops blurring along the last camera update, for when the camera jumps or comes to rest
+    pub fn reset_shutter(&mut self) {
+        self.data.uniforms.reset_shutter();
+        self.invalidate_accumulation();
+    }
+
+    // Applied to every camera from here on, including ones given to `update_camera`. The rasterizer
+    // projects the same way
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.data
+            .uniforms
+            .set_projection(projection, self.aspect_ratio());
+        self.invalidate_accumulation();
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.resolution.0 as f32 / self.resolution.1 as f32
+    }
+
+    // Saves the current camera alongside the scene asset, it is picked up the next time the scene is
+    // loaded
+    pub fn export_camera(&self, scene_file: impl AsRef<Path>) {
+        scene::io::save_camera(&self.data.uniforms.camera(), scene_file);
     }
 
     pub fn toggle_renderer(&mut self) {
-        self.use_pathtracer = !self.use_pathtracer;
+        self.set_use_pathtracer(!self.use_pathtracer);
+    }
+
+    pub fn set_use_pathtracer(&mut self, use_pathtracer: bool) {
+        self.use_pathtracer = use_pathtracer;
+        self.taa_frame = 0;
+        self.invalidate_accumulation();
+    }
+
+    // The render target is zeroed at the start of the next path traced frame
+    pub fn clear_accumulation(&mut self) {
+        self.invalidate_accumulation();
+    }
+
+    // Every change that affects the traced radiance goes through here, otherwise the old and new
+    // images blend together. Changes that only affect post-processing or presentation must not, they
+    // would throw away converged samples for nothing.
+    fn invalidate_accumulation(&mut self) {
+        self.frame = 0;
+        self.needs_clearing = true;
+    }
+
+    // Post-processing only, the accumulated samples are kept
+    pub const fn set_denoise(&mut self, denoise: bool) {
+        self.denoise = denoise;
+    }
+
+    // Shows only the samples of the current frame instead of accumulating them, for interacting with
+    // the scene. The denoiser still applies. Accumulation restarts once it is turned off
+    pub fn set_realtime(&mut self, realtime: bool) {
+        if self.realtime && !realtime {
+            self.invalidate_accumulation();
+        }
+        self.realtime = realtime;
+    }
+
+    // Takes over resolving frames from the render resolution (see `set_render_scale`) to the window
+    // size, None goes back to bilinear filtering. The rasterizer is jittered for it like with TAA
+    pub fn set_upscaler(&mut self, upscaler: Option<Box<dyn Upscaler>>) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.upscaler = upscaler;
+        self.taa_frame = 0;
+    }
+
+    // Temporal antialiasing of the rasterizer, the path tracer already antialiases by accumulating
+    pub const fn set_taa(&mut self, taa: bool) {
+        self.taa = taa;
+        self.taa_frame = 0;
+    }
+
+    // Lets time run while path tracing, which restarts the accumulation every frame since the scene
+    // is no longer static. The rasterizer always animates
+    pub const fn set_animate_path_tracing(&mut self, animate_path_tracing: bool) {
+        self.animate_path_tracing = animate_path_tracing;
+    }
+
+    // Draws all instances of a mesh at once, otherwise every instance is drawn on its own. Only the
+    // rasterizer's CPU overhead differs
+    pub const fn set_instanced_draws(&mut self, instanced_draws: bool) {
+        self.instanced_draws = instanced_draws;
+    }
+
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+        self.invalidate_accumulation();
+    }
+
+    pub fn set_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+        self.invalidate_accumulation();
+    }
+
+    // Occlusion rays only look this far (in world units) for blockers
+    pub fn set_ao_radius(&mut self, ao_radius: f32) {
+        self.ao_radius = ao_radius;
+        self.invalidate_accumulation();
+    }
+
+    // Hits closer than this to the origin of a ray are ignored and bounces start this far off the
+    // surface. Too small and surfaces shadow themselves, too large and light leaks through thin
+    // geometry. When relative it is a fraction of the diagonal of the scene's bounding box.
+    pub fn set_ray_epsilon(&mut self, ray_epsilon: f32, relative: bool) {
+        self.ray_epsilon = if relative {
+            ray_epsilon * self.data.world.info.host.bounding_box.size().length()
+        } else {
+            ray_epsilon
+        };
+        self.invalidate_accumulation();
+    }
+
+    // Paths end this many bounces after their first diffuse or rough glossy one. Long chains of
+    // bounces after those are where the worst caustic fireflies come from, cutting them short darkens
+    // indirect lighting slightly in exchange. Off by default, as the image then no longer matches
+    // the reference. Paths are never longer than the shaders' own maximum bounce count, which large
+    // enough values leave as the only limit.
+    pub fn set_max_bounces_after_rough(&mut self, max_bounces_after_rough: u32) {
+        self.max_bounces_after_rough = max_bounces_after_rough;
+        self.invalidate_accumulation();
+    }
+
+    // How diffuse bounces pick their direction. Both converge to the same image, uniform sampling
+    // only more slowly
+    pub fn set_sampling_strategy(&mut self, sampling_strategy: SamplingStrategy) {
+        self.sampling_strategy = sampling_strategy;
+        self.invalidate_accumulation();
+    }
+
+    // Scales path traced samples down to this luminance before they are accumulated into the shown
+    // image, which trades the fireflies of rare bright paths for bias. The unbiased reference image
+    // is accumulated alongside either way, see `save_reference`
+    pub fn set_max_sample_luminance(&mut self, max_sample_luminance: Option<f32>) {
+        self.max_sample_luminance = max_sample_luminance.filter(|&luminance| luminance > 0.);
+        self.invalidate_accumulation();
+    }
+
+    // Glow around bright parts of the image, applied before tonemapping. Post-processing only like the
+    // other bloom and exposure settings
+    pub const fn set_bloom(&mut self, bloom: bool) {
+        self.bloom = bloom;
+    }
+
+    // Radiance above which pixels start to contribute to bloom
+    pub const fn set_bloom_threshold(&mut self, bloom_threshold: f32) {
+        self.bloom_threshold = bloom_threshold;
+    }
+
+    pub const fn set_bloom_intensity(&mut self, bloom_intensity: f32) {
+        self.bloom_intensity = bloom_intensity;
+    }
+
+    // Linear scale of the displayed radiance, used while auto exposure is off
+    pub const fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    // Adapts the exposure over a few frames towards one that maps the average luminance to middle grey
+    pub const fn set_auto_exposure(&mut self, auto_exposure: bool) {
+        self.auto_exposure = auto_exposure;
+    }
+
+    // Only changes presentation, the accumulated image is kept
+    pub const fn set_tonemap_mode(&mut self, tonemap_mode: TonemapMode) {
+        self.tonemap_mode = tonemap_mode;
+    }
+
+    // Shown wherever no geometry is hit. Path traced escaping rays pick it up as lighting too, so
+    // only changing it there restarts the accumulation
+    pub fn set_background_color(&mut self, background_color: glam::Vec3) {
+        self.clear_color = background_color;
+        if self.data.uniforms.update_background_color(background_color) {
+            self.invalidate_accumulation();
+        }
+    }
+
+    // Blurs the path traced image along the last camera update, such as the steps of a camera path.
+    // Samples are spread over this fraction of the move leading up to the current camera
+    pub fn set_shutter_time(&mut self, shutter_time: f32) {
+        if self
+            .data
+            .uniforms
+            .update_shutter_time(shutter_time.clamp(0., 1.))
+        {
+            self.invalidate_accumulation();
+        }
+    }
+
+    // Renders each eye into one half of the image, the left one on the left, for cardboard style
+    // viewing. The eyes are spread sideways from the camera and see the middle half of its view
+    pub fn set_stereo(&mut self, ipd: Option<Ipd>) {
+        if self.data.uniforms.update_stereo(ipd) {
+            self.invalidate_accumulation();
+        }
+    }
+
+    // Scales the radiance of the environment, which is the background color
+    pub fn set_env_intensity(&mut self, env_intensity: f32) {
+        if self
+            .data
+            .uniforms
+            .update_env_intensity(env_intensity.max(0.))
+        {
+            self.invalidate_accumulation();
+        }
+    }
+
+    // Splits tracing into tiles submitted one after the other, so that no single submission runs
+    // long enough for the OS to reset the device. Only the dispatches change, the image is the same
+    pub fn set_tile_size(&mut self, tile_size: Option<u32>) {
+        self.tile_size = tile_size.map(|tile_size| tile_size.max(1));
+    }
+
+    pub fn set_samples_per_frame(&mut self, samples_per_frame: u32) {
+        self.samples_per_frame = samples_per_frame.max(1);
+        self.invalidate_accumulation();
+    }
+
+    // Offset and size in pixels of the render targets, None traces the whole image again. Pixels
+    // outside the region are left as they are for context
+    pub fn set_crop(&mut self, crop: Option<(glam::UVec2, glam::UVec2)>) {
+        self.crop = crop.map(|(offset, size)| ash::vk::Rect2D {
+            offset: ash::vk::Offset2D {
+                x: offset.x.min(i32::MAX as u32) as _,
+                y: offset.y.min(i32::MAX as u32) as _,
+            },
+            extent: ash::vk::Extent2D {
+                width: size.x,
+                height: size.y,
+            },
+        });
+        // the first frame overwrites the region, so there is nothing to clear
         self.frame = 0;
     }
 
+    pub fn update_instance_transform(&mut self, index: usize, transform: glam::Mat4) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data
+            .world
+            .update_instance_transform(&self.ctx, index, transform);
+        self.invalidate_accumulation();
+    }
+
+    // Applies a pose sampled from the animation of the scene, see `scene::Scene::sample_animation`
+    pub fn set_pose(&mut self, pose: &scene::animation::Pose) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data.world.set_pose(&self.ctx, pose);
+        self.invalidate_accumulation();
+    }
+
+    // Hides the instance from the kinds of rays left out of the mask, eg. from reflections with
+    // `!scene::ray_mask::INDIRECT`. The rasterizer still draws it
+    pub fn set_instance_mask(&mut self, index: usize, mask: u8) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data.world.set_instance_mask(&self.ctx, index, mask);
+        self.invalidate_accumulation();
+    }
+
+    pub fn materials(&self) -> &[scene::Material] {
+        self.data.world.materials()
+    }
+
+    pub fn set_material(&mut self, index: usize, material: scene::Material) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data.world.update_material(&self.ctx, index, material);
+        self.invalidate_accumulation();
+    }
+
+    // Draws the instance with one of the materials of the scene in place of those of its primitives,
+    // None goes back to them
+    pub fn set_instance_material(&mut self, index: usize, material: Option<u32>) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data
+            .world
+            .set_instance_material(&self.ctx, index, material);
+        self.rasterizer_pipeline.rebatch(&self.data.world.info.host);
+        self.invalidate_accumulation();
+    }
+
+    // The lights of the scene with the names of their nodes, see `World::lights`
+    pub fn lights(&self) -> impl Iterator<Item = (&str, LightSettings)> {
+        self.data.world.lights()
+    }
+
+    // Turns the light off and back on without reloading the scene, eg. for soloing a light
+    pub fn set_light_enabled(&mut self, index: usize, enabled: bool) {
+        let settings = LightSettings {
+            enabled,
+            ..self.data.world.light_settings()[index]
+        };
+        self.set_light_settings(index, settings);
+    }
+
+    // Scales the intensity the light was loaded with, 1 restores it
+    pub fn set_light_intensity(&mut self, index: usize, intensity: f32) {
+        let settings = LightSettings {
+            intensity,
+            ..self.data.world.light_settings()[index]
+        };
+        self.set_light_settings(index, settings);
+    }
+
+    fn set_light_settings(&mut self, index: usize, settings: LightSettings) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data.world.update_light(&self.ctx, index, settings);
+        self.invalidate_accumulation();
+    }
+
+    // Path traces only the instances set in the mask (one entry per instance), None brings back all
+    // of them. Meant for culling large scenes, the rasterizer still draws everything
+    pub fn set_visible_instances(&mut self, mask: Option<&[bool]>) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data.set_visible_instances(&self.ctx, mask);
+        self.invalidate_accumulation();
+    }
+
+    // The surface under a point of the window, in pixels from the top left. Traced against the same
+    // instances as the path tracer, whichever renderer is in use
+    #[allow(clippy::cast_sign_loss)]
+    pub fn pick(&self, x: f32, y: f32) -> Option<PickResult> {
+        firestorm::profile_method!(pick);
+
+        unsafe {
+            self.ctx.wait_idle();
+        }
+
+        // the targets may be rendered at a different resolution than the window
+        let window = self.ctx.surface.config.extent;
+        let target = self.data.target.extent;
+        let scaled = |coord: f32, window: u32, target: u32| {
+            ((coord * target as f32 / window as f32) as u32).min(target - 1)
+        };
+        let pixel = glam::uvec2(
+            scaled(x.max(0.), window.width, target.width),
+            scaled(y.max(0.), window.height, target.height),
+        );
+
+        self.pathtracer_pipeline
+            .pick(&self.ctx, &self.data, &self.pathtracer_constants(), pixel)
+            .map(|hit| PickResult {
+                instance: self.data.world.traced_instance(hit.instance),
+                ..hit
+            })
+    }
+
+    // Saves the accumulated image tonemapped like it is presented, at the resolution of the targets
+    // and encoded as 8-bit sRGB
+    pub fn render_to_file(&self, path: impl AsRef<Path>) -> ::image::ImageResult<()> {
+        firestorm::profile_method!(render_to_file);
+
+        unsafe {
+            self.ctx.wait_idle();
+        }
+
+        let constants = inputs::TonemapConstants {
+            encoding: tonemap::encoding::SRGB,
+            ..self.tonemap_constants()
+        };
+        self.tonemap_compute_pipeline.run(
+            &self.ctx,
+            constants,
+            &SyncInfo {
+                wait_on: vec![],
+                signal_to: vec![],
+                fence: None,
+            },
+        );
+
+        self.tonemap_compute_pipeline
+            .output()
+            .read_back_encoded(&self.ctx)
+            .save(path)
+    }
+
+    // The accumulated path traced radiance as it is, without clamping, post processing or tonemapping.
+    // Needs a float format such as EXR to keep its range
+    pub fn save_reference(&self, path: impl AsRef<Path>) -> ::image::ImageResult<()> {
+        firestorm::profile_method!(save_reference);
+
+        unsafe {
+            self.ctx.wait_idle();
+        }
+
+        ::image::DynamicImage::from(self.data.reference.read_back(&self.ctx))
+            .into_rgb32f()
+            .save(path)
+    }
+
+    // The radiance of the render target in linear floats, before post processing and tonemapping and
+    // with values above 1 kept. Its alpha is always 1 and left out
+    pub fn save_exr(&self, path: impl AsRef<Path>) -> exr::error::UnitResult {
+        firestorm::profile_method!(save_exr);
+
+        unsafe {
+            self.ctx.wait_idle();
+        }
+
+        let image = self.data.target.read_back(&self.ctx);
+        exr::prelude::write_rgb_file(
+            path,
+            image.width() as usize,
+            image.height() as usize,
+            |x, y| {
+                let [r, g, b, _] = image.get_pixel(x as u32, y as u32).0;
+                (r, g, b)
+            },
+        )
+    }
+
+    // Makes room for streaming in more geometry without rebuilding the world
+    pub fn reserve_geometry(&mut self, num_vertices: usize, num_indices: usize) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data
+            .world
+            .reserve_geometry(&self.ctx, num_vertices, num_indices);
+    }
+
+    // Anisotropic filtering of the scene textures, clamped to the device limit. Values of 1 or less
+    // fall back to trilinear filtering
+    pub fn set_max_anisotropy(&mut self, max_anisotropy: f32) {
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.data.set_max_anisotropy(&self.ctx, max_anisotropy);
+        self.invalidate_accumulation();
+    }
+
+    // Renders at a fraction (or multiple) of the full resolution, the tonemap pass filters the result
+    // to the swapchain size. Accumulation restarts since the targets are recreated
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        firestorm::profile_method!(set_render_scale);
+
+        self.render_scale = render_scale.clamp(conf::MIN_RENDER_SCALE, conf::MAX_RENDER_SCALE);
+        self.resize_targets();
+    }
+
+    // Independent of the window, the swapchain keeps following the window size and the tonemap pass
+    // filters the result to it. The render scale still applies on top
+    pub fn set_resolution(&mut self, resolution: (u32, u32)) {
+        firestorm::profile_method!(set_resolution);
+
+        self.resolution = (resolution.0.max(1), resolution.1.max(1));
+        self.data.uniforms.set_aspect_ratio(self.aspect_ratio());
+        self.resize_targets();
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    fn resize_targets(&mut self) {
+        let scaled = |size: u32| ((size as f32 * self.render_scale).round() as u32).max(1);
+        let resolution = (scaled(self.resolution.0), scaled(self.resolution.1));
+
+        unsafe {
+            self.ctx.wait_idle();
+
+            self.tonemap_compute_pipeline.destroy_with(&self.ctx);
+            self.tonemap_pipeline.destroy_with(&self.ctx);
+            self.exposure_pipeline.destroy_with(&self.ctx);
+            self.bloom_pipeline.destroy_with(&self.ctx);
+            self.denoise_pipeline.destroy_with(&self.ctx);
+            self.taa_pipeline.destroy_with(&self.ctx);
+            self.rasterizer_pipeline.destroy_with(&self.ctx);
+        }
+
+        self.data.resize_targets(&self.ctx, resolution);
+
+        // every pass besides the path tracer holds images or state sized to the targets
+        self.rasterizer_pipeline =
+            passes::rasterizer::Pipeline::create(&self.ctx, &self.data, self.depth_format);
+        self.taa_pipeline = passes::taa::Pipeline::create(&self.ctx, &self.data);
+        self.denoise_pipeline = passes::denoise::Pipeline::create(&self.ctx, &self.data);
+        self.bloom_pipeline = passes::bloom::Pipeline::create(&self.ctx, &self.data);
+        self.exposure_pipeline = passes::exposure::Pipeline::create(&self.ctx, &self.data);
+        self.tonemap_pipeline =
+            passes::tonemap::Pipeline::create(&self.ctx, &self.upscale_pipeline.output);
+        self.tonemap_compute_pipeline =
+            passes::tonemap_compute::Pipeline::create(&self.ctx, &self.data);
+
+        self.taa_frame = 0;
+        self.invalidate_accumulation();
+    }
+
+    // Recreates the pipelines of the passes whose SPIR-V changed on disk since it was last loaded, for
+    // iterating on shaders without restarting. The build script compiles the GLSL, so rebuilding the
+    // crate after editing it is what changes the SPIR-V. Returns whether any pipeline was recreated
+    pub fn reload_shaders(&mut self) -> bool {
+        firestorm::profile_method!(reload_shaders);
+
+        let since = self.shaders_loaded;
+        let pathtracer = Self::shaders_changed(passes::pathtracer::shaders(), since);
+        let rasterizer = Self::shaders_changed(passes::rasterizer::shaders(), since);
+        let taa = Self::shaders_changed(passes::taa::shaders(), since);
+        let denoise = Self::shaders_changed(passes::denoise::shaders(), since);
+        let bloom = Self::shaders_changed(passes::bloom::shaders(), since);
+        let exposure = Self::shaders_changed(passes::exposure::shaders(), since);
+        // both tonemap passes include the same operators
+        let tonemap = Self::shaders_changed(
+            passes::tonemap::shaders().chain(passes::tonemap_compute::shaders()),
+            since,
+        );
+        if !(pathtracer || rasterizer || taa || denoise || bloom || exposure || tonemap) {
+            return false;
+        }
+
+        unsafe {
+            self.ctx.wait_idle();
+        }
+        self.shaders_loaded = SystemTime::now();
+
+        if pathtracer {
+            unsafe { self.pathtracer_pipeline.destroy_with(&self.ctx) };
+            self.pathtracer_pipeline = passes::pathtracer::Pipeline::create(&self.ctx, &self.data);
+        }
+        if rasterizer {
+            unsafe { self.rasterizer_pipeline.destroy_with(&self.ctx) };
+            self.rasterizer_pipeline =
+                passes::rasterizer::Pipeline::create(&self.ctx, &self.data, self.depth_format);
+        }
+        if taa {
+            unsafe { self.taa_pipeline.destroy_with(&self.ctx) };
+            self.taa_pipeline = passes::taa::Pipeline::create(&self.ctx, &self.data);
+        }
+        if denoise {
+            unsafe { self.denoise_pipeline.destroy_with(&self.ctx) };
+            self.denoise_pipeline = passes::denoise::Pipeline::create(&self.ctx, &self.data);
+        }
+        if bloom {
+            unsafe { self.bloom_pipeline.destroy_with(&self.ctx) };
+            self.bloom_pipeline = passes::bloom::Pipeline::create(&self.ctx, &self.data);
+        }
+        if exposure {
+            unsafe { self.exposure_pipeline.destroy_with(&self.ctx) };
+            self.exposure_pipeline = passes::exposure::Pipeline::create(&self.ctx, &self.data);
+        }
+        if tonemap {
+            unsafe { self.tonemap_pipeline.destroy_with(&self.ctx) };
+            self.tonemap_pipeline =
+                passes::tonemap::Pipeline::create(&self.ctx, &self.upscale_pipeline.output);
+            unsafe { self.tonemap_compute_pipeline.destroy_with(&self.ctx) };
+            self.tonemap_compute_pipeline =
+                passes::tonemap_compute::Pipeline::create(&self.ctx, &self.data);
+        }
+
+        // the recreated passes start over without any history
+        self.taa_frame = 0;
+        self.invalidate_accumulation();
+        true
+    }
+
+    fn shaders_changed(mut shaders: impl Iterator<Item = &'static str>, since: SystemTime) -> bool {
+        shaders.any(|shader| {
+            fs::metadata(shader)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified > since)
+        })
+    }
+
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) -> bool {
+        self.ctx
+            .surface
+            .config
+            .select_present_mode(present_mode.into());
+        self.recreate()
+    }
+
+    // Presents scRGB or HDR10 instead of clamping to SDR, if the surface supports either. Returns
+    // whether the output is HDR, it stays SDR otherwise
+    pub fn set_hdr_output(&mut self, hdr: bool) -> bool {
+        let is_hdr = self.ctx.surface.config.select_hdr_output(hdr);
+        self.recreate();
+        is_hdr
+    }
+
+    pub fn memory_budget(&self) -> MemoryBudget {
+        self.ctx.memory_budget()
+    }
+
+    pub fn stats(&self) -> RenderStats {
+        firestorm::profile_method!(stats);
+
+        let scene_info = &self.data.world.info.host;
+        RenderStats {
+            accumulated_samples: if self.realtime {
+                self.samples_per_frame
+            } else {
+                self.frame * self.samples_per_frame
+            },
+            use_pathtracer: self.use_pathtracer,
+            accel_build_time: self.data.world.tracing.build_time(),
+            num_triangles: scene_info
+                .primitive_sizes
+                .iter()
+                .map(|size| u64::from(size.count()))
+                .sum(),
+            num_instances: scene_info.instances.len(),
+            accel_memory: self.data.world.tracing.memory_report(),
+            vram_used: self
+                .memory_budget()
+                .device_local()
+                .map(|heap| heap.usage)
+                .sum(),
+        }
+    }
+
+    fn check_memory_budget(ctx: &Context) {
+        firestorm::profile_method!(check_memory_budget);
+
+        let (allocated, budget) = ctx
+            .memory_budget()
+            .device_local()
+            .fold((0, 0), |(allocated, budget), heap| {
+                (allocated + heap.allocation_bytes, budget + heap.budget)
+            });
+
+        if allocated as f64 > conf::MEMORY_BUDGET_WARNING_FRACTION * budget as f64 {
+            eprintln!(
+                "Warning: scene data uses {} MiB of the {} MiB device local memory budget",
+                allocated >> 20,
+                budget >> 20,
+            );
+        }
+    }
+
+    // Follows the window to its new size in one call. Windows without an area, such as minimized
+    // ones, are left alone until they have one again. Returns whether the swapchain matches the
+    // window, the render targets keep the resolution they were given
+    pub fn resize(&mut self, window_size: (u32, u32)) -> bool {
+        firestorm::profile_method!(resize);
+
+        if window_size.0 == 0 || window_size.1 == 0 {
+            return false;
+        }
+
+        self.ctx
+            .surface
+            .config
+            .set_window_extent(ash::vk::Extent2D {
+                width: window_size.0,
+                height: window_size.1,
+            });
+        self.recreate()
+    }
+
     pub fn recreate(&mut self) -> bool {
         firestorm::profile_method!(recreate);
 
@@ -187,8 +1449,16 @@ impl Renderer {
         if is_valid {
             unsafe {
                 self.swapchain.destroy_with(&self.ctx);
+                self.tonemap_pipeline.destroy_with(&self.ctx);
+                self.upscale_pipeline.destroy_with(&self.ctx);
             }
             self.swapchain = Swapchain::create(&self.ctx);
+
+            // the frames are upscaled to the window size
+            self.upscale_pipeline = passes::upscale::Pipeline::create(&self.ctx);
+            self.tonemap_pipeline =
+                passes::tonemap::Pipeline::create(&self.ctx, &self.upscale_pipeline.output);
+            self.taa_frame = 0;
         }
 
         is_valid
@@ -206,7 +1476,13 @@ impl Drop for Renderer {
 
             self.swapchain.destroy_with(&self.ctx);
             self.tonemap_pipeline.destroy_with(&self.ctx);
+            self.upscale_pipeline.destroy_with(&self.ctx);
 
+            self.tonemap_compute_pipeline.destroy_with(&self.ctx);
+            self.exposure_pipeline.destroy_with(&self.ctx);
+            self.bloom_pipeline.destroy_with(&self.ctx);
+            self.denoise_pipeline.destroy_with(&self.ctx);
+            self.taa_pipeline.destroy_with(&self.ctx);
             self.rasterizer_pipeline.destroy_with(&self.ctx);
             self.pathtracer_pipeline.destroy_with(&self.ctx);
             self.data.destroy_with(&self.ctx);
@@ -220,6 +1496,14 @@ impl<T: Destroy<C>, C> Destroy<C> for Vec<T> {
     }
 }
 
+impl<T: Destroy<C>, C> Destroy<C> for Option<T> {
+    unsafe fn destroy_with(&mut self, ctx: &C) {
+        if let Some(inner) = self {
+            inner.destroy_with(ctx);
+        }
+    }
+}
+
 impl<T: Destroy<C> + ?Sized, C> Destroy<C> for Box<T> {
     unsafe fn destroy_with(&mut self, ctx: &C) {
         self.deref_mut().destroy_with(ctx);
@@ -30,6 +30,16 @@ pub mod purpose {
         }
     }
 
+    pub fn readback() -> vk_mem::AllocationCreateInfo {
+        vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::Auto,
+            flags: vk_mem::AllocationCreateFlags::HOST_ACCESS_RANDOM
+                | vk_mem::AllocationCreateFlags::MAPPED,
+            priority: super::Priority::Medium.into(),
+            ..Default::default()
+        }
+    }
+
     pub fn staging() -> vk_mem::AllocationCreateInfo {
         vk_mem::AllocationCreateInfo {
             usage: vk_mem::MemoryUsage::Auto,
@@ -0,0 +1,339 @@
+use std::slice;
+
+use ash::vk;
+
+use shared::inputs;
+
+use crate::{
+    commands::Commands,
+    context::Context,
+    descriptors::{DescriptorSetBuilder, Descriptors},
+    image, memory, pipeline,
+    sync_info::SyncInfo,
+    Destroy,
+};
+
+mod conf {
+    pub const NAME: &str = "Bloom";
+    pub const SHADER_COMP: &str = env!("bloom.comp.glsl");
+    pub const WORKGROUP_SIZE: u32 = 16;
+    // each level is half the size of the previous one, starting at half the target size
+    pub const NUM_LEVELS: usize = 5;
+}
+
+pub fn shaders() -> impl Iterator<Item = &'static str> {
+    [conf::SHADER_COMP].into_iter()
+}
+
+// must match bloom.comp.glsl
+mod mode {
+    pub const PREFILTER: u32 = 0;
+    pub const DOWNSAMPLE: u32 = 1;
+    pub const UPSAMPLE: u32 = 2;
+    pub const COMPOSITE: u32 = 3;
+}
+
+pub struct Data<const FORMAT: image::Format> {
+    descriptors: Descriptors,
+    levels: Vec<image::Image<FORMAT>>,
+}
+
+pub struct Pipeline<const FORMAT: image::Format> {
+    data: Data<FORMAT>,
+    pipeline: pipeline::Pipeline<1>,
+}
+
+impl<const FORMAT: image::Format> Data<FORMAT> {
+    pub fn create(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let descriptors = Self::create_descriptors(ctx);
+
+        let commands = Commands::begin_on_queue(
+            ctx,
+            format!("{} - Initialization", conf::NAME),
+            ctx.queues.graphics(),
+        );
+
+        let levels = (1..=conf::NUM_LEVELS)
+            .map(|level| {
+                let info = vk::ImageCreateInfo {
+                    extent: vk::Extent3D {
+                        width: (data.denoised.extent.width >> level).max(1),
+                        height: (data.denoised.extent.height >> level).max(1),
+                        depth: 1,
+                    },
+                    usage: vk::ImageUsageFlags::STORAGE,
+                    ..Default::default()
+                };
+                image::Image::create(
+                    ctx,
+                    commands.buffer,
+                    format!("{} Level {level}", conf::NAME),
+                    &info,
+                    &memory::purpose::dedicated(),
+                    Some(&image::BarrierInfo::GENERAL),
+                )
+            })
+            .collect();
+
+        commands.finish(ctx, &vk::SubmitInfo::default(), None);
+
+        let bloom_data = Self {
+            descriptors,
+            levels,
+        };
+        bloom_data.bind_to_descriptor_sets(ctx, data);
+        bloom_data
+    }
+
+    fn create_descriptors(ctx: &Context) -> Descriptors {
+        firestorm::profile_method!(create_descriptors);
+
+        (0..2)
+            .fold(DescriptorSetBuilder::new(), |builder, binding| {
+                builder.binding(
+                    binding,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    1,
+                    vk::ShaderStageFlags::COMPUTE,
+                )
+            })
+            .build(ctx, Self::num_steps() as _)
+    }
+
+    fn bind_to_descriptor_sets(&self, ctx: &Context, data: &super::Data<FORMAT>) {
+        firestorm::profile_method!(bind_to_descriptor_sets);
+
+        let image_info = |image: &image::Image<FORMAT>| {
+            vk::DescriptorImageInfo::default()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(image.view)
+        };
+
+        for (&set, (_, input, output)) in self.descriptors.sets.iter().zip(self.steps(data)) {
+            let infos = [image_info(input), image_info(output)];
+
+            let writes = infos
+                .iter()
+                .zip(0..)
+                .map(|(info, binding)| {
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(set)
+                        .dst_binding(binding)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(slice::from_ref(info))
+                })
+                .collect::<Vec<_>>();
+
+            unsafe {
+                ctx.update_descriptor_sets(&writes, &[]);
+            }
+        }
+    }
+
+    // (mode, input, output) of every dispatch in order, down the chain and back up into the image
+    fn steps<'a>(
+        &'a self,
+        data: &'a super::Data<FORMAT>,
+    ) -> impl Iterator<Item = (u32, &'a image::Image<FORMAT>, &'a image::Image<FORMAT>)> {
+        let downsamples = self
+            .levels
+            .windows(2)
+            .map(|pair| (mode::DOWNSAMPLE, &pair[0], &pair[1]));
+        let upsamples = self
+            .levels
+            .windows(2)
+            .rev()
+            .map(|pair| (mode::UPSAMPLE, &pair[1], &pair[0]));
+
+        std::iter::once((mode::PREFILTER, &data.denoised, &self.levels[0]))
+            .chain(downsamples)
+            .chain(upsamples)
+            .chain(std::iter::once((
+                mode::COMPOSITE,
+                &self.levels[0],
+                &data.denoised,
+            )))
+    }
+
+    const fn num_steps() -> usize {
+        2 * conf::NUM_LEVELS
+    }
+}
+
+impl<const FORMAT: image::Format> Pipeline<FORMAT> {
+    pub fn create(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let data = Data::create(ctx, data);
+
+        let (layout, pipeline) = Self::create_pipeline(ctx, data.descriptors.layout);
+
+        let descriptor_sets = data.descriptors.sets.iter().copied().map(|a| [a]);
+
+        let pipeline = pipeline::Pipeline::new(
+            ctx,
+            conf::NAME.to_owned(),
+            descriptor_sets,
+            layout,
+            pipeline,
+            ctx.queues.graphics(),
+            1,
+        );
+
+        Self { data, pipeline }
+    }
+
+    fn create_pipeline(
+        ctx: &Context,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> (vk::PipelineLayout, vk::Pipeline) {
+        firestorm::profile_method!(create_pipeline);
+
+        let push_constant_ranges = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<inputs::BloomConstants>() as _,
+        };
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(slice::from_ref(&push_constant_ranges));
+
+        let layout = unsafe {
+            ctx.create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create pipeline layout")
+        };
+
+        let shader_module = ctx.create_shader_module_from_file(conf::SHADER_COMP);
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(crate::cstr!("main"));
+
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = unsafe {
+            ctx.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                slice::from_ref(&create_info),
+                None,
+            )
+            .expect("Failed to create pipeline")[0]
+        };
+
+        unsafe {
+            ctx.destroy_shader_module(shader_module, None);
+        }
+
+        (layout, pipeline)
+    }
+
+    // Adds the blurred bright parts of the image back onto it, in place
+    pub fn run(
+        &self,
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+        threshold: f32,
+        intensity: f32,
+        sync_info: &SyncInfo,
+    ) {
+        firestorm::profile_method!(run);
+
+        let commands = self.pipeline.begin_pipeline(ctx, 0);
+
+        let memory_barrier = |src_stage, src_access, dst_stage, dst_access| unsafe {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access);
+            ctx.cmd_pipeline_barrier(
+                commands.buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                slice::from_ref(&barrier),
+                &[],
+                &[],
+            );
+        };
+
+        memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        unsafe {
+            ctx.cmd_bind_pipeline(
+                commands.buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                *self.pipeline,
+            );
+        }
+
+        for (idx, (mode, _, output)) in self.data.steps(data).enumerate() {
+            let push_constants = inputs::BloomConstants {
+                mode,
+                threshold,
+                intensity,
+            };
+
+            unsafe {
+                ctx.cmd_push_constants(
+                    commands.buffer,
+                    self.pipeline.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    bytemuck::bytes_of(&push_constants),
+                );
+
+                ctx.cmd_bind_descriptor_sets(
+                    commands.buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline.layout,
+                    0,
+                    &self.pipeline.descriptor_sets[idx],
+                    &[],
+                );
+
+                ctx.cmd_dispatch(
+                    commands.buffer,
+                    output.extent.width.div_ceil(conf::WORKGROUP_SIZE),
+                    output.extent.height.div_ceil(conf::WORKGROUP_SIZE),
+                    1,
+                );
+            }
+
+            memory_barrier(
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            );
+        }
+
+        self.pipeline.submit_pipeline(ctx, 0, sync_info);
+    }
+}
+
+impl<const FORMAT: image::Format> Destroy<Context> for Pipeline<FORMAT> {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.pipeline.destroy_with(ctx);
+        self.data.destroy_with(ctx);
+    }
+}
+
+impl<const FORMAT: image::Format> Destroy<Context> for Data<FORMAT> {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.levels.destroy_with(ctx);
+        self.descriptors.destroy_with(ctx);
+    }
+}
@@ -0,0 +1,348 @@
+use std::slice;
+
+use ash::vk;
+
+use shared::inputs;
+
+use crate::{
+    commands::Commands,
+    context::Context,
+    descriptors::{DescriptorSetBuilder, Descriptors},
+    image, memory, pipeline,
+    sync_info::SyncInfo,
+    Destroy,
+};
+
+mod conf {
+    pub const NAME: &str = "Denoise";
+    pub const SHADER_COMP: &str = env!("denoise.comp.glsl");
+    pub const NUM_ITERATIONS: u32 = 5;
+    pub const WORKGROUP_SIZE: u32 = 16;
+}
+
+pub fn shaders() -> impl Iterator<Item = &'static str> {
+    [conf::SHADER_COMP].into_iter()
+}
+
+// Descriptor set per (input -> output) pairing of the ping-ponged filter iterations
+mod set {
+    pub const FROM_TARGET: usize = 0;
+    pub const TO_SCRATCH: usize = 1;
+    pub const TO_DENOISED: usize = 2;
+    pub const COUNT: usize = 3;
+}
+
+pub struct Data<const FORMAT: image::Format> {
+    descriptors: Descriptors,
+    scratch: image::Image<FORMAT>,
+}
+
+pub struct Pipeline<const FORMAT: image::Format> {
+    data: Data<FORMAT>,
+    pipeline: pipeline::Pipeline<1>,
+}
+
+impl<const FORMAT: image::Format> Data<FORMAT> {
+    pub fn create(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let descriptors = Self::create_descriptors(ctx);
+
+        let commands = Commands::begin_on_queue(
+            ctx,
+            format!("{} - Initialization", conf::NAME),
+            ctx.queues.graphics(),
+        );
+
+        let scratch = {
+            let info = vk::ImageCreateInfo {
+                extent: vk::Extent3D {
+                    width: data.target.extent.width,
+                    height: data.target.extent.height,
+                    depth: 1,
+                },
+                usage: vk::ImageUsageFlags::STORAGE,
+                ..Default::default()
+            };
+            image::Image::create(
+                ctx,
+                commands.buffer,
+                format!("{} Scratch", conf::NAME),
+                &info,
+                &memory::purpose::dedicated(),
+                Some(&image::BarrierInfo::GENERAL),
+            )
+        };
+
+        commands.finish(ctx, &vk::SubmitInfo::default(), None);
+
+        let denoise_data = Self {
+            descriptors,
+            scratch,
+        };
+        denoise_data.bind_to_descriptor_sets(ctx, data);
+        denoise_data
+    }
+
+    fn create_descriptors(ctx: &Context) -> Descriptors {
+        firestorm::profile_method!(create_descriptors);
+
+        (0..4)
+            .fold(DescriptorSetBuilder::new(), |builder, binding| {
+                builder.binding(
+                    binding,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    1,
+                    vk::ShaderStageFlags::COMPUTE,
+                )
+            })
+            .build(ctx, set::COUNT as _)
+    }
+
+    fn bind_to_descriptor_sets(&self, ctx: &Context, data: &super::Data<FORMAT>) {
+        firestorm::profile_method!(bind_to_descriptor_sets);
+
+        let image_info = |image: &image::Image<FORMAT>| {
+            vk::DescriptorImageInfo::default()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(image.view)
+        };
+
+        let first_output = match Self::output_of(0) {
+            set::TO_SCRATCH => &self.scratch,
+            _ => &data.denoised,
+        };
+        let pairings = [
+            (set::FROM_TARGET, &data.target, first_output),
+            (set::TO_SCRATCH, &data.denoised, &self.scratch),
+            (set::TO_DENOISED, &self.scratch, &data.denoised),
+        ];
+
+        let albedo_info = image_info(&data.albedo);
+        let normal_info = image_info(&data.normal);
+
+        for (idx, input, output) in pairings {
+            let set = self.descriptors.sets[idx];
+            let infos = [
+                image_info(input),
+                image_info(output),
+                albedo_info,
+                normal_info,
+            ];
+
+            let writes = infos
+                .iter()
+                .zip(0..)
+                .map(|(info, binding)| {
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(set)
+                        .dst_binding(binding)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(slice::from_ref(info))
+                })
+                .collect::<Vec<_>>();
+
+            unsafe {
+                ctx.update_descriptor_sets(&writes, &[]);
+            }
+        }
+    }
+
+    // Iterations alternate outputs such that the last one always lands in the denoised image
+    const fn output_of(iteration: u32) -> usize {
+        if (conf::NUM_ITERATIONS - 1 - iteration).is_multiple_of(2) {
+            set::TO_DENOISED
+        } else {
+            set::TO_SCRATCH
+        }
+    }
+
+    const fn set_for(iteration: u32) -> usize {
+        if iteration == 0 {
+            set::FROM_TARGET
+        } else {
+            Self::output_of(iteration)
+        }
+    }
+}
+
+impl<const FORMAT: image::Format> Pipeline<FORMAT> {
+    pub fn create(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let data = Data::create(ctx, data);
+
+        let (layout, pipeline) = Self::create_pipeline(ctx, data.descriptors.layout);
+
+        let descriptor_sets = data.descriptors.sets.iter().copied().map(|a| [a]);
+
+        let pipeline = pipeline::Pipeline::new(
+            ctx,
+            conf::NAME.to_owned(),
+            descriptor_sets,
+            layout,
+            pipeline,
+            ctx.queues.graphics(),
+            1,
+        );
+
+        Self { data, pipeline }
+    }
+
+    fn create_pipeline(
+        ctx: &Context,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> (vk::PipelineLayout, vk::Pipeline) {
+        firestorm::profile_method!(create_pipeline);
+
+        let push_constant_ranges = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<inputs::DenoiseConstants>() as _,
+        };
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(slice::from_ref(&push_constant_ranges));
+
+        let layout = unsafe {
+            ctx.create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create pipeline layout")
+        };
+
+        let shader_module = ctx.create_shader_module_from_file(conf::SHADER_COMP);
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(crate::cstr!("main"));
+
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = unsafe {
+            ctx.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                slice::from_ref(&create_info),
+                None,
+            )
+            .expect("Failed to create pipeline")[0]
+        };
+
+        unsafe {
+            ctx.destroy_shader_module(shader_module, None);
+        }
+
+        (layout, pipeline)
+    }
+
+    pub fn run(
+        &self,
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+        enabled: bool,
+        sync_info: &SyncInfo,
+    ) {
+        firestorm::profile_method!(run);
+
+        let commands = self.pipeline.begin_pipeline(ctx, 0);
+
+        let memory_barrier = |src_stage, src_access, dst_stage, dst_access| unsafe {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access);
+            ctx.cmd_pipeline_barrier(
+                commands.buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                slice::from_ref(&barrier),
+                &[],
+                &[],
+            );
+        };
+
+        memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ,
+        );
+
+        if enabled {
+            unsafe {
+                ctx.cmd_bind_pipeline(
+                    commands.buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    *self.pipeline,
+                );
+            }
+
+            for iteration in 0..conf::NUM_ITERATIONS {
+                let push_constants = inputs::DenoiseConstants {
+                    step_size: 1 << iteration,
+                };
+
+                unsafe {
+                    ctx.cmd_push_constants(
+                        commands.buffer,
+                        self.pipeline.layout,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        bytemuck::bytes_of(&push_constants),
+                    );
+
+                    ctx.cmd_bind_descriptor_sets(
+                        commands.buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        self.pipeline.layout,
+                        0,
+                        &self.pipeline.descriptor_sets[Data::<FORMAT>::set_for(iteration)],
+                        &[],
+                    );
+
+                    ctx.cmd_dispatch(
+                        commands.buffer,
+                        data.target.extent.width.div_ceil(conf::WORKGROUP_SIZE),
+                        data.target.extent.height.div_ceil(conf::WORKGROUP_SIZE),
+                        1,
+                    );
+                }
+
+                memory_barrier(
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                );
+            }
+        } else {
+            data.target.cmd_copy_to(
+                ctx,
+                commands.buffer,
+                &data.denoised,
+                vk::ImageLayout::GENERAL,
+            );
+        }
+
+        self.pipeline.submit_pipeline(ctx, 0, sync_info);
+    }
+}
+
+impl<const FORMAT: image::Format> Destroy<Context> for Pipeline<FORMAT> {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.pipeline.destroy_with(ctx);
+        self.data.destroy_with(ctx);
+    }
+}
+
+impl<const FORMAT: image::Format> Destroy<Context> for Data<FORMAT> {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.scratch.destroy_with(ctx);
+        self.descriptors.destroy_with(ctx);
+    }
+}
@@ -0,0 +1,341 @@
+use std::slice;
+
+use ash::vk;
+
+use shared::inputs;
+
+use crate::{
+    buffer::Buffer,
+    context::Context,
+    descriptors::{DescriptorSetBuilder, Descriptors},
+    image, memory, pipeline,
+    sync_info::SyncInfo,
+    sync_state, Destroy,
+};
+
+mod conf {
+    pub const NAME: &str = "Exposure";
+    pub const SHADER_COMP: &str = env!("exposure.comp.glsl");
+    pub const WORKGROUP_SIZE: u32 = 16;
+    // must match exposure.comp.glsl, the first bin collects (near) black pixels
+    pub const NUM_BINS: usize = 64;
+    // log2 luminance range covered by the histogram
+    pub const MIN_LOG_LUMINANCE: f32 = -10.;
+    pub const LOG_LUMINANCE_RANGE: f32 = 16.;
+    // the average luminance is exposed to middle grey
+    pub const KEY_VALUE: f32 = 0.18;
+    // fraction of the way to the target exposure moved every frame
+    pub const ADAPTATION_RATE: f32 = 0.05;
+}
+
+pub fn shaders() -> impl Iterator<Item = &'static str> {
+    [conf::SHADER_COMP].into_iter()
+}
+
+// Each frame in flight builds its own histogram, which is read back once its fence is signaled
+pub struct Data {
+    descriptors: Descriptors,
+    histograms: Vec<Buffer>,
+}
+
+pub struct Pipeline {
+    data: Data,
+    pipeline: pipeline::Pipeline<1>,
+}
+
+impl Data {
+    pub fn create<const FORMAT: image::Format>(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let descriptors = Self::create_descriptors(ctx);
+
+        let histograms = (0..sync_state::conf::MAX_FRAMES_IN_FLIGHT)
+            .map(|idx| {
+                let info = vk::BufferCreateInfo::default()
+                    .size((conf::NUM_BINS * std::mem::size_of::<u32>()) as _)
+                    .usage(
+                        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                    );
+                let histogram = Buffer::create(
+                    ctx,
+                    format!("{} Histogram #{idx}", conf::NAME),
+                    info,
+                    &memory::purpose::readback(),
+                );
+                histogram.fill_from(ctx, bytemuck::cast_slice(&[0u32; conf::NUM_BINS]));
+                histogram
+            })
+            .collect();
+
+        let exposure_data = Self {
+            descriptors,
+            histograms,
+        };
+        exposure_data.bind_to_descriptor_sets(ctx, data);
+        exposure_data
+    }
+
+    fn create_descriptors(ctx: &Context) -> Descriptors {
+        firestorm::profile_method!(create_descriptors);
+
+        DescriptorSetBuilder::new()
+            .binding(
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                1,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .binding(
+                1,
+                vk::DescriptorType::STORAGE_BUFFER,
+                1,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .build(ctx, sync_state::conf::MAX_FRAMES_IN_FLIGHT as _)
+    }
+
+    fn bind_to_descriptor_sets<const FORMAT: image::Format>(
+        &self,
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+    ) {
+        firestorm::profile_method!(bind_to_descriptor_sets);
+
+        // the histogram is taken of what the tonemap pass presents
+        let image_info = vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(data.denoised.view);
+
+        for (&set, histogram) in self.descriptors.sets.iter().zip(&self.histograms) {
+            let histogram_info = vk::DescriptorBufferInfo::default()
+                .buffer(**histogram)
+                .range(vk::WHOLE_SIZE);
+
+            let writes = [
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(slice::from_ref(&image_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(slice::from_ref(&histogram_info)),
+            ];
+
+            unsafe {
+                ctx.update_descriptor_sets(&writes, &[]);
+            }
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn create<const FORMAT: image::Format>(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let data = Data::create(ctx, data);
+
+        let (layout, pipeline) = Self::create_pipeline(ctx, data.descriptors.layout);
+
+        let descriptor_sets = data.descriptors.sets.iter().copied().map(|a| [a]);
+
+        let pipeline = pipeline::Pipeline::new(
+            ctx,
+            conf::NAME.to_owned(),
+            descriptor_sets,
+            layout,
+            pipeline,
+            ctx.queues.graphics(),
+            sync_state::conf::MAX_FRAMES_IN_FLIGHT,
+        );
+
+        Self { data, pipeline }
+    }
+
+    fn create_pipeline(
+        ctx: &Context,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> (vk::PipelineLayout, vk::Pipeline) {
+        firestorm::profile_method!(create_pipeline);
+
+        let push_constant_ranges = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<inputs::ExposureConstants>() as _,
+        };
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(slice::from_ref(&push_constant_ranges));
+
+        let layout = unsafe {
+            ctx.create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create pipeline layout")
+        };
+
+        let shader_module = ctx.create_shader_module_from_file(conf::SHADER_COMP);
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(crate::cstr!("main"));
+
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = unsafe {
+            ctx.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                slice::from_ref(&create_info),
+                None,
+            )
+            .expect("Failed to create pipeline")[0]
+        };
+
+        unsafe {
+            ctx.destroy_shader_module(shader_module, None);
+        }
+
+        (layout, pipeline)
+    }
+
+    // Rebuilds the histogram of the frame in flight `idx` from the presented image
+    pub fn run<const FORMAT: image::Format>(
+        &self,
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+        idx: usize,
+        sync_info: &SyncInfo,
+    ) {
+        firestorm::profile_method!(run);
+
+        let commands = self.pipeline.begin_pipeline(ctx, idx);
+
+        let memory_barrier = |src_stage, src_access, dst_stage, dst_access| unsafe {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access);
+            ctx.cmd_pipeline_barrier(
+                commands.buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                slice::from_ref(&barrier),
+                &[],
+                &[],
+            );
+        };
+
+        unsafe {
+            ctx.cmd_fill_buffer(
+                commands.buffer,
+                *self.data.histograms[idx],
+                0,
+                vk::WHOLE_SIZE,
+                0,
+            );
+        }
+
+        memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        let push_constants = inputs::ExposureConstants {
+            min_log_luminance: conf::MIN_LOG_LUMINANCE,
+            log_luminance_range: conf::LOG_LUMINANCE_RANGE,
+        };
+
+        unsafe {
+            ctx.cmd_bind_pipeline(
+                commands.buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                *self.pipeline,
+            );
+
+            ctx.cmd_push_constants(
+                commands.buffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+
+            ctx.cmd_bind_descriptor_sets(
+                commands.buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &self.pipeline.descriptor_sets[idx],
+                &[],
+            );
+
+            ctx.cmd_dispatch(
+                commands.buffer,
+                data.denoised.extent.width.div_ceil(conf::WORKGROUP_SIZE),
+                data.denoised.extent.height.div_ceil(conf::WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        memory_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::HOST,
+            vk::AccessFlags::HOST_READ,
+        );
+
+        self.pipeline.submit_pipeline(ctx, idx, sync_info);
+    }
+
+    // Moves the exposure towards the one that brings the average log luminance of the last
+    // histogram built for `idx` to the key value. Must only be called once that frame has finished
+    pub fn adapt(&self, ctx: &Context, idx: usize, exposure: f32) -> f32 {
+        firestorm::profile_method!(adapt);
+
+        let mut bins = [0u32; conf::NUM_BINS];
+        self.data.histograms[idx].read_into(ctx, bytemuck::cast_slice_mut(&mut bins));
+
+        let (weighted_sum, count) = bins.iter().zip(0..).skip(1).fold(
+            (0., 0),
+            |(weighted_sum, count), (&num_pixels, bin)| {
+                let center = (bin as f32 - 0.5) / (conf::NUM_BINS - 1) as f32;
+                (
+                    (num_pixels as f32).mul_add(center, weighted_sum),
+                    count + num_pixels,
+                )
+            },
+        );
+        if count == 0 {
+            return exposure;
+        }
+
+        let average_log_luminance = (weighted_sum / count as f32)
+            .mul_add(conf::LOG_LUMINANCE_RANGE, conf::MIN_LOG_LUMINANCE);
+        let target = conf::KEY_VALUE / average_log_luminance.exp2();
+
+        (target - exposure).mul_add(conf::ADAPTATION_RATE, exposure)
+    }
+}
+
+impl Destroy<Context> for Pipeline {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.pipeline.destroy_with(ctx);
+        self.data.destroy_with(ctx);
+    }
+}
+
+impl Destroy<Context> for Data {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.histograms.destroy_with(ctx);
+        self.descriptors.destroy_with(ctx);
+    }
+}
@@ -1,6 +1,12 @@
+pub mod bloom;
+pub mod denoise;
+pub mod exposure;
 pub mod pathtracer;
 pub mod rasterizer;
+pub mod taa;
 pub mod tonemap;
+pub mod tonemap_compute;
+pub mod upscale;
 
 use std::slice;
 
@@ -8,19 +14,31 @@ use ash::vk;
 use shared::inputs;
 
 use crate::{
-    commands::Commands, context::Context, descriptors::Descriptors, image, memory,
-    uniforms::Uniforms, world::World, Destroy,
+    acceleration_structure::BuildPreference,
+    commands::Commands,
+    context::Context,
+    descriptors::{DescriptorSetBuilder, Descriptors},
+    image, memory,
+    streaming::TextureLoading,
+    uniforms::Uniforms,
+    world::{Tracing, World},
+    Destroy, Error,
 };
 
-mod conf {
-    pub const MAX_NUM_TEXTURES: u32 = 128;
-}
-
 pub struct Data<const FORMAT: image::Format> {
     pub descriptors: Descriptors,
     pub uniforms: Uniforms,
     pub world: World,
     pub target: image::Image<FORMAT>,
+    pub albedo: image::Image<FORMAT>,
+    pub normal: image::Image<FORMAT>,
+    pub denoised: image::Image<FORMAT>,
+    // screen space motion written by the rasterizer for temporal antialiasing
+    pub velocity: image::Image<FORMAT>,
+    // mean luminance of the accumulated frames and of its square, for estimating their variance
+    pub moments: image::Image<FORMAT>,
+    // the accumulated radiance without the firefly clamping the target is shown with, unbiased
+    pub reference: image::Image<FORMAT>,
 }
 
 impl<const FORMAT: image::Format> Data<FORMAT> {
@@ -29,150 +47,211 @@ impl<const FORMAT: image::Format> Data<FORMAT> {
         scene: scene::Scene,
         resolution: (u32, u32),
         camera: inputs::Camera,
-    ) -> Self {
+        build_preference: BuildPreference,
+        texture_loading: TextureLoading,
+    ) -> Result<Self, Error> {
         firestorm::profile_method!(create);
 
-        let descriptors = Self::create_descriptors(ctx);
-        let uniforms = Uniforms::create(ctx, camera);
-        let world = World::create(ctx, scene);
-
-        let commands = Commands::begin_on_queue(
-            ctx,
-            "Common - Initialization".to_owned(),
-            ctx.queues.graphics(),
-        );
-
-        let target = {
-            let info = vk::ImageCreateInfo {
-                extent: vk::Extent3D {
-                    width: resolution.0,
-                    height: resolution.1,
-                    depth: 1,
-                },
-                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE,
-                ..Default::default()
-            };
-            image::Image::create(
-                ctx,
-                commands.buffer,
-                "Render Target".to_owned(),
-                &info,
-                &memory::purpose::dedicated(),
-                Some(&image::BarrierInfo::GENERAL),
-            )
-        };
+        // before anything is allocated so that nothing needs cleaning up on failure
+        if cfg!(feature = "ray_query_ao") && !ctx.physical_device.supports_ray_query {
+            return Err(Error::RayQueryUnsupported);
+        }
+        World::check_limits(ctx, &scene.info, Self::max_textures(ctx))?;
 
-        commands.finish(ctx, &vk::SubmitInfo::default(), None);
+        // the default texture goes after those of the scene
+        let num_textures = scene.info.textures.len() as u32 + 1;
+        let descriptors = Self::create_descriptors(ctx, num_textures);
+        let uniforms = Uniforms::create(ctx, camera);
+        let world = World::create(ctx, scene, build_preference, texture_loading);
+        let (target, albedo, normal, denoised, velocity, moments, reference) =
+            Self::create_targets(ctx, resolution);
 
         let data = Self {
             descriptors,
             uniforms,
             world,
             target,
+            albedo,
+            normal,
+            denoised,
+            velocity,
+            moments,
+            reference,
         };
         data.bind_to_descriptor_sets(ctx);
-        data
+        Ok(data)
     }
 
-    pub fn create_descriptors(ctx: &Context) -> Descriptors {
-        firestorm::profile_method!(create_descriptors);
+    // Replaces the render targets with ones of the new resolution, their previous contents are lost
+    pub fn resize_targets(&mut self, ctx: &Context, resolution: (u32, u32)) {
+        firestorm::profile_method!(resize_targets);
 
-        let layout = {
-            let bindings = [
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::RAYGEN_KHR),
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(1)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(1)
-                    .stage_flags(
-                        vk::ShaderStageFlags::FRAGMENT
-                            | vk::ShaderStageFlags::RAYGEN_KHR
-                            | vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-                    ),
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(2)
-                    .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                    .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR),
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(3)
-                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                    .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR),
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(4)
-                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .descriptor_count(conf::MAX_NUM_TEXTURES)
-                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::RAYGEN_KHR),
-            ];
-            let binding_flags = [
-                vk::DescriptorBindingFlags::empty(),
-                vk::DescriptorBindingFlags::empty(),
-                vk::DescriptorBindingFlags::empty(),
-                vk::DescriptorBindingFlags::empty(),
-                vk::DescriptorBindingFlags::PARTIALLY_BOUND
-                    | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
-            ];
-            let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
-                .binding_flags(&binding_flags);
-            let info = vk::DescriptorSetLayoutCreateInfo::default()
-                .bindings(&bindings)
-                .push_next(&mut binding_flags_info);
-            unsafe {
-                ctx.create_descriptor_set_layout(&info, None)
-                    .expect("Failed to create descriptor set layout")
-            }
-        };
+        unsafe {
+            self.destroy_targets(ctx);
+        }
+        (
+            self.target,
+            self.albedo,
+            self.normal,
+            self.denoised,
+            self.velocity,
+            self.moments,
+            self.reference,
+        ) = Self::create_targets(ctx, resolution);
+        self.bind_to_descriptor_sets(ctx);
+    }
 
-        let pool = {
-            let sizes = [
-                vk::DescriptorPoolSize::default()
-                    .ty(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(1),
-                vk::DescriptorPoolSize::default()
-                    .ty(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(1),
-                vk::DescriptorPoolSize::default()
-                    .ty(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                    .descriptor_count(1),
-                vk::DescriptorPoolSize::default()
-                    .ty(vk::DescriptorType::STORAGE_IMAGE)
-                    .descriptor_count(1),
-                vk::DescriptorPoolSize::default()
-                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .descriptor_count(conf::MAX_NUM_TEXTURES),
-            ];
+    #[allow(clippy::type_complexity)]
+    fn create_targets(
+        ctx: &Context,
+        resolution: (u32, u32),
+    ) -> (
+        image::Image<FORMAT>,
+        image::Image<FORMAT>,
+        image::Image<FORMAT>,
+        image::Image<FORMAT>,
+        image::Image<FORMAT>,
+        image::Image<FORMAT>,
+        image::Image<FORMAT>,
+    ) {
+        firestorm::profile_method!(create_targets);
 
-            let info = vk::DescriptorPoolCreateInfo::default()
-                .pool_sizes(&sizes)
-                .max_sets(1);
+        let commands = Commands::begin_on_queue(
+            ctx,
+            "Common - Initialization".to_owned(),
+            ctx.queues.graphics(),
+        );
 
-            unsafe {
-                ctx.create_descriptor_pool(&info, None)
-                    .expect("Failed to create descriptor pool")
-            }
+        let create_target =
+            |name, usage| Self::create_target(ctx, commands.buffer, name, resolution, usage);
+
+        let target = create_target(
+            "Render Target",
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+        let albedo = create_target("Albedo Target", vk::ImageUsageFlags::TRANSFER_DST);
+        let normal = create_target("Normal Target", vk::ImageUsageFlags::TRANSFER_DST);
+        let denoised = create_target(
+            "Denoised Target",
+            vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+
+        let velocity = create_target("Velocity Target", vk::ImageUsageFlags::COLOR_ATTACHMENT);
+        let moments = create_target(
+            "Moments Target",
+            vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+
+        let reference = create_target(
+            "Reference Target",
+            vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+
+        commands.finish(ctx, &vk::SubmitInfo::default(), None);
+
+        (
+            target, albedo, normal, denoised, velocity, moments, reference,
+        )
+    }
+
+    fn create_target(
+        ctx: &Context,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+        resolution: (u32, u32),
+        usage: vk::ImageUsageFlags,
+    ) -> image::Image<FORMAT> {
+        let info = vk::ImageCreateInfo {
+            extent: vk::Extent3D {
+                width: resolution.0,
+                height: resolution.1,
+                depth: 1,
+            },
+            usage: vk::ImageUsageFlags::STORAGE | usage,
+            ..Default::default()
         };
+        image::Image::create(
+            ctx,
+            command_buffer,
+            name.to_owned(),
+            &info,
+            &memory::purpose::dedicated(),
+            Some(&image::BarrierInfo::GENERAL),
+        )
+    }
 
-        let sets = {
-            let mut set_counts = vk::DescriptorSetVariableDescriptorCountAllocateInfo::default()
-                .descriptor_counts(&[conf::MAX_NUM_TEXTURES]);
+    // Combined image samplers count against the limits of both samplers and sampled images
+    fn max_textures(ctx: &Context) -> u32 {
+        let limits = &ctx.physical_device.properties.v_1_0.limits;
+        [
+            limits.max_per_stage_descriptor_samplers,
+            limits.max_per_stage_descriptor_sampled_images,
+            limits.max_descriptor_set_samplers,
+            limits.max_descriptor_set_sampled_images,
+        ]
+        .into_iter()
+        .min()
+        .unwrap_or_default()
+    }
 
-            let info = vk::DescriptorSetAllocateInfo::default()
-                .descriptor_pool(pool)
-                .set_layouts(slice::from_ref(&layout))
-                .push_next(&mut set_counts);
+    pub fn create_descriptors(ctx: &Context, num_textures: u32) -> Descriptors {
+        firestorm::profile_method!(create_descriptors);
 
-            unsafe {
-                ctx.allocate_descriptor_sets(&info)
-                    .expect("Failed to allocate descriptor sets")
-            }
+        let supports_ray_tracing = ctx.physical_device.supports_ray_tracing;
+        // the path tracer is a single compute shader when ray tracing is unavailable
+        let (trace_stage, hit_stages) = if supports_ray_tracing {
+            (
+                vk::ShaderStageFlags::RAYGEN_KHR,
+                vk::ShaderStageFlags::CLOSEST_HIT_KHR | vk::ShaderStageFlags::ANY_HIT_KHR,
+            )
+        } else {
+            (vk::ShaderStageFlags::COMPUTE, vk::ShaderStageFlags::empty())
         };
 
-        Descriptors { layout, pool, sets }
+        let mut builder = DescriptorSetBuilder::new()
+            .binding(
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                1,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT | trace_stage,
+            )
+            .binding(
+                1,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                1,
+                vk::ShaderStageFlags::FRAGMENT | trace_stage | hit_stages,
+            );
+        // and it has no TLAS to bind
+        if supports_ray_tracing {
+            let rasterizer_stage = if cfg!(feature = "ray_query_ao") {
+                vk::ShaderStageFlags::FRAGMENT
+            } else {
+                vk::ShaderStageFlags::empty()
+            };
+            builder = builder.binding(
+                2,
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                1,
+                trace_stage | rasterizer_stage,
+            );
+        }
+        builder
+            .binding(3, vk::DescriptorType::STORAGE_IMAGE, 1, trace_stage)
+            .binding(4, vk::DescriptorType::STORAGE_IMAGE, 1, trace_stage)
+            .binding(5, vk::DescriptorType::STORAGE_IMAGE, 1, trace_stage)
+            .binding(6, vk::DescriptorType::STORAGE_IMAGE, 1, trace_stage)
+            .binding(7, vk::DescriptorType::STORAGE_IMAGE, 1, trace_stage)
+            .binding_with_flags(
+                8,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                num_textures,
+                vk::ShaderStageFlags::FRAGMENT | trace_stage | hit_stages,
+                vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                    | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+            )
+            .build(ctx, 1)
     }
 
     fn bind_to_descriptor_sets(&self, ctx: &Context) {
@@ -184,27 +263,48 @@ impl<const FORMAT: image::Format> Data<FORMAT> {
             .buffer(*self.world.scene_desc)
             .range(vk::WHOLE_SIZE);
 
-        let mut accel_info = vk::WriteDescriptorSetAccelerationStructureKHR::default()
-            .acceleration_structures(slice::from_ref(&self.world.accel.tlas));
+        let mut accel_info = match &self.world.tracing {
+            Tracing::Hardware(accel) => Some(
+                vk::WriteDescriptorSetAccelerationStructureKHR::default()
+                    .acceleration_structures(slice::from_ref(&accel.tlas)),
+            ),
+            Tracing::Software(_) => None,
+        };
 
         let target_info = vk::DescriptorImageInfo::default()
             .image_layout(vk::ImageLayout::GENERAL)
             .image_view(self.target.view);
 
+        let albedo_info = vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.albedo.view);
+
+        let normal_info = vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.normal.view);
+
+        let moments_info = vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.moments.view);
+
+        let reference_info = vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.reference.view);
+
         let textures_info: Vec<_> = self
             .world
             .textures
             .iter()
             .map(|tex| {
                 vk::DescriptorImageInfo::default()
-                    .image_view(tex.view)
+                    .image_view(tex.view())
                     .image_layout(image::BarrierInfo::SHADER_READ.layout)
-                    .sampler(*tex.sampler)
+                    .sampler(self.world.samplers.get(tex.address_modes))
             })
             .collect();
 
         for &set in &self.descriptors.sets {
-            let writes = [
+            let mut writes = vec![
                 vk::WriteDescriptorSet::default()
                     .dst_set(set)
                     .dst_binding(0)
@@ -215,12 +315,6 @@ impl<const FORMAT: image::Format> Data<FORMAT> {
                     .dst_binding(1)
                     .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
                     .buffer_info(slice::from_ref(&scene_desc_info)),
-                vk::WriteDescriptorSet::default()
-                    .dst_set(set)
-                    .dst_binding(2)
-                    .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                    .descriptor_count(1)
-                    .push_next(&mut accel_info),
                 vk::WriteDescriptorSet::default()
                     .dst_set(set)
                     .dst_binding(3)
@@ -229,22 +323,122 @@ impl<const FORMAT: image::Format> Data<FORMAT> {
                 vk::WriteDescriptorSet::default()
                     .dst_set(set)
                     .dst_binding(4)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(slice::from_ref(&albedo_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(5)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(slice::from_ref(&normal_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(6)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(slice::from_ref(&moments_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(7)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(slice::from_ref(&reference_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(8)
                     .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                     .image_info(&textures_info),
             ];
+            if let Some(accel_info) = &mut accel_info {
+                writes.push(
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(set)
+                        .dst_binding(2)
+                        .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                        .descriptor_count(1)
+                        .push_next(accel_info),
+                );
+            }
 
             unsafe {
                 ctx.update_descriptor_sets(&writes, &[]);
             }
         }
     }
+
+    // Rebinds the textures if any streamed in, so they must not be in use by the GPU
+    pub fn stream_textures(&mut self, ctx: &Context) -> bool {
+        let streamed = self.world.stream_textures(ctx);
+        if streamed {
+            self.bind_to_descriptor_sets(ctx);
+        }
+        streamed
+    }
+
+    // The TLAS is rebuilt and rebound, so the GPU must be idle
+    pub fn set_visible_instances(&mut self, ctx: &Context, mask: Option<&[bool]>) {
+        self.world.build_tlas_filtered(ctx, mask);
+        self.bind_to_descriptor_sets(ctx);
+    }
+
+    // Recreates the texture samplers, so the GPU must be idle
+    pub fn set_max_anisotropy(&mut self, ctx: &Context, max_anisotropy: f32) {
+        firestorm::profile_method!(set_max_anisotropy);
+
+        self.world.samplers.set_max_anisotropy(ctx, max_anisotropy);
+        self.bind_to_descriptor_sets(ctx);
+    }
+
+    // Zeroes the accumulated render target along with its auxiliary features
+    pub fn cmd_clear_accumulation(&self, ctx: &Context, command_buffer: vk::CommandBuffer) {
+        firestorm::profile_method!(cmd_clear_accumulation);
+
+        for image in [
+            &self.target,
+            &self.albedo,
+            &self.normal,
+            &self.moments,
+            &self.reference,
+        ] {
+            image.transition_layout(
+                ctx,
+                command_buffer,
+                &image::BarrierInfo {
+                    layout: vk::ImageLayout::GENERAL,
+                    stage: vk::PipelineStageFlags::ALL_COMMANDS,
+                    access: vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                },
+                &image::BarrierInfo::CLEAR,
+            );
+            image.cmd_clear(ctx, command_buffer, vk::ImageLayout::GENERAL);
+            image.transition_layout(
+                ctx,
+                command_buffer,
+                &image::BarrierInfo::CLEAR,
+                if ctx.physical_device.supports_ray_tracing {
+                    &image::BarrierInfo::RAY_TRACING_STORAGE
+                } else {
+                    &image::BarrierInfo::COMPUTE_STORAGE
+                },
+            );
+        }
+    }
+}
+
+impl<const FORMAT: image::Format> Data<FORMAT> {
+    unsafe fn destroy_targets(&mut self, ctx: &Context) {
+        self.reference.destroy_with(ctx);
+        self.moments.destroy_with(ctx);
+        self.velocity.destroy_with(ctx);
+        self.denoised.destroy_with(ctx);
+        self.normal.destroy_with(ctx);
+        self.albedo.destroy_with(ctx);
+        self.target.destroy_with(ctx);
+    }
 }
 
 impl<const FORMAT: image::Format> Destroy<Context> for Data<FORMAT> {
     unsafe fn destroy_with(&mut self, ctx: &Context) {
         firestorm::profile_method!(destroy_with);
 
-        self.target.destroy_with(ctx);
+        self.destroy_targets(ctx);
         self.world.destroy_with(ctx);
         self.uniforms.destroy_with(ctx);
         self.descriptors.destroy_with(ctx);
@@ -2,11 +2,12 @@ use std::slice;
 
 use ash::vk;
 
-use shared::inputs;
+use shared::{bsdf, inputs};
 
 use crate::{
+    buffer::Buffer,
     context::Context,
-    image, pipeline,
+    image, memory, pipeline,
     shader_binding_table::{RayTracingShaders, ShaderBindingTable},
     sync_info::SyncInfo,
     Destroy,
@@ -17,27 +18,95 @@ pub mod conf {
     pub const SHADER_RAY_GENERATION: &str = env!("pathtracer.rgen.glsl");
     pub const SHADER_MISSES: &[&str] = &[env!("pathtracer.rmiss.glsl")];
     pub const SHADER_CLOSEST_HITS: &[&str] = &[env!("pathtracer.rchit.glsl")];
+    pub const SHADER_ANY_HITS: &[&str] = &[env!("pathtracer.rahit.glsl")];
+    pub const SHADER_COMPUTE: &str = env!("pathtracer.comp.glsl");
+    pub const WORKGROUP_SIZE: u32 = 8;
+}
+
+pub fn shaders() -> impl Iterator<Item = &'static str> {
+    [conf::SHADER_RAY_GENERATION, conf::SHADER_COMPUTE]
+        .into_iter()
+        .chain(conf::SHADER_MISSES.iter().copied())
+        .chain(conf::SHADER_CLOSEST_HITS.iter().copied())
+        .chain(conf::SHADER_ANY_HITS.iter().copied())
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    Final,
+    Albedo,
+    Normal,
+    Depth,
+    Bounces,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    #[default]
+    PathTracing,
+    // unoccluded fraction of the hemisphere around the primary hit, for quick structural previews
+    AmbientOcclusion,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SamplingStrategy {
+    #[default]
+    CosineWeighted,
+    // directions spread evenly over the hemisphere, for checking the weighting of the diffuse lobe
+    Uniform,
+}
+
+// The first surface under a pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickResult {
+    pub position: glam::Vec3,
+    pub instance: usize,
+    pub primitive: usize,
+    pub material: usize,
 }
 
 pub struct Pipeline {
     pipeline: pipeline::Pipeline<1>,
-    shader_binding_table: ShaderBindingTable,
+    backend: Backend,
+    pick_buffer: Buffer,
+}
+
+// The compute shader traverses the software BVH on devices without ray tracing support
+enum Backend {
+    RayTracing(ShaderBindingTable),
+    Compute,
 }
 
 impl Pipeline {
     pub fn create<const FORMAT: image::Format>(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
         firestorm::profile_method!(create);
 
-        let ray_tracing_shaders = RayTracingShaders::new(
-            ctx,
-            conf::SHADER_RAY_GENERATION,
-            conf::SHADER_MISSES,
-            conf::SHADER_CLOSEST_HITS,
-        );
+        let (layout, pipeline, backend) = if ctx.physical_device.supports_ray_tracing {
+            let ray_tracing_shaders = RayTracingShaders::new(
+                ctx,
+                conf::SHADER_RAY_GENERATION,
+                conf::SHADER_MISSES,
+                conf::SHADER_CLOSEST_HITS,
+                conf::SHADER_ANY_HITS,
+            );
+
+            let (layout, pipeline) =
+                Self::create_ray_tracing_pipeline(ctx, data, &ray_tracing_shaders);
 
-        let (layout, pipeline) = Self::create_pipeline(ctx, data, &ray_tracing_shaders);
+            let shader_binding_table =
+                ShaderBindingTable::create(ctx, ray_tracing_shaders, pipeline);
+
+            (layout, pipeline, Backend::RayTracing(shader_binding_table))
+        } else {
+            eprintln!(
+                "Ray tracing is not supported by the device, path tracing in a compute shader"
+            );
 
-        let shader_binding_table = ShaderBindingTable::create(ctx, ray_tracing_shaders, pipeline);
+            let (layout, pipeline) = Self::create_compute_pipeline(ctx, data);
+
+            (layout, pipeline, Backend::Compute)
+        };
 
         let descriptor_sets = data.descriptors.sets.iter().copied().map(|a| [a]);
 
@@ -51,21 +120,37 @@ impl Pipeline {
             1,
         );
 
+        let pick_buffer = {
+            let info = vk::BufferCreateInfo::default()
+                .size(std::mem::size_of::<inputs::PickResult>() as _)
+                .usage(
+                    vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                );
+            Buffer::create(
+                ctx,
+                format!("{} Pick", conf::NAME),
+                info,
+                &memory::purpose::readback(),
+            )
+        };
+
         Self {
             pipeline,
-            shader_binding_table,
+            backend,
+            pick_buffer,
         }
     }
 
-    fn create_pipeline<const FORMAT: image::Format>(
+    fn create_layout<const FORMAT: image::Format>(
         ctx: &Context,
         data: &super::Data<FORMAT>,
-        ray_tracing_shaders: &RayTracingShaders,
-    ) -> (vk::PipelineLayout, vk::Pipeline) {
-        firestorm::profile_method!(create_pipeline);
+        stage: vk::ShaderStageFlags,
+    ) -> vk::PipelineLayout {
+        firestorm::profile_method!(create_layout);
 
         let push_constant_ranges = vk::PushConstantRange {
-            stage_flags: vk::ShaderStageFlags::RAYGEN_KHR,
+            stage_flags: stage,
             offset: 0,
             size: std::mem::size_of::<inputs::PathtracerConstants>() as _,
         };
@@ -74,10 +159,20 @@ impl Pipeline {
             .set_layouts(slice::from_ref(&data.descriptors.layout))
             .push_constant_ranges(slice::from_ref(&push_constant_ranges));
 
-        let layout = unsafe {
+        unsafe {
             ctx.create_pipeline_layout(&layout_create_info, None)
                 .expect("Failed to create pipeline layout")
-        };
+        }
+    }
+
+    fn create_ray_tracing_pipeline<const FORMAT: image::Format>(
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+        ray_tracing_shaders: &RayTracingShaders,
+    ) -> (vk::PipelineLayout, vk::Pipeline) {
+        firestorm::profile_method!(create_ray_tracing_pipeline);
+
+        let layout = Self::create_layout(ctx, data, vk::ShaderStageFlags::RAYGEN_KHR);
 
         let stages = ray_tracing_shaders.stages_create_infos();
         let groups = ray_tracing_shaders.groups_create_infos();
@@ -102,64 +197,310 @@ impl Pipeline {
         (layout, pipeline)
     }
 
+    fn create_compute_pipeline<const FORMAT: image::Format>(
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+    ) -> (vk::PipelineLayout, vk::Pipeline) {
+        firestorm::profile_method!(create_compute_pipeline);
+
+        let layout = Self::create_layout(ctx, data, vk::ShaderStageFlags::COMPUTE);
+
+        let shader_module = ctx.create_shader_module_from_file(conf::SHADER_COMPUTE);
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(crate::cstr!("main"));
+
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = unsafe {
+            ctx.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                slice::from_ref(&create_info),
+                None,
+            )
+            .expect("Failed to create pipeline")[0]
+        };
+
+        unsafe {
+            ctx.destroy_shader_module(shader_module, None);
+        }
+
+        (layout, pipeline)
+    }
+
+    // Each tile is traced as a crop of its own, in a submission that finishes before the next tile
+    // is recorded. Only the first waits on the semaphores and only the last signals them
+    #[allow(clippy::too_many_arguments)]
     pub fn run<const FORMAT: image::Format>(
         &self,
         ctx: &Context,
         data: &super::Data<FORMAT>,
-        frame: u32,
+        push_constants: &inputs::PathtracerConstants,
+        crop: Option<vk::Rect2D>,
+        tile_size: Option<u32>,
+        clear: bool,
         sync_info: &SyncInfo,
     ) {
         firestorm::profile_method!(run);
 
+        let region = Self::crop_region(crop, data.target.extent);
+        let tiles = Self::tiles(region, tile_size);
+        let last = tiles.len() - 1;
+        for (index, tile) in tiles.into_iter().enumerate() {
+            let tile_sync_info = SyncInfo {
+                wait_on: if index == 0 {
+                    sync_info.wait_on.clone()
+                } else {
+                    vec![]
+                },
+                signal_to: if index == last {
+                    sync_info.signal_to.clone()
+                } else {
+                    vec![]
+                },
+                fence: sync_info.fence.filter(|_| index == last),
+            };
+            self.run_tile(
+                ctx,
+                data,
+                push_constants,
+                tile,
+                clear && index == 0,
+                &tile_sync_info,
+            );
+        }
+    }
+
+    fn run_tile<const FORMAT: image::Format>(
+        &self,
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+        push_constants: &inputs::PathtracerConstants,
+        region: vk::Rect2D,
+        clear: bool,
+        sync_info: &SyncInfo,
+    ) {
+        firestorm::profile_method!(run_tile);
+
+        let push_constants = inputs::PathtracerConstants {
+            crop_offset: glam::uvec2(
+                region.offset.x.cast_unsigned(),
+                region.offset.y.cast_unsigned(),
+            ),
+            crop_extent: glam::uvec2(region.extent.width, region.extent.height),
+            ..*push_constants
+        };
+
         let commands = self.pipeline.begin_pipeline(ctx, 0);
 
-        let push_constants = inputs::PathtracerConstants { frame };
+        if clear {
+            data.cmd_clear_accumulation(ctx, commands.buffer);
+        }
 
         unsafe {
-            ctx.cmd_bind_pipeline(
-                commands.buffer,
-                vk::PipelineBindPoint::RAY_TRACING_KHR,
-                *self.pipeline,
-            );
+            ctx.cmd_bind_pipeline(commands.buffer, self.backend.bind_point(), *self.pipeline);
 
             ctx.cmd_push_constants(
                 commands.buffer,
                 self.pipeline.layout,
-                vk::ShaderStageFlags::RAYGEN_KHR,
+                self.backend.stage(),
                 0,
                 bytemuck::bytes_of(&push_constants),
             );
 
             ctx.cmd_bind_descriptor_sets(
                 commands.buffer,
-                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                self.backend.bind_point(),
                 self.pipeline.layout,
                 0,
                 &self.pipeline.descriptor_sets[0],
                 &[],
             );
 
-            ctx.ext.ray_tracing.cmd_trace_rays(
-                commands.buffer,
-                &self.shader_binding_table.raygen_region,
-                &self.shader_binding_table.misses_region,
-                &self.shader_binding_table.closest_hits_region,
-                &self.shader_binding_table.call_region,
-                data.target.extent.width,
-                data.target.extent.height,
-                1,
-            );
+            match &self.backend {
+                Backend::RayTracing(shader_binding_table) => ctx.ext.ray_tracing.cmd_trace_rays(
+                    commands.buffer,
+                    &shader_binding_table.raygen_region,
+                    &shader_binding_table.misses_region,
+                    &shader_binding_table.closest_hits_region,
+                    &shader_binding_table.call_region,
+                    region.extent.width,
+                    region.extent.height,
+                    1,
+                ),
+                Backend::Compute => ctx.cmd_dispatch(
+                    commands.buffer,
+                    region.extent.width.div_ceil(conf::WORKGROUP_SIZE),
+                    region.extent.height.div_ceil(conf::WORKGROUP_SIZE),
+                    1,
+                ),
+            }
         }
 
         self.pipeline.submit_pipeline(ctx, 0, sync_info);
     }
+
+    // Traces the center of the pixel and waits for its first hit. The instance is the one seen by the
+    // tracer, which may leave out hidden instances
+    pub fn pick<const FORMAT: image::Format>(
+        &self,
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+        push_constants: &inputs::PathtracerConstants,
+        pixel: glam::UVec2,
+    ) -> Option<PickResult> {
+        firestorm::profile_method!(pick);
+
+        self.pick_buffer
+            .fill_with(ctx, &inputs::PickResult::default());
+
+        let push_constants = inputs::PathtracerConstants {
+            pick_address: self.pick_buffer.get_device_address(ctx),
+            ..*push_constants
+        };
+        let crop = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: pixel.x.cast_signed(),
+                y: pixel.y.cast_signed(),
+            },
+            extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+        };
+        // without a fence the submission waits for the queue to go idle
+        let sync_info = SyncInfo {
+            wait_on: vec![],
+            signal_to: vec![],
+            fence: None,
+        };
+        self.run(
+            ctx,
+            data,
+            &push_constants,
+            Some(crop),
+            None,
+            false,
+            &sync_info,
+        );
+
+        let mut result = inputs::PickResult::default();
+        self.pick_buffer
+            .read_into(ctx, bytemuck::bytes_of_mut(&mut result));
+
+        (result.hit != 0).then_some(PickResult {
+            position: result.position,
+            instance: result.instance as _,
+            primitive: result.primitive as _,
+            material: result.material as _,
+        })
+    }
+
+    // The region split row by row into tiles of at most `tile_size` pixels a side, the edge tiles
+    // being smaller when it doesn't divide the region
+    fn tiles(region: vk::Rect2D, tile_size: Option<u32>) -> Vec<vk::Rect2D> {
+        let Some(tile_size) = tile_size else {
+            return vec![region];
+        };
+
+        (0..region.extent.height)
+            .step_by(tile_size as usize)
+            .flat_map(|y| {
+                (0..region.extent.width)
+                    .step_by(tile_size as usize)
+                    .map(move |x| vk::Rect2D {
+                        offset: vk::Offset2D {
+                            x: region.offset.x + x.cast_signed(),
+                            y: region.offset.y + y.cast_signed(),
+                        },
+                        extent: vk::Extent2D {
+                            width: tile_size.min(region.extent.width - x),
+                            height: tile_size.min(region.extent.height - y),
+                        },
+                    })
+            })
+            .collect()
+    }
+
+    // The crop clamped to the target, which may have been resized since it was set
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+    fn crop_region(crop: Option<vk::Rect2D>, extent: vk::Extent2D) -> vk::Rect2D {
+        let Some(crop) = crop else {
+            return extent.into();
+        };
+
+        let x = (crop.offset.x.max(0) as u32).min(extent.width - 1);
+        let y = (crop.offset.y.max(0) as u32).min(extent.height - 1);
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: x as _,
+                y: y as _,
+            },
+            extent: vk::Extent2D {
+                width: crop.extent.width.clamp(1, extent.width - x),
+                height: crop.extent.height.clamp(1, extent.height - y),
+            },
+        }
+    }
+}
+
+impl From<RenderMode> for u32 {
+    fn from(mode: RenderMode) -> Self {
+        match mode {
+            RenderMode::PathTracing => 0,
+            RenderMode::AmbientOcclusion => 1,
+        }
+    }
+}
+
+impl From<SamplingStrategy> for u32 {
+    fn from(sampling_strategy: SamplingStrategy) -> Self {
+        match sampling_strategy {
+            SamplingStrategy::CosineWeighted => bsdf::sampling_strategy::COSINE,
+            SamplingStrategy::Uniform => bsdf::sampling_strategy::UNIFORM,
+        }
+    }
+}
+
+impl From<DebugView> for u32 {
+    fn from(debug_view: DebugView) -> Self {
+        match debug_view {
+            DebugView::Final => 0,
+            DebugView::Albedo => 1,
+            DebugView::Normal => 2,
+            DebugView::Depth => 3,
+            DebugView::Bounces => 4,
+        }
+    }
+}
+
+impl Backend {
+    const fn bind_point(&self) -> vk::PipelineBindPoint {
+        match self {
+            Self::RayTracing(_) => vk::PipelineBindPoint::RAY_TRACING_KHR,
+            Self::Compute => vk::PipelineBindPoint::COMPUTE,
+        }
+    }
+
+    const fn stage(&self) -> vk::ShaderStageFlags {
+        match self {
+            Self::RayTracing(_) => vk::ShaderStageFlags::RAYGEN_KHR,
+            Self::Compute => vk::ShaderStageFlags::COMPUTE,
+        }
+    }
 }
 
 impl Destroy<Context> for Pipeline {
     unsafe fn destroy_with(&mut self, ctx: &Context) {
         firestorm::profile_method!(destroy_with);
 
-        self.shader_binding_table.destroy_with(ctx);
+        if let Backend::RayTracing(shader_binding_table) = &mut self.backend {
+            shader_binding_table.destroy_with(ctx);
+        }
+        self.pick_buffer.destroy_with(ctx);
         self.pipeline.destroy_with(ctx);
     }
 }
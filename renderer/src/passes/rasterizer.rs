@@ -1,26 +1,65 @@
-use std::slice;
+use std::{ops::Range, slice};
 
 use ash::vk;
 
 use shared::{inputs, scene};
 
 use crate::{
-    commands::Commands, context::Context, image, memory, pipeline, sync_info::SyncInfo, Destroy,
+    buffer::Buffer, commands::Commands, context::Context, image, memory, pipeline,
+    sync_info::SyncInfo, Destroy,
 };
 
 pub mod conf {
     pub const NAME: &str = "Rasterizer";
     pub const SHADER_VERT: &str = env!("rasterizer.vert.glsl");
     pub const SHADER_FRAG: &str = env!("rasterizer.frag.glsl");
+    pub const PUSH_CONSTANT_STAGES: ash::vk::ShaderStageFlags = ash::vk::ShaderStageFlags::from_raw(
+        ash::vk::ShaderStageFlags::VERTEX.as_raw() | ash::vk::ShaderStageFlags::FRAGMENT.as_raw(),
+    );
 }
 
+pub fn shaders() -> impl Iterator<Item = &'static str> {
+    [conf::SHADER_VERT, conf::SHADER_FRAG].into_iter()
+}
+
+// Precision of the depth buffer, falls back to less precise formats the device does support
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DepthFormat {
+    Unorm16,
+    Unorm24,
+    #[default]
+    Float32,
+}
+
+// How far the ambient occlusion of builds with the `ray_query_ao` feature looks for blockers, same
+// as for `RenderMode::AmbientOcclusion` of the path tracer
+#[derive(Clone, Copy, Debug)]
+pub struct Occlusion {
+    pub radius: f32,
+    pub ray_epsilon: f32,
+}
+
+// A mesh, the material override of its instances and their range within the draw order
+type Batch = (usize, Option<u32>, Range<u32>);
+
 pub struct Pipeline {
     depth: image::Image<{ image::Format::Depth }>,
     pipeline: pipeline::Pipeline<1>,
+    // refilled every frame in draw order
+    instances: Buffer,
+    // scene instances sorted by mesh and material override
+    draw_order: Vec<usize>,
+    batches: Vec<Batch>,
+    // instance transforms of the last frame, for motion vectors
+    previous_transforms: Vec<glam::Mat4>,
 }
 
 impl Pipeline {
-    pub fn create<const FORMAT: image::Format>(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+    pub fn create<const FORMAT: image::Format>(
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+        depth_format: vk::Format,
+    ) -> Self {
         firestorm::profile_method!(create);
 
         let commands = Commands::begin_on_queue(
@@ -30,19 +69,23 @@ impl Pipeline {
         );
 
         let depth = {
-            let info = vk::ImageCreateInfo::default().extent(data.target.extent.into());
+            // sampled by upscalers
+            let info = vk::ImageCreateInfo::default()
+                .extent(data.target.extent.into())
+                .usage(vk::ImageUsageFlags::SAMPLED);
 
-            image::Image::create(
+            image::Image::create_of_format(
                 ctx,
                 commands.buffer,
                 format!("{} Target - Depth", conf::NAME),
                 &info,
                 &memory::purpose::dedicated(),
                 Some(&image::BarrierInfo::DEPTH),
+                depth_format,
             )
         };
 
-        let (layout, pipeline) = Self::create_pipeline(ctx, data);
+        let (layout, pipeline) = Self::create_pipeline(ctx, data, depth_format);
 
         let descriptor_sets = data.descriptors.sets.iter().copied().map(|a| [a]);
 
@@ -58,17 +101,102 @@ impl Pipeline {
 
         commands.finish(ctx, &vk::SubmitInfo::default(), None);
 
-        Self { depth, pipeline }
+        let scene_info = &data.world.info.host;
+        let (draw_order, batches) = Self::batch_instances(scene_info);
+
+        let instances = {
+            let info = vk::BufferCreateInfo::default()
+                .usage(
+                    vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                )
+                .size(
+                    (std::mem::size_of::<inputs::RasterizerInstance>()
+                        * scene_info.instances.len().max(1)) as _,
+                );
+
+            Buffer::create(
+                ctx,
+                format!("{} Instances", conf::NAME),
+                info,
+                &memory::purpose::staging(),
+            )
+        };
+
+        Self {
+            depth,
+            pipeline,
+            instances,
+            draw_order,
+            batches,
+            previous_transforms: Vec::new(),
+        }
+    }
+
+    // The preferred format if the device supports it, otherwise the next less precise one
+    pub fn select_depth_format(ctx: &Context, preference: DepthFormat) -> vk::Format {
+        let supported = &ctx.physical_device.depth_formats;
+        let mut candidates = [
+            (DepthFormat::Float32, vk::Format::D32_SFLOAT),
+            (DepthFormat::Unorm24, vk::Format::D24_UNORM_S8_UINT),
+            (DepthFormat::Unorm16, vk::Format::D16_UNORM),
+        ]
+        .into_iter()
+        .filter(|&(depth_format, _)| depth_format <= preference)
+        .map(|(_, format)| format)
+        .peekable();
+
+        let preferred = candidates.peek().copied();
+        // D16_UNORM is required to be supported for depth attachments
+        let format = candidates
+            .find(|format| supported.contains(format))
+            .unwrap_or_else(|| image::Format::Depth.into());
+
+        if Some(format) != preferred {
+            eprintln!("Warning: {preference:?} depth is not supported, using {format:?} instead");
+        }
+        format
+    }
+
+    // Batches the instances again after their material overrides changed
+    pub fn rebatch(&mut self, scene_info: &::scene::Info) {
+        (self.draw_order, self.batches) = Self::batch_instances(scene_info);
+    }
+
+    fn batch_instances(scene_info: &::scene::Info) -> (Vec<usize>, Vec<Batch>) {
+        let key = |instance_index: usize| {
+            let instance = &scene_info.instances[instance_index];
+            (instance.blas_index, instance.material)
+        };
+
+        let mut draw_order = (0..scene_info.instances.len()).collect::<Vec<_>>();
+        draw_order.sort_by_key(|&instance_index| key(instance_index));
+
+        let mut batches = Vec::<Batch>::new();
+        for (slot, &instance_index) in draw_order.iter().enumerate() {
+            let (mesh, material) = key(instance_index);
+            match batches.last_mut() {
+                Some((last_mesh, last_material, range))
+                    if (*last_mesh, *last_material) == (mesh, material) =>
+                {
+                    range.end += 1;
+                }
+                _ => batches.push((mesh, material, slot as u32..slot as u32 + 1)),
+            }
+        }
+
+        (draw_order, batches)
     }
 
     fn create_pipeline<const FORMAT: image::Format>(
         ctx: &Context,
         data: &super::Data<FORMAT>,
+        depth_format: vk::Format,
     ) -> (vk::PipelineLayout, vk::Pipeline) {
         firestorm::profile_method!(create_pipeline);
 
         let push_constant_ranges = vk::PushConstantRange {
-            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            stage_flags: conf::PUSH_CONSTANT_STAGES,
             offset: 0,
             size: std::mem::size_of::<inputs::RasterizerConstants>() as _,
         };
@@ -105,16 +233,8 @@ impl Pipeline {
         let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::default()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
 
-        let viewport = vk::Viewport::default()
-            .width(data.target.extent.width as _)
-            .height(data.target.extent.height as _)
-            .max_depth(1.0);
-
-        let scissor = data.target.extent.into();
-
-        let viewport_info = vk::PipelineViewportStateCreateInfo::default()
-            .viewports(slice::from_ref(&viewport))
-            .scissors(slice::from_ref(&scissor));
+        // set per eye when drawing
+        let viewport_info = vk::PipelineViewportStateCreateInfo::default();
 
         let rasterization_info = vk::PipelineRasterizationStateCreateInfo::default()
             .line_width(1.0)
@@ -124,17 +244,22 @@ impl Pipeline {
         let multisample_info = vk::PipelineMultisampleStateCreateInfo::default()
             .rasterization_samples(vk::SampleCountFlags::TYPE_1);
 
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState::default()
-            .color_write_mask(vk::ColorComponentFlags::RGBA)
-            .blend_enable(true)
-            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-            .color_blend_op(vk::BlendOp::ADD)
-            .src_alpha_blend_factor(vk::BlendFactor::ONE)
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-            .alpha_blend_op(vk::BlendOp::ADD);
-        let color_blend_info = vk::PipelineColorBlendStateCreateInfo::default()
-            .attachments(slice::from_ref(&color_blend_attachment));
+        let color_blend_attachments = [
+            vk::PipelineColorBlendAttachmentState::default()
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+                .alpha_blend_op(vk::BlendOp::ADD),
+            // velocity
+            vk::PipelineColorBlendAttachmentState::default()
+                .color_write_mask(vk::ColorComponentFlags::RGBA),
+        ];
+        let color_blend_info =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
 
         let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::default()
             .depth_test_enable(true)
@@ -144,10 +269,17 @@ impl Pipeline {
             .max_depth_bounds(1.0)
             .stencil_test_enable(false);
 
-        let color_formats = [FORMAT.into()];
+        let dynamic_states = [
+            vk::DynamicState::VIEWPORT_WITH_COUNT,
+            vk::DynamicState::SCISSOR_WITH_COUNT,
+        ];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let color_formats = [FORMAT.into(), FORMAT.into()];
         let mut rendering_info = vk::PipelineRenderingCreateInfo::default()
             .color_attachment_formats(&color_formats)
-            .depth_attachment_format(image::Format::Depth.into());
+            .depth_attachment_format(depth_format);
 
         let create_info = vk::GraphicsPipelineCreateInfo::default()
             .stages(&shader_stages)
@@ -158,6 +290,7 @@ impl Pipeline {
             .multisample_state(&multisample_info)
             .color_blend_state(&color_blend_info)
             .depth_stencil_state(&depth_stencil_info)
+            .dynamic_state(&dynamic_state_info)
             .layout(layout)
             .push_next(&mut rendering_info);
 
@@ -180,7 +313,7 @@ impl Pipeline {
 
     fn vertex_binding_info() -> (
         [vk::VertexInputBindingDescription; 1],
-        [vk::VertexInputAttributeDescription; 2],
+        [vk::VertexInputAttributeDescription; 4],
     ) {
         let bindings = [vk::VertexInputBindingDescription {
             binding: 0,
@@ -201,26 +334,84 @@ impl Pipeline {
                 format: vk::Format::R32G32B32A32_SFLOAT,
                 offset: bytemuck::offset_of!(scene::Vertex, tex_coords) as _,
             },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: bytemuck::offset_of!(scene::Vertex, normal) as _,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 3,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: bytemuck::offset_of!(scene::Vertex, color) as _,
+            },
         ];
 
         (bindings, attributes)
     }
 
+    // Writes the instances in draw order and returns the address the vertex shader reads them from
+    fn upload_instances(&self, ctx: &Context, scene_info: &::scene::Info) -> vk::DeviceAddress {
+        firestorm::profile_method!(upload_instances);
+
+        let instance_data = self
+            .draw_order
+            .iter()
+            .map(|&instance_index| {
+                let transform = scene_info.instances[instance_index].transform;
+                inputs::RasterizerInstance {
+                    model_transform: transform,
+                    previous_model_transform: self
+                        .previous_transforms
+                        .get(instance_index)
+                        .copied()
+                        .unwrap_or(transform),
+                }
+            })
+            .collect::<Vec<_>>();
+        self.instances
+            .fill_from(ctx, bytemuck::cast_slice(&instance_data));
+        self.instances.get_device_address(ctx)
+    }
+
+    pub const fn depth(&self) -> &image::Image<{ image::Format::Depth }> {
+        &self.depth
+    }
+
     pub fn run<const FORMAT: image::Format>(
-        &self,
+        &mut self,
         ctx: &Context,
         data: &super::Data<FORMAT>,
+        clear_color: glam::Vec3,
+        instanced: bool,
+        occlusion: Occlusion,
         sync_info: &SyncInfo,
     ) {
         firestorm::profile_method!(run);
 
+        let scene_info = &data.world.info.host;
+        let instances_address = self.upload_instances(ctx, scene_info);
+
         let commands = self.pipeline.begin_pipeline(ctx, 0);
 
-        let color_attachments = [vk::RenderingAttachmentInfo::default()
-            .image_view(data.target.view)
-            .image_layout(vk::ImageLayout::GENERAL)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)];
+        let color_attachments = [
+            vk::RenderingAttachmentInfo::default()
+                .image_view(data.target.view)
+                .image_layout(vk::ImageLayout::GENERAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: clear_color.extend(1.).to_array(),
+                    },
+                }),
+            vk::RenderingAttachmentInfo::default()
+                .image_view(data.velocity.view)
+                .image_layout(vk::ImageLayout::GENERAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE),
+        ];
 
         let depth_attachment = vk::RenderingAttachmentInfo::default()
             .image_view(self.depth.view)
@@ -257,51 +448,135 @@ impl Pipeline {
                 0,
                 vk::IndexType::UINT32,
             );
+
+            ctx.cmd_bind_descriptor_sets(
+                commands.buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &self.pipeline.descriptor_sets[0],
+                &[],
+            );
         }
 
-        let scene_info = &data.world.info.host;
-        for instance in &scene_info.instances {
+        for (eye, viewport, scissor) in
+            Self::eye_viewports(self.depth.extent, data.uniforms.stereo())
+        {
+            unsafe {
+                ctx.cmd_set_viewport_with_count(commands.buffer, slice::from_ref(&viewport));
+                ctx.cmd_set_scissor_with_count(commands.buffer, slice::from_ref(&scissor));
+            }
+
+            let push_constants = inputs::RasterizerConstants {
+                instances_address,
+                material: 0,
+                eye,
+                ao_radius: occlusion.radius,
+                ray_epsilon: occlusion.ray_epsilon,
+            };
+            self.cmd_draw(ctx, commands.buffer, scene_info, push_constants, instanced);
+        }
+
+        unsafe { ctx.cmd_end_rendering(commands.buffer) };
+
+        self.previous_transforms.clear();
+        self.previous_transforms.extend(
+            scene_info
+                .instances
+                .iter()
+                .map(|instance| instance.transform),
+        );
+
+        self.pipeline.submit_pipeline(ctx, 0, sync_info);
+    }
+
+    // The eye drawn into each region of the target, side by side in stereo (see
+    // `Renderer::set_stereo`) or the whole target for the single eye
+    fn eye_viewports(extent: vk::Extent2D, stereo: bool) -> Vec<(f32, vk::Viewport, vk::Rect2D)> {
+        let half_width = extent.width / 2;
+        let eyes = if stereo {
+            vec![
+                (-1., 0, half_width),
+                (1., half_width, extent.width - half_width),
+            ]
+        } else {
+            vec![(0., 0, extent.width)]
+        };
+
+        eyes.into_iter()
+            .map(|(eye, x, width)| {
+                let viewport = vk::Viewport::default()
+                    .x(x as _)
+                    .width(width as _)
+                    .height(extent.height as _)
+                    .max_depth(1.0);
+                let scissor = vk::Rect2D::default()
+                    .offset(vk::Offset2D { x: x as _, y: 0 })
+                    .extent(vk::Extent2D {
+                        width,
+                        height: extent.height,
+                    });
+                (eye, viewport, scissor)
+            })
+            .collect()
+    }
+
+    // One draw per primitive of a mesh covering all of its instances, or when not instanced one per
+    // primitive of every instance. The push constants are those of every draw, but for the material,
+    // which is the instance's override if it has one
+    fn cmd_draw(
+        &self,
+        ctx: &Context,
+        command_buffer: vk::CommandBuffer,
+        scene_info: &::scene::Info,
+        push_constants: inputs::RasterizerConstants,
+        instanced: bool,
+    ) {
+        firestorm::profile_method!(cmd_draw);
+
+        // the instances are picked out of the instance buffer by the instance index
+        let draw = |primitive_index: usize, material: Option<u32>, instances: Range<u32>| {
+            let primitive_info = &scene_info.primitive_infos[primitive_index];
             let push_constants = inputs::RasterizerConstants {
-                model_transform: instance.transform,
-                material_index: scene_info.primitive_infos[instance.primitive_index].material,
-                ..Default::default()
+                material: material.unwrap_or(primitive_info.material),
+                ..push_constants
             };
 
             unsafe {
                 ctx.cmd_push_constants(
-                    commands.buffer,
+                    command_buffer,
                     self.pipeline.layout,
-                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    conf::PUSH_CONSTANT_STAGES,
                     0,
                     bytemuck::bytes_of(&push_constants),
                 );
 
-                ctx.cmd_bind_descriptor_sets(
-                    commands.buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    self.pipeline.layout,
-                    0,
-                    &self.pipeline.descriptor_sets[0],
-                    &[],
-                );
-
                 ctx.cmd_draw_indexed(
-                    commands.buffer,
-                    scene_info.primitive_sizes[instance.primitive_index].indices_size,
-                    1,
-                    scene_info.primitive_infos[instance.primitive_index].indices_offset,
-                    scene_info.primitive_infos[instance.primitive_index]
-                        .vertices_offset
-                        .try_into()
-                        .unwrap(),
-                    0,
+                    command_buffer,
+                    scene_info.primitive_sizes[primitive_index].indices_size,
+                    instances.len() as u32,
+                    primitive_info.indices_offset,
+                    primitive_info.vertices_offset.try_into().unwrap(),
+                    instances.start,
                 );
             }
-        }
-
-        unsafe { ctx.cmd_end_rendering(commands.buffer) };
+        };
 
-        self.pipeline.submit_pipeline(ctx, 0, sync_info);
+        if instanced {
+            for (mesh, material, instances) in &self.batches {
+                for primitive_index in scene_info.meshes[*mesh].primitives.clone() {
+                    draw(primitive_index, *material, instances.clone());
+                }
+            }
+        } else {
+            for (slot, &instance_index) in self.draw_order.iter().enumerate() {
+                let slot = slot as u32;
+                let instance = &scene_info.instances[instance_index];
+                for primitive_index in scene_info.meshes[instance.blas_index].primitives.clone() {
+                    draw(primitive_index, instance.material, slot..slot + 1);
+                }
+            }
+        }
     }
 }
 
@@ -311,5 +586,6 @@ impl Destroy<Context> for Pipeline {
 
         self.depth.destroy_with(ctx);
         self.pipeline.destroy_with(ctx);
+        self.instances.destroy_with(ctx);
     }
 }
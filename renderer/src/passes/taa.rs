@@ -0,0 +1,337 @@
+use std::slice;
+
+use ash::vk;
+
+use shared::inputs;
+
+use crate::{
+    commands::Commands,
+    context::Context,
+    descriptors::{DescriptorSetBuilder, Descriptors},
+    image, memory, pipeline,
+    sync_info::SyncInfo,
+    Destroy,
+};
+
+mod conf {
+    pub const NAME: &str = "Temporal Antialiasing";
+    pub const SHADER_COMP: &str = env!("taa.comp.glsl");
+    pub const WORKGROUP_SIZE: u32 = 16;
+    // fraction of the reprojected history kept every frame
+    pub const HISTORY_WEIGHT: f32 = 0.9;
+    // length of the subpixel jitter sequence
+    pub const NUM_JITTER_OFFSETS: u32 = 8;
+}
+
+pub fn shaders() -> impl Iterator<Item = &'static str> {
+    [conf::SHADER_COMP].into_iter()
+}
+
+// The resolved image ping-pongs between the two history images, each set reads one and writes the other
+const NUM_HISTORIES: usize = 2;
+
+pub struct Data<const FORMAT: image::Format> {
+    descriptors: Descriptors,
+    history: [image::Image<FORMAT>; NUM_HISTORIES],
+}
+
+pub struct Pipeline<const FORMAT: image::Format> {
+    data: Data<FORMAT>,
+    pipeline: pipeline::Pipeline<1>,
+}
+
+impl<const FORMAT: image::Format> Data<FORMAT> {
+    pub fn create(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let descriptors = Self::create_descriptors(ctx);
+
+        let commands = Commands::begin_on_queue(
+            ctx,
+            format!("{} - Initialization", conf::NAME),
+            ctx.queues.graphics(),
+        );
+
+        let history = [0, 1].map(|idx| {
+            let info = vk::ImageCreateInfo {
+                extent: vk::Extent3D {
+                    width: data.target.extent.width,
+                    height: data.target.extent.height,
+                    depth: 1,
+                },
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+                ..Default::default()
+            };
+            image::Image::create(
+                ctx,
+                commands.buffer,
+                format!("{} History {idx}", conf::NAME),
+                &info,
+                &memory::purpose::dedicated(),
+                Some(&image::BarrierInfo::GENERAL),
+            )
+        });
+
+        commands.finish(ctx, &vk::SubmitInfo::default(), None);
+
+        let taa_data = Self {
+            descriptors,
+            history,
+        };
+        taa_data.bind_to_descriptor_sets(ctx, data);
+        taa_data
+    }
+
+    fn create_descriptors(ctx: &Context) -> Descriptors {
+        firestorm::profile_method!(create_descriptors);
+
+        (0..4)
+            .fold(DescriptorSetBuilder::new(), |builder, binding| {
+                builder.binding(
+                    binding,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    1,
+                    vk::ShaderStageFlags::COMPUTE,
+                )
+            })
+            .build(ctx, NUM_HISTORIES as _)
+    }
+
+    fn bind_to_descriptor_sets(&self, ctx: &Context, data: &super::Data<FORMAT>) {
+        firestorm::profile_method!(bind_to_descriptor_sets);
+
+        let image_info = |image: &image::Image<FORMAT>| {
+            vk::DescriptorImageInfo::default()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(image.view)
+        };
+
+        let target_info = image_info(&data.target);
+        let velocity_info = image_info(&data.velocity);
+
+        for (idx, &set) in self.descriptors.sets.iter().enumerate() {
+            let infos = [
+                target_info,
+                velocity_info,
+                image_info(&self.history[idx]),
+                image_info(&self.history[Self::output_of(idx)]),
+            ];
+
+            let writes = infos
+                .iter()
+                .zip(0..)
+                .map(|(info, binding)| {
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(set)
+                        .dst_binding(binding)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(slice::from_ref(info))
+                })
+                .collect::<Vec<_>>();
+
+            unsafe {
+                ctx.update_descriptor_sets(&writes, &[]);
+            }
+        }
+    }
+
+    const fn output_of(set: usize) -> usize {
+        (set + 1) % NUM_HISTORIES
+    }
+}
+
+impl<const FORMAT: image::Format> Pipeline<FORMAT> {
+    pub fn create(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let data = Data::create(ctx, data);
+
+        let (layout, pipeline) = Self::create_pipeline(ctx, data.descriptors.layout);
+
+        let descriptor_sets = data.descriptors.sets.iter().copied().map(|a| [a]);
+
+        let pipeline = pipeline::Pipeline::new(
+            ctx,
+            conf::NAME.to_owned(),
+            descriptor_sets,
+            layout,
+            pipeline,
+            ctx.queues.graphics(),
+            1,
+        );
+
+        Self { data, pipeline }
+    }
+
+    fn create_pipeline(
+        ctx: &Context,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> (vk::PipelineLayout, vk::Pipeline) {
+        firestorm::profile_method!(create_pipeline);
+
+        let push_constant_ranges = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<inputs::TaaConstants>() as _,
+        };
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(slice::from_ref(&push_constant_ranges));
+
+        let layout = unsafe {
+            ctx.create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create pipeline layout")
+        };
+
+        let shader_module = ctx.create_shader_module_from_file(conf::SHADER_COMP);
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(crate::cstr!("main"));
+
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = unsafe {
+            ctx.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                slice::from_ref(&create_info),
+                None,
+            )
+            .expect("Failed to create pipeline")[0]
+        };
+
+        unsafe {
+            ctx.destroy_shader_module(shader_module, None);
+        }
+
+        (layout, pipeline)
+    }
+
+    // Blends the rasterized frame into the reprojected history and writes the result back into the
+    // render target. Frame 0 starts a new history.
+    pub fn run(&self, ctx: &Context, data: &super::Data<FORMAT>, frame: u32, sync_info: &SyncInfo) {
+        firestorm::profile_method!(run);
+
+        let set = frame as usize % NUM_HISTORIES;
+
+        let commands = self.pipeline.begin_pipeline(ctx, 0);
+
+        let memory_barrier = |src_stage, src_access, dst_stage, dst_access| unsafe {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access);
+            ctx.cmd_pipeline_barrier(
+                commands.buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                slice::from_ref(&barrier),
+                &[],
+                &[],
+            );
+        };
+
+        memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::SHADER_WRITE
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+
+        let push_constants = inputs::TaaConstants {
+            history_weight: if frame == 0 { 0. } else { conf::HISTORY_WEIGHT },
+        };
+
+        unsafe {
+            ctx.cmd_bind_pipeline(
+                commands.buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                *self.pipeline,
+            );
+
+            ctx.cmd_push_constants(
+                commands.buffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+
+            ctx.cmd_bind_descriptor_sets(
+                commands.buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &self.pipeline.descriptor_sets[set],
+                &[],
+            );
+
+            ctx.cmd_dispatch(
+                commands.buffer,
+                data.target.extent.width.div_ceil(conf::WORKGROUP_SIZE),
+                data.target.extent.height.div_ceil(conf::WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        memory_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+        );
+
+        self.data.history[Data::<FORMAT>::output_of(set)].cmd_copy_to(
+            ctx,
+            commands.buffer,
+            &data.target,
+            vk::ImageLayout::GENERAL,
+        );
+
+        self.pipeline.submit_pipeline(ctx, 0, sync_info);
+    }
+}
+
+// Offset of the projection for the given frame in normalized device coordinates, following the
+// (2, 3) Halton sequence so that consecutive frames cover the pixel evenly
+pub fn jitter(frame: u32, extent: vk::Extent2D) -> glam::Vec2 {
+    let index = frame % conf::NUM_JITTER_OFFSETS + 1;
+    let offset = glam::vec2(halton(index, 2), halton(index, 3)) - 0.5;
+    2. * offset / glam::vec2(extent.width as f32, extent.height as f32)
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.;
+    let mut result = 0.;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+impl<const FORMAT: image::Format> Destroy<Context> for Pipeline<FORMAT> {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.pipeline.destroy_with(ctx);
+        self.data.destroy_with(ctx);
+    }
+}
+
+impl<const FORMAT: image::Format> Destroy<Context> for Data<FORMAT> {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.history
+            .iter_mut()
+            .for_each(|history| history.destroy_with(ctx));
+        self.descriptors.destroy_with(ctx);
+    }
+}
@@ -2,9 +2,15 @@ use std::{ops::Deref, slice};
 
 use ash::vk;
 
+use shared::{inputs, tonemap};
+
 use crate::{
-    context::Context, descriptors::Descriptors, image, pipeline, sampler::Sampler,
-    sync_info::SyncInfo, Destroy,
+    context::Context,
+    descriptors::{DescriptorSetBuilder, Descriptors},
+    image, pipeline,
+    sampler::{AddressModes, Sampler},
+    sync_info::SyncInfo,
+    Destroy,
 };
 
 mod conf {
@@ -13,6 +19,20 @@ mod conf {
     pub const SHADER_FRAG: &str = env!("tonemap.frag.glsl");
 }
 
+pub fn shaders() -> impl Iterator<Item = &'static str> {
+    [conf::SHADER_VERT, conf::SHADER_FRAG].into_iter()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TonemapMode {
+    #[default]
+    Standard,
+    // luminance bands from blue (underexposed) to red (clipped) for judging exposure
+    FalseColor,
+    // the false color ramp spread evenly over [0, 1], for debug views that are not radiance
+    Heatmap,
+}
+
 pub struct Data<const FORMAT: image::Format> {
     descriptors: Descriptors,
     input_image: image::Image<FORMAT>,
@@ -25,7 +45,7 @@ pub struct Pipeline<const INPUT_FORMAT: image::Format, const OUTPUT_FORMAT: imag
 }
 
 impl<const FORMAT: image::Format> Data<FORMAT> {
-    pub fn create(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+    pub fn create(ctx: &Context, input: &image::Image<FORMAT>) -> Self {
         firestorm::profile_method!(create);
 
         let descriptors = Self::create_descriptors(ctx);
@@ -33,15 +53,16 @@ impl<const FORMAT: image::Format> Data<FORMAT> {
         let input_image = image::Image::new(
             ctx,
             format!("{} Input", conf::NAME),
-            data.target.image,
-            data.target.extent,
+            input.image,
+            input.extent,
             None,
         );
 
         let data = Self {
             descriptors,
             input_image,
-            sampler: Sampler::create(ctx, conf::NAME.to_owned()),
+            // the target is magnified at most, anisotropic filtering would not help
+            sampler: Sampler::create(ctx, conf::NAME.to_owned(), 1., AddressModes::REPEAT),
         };
         data.bind_to_descriptor_sets(ctx);
         data
@@ -50,46 +71,14 @@ impl<const FORMAT: image::Format> Data<FORMAT> {
     fn create_descriptors(ctx: &Context) -> Descriptors {
         firestorm::profile_method!(create_descriptors);
 
-        let layout = {
-            let binding = vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .descriptor_count(1)
-                .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-            let info =
-                vk::DescriptorSetLayoutCreateInfo::default().bindings(slice::from_ref(&binding));
-            unsafe {
-                ctx.create_descriptor_set_layout(&info, None)
-                    .expect("Failed to create descriptor set layout")
-            }
-        };
-
-        let pool = {
-            let num_frames = ctx.surface.config.image_count;
-            let size = vk::DescriptorPoolSize::default()
-                .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .descriptor_count(num_frames);
-            let info = vk::DescriptorPoolCreateInfo::default()
-                .pool_sizes(slice::from_ref(&size))
-                .max_sets(num_frames);
-            unsafe {
-                ctx.create_descriptor_pool(&info, None)
-                    .expect("Failed to create descriptor pool")
-            }
-        };
-
-        let sets = {
-            let layouts = vec![layout; ctx.surface.config.image_count as usize];
-            let info = vk::DescriptorSetAllocateInfo::default()
-                .descriptor_pool(pool)
-                .set_layouts(&layouts);
-            unsafe {
-                ctx.allocate_descriptor_sets(&info)
-                    .expect("Failed to allocate descriptor sets")
-            }
-        };
-
-        Descriptors { layout, pool, sets }
+        DescriptorSetBuilder::new()
+            .binding(
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                1,
+                vk::ShaderStageFlags::FRAGMENT,
+            )
+            .build(ctx, ctx.surface.config.image_count)
     }
 
     fn bind_to_descriptor_sets(&self, ctx: &Context) {
@@ -117,10 +106,15 @@ impl<const FORMAT: image::Format> Data<FORMAT> {
 impl<const INPUT_FORMAT: image::Format, const OUTPUT_FORMAT: image::Format>
     Pipeline<INPUT_FORMAT, OUTPUT_FORMAT>
 {
-    pub fn create(ctx: &Context, data: &super::Data<INPUT_FORMAT>) -> Self {
+    pub fn create(ctx: &Context, input: &image::Image<INPUT_FORMAT>) -> Self {
         firestorm::profile_method!(create);
 
-        let data = Data::create(ctx, data);
+        // shading happens in linear space, SDR output is encoded by the sRGB format of the surface and
+        // HDR output by the shader
+        assert_eq!(INPUT_FORMAT.color_space(), image::ColorSpace::Linear);
+        assert_eq!(OUTPUT_FORMAT.color_space(), image::ColorSpace::Srgb);
+
+        let data = Data::create(ctx, input);
 
         let (layout, pipeline) = Self::create_pipeline(ctx, data.descriptors.layout);
 
@@ -200,15 +194,23 @@ impl<const INPUT_FORMAT: image::Format, const OUTPUT_FORMAT: image::Format>
         let dynamic_state_info =
             vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
 
+        let push_constant_ranges = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<inputs::TonemapConstants>() as _,
+        };
+
         let layout_create_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(slice::from_ref(&descriptor_set_layout));
+            .set_layouts(slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(slice::from_ref(&push_constant_ranges));
 
         let layout = unsafe {
             ctx.create_pipeline_layout(&layout_create_info, None)
                 .expect("Failed to create pipeline layout")
         };
 
-        let color_formats = [OUTPUT_FORMAT.into()];
+        // the surface format is picked at runtime, HDR surfaces have formats of their own
+        let color_formats = [ctx.surface.config.surface_format.format];
         let mut rendering_info =
             vk::PipelineRenderingCreateInfo::default().color_attachment_formats(&color_formats);
 
@@ -246,6 +248,7 @@ impl<const INPUT_FORMAT: image::Format, const OUTPUT_FORMAT: image::Format>
         &self,
         ctx: &Context,
         idx: usize,
+        push_constants: inputs::TonemapConstants,
         sync_info: &SyncInfo,
         output_to: &image::Image<{ OUTPUT_FORMAT }>,
     ) {
@@ -293,6 +296,14 @@ impl<const INPUT_FORMAT: image::Format, const OUTPUT_FORMAT: image::Format>
                 &[],
             );
 
+            ctx.cmd_push_constants(
+                commands.buffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+
             let viewport = vk::Viewport::default()
                 .width(ctx.surface.config.extent.width as f32)
                 .height(ctx.surface.config.extent.height as f32)
@@ -318,6 +329,25 @@ impl<const INPUT_FORMAT: image::Format, const OUTPUT_FORMAT: image::Format>
     }
 }
 
+// How the output has to be encoded for the color space of the surface
+pub const fn encoding(color_space: vk::ColorSpaceKHR) -> u32 {
+    match color_space {
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => tonemap::encoding::SCRGB,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT => tonemap::encoding::HDR10,
+        _ => tonemap::encoding::SDR,
+    }
+}
+
+impl From<TonemapMode> for u32 {
+    fn from(mode: TonemapMode) -> Self {
+        match mode {
+            TonemapMode::Standard => tonemap::mode::STANDARD,
+            TonemapMode::FalseColor => tonemap::mode::FALSE_COLOR,
+            TonemapMode::Heatmap => tonemap::mode::HEATMAP,
+        }
+    }
+}
+
 impl<const INPUT_FORMAT: image::Format, const OUTPUT_FORMAT: image::Format> Destroy<Context>
     for Pipeline<INPUT_FORMAT, OUTPUT_FORMAT>
 {
@@ -0,0 +1,417 @@
+use std::slice;
+
+use ash::vk;
+
+use shared::inputs;
+
+use crate::{
+    commands::Commands,
+    context::Context,
+    descriptors::{DescriptorSetBuilder, Descriptors},
+    image, memory, pipeline,
+    sync_info::SyncInfo,
+    sync_state, Destroy,
+};
+
+mod conf {
+    pub const NAME: &str = "Tonemap (Compute)";
+    pub const SHADER_COMP: &str = env!("tonemap.comp.glsl");
+    pub const WORKGROUP_SIZE: u32 = 16;
+}
+
+pub fn shaders() -> impl Iterator<Item = &'static str> {
+    [conf::SHADER_COMP].into_iter()
+}
+
+// Tonemaps the post processed target into an image of its own without a render pass, for output
+// that is not presented. The shader encodes SDR output itself, see `tonemap::encoding::SRGB`
+// An image of the caller's to render into, see `Renderer::render_to_image`. It is in `layout`
+// before and after, which can't be UNDEFINED, and needs to support being blitted to
+#[derive(Clone, Copy, Debug)]
+pub struct ExternalImage {
+    pub image: vk::Image,
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub layout: vk::ImageLayout,
+}
+
+pub struct Data {
+    descriptors: Descriptors,
+    output: image::Image<{ image::Format::Encoded }>,
+}
+
+pub struct Pipeline {
+    data: Data,
+    pipeline: pipeline::Pipeline<1>,
+}
+
+impl Data {
+    pub fn create<const FORMAT: image::Format>(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let descriptors = Self::create_descriptors(ctx);
+
+        let commands = Commands::begin_on_queue(
+            ctx,
+            format!("{} - Initialization", conf::NAME),
+            ctx.queues.graphics(),
+        );
+
+        let info = vk::ImageCreateInfo {
+            extent: vk::Extent3D {
+                width: data.denoised.extent.width,
+                height: data.denoised.extent.height,
+                depth: 1,
+            },
+            usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+            ..Default::default()
+        };
+        let output = image::Image::create(
+            ctx,
+            commands.buffer,
+            format!("{} Output", conf::NAME),
+            &info,
+            &memory::purpose::dedicated(),
+            Some(&image::BarrierInfo::GENERAL),
+        );
+
+        commands.finish(ctx, &vk::SubmitInfo::default(), None);
+
+        let tonemap_data = Self {
+            descriptors,
+            output,
+        };
+        tonemap_data.bind_to_descriptor_sets(ctx, data);
+        tonemap_data
+    }
+
+    fn create_descriptors(ctx: &Context) -> Descriptors {
+        firestorm::profile_method!(create_descriptors);
+
+        (0..2)
+            .fold(DescriptorSetBuilder::new(), |builder, binding| {
+                builder.binding(
+                    binding,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    1,
+                    vk::ShaderStageFlags::COMPUTE,
+                )
+            })
+            .build(ctx, 1)
+    }
+
+    fn bind_to_descriptor_sets<const FORMAT: image::Format>(
+        &self,
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+    ) {
+        firestorm::profile_method!(bind_to_descriptor_sets);
+
+        let input_info = vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(data.denoised.view);
+        let output_info = vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.output.view);
+
+        for &set in &self.descriptors.sets {
+            let writes = [
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(slice::from_ref(&input_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(slice::from_ref(&output_info)),
+            ];
+
+            unsafe {
+                ctx.update_descriptor_sets(&writes, &[]);
+            }
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn create<const FORMAT: image::Format>(ctx: &Context, data: &super::Data<FORMAT>) -> Self {
+        firestorm::profile_method!(create);
+
+        let data = Data::create(ctx, data);
+
+        let (layout, pipeline) = Self::create_pipeline(ctx, data.descriptors.layout);
+
+        let descriptor_sets = data.descriptors.sets.iter().copied().map(|a| [a]);
+
+        // frames rendered into external images can be in flight like presented ones
+        let pipeline = pipeline::Pipeline::new(
+            ctx,
+            conf::NAME.to_owned(),
+            descriptor_sets,
+            layout,
+            pipeline,
+            ctx.queues.graphics(),
+            sync_state::conf::MAX_FRAMES_IN_FLIGHT,
+        );
+
+        Self { data, pipeline }
+    }
+
+    fn create_pipeline(
+        ctx: &Context,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> (vk::PipelineLayout, vk::Pipeline) {
+        firestorm::profile_method!(create_pipeline);
+
+        let push_constant_ranges = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<inputs::TonemapConstants>() as _,
+        };
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(slice::from_ref(&push_constant_ranges));
+
+        let layout = unsafe {
+            ctx.create_pipeline_layout(&layout_create_info, None)
+                .expect("Failed to create pipeline layout")
+        };
+
+        let shader_module = ctx.create_shader_module_from_file(conf::SHADER_COMP);
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(crate::cstr!("main"));
+
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = unsafe {
+            ctx.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                slice::from_ref(&create_info),
+                None,
+            )
+            .expect("Failed to create pipeline")[0]
+        };
+
+        unsafe {
+            ctx.destroy_shader_module(shader_module, None);
+        }
+
+        (layout, pipeline)
+    }
+
+    pub fn run(
+        &self,
+        ctx: &Context,
+        push_constants: inputs::TonemapConstants,
+        sync_info: &SyncInfo,
+    ) {
+        firestorm::profile_method!(run);
+
+        let commands = self.pipeline.begin_pipeline(ctx, 0);
+        self.cmd_tonemap(ctx, commands.buffer, push_constants);
+        self.pipeline.submit_pipeline(ctx, 0, sync_info);
+    }
+
+    // Tonemaps and then blits the output into the image of the caller, scaled to fit. The image is
+    // only touched once the semaphores are signaled. `idx` is that of the frame in flight
+    pub fn run_into(
+        &self,
+        ctx: &Context,
+        idx: usize,
+        push_constants: inputs::TonemapConstants,
+        target: &ExternalImage,
+        sync_info: &SyncInfo,
+    ) {
+        firestorm::profile_method!(run_into);
+
+        let commands = self.pipeline.begin_pipeline(ctx, idx);
+        self.cmd_tonemap(ctx, commands.buffer, push_constants);
+        self.cmd_blit_into(ctx, commands.buffer, target);
+        self.pipeline.submit_pipeline_waiting_at(
+            ctx,
+            idx,
+            sync_info,
+            vk::PipelineStageFlags::TRANSFER,
+        );
+    }
+
+    fn cmd_tonemap(
+        &self,
+        ctx: &Context,
+        command_buffer: vk::CommandBuffer,
+        push_constants: inputs::TonemapConstants,
+    ) {
+        unsafe {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+            ctx.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                slice::from_ref(&barrier),
+                &[],
+                &[],
+            );
+
+            ctx.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                *self.pipeline,
+            );
+
+            ctx.cmd_push_constants(
+                command_buffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+
+            ctx.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &self.pipeline.descriptor_sets[0],
+                &[],
+            );
+
+            ctx.cmd_dispatch(
+                command_buffer,
+                self.data.output.extent.width.div_ceil(conf::WORKGROUP_SIZE),
+                self.data
+                    .output
+                    .extent
+                    .height
+                    .div_ceil(conf::WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    fn cmd_blit_into(
+        &self,
+        ctx: &Context,
+        command_buffer: vk::CommandBuffer,
+        target: &ExternalImage,
+    ) {
+        let output = &self.data.output;
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let corner = |extent: vk::Extent2D| vk::Offset3D {
+            x: extent.width as _,
+            y: extent.height as _,
+            z: 1,
+        };
+
+        unsafe {
+            let barriers = [
+                vk::ImageMemoryBarrier::default()
+                    .image(output.image)
+                    .old_layout(vk::ImageLayout::GENERAL)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .subresource_range(subresource_range),
+                // its contents are overwritten, the layout it was in does not matter
+                vk::ImageMemoryBarrier::default()
+                    .image(target.image)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::empty())
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .subresource_range(subresource_range),
+            ];
+            ctx.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+
+            let region = vk::ImageBlit::default()
+                .src_subresource(subresource)
+                .src_offsets([vk::Offset3D::default(), corner(output.extent)])
+                .dst_subresource(subresource)
+                .dst_offsets([vk::Offset3D::default(), corner(target.extent)]);
+            ctx.cmd_blit_image(
+                command_buffer,
+                output.image,
+                vk::ImageLayout::GENERAL,
+                target.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                slice::from_ref(&region),
+                vk::Filter::LINEAR,
+            );
+
+            // whatever the caller does with it next waits on the semaphores
+            let barrier = vk::ImageMemoryBarrier::default()
+                .image(target.image)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(target.layout)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::empty())
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(subresource_range);
+            ctx.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                slice::from_ref(&barrier),
+            );
+        }
+    }
+
+    pub const fn output(&self) -> &image::Image<{ image::Format::Encoded }> {
+        &self.data.output
+    }
+}
+
+impl Destroy<Context> for Pipeline {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.pipeline.destroy_with(ctx);
+        self.data.destroy_with(ctx);
+    }
+}
+
+impl Destroy<Context> for Data {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.output.destroy_with(ctx);
+        self.descriptors.destroy_with(ctx);
+    }
+}
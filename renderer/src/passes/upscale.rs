@@ -0,0 +1,235 @@
+use std::slice;
+
+use ash::vk;
+
+use crate::{commands::Commands, context::Context, image, memory, Destroy};
+
+mod conf {
+    pub const NAME: &str = "Upscale";
+}
+
+// An image handed to an upscaler, in the layout it is in and has to be left in
+#[derive(Clone, Copy, Debug)]
+pub struct UpscaleImage {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub layout: vk::ImageLayout,
+}
+
+// The inputs are at the render resolution and the output at the window's. The color is linear HDR
+// after denoising and bloom, exposure and tonemapping come after the upscale
+pub struct UpscaleInputs {
+    pub color: UpscaleImage,
+    // the depth and motion are only written by the rasterizer
+    pub depth: UpscaleImage,
+    // difference of the unjittered current and previous positions, in normalized device coordinates
+    pub motion: UpscaleImage,
+    // subpixel offset of the projection in normalized device coordinates
+    pub jitter: glam::Vec2,
+    // the history no longer lines up with the frame, after a cut or while path tracing
+    pub reset: bool,
+    pub output: UpscaleImage,
+}
+
+// Resolves every frame from the render resolution to the window's before it is tonemapped. The
+// commands are recorded into a buffer that the renderer submits, after the earlier passes' writes
+// are visible. Anything the upscaler allocates is its own to clean up
+pub trait Upscaler {
+    fn upscale(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        inputs: &UpscaleInputs,
+    );
+}
+
+// The default, filters each frame to the output on its own without any history
+pub struct Bilinear {
+    filter: vk::Filter,
+}
+
+pub struct Pipeline<const FORMAT: image::Format> {
+    pub output: image::Image<FORMAT>,
+    commands: Commands,
+    bilinear: Bilinear,
+}
+
+impl Bilinear {
+    fn create(ctx: &Context, format: vk::Format) -> Self {
+        let filter = if ctx.supports_linear_blit(format) {
+            vk::Filter::LINEAR
+        } else {
+            vk::Filter::NEAREST
+        };
+        Self { filter }
+    }
+}
+
+impl Upscaler for Bilinear {
+    #[allow(clippy::cast_possible_wrap)]
+    fn upscale(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        inputs: &UpscaleInputs,
+    ) {
+        firestorm::profile_method!(upscale);
+
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let corner = |extent: vk::Extent2D| vk::Offset3D {
+            x: extent.width as _,
+            y: extent.height as _,
+            z: 1,
+        };
+        let region = vk::ImageBlit::default()
+            .src_subresource(subresource)
+            .src_offsets([vk::Offset3D::default(), corner(inputs.color.extent)])
+            .dst_subresource(subresource)
+            .dst_offsets([vk::Offset3D::default(), corner(inputs.output.extent)]);
+
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                inputs.color.image,
+                inputs.color.layout,
+                inputs.output.image,
+                inputs.output.layout,
+                slice::from_ref(&region),
+                self.filter,
+            );
+        }
+    }
+}
+
+impl<const FORMAT: image::Format> Pipeline<FORMAT> {
+    pub fn create(ctx: &Context) -> Self {
+        firestorm::profile_method!(create);
+
+        let commands = Commands::begin_on_queue(
+            ctx,
+            format!("{} - Initialization", conf::NAME),
+            ctx.queues.graphics(),
+        );
+
+        let output = {
+            let info = vk::ImageCreateInfo {
+                extent: ctx.surface.config.extent.into(),
+                usage: vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+                ..Default::default()
+            };
+            image::Image::create(
+                ctx,
+                commands.buffer,
+                format!("{} Output", conf::NAME),
+                &info,
+                &memory::purpose::dedicated(),
+                Some(&image::BarrierInfo::GENERAL),
+            )
+        };
+
+        commands.finish(ctx, &vk::SubmitInfo::default(), None);
+
+        Self {
+            output,
+            commands: Commands::create_on_queue(ctx, conf::NAME.to_owned(), ctx.queues.graphics()),
+            bilinear: Bilinear::create(ctx, FORMAT.into()),
+        }
+    }
+
+    // Without an upscaler of the user's the frame is filtered bilinearly. The submission waits for
+    // the queue to go idle, like those of the passes before it
+    pub fn run(
+        &mut self,
+        ctx: &Context,
+        data: &super::Data<FORMAT>,
+        depth: &image::Image<{ image::Format::Depth }>,
+        reset: bool,
+        upscaler: Option<&mut (dyn Upscaler + 'static)>,
+    ) {
+        firestorm::profile_method!(run);
+
+        let commands = self.commands.restart(ctx);
+
+        let memory_barrier = |src_stage, src_access, dst_stage, dst_access| unsafe {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access);
+            ctx.cmd_pipeline_barrier(
+                commands.buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                slice::from_ref(&barrier),
+                &[],
+                &[],
+            );
+        };
+
+        memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::SHADER_WRITE
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ,
+        );
+
+        let inputs = UpscaleInputs {
+            color: UpscaleImage::general(&data.denoised),
+            depth: UpscaleImage {
+                layout: image::BarrierInfo::DEPTH.layout,
+                ..UpscaleImage::general(depth)
+            },
+            motion: UpscaleImage::general(&data.velocity),
+            jitter: data.uniforms.jitter(),
+            reset,
+            output: UpscaleImage::general(&self.output),
+        };
+
+        upscaler
+            .unwrap_or(&mut self.bilinear)
+            .upscale(ctx, commands.buffer, &inputs);
+
+        // the tonemap pass samples the output
+        memory_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::SHADER_WRITE
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        commands.submit(ctx, &vk::SubmitInfo::default(), None);
+    }
+}
+
+impl UpscaleImage {
+    const fn general<const FORMAT: image::Format>(image: &image::Image<FORMAT>) -> Self {
+        Self {
+            image: image.image,
+            view: image.view,
+            extent: image.extent,
+            format: image.format,
+            layout: vk::ImageLayout::GENERAL,
+        }
+    }
+}
+
+impl<const FORMAT: image::Format> Destroy<Context> for Pipeline<FORMAT> {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.commands.destroy_with(ctx);
+        self.output.destroy_with(ctx);
+    }
+}
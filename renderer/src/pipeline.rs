@@ -53,13 +53,30 @@ impl<const NUM_SETS: usize> Pipeline<{ NUM_SETS }> {
     }
 
     pub fn submit_pipeline(&self, ctx: &Context, idx: usize, sync_info: &SyncInfo) {
-        firestorm::profile_method!(submit_pipeline);
+        self.submit_pipeline_waiting_at(
+            ctx,
+            idx,
+            sync_info,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        );
+    }
+
+    // For pipelines whose first use of what the semaphores guard is not a color attachment
+    pub fn submit_pipeline_waiting_at(
+        &self,
+        ctx: &Context,
+        idx: usize,
+        sync_info: &SyncInfo,
+        wait_stage: vk::PipelineStageFlags,
+    ) {
+        firestorm::profile_method!(submit_pipeline_waiting_at);
 
+        let wait_stages = vec![wait_stage; sync_info.wait_on.len()];
         let mut submit_info = vk::SubmitInfo::default();
         if !sync_info.wait_on.is_empty() {
             submit_info = submit_info
                 .wait_semaphores(&sync_info.wait_on)
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT]);
+                .wait_dst_stage_mask(&wait_stages);
         }
         if !sync_info.signal_to.is_empty() {
             submit_info = submit_info.signal_semaphores(&sync_info.signal_to);
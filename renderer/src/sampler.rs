@@ -1,32 +1,69 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
 
 use ash::vk;
 
-use super::{context::Context, Destroy};
+use super::{
+    context::{Context, Resource},
+    Destroy,
+};
+
+mod conf {
+    pub const DEFAULT_MAX_ANISOTROPY: f32 = 16.;
+}
 
 pub struct Sampler {
     sampler: vk::Sampler,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AddressModes {
+    pub u: vk::SamplerAddressMode,
+    pub v: vk::SamplerAddressMode,
+}
+
+// Samplers shared between all the textures with the same address modes
+pub struct Cache {
+    max_anisotropy: f32,
+    samplers: HashMap<AddressModes, Sampler>,
+}
+
 impl Sampler {
-    pub fn create(ctx: &Context, name: String) -> Self {
+    // Anisotropy is clamped to what the device supports, with 1 or less (or no device support)
+    // falling back to trilinear filtering
+    pub fn create(
+        ctx: &Context,
+        name: String,
+        max_anisotropy: f32,
+        address_modes: AddressModes,
+    ) -> Self {
         firestorm::profile_method!(create);
 
+        let max_anisotropy = max_anisotropy.min(
+            ctx.physical_device
+                .properties
+                .v_1_0
+                .limits
+                .max_sampler_anisotropy,
+        );
+        let anisotropy_enable = ctx.physical_device.supports_anisotropy && max_anisotropy > 1.;
+
         let info = vk::SamplerCreateInfo::default()
             .mag_filter(vk::Filter::LINEAR)
             .min_filter(vk::Filter::LINEAR)
             .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .address_mode_u(vk::SamplerAddressMode::REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::REPEAT)
+            .max_lod(vk::LOD_CLAMP_NONE)
+            .address_mode_u(address_modes.u)
+            .address_mode_v(address_modes.v)
             .address_mode_w(vk::SamplerAddressMode::REPEAT)
-            .anisotropy_enable(true)
-            .max_anisotropy(
-                ctx.physical_device
-                    .properties
-                    .v_1_0
-                    .limits
-                    .max_sampler_anisotropy,
-            )
+            .anisotropy_enable(anisotropy_enable)
+            .max_anisotropy(if anisotropy_enable {
+                max_anisotropy
+            } else {
+                1.
+            })
             .unnormalized_coordinates(false)
             .compare_enable(false)
             .compare_op(vk::CompareOp::ALWAYS);
@@ -36,16 +73,89 @@ impl Sampler {
                 .expect("Failed to create sampler")
         };
         ctx.set_debug_name(sampler, &(name + " - Sampler"));
+        ctx.track_created(Resource::Sampler);
 
         Self { sampler }
     }
 }
 
+impl AddressModes {
+    pub const REPEAT: Self = Self {
+        u: vk::SamplerAddressMode::REPEAT,
+        v: vk::SamplerAddressMode::REPEAT,
+    };
+
+    pub const fn for_texture(texture: &scene::TextureInfo) -> Self {
+        Self {
+            u: address_mode(texture.wrap_s),
+            v: address_mode(texture.wrap_t),
+        }
+    }
+}
+
+const fn address_mode(wrap: scene::Wrap) -> vk::SamplerAddressMode {
+    match wrap {
+        scene::Wrap::Repeat => vk::SamplerAddressMode::REPEAT,
+        scene::Wrap::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        scene::Wrap::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+    }
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self {
+            max_anisotropy: conf::DEFAULT_MAX_ANISOTROPY,
+            samplers: HashMap::new(),
+        }
+    }
+
+    pub fn get_or_create(&mut self, ctx: &Context, address_modes: AddressModes) -> vk::Sampler {
+        let max_anisotropy = self.max_anisotropy;
+        **self.samplers.entry(address_modes).or_insert_with(|| {
+            Sampler::create(
+                ctx,
+                format!("Texture {:?} {:?}", address_modes.u, address_modes.v),
+                max_anisotropy,
+                address_modes,
+            )
+        })
+    }
+
+    pub fn get(&self, address_modes: AddressModes) -> vk::Sampler {
+        *self.samplers[&address_modes]
+    }
+
+    // Recreates every sampler, the previous ones must no longer be in use
+    pub fn set_max_anisotropy(&mut self, ctx: &Context, max_anisotropy: f32) {
+        firestorm::profile_method!(set_max_anisotropy);
+
+        self.max_anisotropy = max_anisotropy;
+        let address_modes = self.samplers.keys().copied().collect::<Vec<_>>();
+        unsafe {
+            self.destroy_with(ctx);
+        }
+        for address_modes in address_modes {
+            self.get_or_create(ctx, address_modes);
+        }
+    }
+}
+
+impl Destroy<Context> for Cache {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        self.samplers
+            .drain()
+            .for_each(|(_, mut sampler)| sampler.destroy_with(ctx));
+    }
+}
+
 impl Destroy<Context> for Sampler {
     unsafe fn destroy_with(&mut self, ctx: &Context) {
         firestorm::profile_method!(destroy_with);
 
         ctx.destroy_sampler(self.sampler, None);
+        ctx.track_destroyed(Resource::Sampler);
     }
 }
 
@@ -14,6 +14,8 @@ pub struct RayTracingShaders {
     raygen: vk::ShaderModule,
     misses: Vec<vk::ShaderModule>,
     closest_hits: Vec<vk::ShaderModule>,
+    // the i-th any-hit shader is paired with the i-th closest-hit shader in its hit group
+    any_hits: Vec<vk::ShaderModule>,
 }
 
 impl ShaderBindingTable {
@@ -61,8 +63,8 @@ impl ShaderBindingTable {
                 .get_ray_tracing_shader_group_handles(
                     pipeline,
                     0,
-                    rt_shaders.num_stages() as _,
-                    rt_shaders.num_stages() * handle_size,
+                    rt_shaders.num_groups() as _,
+                    rt_shaders.num_groups() * handle_size,
                 )
                 .expect("Failed to get ray tracing shader group handles")
         };
@@ -121,6 +123,7 @@ impl RayTracingShaders {
         raygen_file: &str,
         misses_files: &[&str],
         closest_hits_files: &[&str],
+        any_hits_files: &[&str],
     ) -> Self {
         firestorm::profile_method!(new);
 
@@ -142,11 +145,20 @@ impl RayTracingShaders {
                 module
             })
             .collect();
+        let any_hits = any_hits_files
+            .iter()
+            .map(|shader| {
+                let module = ctx.create_shader_module_from_file(shader);
+                ctx.set_debug_name(module, shader);
+                module
+            })
+            .collect();
 
         Self {
             raygen,
             misses,
             closest_hits,
+            any_hits,
         }
     }
 
@@ -178,11 +190,20 @@ impl RayTracingShaders {
             );
         }
 
+        for any_hit_shader in &self.any_hits {
+            stages.push(
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::ANY_HIT_KHR)
+                    .module(*any_hit_shader)
+                    .name(crate::cstr!("main")),
+            );
+        }
+
         stages
     }
 
     pub fn groups_create_infos(&self) -> Vec<vk::RayTracingShaderGroupCreateInfoKHR> {
-        let mut groups = Vec::with_capacity(self.num_stages());
+        let mut groups = Vec::with_capacity(self.num_groups());
 
         groups.push(
             vk::RayTracingShaderGroupCreateInfoKHR::default()
@@ -204,13 +225,19 @@ impl RayTracingShaders {
             );
         }
 
+        let any_hits_start = 1 + self.misses.len() + self.closest_hits.len();
         for i in 0..self.closest_hits.len() {
+            let any_hit_shader = if i < self.any_hits.len() {
+                (any_hits_start + i) as _
+            } else {
+                vk::SHADER_UNUSED_KHR
+            };
             groups.push(
                 vk::RayTracingShaderGroupCreateInfoKHR::default()
                     .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
                     .general_shader(vk::SHADER_UNUSED_KHR)
                     .closest_hit_shader((1 + self.misses.len() + i) as _)
-                    .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                    .any_hit_shader(any_hit_shader)
                     .intersection_shader(vk::SHADER_UNUSED_KHR),
             );
         }
@@ -219,6 +246,10 @@ impl RayTracingShaders {
     }
 
     fn num_stages(&self) -> usize {
+        self.num_groups() + self.any_hits.len()
+    }
+
+    fn num_groups(&self) -> usize {
         1 + self.misses.len() + self.closest_hits.len()
     }
 }
@@ -242,5 +273,8 @@ impl Destroy<Context> for RayTracingShaders {
         self.closest_hits
             .iter()
             .for_each(|&module| ctx.destroy_shader_module(module, None));
+        self.any_hits
+            .iter()
+            .for_each(|&module| ctx.destroy_shader_module(module, None));
     }
 }
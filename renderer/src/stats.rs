@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderStats {
+    // samples per pixel accumulated since the last reset
+    pub accumulated_samples: u32,
+    pub use_pathtracer: bool,
+    // acceleration structures, or the BVH when tracing in software
+    pub accel_build_time: Duration,
+    // unique triangles, instanced meshes are only counted once
+    pub num_triangles: u64,
+    pub num_instances: usize,
+    // None when tracing in software
+    pub accel_memory: Option<AsMemoryReport>,
+    // device local memory in use by the whole process, as reported by the driver
+    pub vram_used: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AsMemoryReport {
+    // sizes of the acceleration structures themselves, excluding scratch and instance buffers
+    pub blas_bytes: u64,
+    pub tlas_bytes: u64,
+    // BLAS bytes before compaction, the same as `blas_bytes` when compaction is skipped
+    pub uncompacted_blas_bytes: u64,
+}
+
+impl AsMemoryReport {
+    // Fraction of the uncompacted BLAS memory still in use after compaction
+    pub fn compaction_ratio(&self) -> f64 {
+        if self.uncompacted_blas_bytes == 0 {
+            1.
+        } else {
+            self.blas_bytes as f64 / self.uncompacted_blas_bytes as f64
+        }
+    }
+}
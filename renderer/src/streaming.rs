@@ -0,0 +1,86 @@
+use std::{path::PathBuf, sync::mpsc, thread};
+
+use crate::texture::Texels;
+
+mod conf {
+    // decoded images waiting to be uploaded, bounds the host memory they hold on to
+    pub const DECODED_QUEUE_LENGTH: usize = 4;
+    // uploads per frame, so that streaming never stalls rendering for long
+    pub const MAX_UPLOADS_PER_FRAME: usize = 2;
+}
+
+// Streamed images are decoded in the background and uploaded a few per rendered frame, the textures
+// sample a placeholder until then. With a budget (in bytes), images that would push the resident
+// texture memory past it are never uploaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureLoading {
+    #[default]
+    Eager,
+    Streamed {
+        budget: Option<u64>,
+    },
+}
+
+pub struct Decoded {
+    pub index: usize,
+    pub name: String,
+    pub texels: Texels,
+}
+
+pub struct Streamer {
+    decoded: mpsc::Receiver<Decoded>,
+    budget: Option<u64>,
+    resident_bytes: u64,
+}
+
+impl Streamer {
+    pub fn start(sources: Vec<PathBuf>, budget: Option<u64>) -> Self {
+        firestorm::profile_method!(start);
+
+        let (sender, decoded) = mpsc::sync_channel(conf::DECODED_QUEUE_LENGTH);
+
+        thread::spawn(move || {
+            for (index, source) in sources.into_iter().enumerate() {
+                let decoded = Decoded {
+                    index,
+                    name: source.to_str().unwrap_or_default().to_owned(),
+                    texels: Texels::open(&source),
+                };
+                // the world was destroyed, there is nothing left to stream to
+                if sender.send(decoded).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            decoded,
+            budget,
+            resident_bytes: 0,
+        }
+    }
+
+    // Decoded images to upload this frame, those that do not fit in the budget are dropped
+    pub fn next_uploads(&mut self) -> Vec<Decoded> {
+        firestorm::profile_method!(next_uploads);
+
+        let mut uploads = Vec::new();
+        while uploads.len() < conf::MAX_UPLOADS_PER_FRAME {
+            let Ok(decoded) = self.decoded.try_recv() else {
+                break;
+            };
+
+            let size = decoded.texels.resident_size();
+            if self
+                .budget
+                .is_some_and(|budget| self.resident_bytes + size > budget)
+            {
+                continue;
+            }
+
+            self.resident_bytes += size;
+            uploads.push(decoded);
+        }
+        uploads
+    }
+}
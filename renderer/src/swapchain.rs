@@ -4,6 +4,23 @@ use ash::vk;
 
 use crate::{context::Context, image, Destroy};
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+// How well the swapchain still matches the surface, ordered from best to worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Optimal,
+    // still usable, the frame is presented but the swapchain should be recreated after it
+    Suboptimal,
+    // nothing was acquired or presented, the swapchain must be recreated first
+    OutOfDate,
+}
+
 pub struct Swapchain {
     pub swapchain: vk::SwapchainKHR,
     pub images: Vec<image::Image<{ image::Format::Swapchain }>>,
@@ -57,24 +74,31 @@ impl Swapchain {
         Self { swapchain, images }
     }
 
-    pub fn get_next_image(&self, ctx: &Context, signal_to: vk::Semaphore) -> (u32, bool) {
+    pub fn get_next_image(&self, ctx: &Context, signal_to: vk::Semaphore) -> (u32, Status) {
         firestorm::profile_method!(get_next_image);
 
         unsafe {
             ctx.ext
                 .swapchain
                 .acquire_next_image(self.swapchain, u64::MAX, signal_to, vk::Fence::null())
-                .unwrap_or((0, true))
+                .map_or_else(
+                    |err| {
+                        if err == vk::Result::ERROR_DEVICE_LOST {
+                            ctx.fail(err);
+                        }
+                        (0, Status::OutOfDate)
+                    },
+                    |(image_index, suboptimal)| (image_index, Status::from_suboptimal(suboptimal)),
+                )
         }
     }
 
-    // Returns true if the swapchain needs recreating
     pub fn present_to_when(
         &self,
         ctx: &Context,
         image_index: usize,
         wait_on: &[vk::Semaphore],
-    ) -> bool {
+    ) -> Status {
         firestorm::profile_method!(present_to_when);
 
         let image_index = image_index as _;
@@ -87,7 +111,25 @@ impl Swapchain {
             ctx.ext
                 .swapchain
                 .queue_present(**ctx.queues.graphics(), &present_info)
-                .unwrap_or(true)
+                .map_or_else(
+                    |err| {
+                        if err == vk::Result::ERROR_DEVICE_LOST {
+                            ctx.fail(err);
+                        }
+                        Status::OutOfDate
+                    },
+                    Status::from_suboptimal,
+                )
+        }
+    }
+}
+
+impl Status {
+    const fn from_suboptimal(suboptimal: bool) -> Self {
+        if suboptimal {
+            Self::Suboptimal
+        } else {
+            Self::Optimal
         }
     }
 }
@@ -100,3 +142,13 @@ impl Destroy<Context> for Swapchain {
         ctx.ext.swapchain.destroy_swapchain(self.swapchain, None);
     }
 }
+
+impl From<PresentMode> for vk::PresentModeKHR {
+    fn from(present_mode: PresentMode) -> Self {
+        match present_mode {
+            PresentMode::Fifo => Self::FIFO,
+            PresentMode::Mailbox => Self::MAILBOX,
+            PresentMode::Immediate => Self::IMMEDIATE,
+        }
+    }
+}
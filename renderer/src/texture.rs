@@ -1,28 +1,152 @@
+use std::path::Path;
+
 use ash::vk;
 
-use super::{context::Context, image, sampler::Sampler, Destroy};
+use super::{context::Context, image, sampler::AddressModes, scope::Scope, Destroy};
+
+pub struct Texture {
+    image: TextureImage, // view only, the image memory is owned by the world
+    // key of the sampler in the world's sampler cache
+    pub address_modes: AddressModes,
+}
+
+// Decoded texels of a scene image, at the precision of its source
+pub enum Texels {
+    Color(::image::RgbaImage),
+    Color16(image::Rgba16Image),
+    Hdr(::image::Rgba32FImage),
+}
 
-pub struct Texture<const FORMAT: image::Format> {
-    pub view: vk::ImageView,
-    pub sampler: Sampler,
+// Scene images are uploaded in a format that keeps the precision of their source
+pub enum TextureImage {
+    Color(image::Image<{ image::Format::Color }>),
+    Color16(image::Image<{ image::Format::Color16 }>),
+    Hdr(image::Image<{ image::Format::Hdr }>),
 }
 
-impl<const FORMAT: image::Format> Texture<FORMAT> {
-    pub fn for_image(ctx: &Context, name: String, image: &image::Image<{ FORMAT }>) -> Self {
+impl Texture {
+    pub fn for_image(
+        ctx: &Context,
+        name: String,
+        image: &TextureImage,
+        color_space: image::ColorSpace,
+        address_modes: AddressModes,
+    ) -> Self {
         firestorm::profile_method!(for_image);
 
-        let sampler = Sampler::create(ctx, name);
         Self {
-            view: image.view,
-            sampler,
+            image: image.view(ctx, name, color_space),
+            address_modes,
+        }
+    }
+
+    pub const fn view(&self) -> vk::ImageView {
+        match &self.image {
+            TextureImage::Color(image) => image.view,
+            TextureImage::Color16(image) => image.view,
+            TextureImage::Hdr(image) => image.view,
         }
     }
 }
 
-impl<const FORMAT: image::Format> Destroy<Context> for Texture<FORMAT> {
+impl Texels {
+    // 8-bit sources are widened to RGBA, 16-bit ones keep their precision and float ones their range
+    pub fn open(source: &Path) -> Self {
+        let decoded = ::image::open(source).expect("Unable to load image");
+        match decoded.color() {
+            ::image::ColorType::Rgb32F | ::image::ColorType::Rgba32F => {
+                Self::Hdr(decoded.into_rgba32f())
+            }
+            ::image::ColorType::L16
+            | ::image::ColorType::La16
+            | ::image::ColorType::Rgb16
+            | ::image::ColorType::Rgba16 => Self::Color16(decoded.into_rgba16()),
+            _ => Self::Color(decoded.into_rgba8()),
+        }
+    }
+
+    // the mip chain adds up to a third on top of the base level
+    pub fn resident_size(&self) -> u64 {
+        let (dimensions, texel_size) = match self {
+            Self::Color(texels) => (texels.dimensions(), 4),
+            Self::Color16(texels) => (texels.dimensions(), 8),
+            Self::Hdr(texels) => (texels.dimensions(), 16),
+        };
+        u64::from(dimensions.0) * u64::from(dimensions.1) * texel_size * 4 / 3
+    }
+}
+
+impl TextureImage {
+    // The 16-bit and float formats have no sRGB counterpart to view them with. 16-bit texels that
+    // are sampled as sRGB are decoded to linear values beforehand, float ones are linear already.
+    pub fn upload(
+        ctx: &Context,
+        scope: &mut Scope,
+        name: String,
+        texels: Texels,
+        srgb: bool,
+    ) -> Self {
+        firestorm::profile_method!(upload);
+
+        match texels {
+            Texels::Color(texels) => {
+                Self::Color(image::Image::<{ image::Format::Color }>::create_from_image(
+                    ctx, scope, name, &texels,
+                ))
+            }
+            Texels::Color16(mut texels) => {
+                if srgb {
+                    for pixel in texels.pixels_mut() {
+                        for channel in &mut pixel.0[..3] {
+                            *channel = decode_srgb16(*channel);
+                        }
+                    }
+                }
+                Self::Color16(
+                    image::Image::<{ image::Format::Color16 }>::create_from_image(
+                        ctx, scope, name, &texels,
+                    ),
+                )
+            }
+            Texels::Hdr(texels) => {
+                Self::Hdr(image::Image::<{ image::Format::Hdr }>::create_from_image(
+                    ctx, scope, name, &texels,
+                ))
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context, name: String, color_space: image::ColorSpace) -> Self {
+        match self {
+            Self::Color(image) => Self::Color(image.view_as(ctx, name, color_space)),
+            Self::Color16(image) => Self::Color16(image.view_as(ctx, name, color_space)),
+            Self::Hdr(image) => Self::Hdr(image.view_as(ctx, name, color_space)),
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn decode_srgb16(encoded: u16) -> u16 {
+    let linear = shared::tonemap::srgb_to_linear(f32::from(encoded) / f32::from(u16::MAX));
+    (linear * f32::from(u16::MAX)).round() as u16
+}
+
+impl Destroy<Context> for Texture {
     unsafe fn destroy_with(&mut self, ctx: &Context) {
         firestorm::profile_method!(destroy_with);
 
-        self.sampler.destroy_with(ctx);
+        self.image.destroy_with(ctx);
+    }
+}
+
+impl Destroy<Context> for TextureImage {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        match self {
+            Self::Color(image) => image.destroy_with(ctx),
+            Self::Color16(image) => image.destroy_with(ctx),
+            Self::Hdr(image) => image.destroy_with(ctx),
+        }
     }
 }
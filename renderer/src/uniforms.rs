@@ -1,11 +1,43 @@
+use std::time::Instant;
+
 use ash::vk;
 
 use shared::inputs;
 
 use super::{buffer::Buffer, context::Context, Destroy};
 
+mod conf {
+    // the background also lights the scene in the path tracer
+    pub const DEFAULT_BACKGROUND_COLOR: glam::Vec3 = glam::Vec3::ONE;
+    // primary rays start on the near plane, so nothing behind the camera is seen
+    pub const ORTHOGRAPHIC_Z_NEAR: f32 = 0.;
+    pub const ORTHOGRAPHIC_Z_FAR: f32 = 1e+4;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    // whatever projection the camera comes with
+    #[default]
+    Perspective,
+    // parallel rays through a view `height` world units tall, as wide as the aspect ratio allows
+    Orthographic {
+        height: f32,
+    },
+}
+
+// Distance between the eyes in world units
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ipd(pub f32);
+
 pub struct Uniforms {
     data: inputs::Uniforms,
+    // as given, before the projection is applied
+    camera: inputs::Camera,
+    projection: Projection,
+    aspect_ratio: f32,
+    // camera of the frame currently being rendered, becomes the previous camera of the next one
+    frame_camera: inputs::Camera,
+    last_frame: Instant,
     buffer: Buffer,
     dirty: bool,
 }
@@ -14,7 +46,13 @@ impl Uniforms {
     pub fn create(ctx: &Context, camera: inputs::Camera) -> Self {
         firestorm::profile_method!(create);
 
-        let data = inputs::Uniforms { camera };
+        let data = inputs::Uniforms {
+            camera,
+            shutter_camera: camera,
+            background_color: conf::DEFAULT_BACKGROUND_COLOR,
+            env_intensity: 1.,
+            ..Default::default()
+        };
 
         let buffer_info = vk::BufferCreateInfo::default()
             .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
@@ -29,6 +67,11 @@ impl Uniforms {
 
         Self {
             data,
+            camera,
+            projection: Projection::default(),
+            aspect_ratio: 1.,
+            frame_camera: camera,
+            last_frame: Instant::now(),
             buffer,
             dirty: false,
         }
@@ -43,11 +86,120 @@ impl Uniforms {
         }
     }
 
+    // Called once at the start of every frame, time is frozen unless it advances
+    pub fn begin_frame(&mut self, jitter: glam::Vec2, advance_time: bool) {
+        self.data.previous_camera = self.frame_camera;
+        self.frame_camera = self.data.camera;
+        self.data.jitter = jitter;
+
+        let now = Instant::now();
+        self.data.delta_time = if advance_time {
+            (now - self.last_frame).as_secs_f32()
+        } else {
+            0.
+        };
+        self.data.time += self.data.delta_time;
+        self.last_frame = now;
+
+        self.dirty = true;
+    }
+
+    pub const fn jitter(&self) -> glam::Vec2 {
+        self.data.jitter
+    }
+
+    pub const fn camera(&self) -> inputs::Camera {
+        self.data.camera
+    }
+
+    // The camera it replaces is kept as the shutter camera, for motion blur
     pub fn update_camera(&mut self, camera: inputs::Camera) {
-        self.data.camera = camera;
+        self.data.shutter_camera = self.data.camera;
+        self.camera = camera;
+        self.apply_projection();
+    }
+
+    // Opens the shutter at the current camera, so that nothing is blurred until it moves again
+    pub const fn reset_shutter(&mut self) {
+        self.data.shutter_camera = self.data.camera;
+    }
+
+    pub fn set_projection(&mut self, projection: Projection, aspect_ratio: f32) {
+        self.projection = projection;
+        self.aspect_ratio = aspect_ratio;
+        self.apply_projection();
+        // blurring from one projection to another would smear the whole image
+        self.reset_shutter();
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        self.aspect_ratio = aspect_ratio;
+        self.apply_projection();
+        self.reset_shutter();
+    }
+
+    fn apply_projection(&mut self) {
+        self.data.camera = match self.projection {
+            Projection::Perspective => self.camera,
+            Projection::Orthographic { height } => {
+                let half_extent = glam::vec2(height * self.aspect_ratio, height) / 2.;
+                inputs::Camera {
+                    proj: inputs::Transform::proj(glam::Mat4::orthographic_rh(
+                        -half_extent.x,
+                        half_extent.x,
+                        -half_extent.y,
+                        half_extent.y,
+                        conf::ORTHOGRAPHIC_Z_NEAR,
+                        conf::ORTHOGRAPHIC_Z_FAR,
+                    )),
+                    ..self.camera
+                }
+            }
+        };
+        self.data.orthographic = matches!(self.projection, Projection::Orthographic { .. }).into();
         self.dirty = true;
     }
 
+    // Whether the color changed, which invalidates the accumulated image
+    pub fn update_background_color(&mut self, background_color: glam::Vec3) -> bool {
+        let changed = self.data.background_color != background_color;
+        self.data.background_color = background_color;
+        self.dirty |= changed;
+        changed
+    }
+
+    // Whether the intensity changed, which invalidates the accumulated image
+    #[allow(clippy::float_cmp)]
+    pub fn update_env_intensity(&mut self, env_intensity: f32) -> bool {
+        let changed = self.data.env_intensity != env_intensity;
+        self.data.env_intensity = env_intensity;
+        self.dirty |= changed;
+        changed
+    }
+
+    // Whether the shutter time changed, which invalidates the accumulated image
+    #[allow(clippy::float_cmp)]
+    pub fn update_shutter_time(&mut self, shutter_time: f32) -> bool {
+        let changed = self.data.shutter_time != shutter_time;
+        self.data.shutter_time = shutter_time;
+        self.dirty |= changed;
+        changed
+    }
+
+    // Whether stereo changed, which invalidates the accumulated image
+    #[allow(clippy::float_cmp)]
+    pub fn update_stereo(&mut self, ipd: Option<Ipd>) -> bool {
+        let eye_separation = ipd.map_or(0., |Ipd(ipd)| ipd);
+        let changed = self.data.eye_separation != eye_separation;
+        self.data.eye_separation = eye_separation;
+        self.dirty |= changed;
+        changed
+    }
+
+    pub fn stereo(&self) -> bool {
+        self.data.eye_separation != 0.
+    }
+
     pub fn buffer_info(&self) -> vk::DescriptorBufferInfo {
         vk::DescriptorBufferInfo::default()
             .buffer(*self.buffer)
@@ -1,28 +1,69 @@
+use std::{slice, time::Duration};
+
 use ash::vk;
 
 use crate::commands::Commands;
 
 use super::{
-    acceleration_structure::AccelerationStructures,
+    acceleration_structure::{AccelerationStructures, BuildPreference},
     buffer::Buffer,
+    bvh::Bvh,
     context::Context,
-    image::{Format, Image},
+    image::{ColorSpace, Format, Image},
     memory,
+    sampler::{self, AddressModes},
     scope::Scope,
-    texture::Texture,
-    Destroy,
+    stats::AsMemoryReport,
+    streaming::{Streamer, TextureLoading},
+    texture::{Texels, Texture, TextureImage},
+    Destroy, Error,
 };
 
+mod conf {
+    // stands out against anything a scene would plausibly be made of
+    pub const ERROR_MATERIAL_COLOR: glam::Vec3 = glam::Vec3::new(1., 0., 1.);
+}
+
 pub struct World {
     pub indices: Buffer,
     pub vertices: Buffer,
     primitives: Buffer,
     materials: Buffer,
+    // the material override of each instance, in the order the tracer reports instances in
+    instance_materials: Buffer,
+    lights: Buffer,
+    // kept to be edited and uploaded again as a whole
+    host_materials: Vec<scene::Material>,
+    // as loaded, each is uploaded again with its settings applied when they change
+    host_lights: Vec<scene::Light>,
+    light_settings: Vec<LightSettings>,
     pub scene_desc: Buffer,
-    images: Vec<Image<{ Format::Color }>>,
-    pub textures: Vec<Texture<{ Format::Color }>>,
+    // None until a streamed image is uploaded
+    images: Vec<Option<TextureImage>>,
+    // white, stands in for images that are not loaded and backs the default texture
+    placeholder: TextureImage,
+    streamer: Option<Streamer>,
+    // those of the scene followed by the default texture, see `clamp_texture_indices`
+    pub textures: Vec<Texture>,
+    pub samplers: sampler::Cache,
     pub info: SceneInfo,
-    pub accel: AccelerationStructures,
+    pub tracing: Tracing,
+    // instances left out of tracing, None traces all of them
+    visible_instances: Option<Vec<bool>>,
+}
+
+pub enum Tracing {
+    Hardware(AccelerationStructures),
+    // devices without ray tracing support trace through a BVH in a compute shader
+    Software(Bvh),
+}
+
+// How a light of the scene is shown, see `Renderer::set_light_enabled`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSettings {
+    pub enabled: bool,
+    // scales the intensity the light was loaded with
+    pub intensity: f32,
 }
 
 pub struct SceneInfo {
@@ -31,9 +72,81 @@ pub struct SceneInfo {
 }
 
 impl World {
-    pub fn create(ctx: &Context, scene: scene::Scene) -> Self {
+    pub fn check_limits(ctx: &Context, info: &scene::Info, max_textures: u32) -> Result<(), Error> {
+        firestorm::profile_method!(check_limits);
+
+        // the default texture takes up a slot as well
+        if info.textures.len() >= max_textures as usize {
+            return Err(Error::TooManyTextures {
+                count: info.textures.len(),
+                max: max_textures - 1,
+            });
+        }
+
+        // the compute fallback has no limits besides memory
+        if !ctx.physical_device.supports_ray_tracing {
+            return Ok(());
+        }
+        let limits = &ctx.physical_device.properties.acceleration_structure;
+
+        if info.instances.len() as u64 > limits.max_instance_count {
+            return Err(Error::TooManyInstances {
+                count: info.instances.len(),
+                max: limits.max_instance_count,
+            });
+        }
+
+        for (mesh_index, mesh) in info.meshes.iter().enumerate() {
+            if mesh.primitives.len() as u64 > limits.max_geometry_count {
+                return Err(Error::TooManyPrimitives {
+                    mesh: mesh_index,
+                    count: mesh.primitives.len(),
+                    max: limits.max_geometry_count,
+                });
+            }
+
+            let triangles = info.primitive_sizes[mesh.primitives.clone()]
+                .iter()
+                .map(|size| u64::from(size.count()))
+                .sum();
+            if triangles > limits.max_primitive_count {
+                return Err(Error::TooManyTriangles {
+                    mesh: mesh_index,
+                    count: triangles,
+                    max: limits.max_primitive_count,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // The build preference only applies to hardware acceleration structures
+    pub fn create(
+        ctx: &Context,
+        mut scene: scene::Scene,
+        build_preference: BuildPreference,
+        texture_loading: TextureLoading,
+    ) -> Self {
         firestorm::profile_method!(create);
 
+        Self::remap_invalid_materials(&mut scene);
+
+        let num_textures = scene.info.textures.len();
+        let clamped = scene
+            .data
+            .materials
+            .iter_mut()
+            .map(|material| Self::clamp_texture_indices(material, num_textures))
+            .filter(|&clamped| clamped)
+            .count();
+        if clamped > 0 {
+            eprintln!(
+                "Warning: {clamped} materials refer to textures that don't exist, they sample the \
+                 default texture instead"
+            );
+        }
+
         let mut scope = Scope::new(Commands::begin_on_queue(
             ctx,
             "World - Initialization".to_owned(),
@@ -43,13 +156,26 @@ impl World {
         let (vertices, indices) = Self::init_vertex_index_buffer(ctx, &mut scope, &scene.data);
         let primitives = Self::init_primitives_buffer(ctx, &mut scope, &scene.info);
         let materials = Self::init_materials_buffer(ctx, &mut scope, &scene.data);
+        let instance_materials = Self::init_instance_materials_buffer(ctx, &mut scope, &scene.info);
+        let lights = Self::init_lights_buffer(ctx, &mut scope, &scene.data);
+
+        // the BVH is built from the host copy of the geometry, before the textures consume it
+        let bvh = (!ctx.physical_device.supports_ray_tracing)
+            .then(|| Bvh::build(ctx, &scene.info, &scene.data));
 
-        let device_info = scene::SceneDesc {
+        let mut device_info = scene::SceneDesc {
             vertices_address: vertices.get_device_address(ctx),
             indices_address: indices.get_device_address(ctx),
             materials_address: materials.get_device_address(ctx),
             primitives_address: primitives.get_device_address(ctx),
+            instance_materials_address: instance_materials.get_device_address(ctx),
+            lights_address: lights.get_device_address(ctx),
+            num_lights: scene.data.lights.len() as _,
+            ..Default::default()
         };
+        if let Some(bvh) = &bvh {
+            bvh.fill_scene_desc(ctx, &mut device_info);
+        }
         let scene_desc = Self::init_scene_desc_buffer(ctx, &mut scope, &device_info);
 
         scope.finish(ctx);
@@ -60,8 +186,18 @@ impl World {
             ctx.queues.graphics(),
         ));
 
-        let scene::Scene { info, data } = scene;
-        let (images, textures) = Self::init_textures(ctx, &mut scope, &info, data);
+        let scene::Scene { info, mut data, .. } = scene;
+        let host_materials = std::mem::take(&mut data.materials);
+        let host_lights = std::mem::take(&mut data.lights);
+        let light_settings = vec![LightSettings::default(); host_lights.len()];
+        let (images, streamer) = Self::init_images(ctx, &mut scope, &info, data, texture_loading);
+        let placeholder = TextureImage::Color(Image::<{ Format::Color }>::create_from_image(
+            ctx,
+            &mut scope,
+            "Default Texture Pixel".to_owned(),
+            &image::RgbaImage::from_pixel(1, 1, image::Rgba([u8::MAX; 4])),
+        ));
+        let (textures, samplers) = Self::init_textures(ctx, &info, &images, &placeholder);
 
         scope.finish(ctx);
 
@@ -70,19 +206,396 @@ impl World {
             device: device_info,
         };
 
-        let accel = AccelerationStructures::build(ctx, &info);
+        let tracing = bvh.map_or_else(
+            || {
+                Tracing::Hardware(AccelerationStructures::build(
+                    ctx,
+                    &info,
+                    &host_materials,
+                    build_preference,
+                ))
+            },
+            Tracing::Software,
+        );
 
         Self {
             indices,
             vertices,
             primitives,
             materials,
+            instance_materials,
+            lights,
+            host_materials,
+            host_lights,
+            light_settings,
             scene_desc,
             images,
+            placeholder,
+            streamer,
             textures,
+            samplers,
             info,
-            accel,
+            tracing,
+            visible_instances: None,
+        }
+    }
+
+    pub fn update_instance_transform(
+        &mut self,
+        ctx: &Context,
+        index: usize,
+        transform: glam::Mat4,
+    ) {
+        firestorm::profile_method!(update_instance_transform);
+
+        self.info.host.instances[index].transform = transform;
+        self.update_instance(ctx, index);
+    }
+
+    // Rays whose cull mask shares no bits with the mask (see `scene::ray_mask`) pass through the
+    // instance
+    pub fn set_instance_mask(&mut self, ctx: &Context, index: usize, mask: u8) {
+        firestorm::profile_method!(set_instance_mask);
+
+        self.info.host.instances[index].visibility_mask = mask;
+        self.update_instance(ctx, index);
+    }
+
+    // Moves the instances and replaces the vertices of the primitives as posed by an animation, then
+    // rebuilds what is traced through the posed primitives
+    pub fn set_pose(&mut self, ctx: &Context, pose: &scene::animation::Pose) {
+        firestorm::profile_method!(set_pose);
+
+        for &(index, transform) in &pose.instance_transforms {
+            self.info.host.instances[index].transform = transform;
+        }
+
+        if !pose.primitive_vertices.is_empty() {
+            let mut scope = Scope::new(Commands::begin_on_queue(
+                ctx,
+                "World - Upload Posed Vertices".to_owned(),
+                ctx.queues.transfer(),
+            ));
+            for (primitive, vertices) in &pose.primitive_vertices {
+                let offset = self.info.host.primitive_infos[*primitive].vertices_offset as usize
+                    * std::mem::size_of::<scene::Vertex>();
+                self.vertices.cmd_fill_staged_at(
+                    ctx,
+                    &mut scope,
+                    offset as _,
+                    bytemuck::cast_slice(vertices),
+                );
+            }
+            scope.finish(ctx);
+        }
+
+        let visible = self.visible_instances.as_deref();
+        match &mut self.tracing {
+            Tracing::Hardware(accel) => {
+                let posed_meshes = self
+                    .info
+                    .host
+                    .meshes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, mesh)| {
+                        pose.primitive_vertices
+                            .iter()
+                            .any(|(primitive, _)| mesh.primitives.contains(primitive))
+                    })
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>();
+                accel.rebuild_meshes(
+                    ctx,
+                    &self.info,
+                    &self.host_materials,
+                    &posed_meshes,
+                    visible,
+                );
+            }
+            Tracing::Software(bvh) => {
+                for (primitive, vertices) in &pose.primitive_vertices {
+                    bvh.repose_primitive(*primitive, vertices);
+                }
+                Self::rebuild_bvh(ctx, bvh, &mut self.info, &self.scene_desc, visible);
+            }
+        }
+    }
+
+    fn update_instance(&mut self, ctx: &Context, index: usize) {
+        let visible = self.visible_instances.as_deref();
+        match &mut self.tracing {
+            Tracing::Hardware(accel) => {
+                accel.update_instance(ctx, &self.info.host, &self.host_materials, index, visible);
+            }
+            Tracing::Software(bvh) => {
+                Self::rebuild_bvh(ctx, bvh, &mut self.info, &self.scene_desc, visible);
+            }
+        }
+    }
+
+    // Traces only the instances set in the mask, or all of them again without one. Hidden instances
+    // are compacted out of the TLAS, so the acceleration structure descriptor must be rewritten
+    pub fn build_tlas_filtered(&mut self, ctx: &Context, mask: Option<&[bool]>) {
+        firestorm::profile_method!(build_tlas_filtered);
+
+        if let Some(mask) = mask {
+            assert_eq!(
+                mask.len(),
+                self.info.host.instances.len(),
+                "The visibility mask needs an entry per instance"
+            );
+        }
+        self.visible_instances = mask.map(<[bool]>::to_vec);
+
+        let visible = self.visible_instances.as_deref();
+        match &mut self.tracing {
+            Tracing::Hardware(accel) => {
+                accel.rebuild_tlas(ctx, &self.info.host, &self.host_materials, visible);
+            }
+            Tracing::Software(bvh) => {
+                Self::rebuild_bvh(ctx, bvh, &mut self.info, &self.scene_desc, visible);
+            }
+        }
+        self.upload_instance_materials(ctx);
+    }
+
+    // The scene instance of an instance index reported by the tracer, which counts only the visible
+    // instances in the TLAS
+    pub fn traced_instance(&self, index: usize) -> usize {
+        match (&self.tracing, &self.visible_instances) {
+            (Tracing::Hardware(_), Some(visible)) => visible
+                .iter()
+                .enumerate()
+                .filter(|&(_, &visible)| visible)
+                .nth(index)
+                .map_or(index, |(instance, _)| instance),
+            _ => index,
+        }
+    }
+
+    // Draws every primitive of the instance with the material instead of its own, or with their own
+    // again for None. The instance is made as opaque as the material is
+    pub fn set_instance_material(&mut self, ctx: &Context, index: usize, material: Option<u32>) {
+        firestorm::profile_method!(set_instance_material);
+
+        if let Some(material) = material {
+            assert!(
+                (material as usize) < self.host_materials.len(),
+                "The material override needs to be one of the materials of the scene"
+            );
+        }
+        self.info.host.instances[index].material = material;
+        self.upload_instance_materials(ctx);
+
+        // the BVH checks the alpha of every triangle it hits, whatever the material
+        if let Tracing::Hardware(accel) = &mut self.tracing {
+            let visible = self.visible_instances.as_deref();
+            accel.update_instance(ctx, &self.info.host, &self.host_materials, index, visible);
+        }
+    }
+
+    fn upload_instance_materials(&self, ctx: &Context) {
+        let visible = match self.tracing {
+            Tracing::Hardware(_) => self.visible_instances.as_deref(),
+            Tracing::Software(_) => None,
+        };
+
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "World - Update Instance Materials".to_owned(),
+            ctx.queues.transfer(),
+        ));
+        self.instance_materials.cmd_fill_staged(
+            ctx,
+            &mut scope,
+            bytemuck::cast_slice(&Self::traced_instance_materials(&self.info.host, visible)),
+        );
+        scope.finish(ctx);
+    }
+
+    // The hardware tracer counts only the visible instances, see `traced_instance`, while the BVH
+    // keeps all of them
+    fn traced_instance_materials(scene_info: &scene::Info, visible: Option<&[bool]>) -> Vec<u32> {
+        let mut materials = scene_info
+            .instances
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| visible.is_none_or(|visible| visible[index]))
+            .map(|(_, instance)| instance.material.unwrap_or(scene::NO_MATERIAL_OVERRIDE))
+            .collect::<Vec<_>>();
+        // buffers can't be empty
+        if materials.is_empty() {
+            materials.push(scene::NO_MATERIAL_OVERRIDE);
+        }
+        materials
+    }
+
+    fn rebuild_bvh(
+        ctx: &Context,
+        bvh: &mut Bvh,
+        info: &mut SceneInfo,
+        scene_desc: &Buffer,
+        visible: Option<&[bool]>,
+    ) {
+        bvh.rebuild(ctx, &info.host, visible);
+        bvh.fill_scene_desc(ctx, &mut info.device);
+
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "World - Update Scene Desc".to_owned(),
+            ctx.queues.transfer(),
+        ));
+        scene_desc.cmd_fill_staged(ctx, &mut scope, bytemuck::bytes_of(&info.device));
+        scope.finish(ctx);
+    }
+
+    pub fn materials(&self) -> &[scene::Material] {
+        &self.host_materials
+    }
+
+    // Geometry keeps the opacity it was built with, so alpha mode changes only show on primitives
+    // that were not opaque to begin with
+    pub fn update_material(&mut self, ctx: &Context, index: usize, material: scene::Material) {
+        firestorm::profile_method!(update_material);
+
+        self.host_materials[index] = material;
+        Self::clamp_texture_indices(
+            &mut self.host_materials[index],
+            self.info.host.textures.len(),
+        );
+
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "World - Update Materials".to_owned(),
+            ctx.queues.transfer(),
+        ));
+        self.materials
+            .cmd_fill_staged(ctx, &mut scope, bytemuck::cast_slice(&self.host_materials));
+        scope.finish(ctx);
+    }
+
+    // Each light with the name of its node, which is empty for unnamed nodes and scenes saved before
+    // lights were named
+    pub fn lights(&self) -> impl Iterator<Item = (&str, LightSettings)> {
+        self.light_settings
+            .iter()
+            .enumerate()
+            .map(|(index, &settings)| {
+                let name = self.info.host.light_names.get(index);
+                (name.map_or("", String::as_str), settings)
+            })
+    }
+
+    pub fn light_settings(&self) -> &[LightSettings] {
+        &self.light_settings
+    }
+
+    // Only the light itself is uploaded again, disabled lights are uploaded without any intensity
+    pub fn update_light(&mut self, ctx: &Context, index: usize, settings: LightSettings) {
+        firestorm::profile_method!(update_light);
+
+        self.light_settings[index] = settings;
+        let light = self.host_lights[index];
+        let light = scene::Light {
+            intensity: if settings.enabled {
+                light.intensity * settings.intensity
+            } else {
+                glam::Vec3::ZERO
+            },
+            ..light
+        };
+
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "World - Update Light".to_owned(),
+            ctx.queues.transfer(),
+        ));
+        self.lights.cmd_fill_staged_at(
+            ctx,
+            &mut scope,
+            (index * std::mem::size_of::<scene::Light>()) as _,
+            bytemuck::bytes_of(&light),
+        );
+        scope.finish(ctx);
+    }
+
+    // Uploads the next few streamed images and points their textures at them, returns whether any
+    // changed. The textures must not be in use by the GPU
+    pub fn stream_textures(&mut self, ctx: &Context) -> bool {
+        firestorm::profile_method!(stream_textures);
+
+        let Some(streamer) = &mut self.streamer else {
+            return false;
+        };
+
+        let uploads = streamer.next_uploads();
+        if uploads.is_empty() {
+            return false;
+        }
+
+        // mips are blitted, which needs a graphics queue
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "World - Stream Textures".to_owned(),
+            ctx.queues.graphics(),
+        ));
+        let uploaded = uploads
+            .into_iter()
+            .map(|decoded| {
+                self.images[decoded.index] = Some(TextureImage::upload(
+                    ctx,
+                    &mut scope,
+                    decoded.name,
+                    decoded.texels,
+                    Self::sampled_as_srgb(&self.info.host, decoded.index),
+                ));
+                decoded.index as u32
+            })
+            .collect::<Vec<_>>();
+        scope.finish(ctx);
+
+        for (idx, tex) in self.info.host.textures.iter().enumerate() {
+            if uploaded.contains(&tex.image_index) {
+                unsafe { self.textures[idx].destroy_with(ctx) };
+                self.textures[idx] =
+                    Self::create_texture(ctx, idx, tex, &self.images, &self.placeholder);
+            }
         }
+
+        true
+    }
+
+    // Grows the vertex and index buffers ahead of streaming in additional geometry
+    pub fn reserve_geometry(&mut self, ctx: &Context, num_vertices: usize, num_indices: usize) {
+        firestorm::profile_method!(reserve_geometry);
+
+        let mut scope = Scope::new(Commands::begin_on_queue(
+            ctx,
+            "World - Reserve Geometry".to_owned(),
+            ctx.queues.transfer(),
+        ));
+
+        let device_info = &mut self.info.device;
+        self.vertices.resize(
+            ctx,
+            &mut scope,
+            (num_vertices * std::mem::size_of::<scene::Vertex>()) as _,
+            memory::Priority::Medium,
+            |buffer| device_info.vertices_address = buffer.get_device_address(ctx),
+        );
+        self.indices.resize(
+            ctx,
+            &mut scope,
+            (num_indices * std::mem::size_of::<u32>()) as _,
+            memory::Priority::Medium,
+            |buffer| device_info.indices_address = buffer.get_device_address(ctx),
+        );
+
+        self.scene_desc
+            .cmd_fill_staged(ctx, &mut scope, bytemuck::bytes_of(&self.info.device));
+
+        scope.finish(ctx);
     }
 
     fn init_vertex_index_buffer(
@@ -92,12 +605,18 @@ impl World {
     ) -> (Buffer, Buffer) {
         firestorm::profile_method!(init_vertex_index_buffer);
 
+        let build_input_usage = if ctx.physical_device.supports_ray_tracing {
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+        } else {
+            vk::BufferUsageFlags::empty()
+        };
+
         let vertices = {
             let create_info = vk::BufferCreateInfo::default().usage(
                 vk::BufferUsageFlags::VERTEX_BUFFER
                     | vk::BufferUsageFlags::STORAGE_BUFFER
                     | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+                    | build_input_usage,
             );
 
             Buffer::create_with_staged_data(
@@ -115,7 +634,7 @@ impl World {
                 vk::BufferUsageFlags::INDEX_BUFFER
                     | vk::BufferUsageFlags::STORAGE_BUFFER
                     | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+                    | build_input_usage,
             );
 
             Buffer::create_with_staged_data(
@@ -165,6 +684,100 @@ impl World {
         )
     }
 
+    fn init_instance_materials_buffer(
+        ctx: &Context,
+        scope: &mut Scope,
+        scene: &scene::Info,
+    ) -> Buffer {
+        firestorm::profile_method!(init_instance_materials_buffer);
+
+        let create_info = vk::BufferCreateInfo::default().usage(
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        );
+
+        // every instance is traced to begin with, which later uploads never have more of
+        Buffer::create_with_staged_data(
+            ctx,
+            scope,
+            "Instance Materials".to_owned(),
+            create_info,
+            bytemuck::cast_slice(&Self::traced_instance_materials(scene, None)),
+            memory::Priority::Medium,
+        )
+    }
+
+    // Primitives of malformed assets may refer to materials that don't exist, which the shaders would
+    // read past the end of the buffer for. They are shown in an emissive error color instead
+    fn remap_invalid_materials(scene: &mut scene::Scene) {
+        firestorm::profile_method!(remap_invalid_materials);
+
+        // the error material goes after the existing ones
+        let error_material = scene.data.materials.len() as u32;
+        let mut count = 0;
+        for primitive in &mut scene.info.primitive_infos {
+            if primitive.material >= error_material {
+                primitive.material = error_material;
+                count += 1;
+            }
+        }
+        for material in scene
+            .info
+            .instances
+            .iter_mut()
+            .filter_map(|instance| instance.material.as_mut())
+        {
+            if *material >= error_material {
+                *material = error_material;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return;
+        }
+
+        eprintln!(
+            "Warning: {count} primitives and instances refer to materials that don't exist, they are \
+             shown in the error material"
+        );
+
+        scene.data.materials.push(scene::Material {
+            color: conf::ERROR_MATERIAL_COLOR,
+            color_texture: -1,
+            emittance: conf::ERROR_MATERIAL_COLOR,
+            emittance_texture: -1,
+            roughness: 1.,
+            metallic_roughness_texture: -1,
+            double_sided: 1,
+            ior: 1.,
+            ..Default::default()
+        });
+    }
+
+    // Scenes without lights still get a buffer, with a single unused light
+    fn init_lights_buffer(ctx: &Context, scope: &mut Scope, scene: &scene::Data) -> Buffer {
+        firestorm::profile_method!(init_lights_buffer);
+
+        let create_info = vk::BufferCreateInfo::default().usage(
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        );
+
+        let placeholder = [scene::Light::default()];
+        let lights = if scene.lights.is_empty() {
+            &placeholder
+        } else {
+            scene.lights.as_slice()
+        };
+
+        Buffer::create_with_staged_data(
+            ctx,
+            scope,
+            "Lights".to_owned(),
+            create_info,
+            bytemuck::cast_slice(lights),
+            memory::Priority::Medium,
+        )
+    }
+
     fn init_scene_desc_buffer(
         ctx: &Context,
         scope: &mut Scope,
@@ -185,60 +798,163 @@ impl World {
         )
     }
 
-    fn init_textures(
+    // Streamed images start out unloaded
+    fn init_images(
         ctx: &Context,
         scope: &mut Scope,
         scene_info: &scene::Info,
         scene_data: scene::Data,
-    ) -> (
-        Vec<Image<{ Format::Color }>>,
-        Vec<Texture<{ Format::Color }>>,
-    ) {
+        texture_loading: TextureLoading,
+    ) -> (Vec<Option<TextureImage>>, Option<Streamer>) {
+        firestorm::profile_method!(init_images);
+
+        let sources = scene_data
+            .images
+            .into_iter()
+            .map(|scene::Image { source }| source)
+            .collect::<Vec<_>>();
+
+        match texture_loading {
+            TextureLoading::Eager => {
+                let images = sources
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, source)| {
+                        Some(TextureImage::upload(
+                            ctx,
+                            scope,
+                            source.to_str().unwrap_or_default().to_owned(),
+                            Texels::open(&source),
+                            Self::sampled_as_srgb(scene_info, index),
+                        ))
+                    })
+                    .collect();
+                (images, None)
+            }
+            TextureLoading::Streamed { budget } => {
+                let images = sources.iter().map(|_| None).collect();
+                (images, Some(Streamer::start(sources, budget)))
+            }
+        }
+    }
+
+    fn init_textures(
+        ctx: &Context,
+        scene_info: &scene::Info,
+        images: &[Option<TextureImage>],
+        placeholder: &TextureImage,
+    ) -> (Vec<Texture>, sampler::Cache) {
         firestorm::profile_method!(init_textures);
 
-        let images = if scene_data.images.is_empty() {
-            vec![Image::create_from_image(
-                ctx,
-                scope,
-                "Placeholder Texture Pixel".to_owned(),
-                &image::RgbaImage::new(1, 1),
-            )]
-        } else {
-            scene_data
-                .images
-                .into_iter()
-                .map(|scene::Image { source }| {
-                    let image = image::open(&source)
-                        .expect("Unable to load image")
-                        .into_rgba8();
-                    Image::create_from_image(
-                        ctx,
-                        scope,
-                        source.to_str().unwrap_or_default().to_owned(),
-                        &image,
-                    )
-                })
-                .collect::<Vec<_>>()
+        // refers to no image, so that it always samples the placeholder
+        const DEFAULT_TEXTURE: scene::TextureInfo = scene::TextureInfo {
+            image_index: u32::MAX,
+            srgb: false,
+            wrap_s: scene::Wrap::Repeat,
+            wrap_t: scene::Wrap::Repeat,
         };
 
-        let scene_textures = if scene_info.textures.is_empty() {
-            std::slice::from_ref(&scene::TextureInfo { image_index: 0 })
-        } else {
-            scene_info.textures.as_slice()
-        };
-        let textures = scene_textures
+        let mut samplers = sampler::Cache::new();
+        let textures = scene_info
+            .textures
             .iter()
+            .chain(slice::from_ref(&DEFAULT_TEXTURE))
             .enumerate()
             .map(|(idx, tex)| {
-                Texture::for_image(
-                    ctx,
-                    format!("Texture - #{idx}"),
-                    &images[tex.image_index as usize],
-                )
+                samplers.get_or_create(ctx, AddressModes::for_texture(tex));
+                Self::create_texture(ctx, idx, tex, images, placeholder)
             })
             .collect();
 
-        (images, textures)
+        (textures, samplers)
+    }
+
+    // Points texture indices past the scene's textures at the default texture after them, so that
+    // every index the shaders sample with is bound. Returns whether any were out of range
+    fn clamp_texture_indices(material: &mut scene::Material, num_textures: usize) -> bool {
+        let default_texture = num_textures as i32;
+        let mut clamped = false;
+        for texture in [
+            &mut material.color_texture,
+            &mut material.emittance_texture,
+            &mut material.metallic_roughness_texture,
+        ] {
+            if *texture >= default_texture {
+                *texture = default_texture;
+                clamped = true;
+            }
+        }
+        clamped
+    }
+
+    // Whether any texture samples the image as sRGB
+    fn sampled_as_srgb(scene_info: &scene::Info, image_index: usize) -> bool {
+        scene_info
+            .textures
+            .iter()
+            .any(|tex| tex.srgb && tex.image_index as usize == image_index)
+    }
+
+    // Images that are not loaded (yet) are stood in for by the placeholder
+    fn create_texture(
+        ctx: &Context,
+        idx: usize,
+        tex: &scene::TextureInfo,
+        images: &[Option<TextureImage>],
+        placeholder: &TextureImage,
+    ) -> Texture {
+        let image = images
+            .get(tex.image_index as usize)
+            .and_then(Option::as_ref)
+            .unwrap_or(placeholder);
+
+        Texture::for_image(
+            ctx,
+            format!("Texture - #{idx}"),
+            image,
+            if tex.srgb {
+                ColorSpace::Srgb
+            } else {
+                ColorSpace::Linear
+            },
+            AddressModes::for_texture(tex),
+        )
+    }
+}
+
+impl Tracing {
+    pub const fn build_time(&self) -> Duration {
+        match self {
+            Self::Hardware(accel) => accel.build_time,
+            Self::Software(bvh) => bvh.build_time,
+        }
+    }
+
+    pub fn memory_report(&self) -> Option<AsMemoryReport> {
+        match self {
+            Self::Hardware(accel) => Some(accel.memory_report()),
+            Self::Software(_) => None,
+        }
+    }
+}
+
+impl Destroy<Context> for Tracing {
+    unsafe fn destroy_with(&mut self, ctx: &Context) {
+        firestorm::profile_method!(destroy_with);
+
+        match self {
+            Self::Hardware(accel) => accel.destroy_with(ctx),
+            Self::Software(bvh) => bvh.destroy_with(ctx),
+        }
+    }
+}
+
+impl Default for LightSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 1.,
+        }
     }
 }
 
@@ -246,11 +962,15 @@ impl Destroy<Context> for World {
     unsafe fn destroy_with(&mut self, ctx: &Context) {
         firestorm::profile_method!(destroy_with);
 
-        self.accel.destroy_with(ctx);
+        self.tracing.destroy_with(ctx);
         self.textures.destroy_with(ctx);
+        self.samplers.destroy_with(ctx);
         self.images.destroy_with(ctx);
+        self.placeholder.destroy_with(ctx);
         self.scene_desc.destroy_with(ctx);
         self.primitives.destroy_with(ctx);
+        self.lights.destroy_with(ctx);
+        self.instance_materials.destroy_with(ctx);
         self.materials.destroy_with(ctx);
         self.vertices.destroy_with(ctx);
         self.indices.destroy_with(ctx);
@@ -0,0 +1,405 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Vertex;
+
+// Keyframed node transforms and the skins they deform, sampled on the CPU into a `Pose`
+#[derive(Default, Deserialize, Serialize)]
+pub struct Animation {
+    // parents come before their children
+    pub nodes: Vec<Node>,
+    pub channels: Vec<Channel>,
+    pub skins: Vec<Skin>,
+    // instances placed rigidly by a node
+    pub instances: Vec<AnimatedInstance>,
+    pub skinned_primitives: Vec<SkinnedPrimitive>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct Node {
+    pub parent: Option<usize>,
+    // the rest pose, kept for the properties without a channel
+    pub translation: glam::Vec3,
+    pub rotation: glam::Quat,
+    pub scale: glam::Vec3,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    // in seconds, ascending
+    pub times: Vec<f32>,
+    // xyz for translations and scales, a quaternion for rotations. Cubic spline keyframes are
+    // stored as in tangent, value and out tangent
+    pub values: Vec<glam::Vec4>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Skin {
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<glam::Mat4>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct AnimatedInstance {
+    pub instance: usize,
+    pub node: usize,
+}
+
+// Skinned vertices end up in world space, so the instance of the primitive has to stay at the
+// origin
+#[derive(Deserialize, Serialize)]
+pub struct SkinnedPrimitive {
+    pub primitive: usize,
+    pub skin: usize,
+    // the vertices as loaded, which the joints deform
+    pub bind_vertices: Vec<Vertex>,
+    // up to four joints of the skin per vertex
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<glam::Vec4>,
+}
+
+// The animation at one point in time, ready to be handed to the renderer
+#[derive(Default)]
+pub struct Pose {
+    pub instance_transforms: Vec<(usize, glam::Mat4)>,
+    // replace the vertices of each primitive
+    pub primitive_vertices: Vec<(usize, Vec<Vertex>)>,
+}
+
+impl Animation {
+    pub const fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    // in seconds, until the last keyframe of any channel
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0., f32::max)
+    }
+
+    // The time wraps around the duration so that the animation loops
+    pub fn sample(&self, time: f32) -> Pose {
+        firestorm::profile_method!(sample);
+
+        let duration = self.duration();
+        let time = if duration > 0. {
+            time.rem_euclid(duration)
+        } else {
+            0.
+        };
+
+        let mut locals = self
+            .nodes
+            .iter()
+            .map(|node| (node.translation, node.rotation, node.scale))
+            .collect::<Vec<_>>();
+        for channel in &self.channels {
+            let value = channel.sample(time);
+            let local = &mut locals[channel.node];
+            match channel.property {
+                Property::Translation => local.0 = value.truncate(),
+                Property::Rotation => local.1 = glam::Quat::from_vec4(value).normalize(),
+                Property::Scale => local.2 = value.truncate(),
+            }
+        }
+
+        // parents are visited first so their global transforms are already known
+        let mut globals = Vec::<glam::Mat4>::with_capacity(self.nodes.len());
+        for (node, &(translation, rotation, scale)) in self.nodes.iter().zip(&locals) {
+            let local = glam::Mat4::from_scale_rotation_translation(scale, rotation, translation);
+            globals.push(node.parent.map_or(local, |parent| globals[parent] * local));
+        }
+
+        let joint_matrices = self
+            .skins
+            .iter()
+            .map(|skin| {
+                skin.joints
+                    .iter()
+                    .zip(&skin.inverse_bind_matrices)
+                    .map(|(&joint, &inverse_bind_matrix)| globals[joint] * inverse_bind_matrix)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        Pose {
+            instance_transforms: self
+                .instances
+                .iter()
+                .map(|animated| (animated.instance, globals[animated.node]))
+                .collect(),
+            primitive_vertices: self
+                .skinned_primitives
+                .iter()
+                .map(|skinned| {
+                    (
+                        skinned.primitive,
+                        skinned.pose(&joint_matrices[skinned.skin]),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    // Appends the other animation, rebasing onto the instances and primitives it was merged after
+    pub(crate) fn merge(&mut self, other: Self, instances_offset: usize, primitives_offset: usize) {
+        let nodes_offset = self.nodes.len();
+        let skins_offset = self.skins.len();
+
+        self.nodes.extend(other.nodes.into_iter().map(|node| Node {
+            parent: node.parent.map(|parent| parent + nodes_offset),
+            ..node
+        }));
+        self.channels
+            .extend(other.channels.into_iter().map(|channel| Channel {
+                node: channel.node + nodes_offset,
+                ..channel
+            }));
+        self.skins.extend(other.skins.into_iter().map(|skin| {
+            Skin {
+                joints: skin
+                    .joints
+                    .into_iter()
+                    .map(|joint| joint + nodes_offset)
+                    .collect(),
+                ..skin
+            }
+        }));
+        self.instances.extend(
+            other
+                .instances
+                .into_iter()
+                .map(|animated| AnimatedInstance {
+                    instance: animated.instance + instances_offset,
+                    node: animated.node + nodes_offset,
+                }),
+        );
+        self.skinned_primitives
+            .extend(
+                other
+                    .skinned_primitives
+                    .into_iter()
+                    .map(|skinned| SkinnedPrimitive {
+                        primitive: skinned.primitive + primitives_offset,
+                        skin: skinned.skin + skins_offset,
+                        ..skinned
+                    }),
+            );
+    }
+}
+
+impl Channel {
+    fn sample(&self, time: f32) -> glam::Vec4 {
+        let value = |keyframe: usize| match self.interpolation {
+            Interpolation::CubicSpline => self.values[3 * keyframe + 1],
+            _ => self.values[keyframe],
+        };
+
+        // held before the first and after the last keyframe
+        let next = self.times.partition_point(|&keyframe| keyframe <= time);
+        if next == 0 {
+            return value(0);
+        }
+        if next == self.times.len() {
+            return value(next - 1);
+        }
+        let prev = next - 1;
+
+        let delta = self.times[next] - self.times[prev];
+        let t = (time - self.times[prev]) / delta;
+
+        match (self.interpolation, self.property) {
+            (Interpolation::Step, _) => value(prev),
+            (Interpolation::Linear, Property::Rotation) => glam::Quat::from_vec4(value(prev))
+                .slerp(glam::Quat::from_vec4(value(next)), t)
+                .into(),
+            (Interpolation::Linear, _) => value(prev).lerp(value(next), t),
+            (Interpolation::CubicSpline, _) => {
+                let out_tangent = self.values[3 * prev + 2] * delta;
+                let in_tangent = self.values[3 * next] * delta;
+                // the hermite basis
+                let t2 = t * t;
+                value(prev) * t2.mul_add(2f32.mul_add(t, -3.), 1.)
+                    + out_tangent * t * (t - 1.) * (t - 1.)
+                    + value(next) * t2 * 2f32.mul_add(-t, 3.)
+                    + in_tangent * t2 * (t - 1.)
+            }
+        }
+    }
+}
+
+impl SkinnedPrimitive {
+    fn pose(&self, joint_matrices: &[glam::Mat4]) -> Vec<Vertex> {
+        self.bind_vertices
+            .iter()
+            .zip(&self.joints)
+            .zip(&self.weights)
+            .map(|((vertex, joints), weights)| {
+                let skin = joints
+                    .iter()
+                    .zip(weights.to_array())
+                    .map(|(&joint, weight)| joint_matrices[joint as usize] * weight)
+                    .fold(glam::Mat4::ZERO, |skin, matrix| skin + matrix);
+                // exact for rotations and uniform scales, which joints mostly are
+                let normal = skin
+                    .transform_vector3(vertex.normal.truncate())
+                    .normalize_or_zero();
+                Vertex {
+                    position: skin
+                        .transform_point3(vertex.position.truncate())
+                        .extend(vertex.position.w),
+                    normal: normal.extend(vertex.normal.w),
+                    ..*vertex
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rest(parent: Option<usize>, translation: glam::Vec3) -> Node {
+        Node {
+            parent,
+            translation,
+            rotation: glam::Quat::IDENTITY,
+            scale: glam::Vec3::ONE,
+        }
+    }
+
+    fn translation(node: usize, interpolation: Interpolation, values: &[[f32; 3]]) -> Channel {
+        Channel {
+            node,
+            property: Property::Translation,
+            interpolation,
+            times: (0..values.len()).map(|keyframe| keyframe as f32).collect(),
+            values: values
+                .iter()
+                .map(|&value| glam::Vec3::from(value).extend(0.))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn children_follow_their_animated_parent() {
+        let animation = Animation {
+            nodes: vec![rest(None, glam::Vec3::ZERO), rest(Some(0), glam::Vec3::Y)],
+            channels: vec![translation(
+                0,
+                Interpolation::Linear,
+                &[[0., 0., 0.], [2., 0., 0.]],
+            )],
+            instances: vec![AnimatedInstance {
+                instance: 3,
+                node: 1,
+            }],
+            ..Default::default()
+        };
+
+        let pose = animation.sample(0.5);
+        assert_eq!(pose.instance_transforms.len(), 1);
+        let (instance, transform) = pose.instance_transforms[0];
+        assert_eq!(instance, 3);
+        assert!(transform
+            .transform_point3(glam::Vec3::ZERO)
+            .abs_diff_eq(glam::vec3(1., 1., 0.), 1e-5));
+    }
+
+    #[test]
+    fn time_loops_and_steps_hold() {
+        let channel = translation(
+            0,
+            Interpolation::Step,
+            &[[0., 0., 0.], [1., 0., 0.], [2., 0., 0.]],
+        );
+        assert_eq!(channel.sample(0.9), glam::Vec4::ZERO);
+        assert_eq!(channel.sample(1.5), glam::Vec4::X);
+        assert_eq!(channel.sample(5.), glam::Vec4::X * 2.);
+
+        let animation = Animation {
+            nodes: vec![rest(None, glam::Vec3::ZERO)],
+            channels: vec![channel],
+            instances: vec![AnimatedInstance {
+                instance: 0,
+                node: 0,
+            }],
+            ..Default::default()
+        };
+        let (_, transform) = animation.sample(2.5).instance_transforms[0];
+        assert_eq!(transform.w_axis, glam::Vec4::W);
+    }
+
+    #[test]
+    fn rotations_are_slerped() {
+        let channel = Channel {
+            node: 0,
+            property: Property::Rotation,
+            interpolation: Interpolation::Linear,
+            times: vec![0., 1.],
+            values: vec![
+                glam::Quat::IDENTITY.into(),
+                glam::Quat::from_rotation_y(std::f32::consts::PI * 0.5).into(),
+            ],
+        };
+        let rotation = glam::Quat::from_vec4(channel.sample(0.5));
+        assert!(rotation.abs_diff_eq(
+            glam::Quat::from_rotation_y(std::f32::consts::FRAC_PI_4),
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn skinning_blends_joints() {
+        let vertex = Vertex::new(&[1., 0., 0.], &[0., 1., 0.], &[0.; 2], &[0.; 2], &[1.; 4]);
+        let animation = Animation {
+            nodes: vec![
+                rest(None, glam::Vec3::ZERO),
+                rest(None, glam::vec3(0., 2., 0.)),
+            ],
+            skins: vec![Skin {
+                joints: vec![0, 1],
+                inverse_bind_matrices: vec![glam::Mat4::IDENTITY; 2],
+            }],
+            skinned_primitives: vec![SkinnedPrimitive {
+                primitive: 0,
+                skin: 0,
+                bind_vertices: vec![vertex],
+                joints: vec![[0, 1, 0, 0]],
+                weights: vec![glam::vec4(0.5, 0.5, 0., 0.)],
+            }],
+            ..Default::default()
+        };
+
+        let pose = animation.sample(0.);
+        let (primitive, vertices) = &pose.primitive_vertices[0];
+        assert_eq!(*primitive, 0);
+        assert!(vertices[0]
+            .position
+            .truncate()
+            .abs_diff_eq(glam::vec3(1., 1., 0.), 1e-5));
+        assert!(vertices[0]
+            .normal
+            .truncate()
+            .abs_diff_eq(glam::Vec3::Y, 1e-5));
+    }
+}
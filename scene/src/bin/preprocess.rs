@@ -3,14 +3,29 @@ use std::{env, path::Path};
 use scene::io::FileLoader;
 
 fn main() {
-    let filename = env::args().nth(1).expect("No asset filename provided");
+    let mut filename = None;
+    let mut weld = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            // merges the duplicated vertices of each primitive, see `Scene::weld_vertices`
+            "--weld" => weld = true,
+            arg if arg.starts_with("--") => panic!("Unknown argument: {arg}"),
+            _ => filename = Some(arg),
+        }
+    }
+    let filename = filename.expect("No asset filename provided");
     let filepath = Path::new(&filename);
 
-    let scene = if scene::gltf::Gltf::can_load(filepath) {
+    let mut scene = if scene::gltf::Gltf::can_load(filepath) {
         scene::gltf::Gltf::load(filepath)
     } else {
         panic!("No loader found");
     };
 
+    if weld {
+        let removed = scene.weld_vertices();
+        println!("Welded away {removed} duplicate vertices");
+    }
+
     scene::io::save(&scene, filepath);
 }
@@ -0,0 +1,426 @@
+use std::{ops::Range, path::PathBuf};
+
+use shared::inputs;
+
+use crate::{
+    alpha_mode, ray_mask, BoundingBox, Image, Instance, Light, Material, Mesh, PrimitiveInfo,
+    PrimitiveSize, Scene, TextureInfo, Vertex,
+};
+
+// Assembles a scene in code instead of loading it from a file. Everything is referred to by the
+// index returned when it was added, which `build` checks before handing out the scene
+#[derive(Default)]
+pub struct SceneBuilder {
+    scene: Scene,
+    // of each primitive, in object space
+    bounding_boxes: Vec<BoundingBox>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    UnknownMaterial {
+        primitive: usize,
+        material: u32,
+    },
+    IndexOutOfBounds {
+        primitive: usize,
+        index: u32,
+        vertices: u32,
+    },
+    IncompleteTriangle {
+        primitive: usize,
+        indices: u32,
+    },
+    UnknownImage {
+        texture: usize,
+        image: u32,
+    },
+    UnknownTexture {
+        material: usize,
+        texture: i32,
+    },
+    // the primitives of a mesh can't be empty or go past the last primitive
+    InvalidMesh {
+        mesh: usize,
+        primitives: Range<usize>,
+    },
+    UnknownMesh {
+        instance: usize,
+        mesh: usize,
+    },
+    UnknownInstanceMaterial {
+        instance: usize,
+        material: u32,
+    },
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_material(&mut self, material: Material) -> u32 {
+        self.scene.data.materials.push(material);
+        self.scene.data.materials.len() as u32 - 1
+    }
+
+    pub fn add_image(&mut self, source: impl Into<PathBuf>) -> u32 {
+        self.scene.data.images.push(Image {
+            source: source.into(),
+        });
+        self.scene.data.images.len() as u32 - 1
+    }
+
+    // The index to set as one of the textures of a material
+    pub fn add_texture(&mut self, texture: TextureInfo) -> i32 {
+        self.scene.info.textures.push(texture);
+        self.scene.info.textures.len() as i32 - 1
+    }
+
+    // A triangle list, with indices relative to its own vertices
+    pub fn add_primitive(
+        &mut self,
+        vertices: impl IntoIterator<Item = Vertex>,
+        indices: impl IntoIterator<Item = u32>,
+        material: u32,
+    ) -> usize {
+        let data = &mut self.scene.data;
+
+        let indices_offset = data.indices.len() as u32;
+        data.indices.extend(indices);
+        let indices_size = data.indices.len() as u32 - indices_offset;
+
+        let vertices_offset = data.vertices.len() as u32;
+        data.vertices.extend(vertices);
+        let vertices_size = data.vertices.len() as u32 - vertices_offset;
+
+        let bounding_box = data.vertices[vertices_offset as usize..]
+            .iter()
+            .map(|vertex| BoundingBox::new(vertex.position.truncate(), vertex.position.truncate()))
+            .fold(BoundingBox::default(), BoundingBox::union);
+
+        self.scene.info.primitive_infos.push(PrimitiveInfo {
+            indices_offset,
+            vertices_offset,
+            material,
+        });
+        self.scene.info.primitive_sizes.push(PrimitiveSize {
+            indices_size,
+            vertices_size,
+        });
+        self.bounding_boxes.push(bounding_box);
+        self.scene.info.primitive_infos.len() - 1
+    }
+
+    // Groups primitives that were added one after the other
+    pub fn add_mesh(&mut self, primitives: Range<usize>) -> usize {
+        self.scene.info.meshes.push(Mesh { primitives });
+        self.scene.info.meshes.len() - 1
+    }
+
+    pub fn add_instance(&mut self, mesh: usize, transform: glam::Mat4) -> usize {
+        self.scene.info.instances.push(Instance {
+            blas_index: mesh,
+            transform,
+            visibility_mask: ray_mask::ALL,
+            material: None,
+        });
+        self.scene.info.instances.len() - 1
+    }
+
+    // Draws every primitive of the instance with `material` instead of its own
+    pub fn set_instance_material(&mut self, instance: usize, material: u32) {
+        self.scene.info.instances[instance].material = Some(material);
+    }
+
+    pub fn add_light(&mut self, name: &str, light: Light) {
+        self.scene.data.lights.push(light);
+        self.scene.info.light_names.push(name.to_owned());
+    }
+
+    pub const fn set_camera(&mut self, camera: inputs::Camera) {
+        self.scene.camera = Some(camera);
+    }
+
+    // Checks that everything refers to something that was added, then fills in what is derived
+    // from the rest: the opacity of each primitive and the bounding box of the scene
+    pub fn build(self) -> Result<Scene, Error> {
+        firestorm::profile_method!(build);
+
+        self.validate()?;
+
+        let Self {
+            mut scene,
+            bounding_boxes,
+        } = self;
+
+        scene.info.primitive_opacities = scene
+            .info
+            .primitive_infos
+            .iter()
+            .map(|primitive| {
+                scene.data.materials[primitive.material as usize].alpha_mode != alpha_mode::MASK
+            })
+            .collect();
+
+        scene.info.bounding_box = scene
+            .info
+            .instances
+            .iter()
+            .flat_map(|instance| {
+                bounding_boxes[scene.info.meshes[instance.blas_index].primitives.clone()]
+                    .iter()
+                    .map(|bounding_box| bounding_box.transform(instance.transform))
+            })
+            .fold(BoundingBox::default(), BoundingBox::union);
+
+        Ok(scene)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let Scene { data, info, .. } = &self.scene;
+
+        for (primitive, (primitive_info, size)) in info
+            .primitive_infos
+            .iter()
+            .zip(&info.primitive_sizes)
+            .enumerate()
+        {
+            if primitive_info.material as usize >= data.materials.len() {
+                return Err(Error::UnknownMaterial {
+                    primitive,
+                    material: primitive_info.material,
+                });
+            }
+            if size.indices_size % 3 != 0 {
+                return Err(Error::IncompleteTriangle {
+                    primitive,
+                    indices: size.indices_size,
+                });
+            }
+            let start = primitive_info.indices_offset as usize;
+            let indices = &data.indices[start..start + size.indices_size as usize];
+            if let Some(&index) = indices.iter().find(|&&index| index >= size.vertices_size) {
+                return Err(Error::IndexOutOfBounds {
+                    primitive,
+                    index,
+                    vertices: size.vertices_size,
+                });
+            }
+        }
+
+        for (texture, texture_info) in info.textures.iter().enumerate() {
+            if texture_info.image_index as usize >= data.images.len() {
+                return Err(Error::UnknownImage {
+                    texture,
+                    image: texture_info.image_index,
+                });
+            }
+        }
+
+        for (index, material) in data.materials.iter().enumerate() {
+            let textures = [
+                material.color_texture,
+                material.emittance_texture,
+                material.metallic_roughness_texture,
+            ];
+            // negative texture indices mean no texture
+            if let Some(texture) = textures
+                .into_iter()
+                .find(|&texture| texture >= info.textures.len() as i32)
+            {
+                return Err(Error::UnknownTexture {
+                    material: index,
+                    texture,
+                });
+            }
+        }
+
+        for (index, mesh) in info.meshes.iter().enumerate() {
+            if mesh.primitives.is_empty() || mesh.primitives.end > info.primitive_infos.len() {
+                return Err(Error::InvalidMesh {
+                    mesh: index,
+                    primitives: mesh.primitives.clone(),
+                });
+            }
+        }
+
+        for (index, instance) in info.instances.iter().enumerate() {
+            if instance.blas_index >= info.meshes.len() {
+                return Err(Error::UnknownMesh {
+                    instance: index,
+                    mesh: instance.blas_index,
+                });
+            }
+            if let Some(material) = instance.material
+                && material as usize >= data.materials.len()
+            {
+                return Err(Error::UnknownInstanceMaterial {
+                    instance: index,
+                    material,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownMaterial {
+                primitive,
+                material,
+            } => write!(f, "primitive {primitive} uses material {material}, which was not added"),
+            Self::IndexOutOfBounds {
+                primitive,
+                index,
+                vertices,
+            } => write!(
+                f,
+                "primitive {primitive} refers to vertex {index} but only has {vertices} vertices"
+            ),
+            Self::IncompleteTriangle { primitive, indices } => write!(
+                f,
+                "primitive {primitive} has {indices} indices, which is not a whole number of triangles"
+            ),
+            Self::UnknownImage { texture, image } => {
+                write!(f, "texture {texture} samples image {image}, which was not added")
+            }
+            Self::UnknownTexture { material, texture } => {
+                write!(f, "material {material} uses texture {texture}, which was not added")
+            }
+            Self::InvalidMesh { mesh, primitives } => write!(
+                f,
+                "mesh {mesh} consists of primitives {primitives:?}, which are empty or were not added"
+            ),
+            Self::UnknownMesh { instance, mesh } => {
+                write!(f, "instance {instance} places mesh {mesh}, which was not added")
+            }
+            Self::UnknownInstanceMaterial { instance, material } => {
+                write!(f, "instance {instance} uses material {material}, which was not added")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> [Vertex; 3] {
+        [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]]
+            .map(|position| Vertex::new(&position, &[0., 0., 1.], &[0.; 2], &[0.; 2], &[1.; 4]))
+    }
+
+    fn untextured() -> Material {
+        Material {
+            color_texture: -1,
+            emittance_texture: -1,
+            metallic_roughness_texture: -1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn build_derives_bounds_and_opacities() {
+        let mut builder = SceneBuilder::new();
+        let opaque = builder.add_material(untextured());
+        let masked = builder.add_material(Material {
+            alpha_mode: alpha_mode::MASK,
+            ..untextured()
+        });
+        builder.add_primitive(triangle(), [0, 1, 2], opaque);
+        builder.add_primitive(triangle(), [0, 2, 1], masked);
+        let mesh = builder.add_mesh(0..2);
+        builder.add_instance(mesh, glam::Mat4::from_translation(glam::Vec3::Z));
+        builder.add_instance(mesh, glam::Mat4::from_scale(glam::Vec3::splat(2.)));
+
+        let scene = builder.build().unwrap();
+        assert_eq!(scene.info.primitive_opacities, [true, false]);
+        assert_eq!(scene.info.primitive_infos[1].indices_offset, 3);
+        assert_eq!(scene.info.primitive_infos[1].vertices_offset, 3);
+        assert_eq!(scene.info.bounding_box.min, glam::Vec3::ZERO);
+        assert_eq!(scene.info.bounding_box.max, glam::vec3(2., 2., 1.));
+    }
+
+    #[test]
+    fn build_rejects_indices_past_the_primitive() {
+        let mut builder = SceneBuilder::new();
+        let material = builder.add_material(untextured());
+        builder.add_primitive(triangle(), [0, 1, 2], material);
+        builder.add_primitive(triangle(), [0, 1, 3], material);
+
+        assert_eq!(
+            builder.build().err(),
+            Some(Error::IndexOutOfBounds {
+                primitive: 1,
+                index: 3,
+                vertices: 3
+            })
+        );
+    }
+
+    #[test]
+    fn build_rejects_dangling_references() {
+        let mut builder = SceneBuilder::new();
+        builder.add_primitive(triangle(), [0, 1, 2], 0);
+        assert_eq!(
+            builder.build().err(),
+            Some(Error::UnknownMaterial {
+                primitive: 0,
+                material: 0
+            })
+        );
+
+        let mut builder = SceneBuilder::new();
+        builder.add_material(Material {
+            color_texture: 0,
+            ..untextured()
+        });
+        assert_eq!(
+            builder.build().err(),
+            Some(Error::UnknownTexture {
+                material: 0,
+                texture: 0
+            })
+        );
+
+        let mut builder = SceneBuilder::new();
+        builder.add_mesh(0..1);
+        assert_eq!(
+            builder.build().err(),
+            Some(Error::InvalidMesh {
+                mesh: 0,
+                primitives: 0..1
+            })
+        );
+
+        let mut builder = SceneBuilder::new();
+        builder.add_instance(0, glam::Mat4::IDENTITY);
+        assert_eq!(
+            builder.build().err(),
+            Some(Error::UnknownMesh {
+                instance: 0,
+                mesh: 0
+            })
+        );
+
+        let mut builder = SceneBuilder::new();
+        let material = builder.add_material(untextured());
+        builder.add_primitive(triangle(), [0, 1, 2], material);
+        let mesh = builder.add_mesh(0..1);
+        let instance = builder.add_instance(mesh, glam::Mat4::IDENTITY);
+        builder.set_instance_material(instance, material + 1);
+        assert_eq!(
+            builder.build().err(),
+            Some(Error::UnknownInstanceMaterial {
+                instance: 0,
+                material: 1
+            })
+        );
+    }
+}
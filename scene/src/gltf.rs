@@ -1,12 +1,34 @@
-use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+use std::{collections::HashMap, fs::File, io::BufReader, ops::Range, path::Path};
 
-use gltf::{image, mesh, texture};
+use gltf::{
+    animation::util::ReadOutputs,
+    camera::Projection,
+    image,
+    khr_lights_punctual::{self, Kind},
+    material::AlphaMode,
+    mesh,
+    texture::{self, WrappingMode},
+};
+
+use shared::inputs;
 
 use crate::{
-    io::FileLoader, BoundingBox, Image, Instance, Material, PrimitiveInfo, PrimitiveSize, Scene,
-    TextureInfo, Vertex,
+    alpha_mode,
+    animation::{self, AnimatedInstance, Channel, Interpolation, Property, SkinnedPrimitive},
+    io::FileLoader,
+    light_kind, ray_mask, BoundingBox, Image, Instance, Light, Material, Mesh, PrimitiveInfo,
+    PrimitiveSize, Scene, TextureInfo, Vertex, Wrap,
 };
 
+mod conf {
+    // KHR_materials_ior default
+    pub const DEFAULT_IOR: f32 = 1.5;
+    // glTF default for materials in MASK mode
+    pub const DEFAULT_ALPHA_CUTOFF: f32 = 0.5;
+    // glTF leaves it to the viewport, which is not known until rendering
+    pub const DEFAULT_ASPECT_RATIO: f32 = 1.;
+}
+
 pub struct Gltf;
 
 impl FileLoader for Gltf {
@@ -47,13 +69,19 @@ impl FileLoader for Gltf {
 
         // json texture index -> loaded texture index
         let mut processed_textures = HashMap::new();
-        let mut handle_texture = |scene: &mut Scene, tex_info: texture::Info| {
+        let mut handle_texture = |scene: &mut Scene, tex_info: texture::Info, srgb: bool| {
             let texture = tex_info.texture();
             *processed_textures
-                .entry(texture.index())
+                .entry((texture.index(), srgb))
                 .or_insert_with(|| {
                     let image_index = handle_image(scene, texture.source()) as _;
-                    scene.info.textures.push(TextureInfo { image_index });
+                    let sampler = texture.sampler();
+                    scene.info.textures.push(TextureInfo {
+                        image_index,
+                        srgb,
+                        wrap_s: wrap(sampler.wrap_s()),
+                        wrap_t: wrap(sampler.wrap_t()),
+                    });
                     scene.info.textures.len() - 1
                 })
         };
@@ -64,16 +92,20 @@ impl FileLoader for Gltf {
             *processed_materials
                 .entry(material.index().unwrap_or_default())
                 .or_insert_with(|| {
+                    // (texture index, uv set)
+                    let mut handle_texture_ref = |tex_info: Option<texture::Info>, srgb| {
+                        tex_info.map_or((-1, 0), |tex_info| {
+                            let tex_coord = tex_info.tex_coord();
+                            (handle_texture(scene, tex_info, srgb) as _, tex_coord)
+                        })
+                    };
                     let pbr = material.pbr_metallic_roughness();
-                    let color_texture = pbr
-                        .base_color_texture()
-                        .map_or(-1, |tex_info| handle_texture(scene, tex_info) as _);
-                    let emittance_texture = material
-                        .emissive_texture()
-                        .map_or(-1, |tex_info| handle_texture(scene, tex_info) as _);
-                    let metallic_roughness_texture = pbr
-                        .metallic_roughness_texture()
-                        .map_or(-1, |tex_info| handle_texture(scene, tex_info) as _);
+                    let (color_texture, color_tex_coord) =
+                        handle_texture_ref(pbr.base_color_texture(), true);
+                    let (emittance_texture, emittance_tex_coord) =
+                        handle_texture_ref(material.emissive_texture(), true);
+                    let (metallic_roughness_texture, metallic_roughness_tex_coord) =
+                        handle_texture_ref(pbr.metallic_roughness_texture(), false);
                     scene.data.materials.push(Material {
                         color: glam::Vec4::from(pbr.base_color_factor()).truncate(),
                         color_texture,
@@ -82,6 +114,23 @@ impl FileLoader for Gltf {
                         metallic: pbr.metallic_factor(),
                         roughness: pbr.roughness_factor(),
                         metallic_roughness_texture,
+                        double_sided: material.double_sided().into(),
+                        transmission: material
+                            .transmission()
+                            .map_or(0., |transmission| transmission.transmission_factor()),
+                        ior: material.ior().unwrap_or(conf::DEFAULT_IOR),
+                        alpha_mode: match material.alpha_mode() {
+                            AlphaMode::Opaque => alpha_mode::OPAQUE,
+                            AlphaMode::Mask => alpha_mode::MASK,
+                            AlphaMode::Blend => alpha_mode::BLEND,
+                        },
+                        alpha_cutoff: material
+                            .alpha_cutoff()
+                            .unwrap_or(conf::DEFAULT_ALPHA_CUTOFF),
+                        color_tex_coord,
+                        emittance_tex_coord,
+                        metallic_roughness_tex_coord,
+                        pad: 0,
                     });
                     scene.data.materials.len() - 1
                 })
@@ -89,11 +138,14 @@ impl FileLoader for Gltf {
 
         let mut bounding_boxes = Vec::new();
         let mut add_primitive = |scene: &mut Scene, primitive: mesh::Primitive| {
-            assert_eq!(primitive.mode(), mesh::Mode::Triangles);
-
             let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
 
-            let indices = reader.read_indices().expect("No indices found").into_u32();
+            let indices = reader
+                .read_indices()
+                .expect("No indices found")
+                .into_u32()
+                .collect::<Vec<_>>();
+            let indices = triangulate(primitive.mode(), &indices);
 
             let positions = reader.read_positions().expect("No positions found");
             let normals = reader.read_normals().map_or_else(
@@ -114,13 +166,22 @@ impl FileLoader for Gltf {
                     || Box::new(std::iter::repeat_with(Default::default)) as Box<_>,
                     |uv| Box::new(uv) as Box<dyn Iterator<Item = [f32; 2]>>,
                 );
+            let colors = reader
+                .read_colors(0)
+                .map(mesh::util::ReadColors::into_rgba_f32)
+                .map_or_else(
+                    || Box::new(std::iter::repeat([1f32; 4])) as Box<_>,
+                    |c| Box::new(c) as Box<dyn Iterator<Item = [f32; 4]>>,
+                );
 
             let vertices = positions
                 .zip(normals)
                 .zip(tex_coords0)
                 .zip(tex_coords1)
+                .zip(colors)
                 .map(Vertex::from);
 
+            let is_opaque = primitive.material().alpha_mode() != AlphaMode::Mask;
             let material = handle_material(scene, primitive.material()) as _;
 
             let bbox = primitive.bounding_box();
@@ -146,85 +207,434 @@ impl FileLoader for Gltf {
                 vertices_size,
             });
 
+            scene.info.primitive_opacities.push(is_opaque);
+
             bounding_boxes.push(bounding_box);
         };
 
-        // json mesh index -> loaded primitives range
+        // json mesh index -> loaded mesh index, None if none of its primitives are triangles
         let mut processed_meshes = HashMap::new();
         let mut handle_mesh = |scene: &mut Scene, mesh: &mesh::Mesh| {
-            processed_meshes
-                .entry(mesh.index())
-                .or_insert_with(|| {
-                    let primitives_start = scene.info.primitive_infos.len();
-                    mesh.primitives()
-                        .for_each(|primitive| add_primitive(scene, primitive));
-                    let primitives_end = scene.info.primitive_infos.len();
-                    primitives_start..primitives_end
-                })
-                .clone()
+            *processed_meshes.entry(mesh.index()).or_insert_with(|| {
+                let primitives_start = scene.info.primitive_infos.len();
+                for primitive in mesh.primitives() {
+                    if has_triangles(primitive.mode()) {
+                        add_primitive(scene, primitive);
+                    } else {
+                        eprintln!(
+                            "Warning: skipping {:?} primitive of mesh {}, only triangles are rendered",
+                            primitive.mode(),
+                            mesh.index()
+                        );
+                    }
+                }
+                let primitives_end = scene.info.primitive_infos.len();
+                if primitives_start == primitives_end {
+                    return None;
+                }
+                scene.info.meshes.push(Mesh {
+                    primitives: primitives_start..primitives_end,
+                });
+                Some(scene.info.meshes.len() - 1)
+            })
         };
 
-        default_scene.nodes().traverse_meshes(
-            glam::Mat4::IDENTITY,
-            &mut |mesh: &mesh::Mesh<'_>, transform| {
-                let primitives_range = handle_mesh(&mut scene, mesh);
-
-                scene
-                    .info
-                    .instances
-                    .extend(primitives_range.map(|primitive_index| Instance {
-                        primitive_index,
-                        transform,
-                    }));
-            },
-        );
+        // json node index -> animation node index, only kept track of when something is animated
+        let mut node_indices = HashMap::new();
+        if document.animations().len() > 0 || document.skins().len() > 0 {
+            default_scene.nodes().for_each(|node| {
+                load_node(&node, None, &mut scene.animation.nodes, &mut node_indices);
+            });
+        }
+
+        // glTF leaves choosing between animations to the application, so all of them play at once
+        scene.animation.channels = document
+            .animations()
+            .flat_map(|animation| animation.channels())
+            .filter_map(|channel| load_channel(&channel, &buffers, &node_indices))
+            .collect();
+
+        // json skin index -> loaded skin index, None for skins that can't be posed
+        let mut processed_skins = HashMap::new();
+
+        default_scene
+            .nodes()
+            .traverse(glam::Mat4::IDENTITY, &mut |node, transform| {
+                if let Some((mesh, blas_index)) = node.mesh().and_then(|mesh| {
+                    let blas_index = handle_mesh(&mut scene, &mesh)?;
+                    Some((mesh, blas_index))
+                }) {
+                    let instance = scene.info.instances.len();
+
+                    let skin = node.skin().and_then(|skin| {
+                        *processed_skins.entry(skin.index()).or_insert_with(|| {
+                            let skin = load_skin(&skin, &buffers, &node_indices)?;
+                            scene.animation.skins.push(skin);
+                            Some(scene.animation.skins.len() - 1)
+                        })
+                    });
+                    let primitives = scene.info.meshes[blas_index].primitives.clone();
+                    let skinned = skin.is_some_and(|skin| {
+                        add_skinned_primitives(&mut scene, &mesh, primitives, skin, &buffers)
+                    });
+
+                    // the transform of a skinned node is ignored in favor of those of its joints
+                    scene.info.instances.push(Instance {
+                        blas_index,
+                        transform: if skinned {
+                            glam::Mat4::IDENTITY
+                        } else {
+                            transform
+                        },
+                        visibility_mask: ray_mask::ALL,
+                        material: None,
+                    });
+                    if let (false, Some(&node)) = (skinned, node_indices.get(&node.index())) {
+                        scene
+                            .animation
+                            .instances
+                            .push(AnimatedInstance { instance, node });
+                    }
+                }
+
+                if let Some(light) = node.light() {
+                    scene.data.lights.push(load_light(&light, transform));
+                    scene
+                        .info
+                        .light_names
+                        .push(node.name().unwrap_or_default().to_owned());
+                }
+
+                // glTF has no notion of an active camera so the first one is used
+                if scene.camera.is_none() {
+                    scene.camera = node
+                        .camera()
+                        .and_then(|camera| load_camera(&camera, transform));
+                }
+            });
+
+        // starts out at the first keyframe, with the skinned primitives moved into world space
+        if !scene.animation.nodes.is_empty() {
+            let pose = scene.sample_animation(0.);
+            scene.apply_pose(&pose);
+            for (primitive, vertices) in &pose.primitive_vertices {
+                bounding_boxes[*primitive] = vertices
+                    .iter()
+                    .map(|vertex| {
+                        BoundingBox::new(vertex.position.truncate(), vertex.position.truncate())
+                    })
+                    .fold(BoundingBox::default(), BoundingBox::union);
+            }
+        }
 
         scene.info.bounding_box = scene
             .info
             .instances
             .iter()
-            .map(|instance| bounding_boxes[instance.primitive_index].transform(instance.transform))
+            .flat_map(|instance| {
+                scene.info.meshes[instance.blas_index]
+                    .primitives
+                    .clone()
+                    .map(|primitive_index| {
+                        bounding_boxes[primitive_index].transform(instance.transform)
+                    })
+            })
             .fold(BoundingBox::default(), BoundingBox::union);
 
         scene
     }
 }
 
-trait Traversable {
-    fn traverse_meshes(
-        self,
-        transform: glam::Mat4,
-        f: &mut impl FnMut(&mesh::Mesh<'_>, glam::Mat4),
+// Orthographic cameras are skipped since the renderer only supports perspective projections
+fn load_camera(camera: &gltf::Camera, transform: glam::Mat4) -> Option<inputs::Camera> {
+    let Projection::Perspective(perspective) = camera.projection() else {
+        return None;
+    };
+
+    let yfov = perspective.yfov();
+    let aspect_ratio = perspective
+        .aspect_ratio()
+        .unwrap_or(conf::DEFAULT_ASPECT_RATIO);
+    let proj = perspective.zfar().map_or_else(
+        || glam::Mat4::perspective_infinite_rh(yfov, aspect_ratio, perspective.znear()),
+        |zfar| glam::Mat4::perspective_rh(yfov, aspect_ratio, perspective.znear(), zfar),
     );
+
+    Some(inputs::Camera {
+        view: inputs::Transform::new(transform.inverse()),
+        proj: inputs::Transform::proj(proj),
+    })
+}
+
+// Lights sit at the origin of their node and point down its -Z axis, the scale of the node is ignored
+fn load_light(light: &khr_lights_punctual::Light, transform: glam::Mat4) -> Light {
+    let (kind, (cos_inner_cone, cos_outer_cone)) = match light.kind() {
+        Kind::Point => (light_kind::POINT, (-1., -1.)),
+        Kind::Directional => (light_kind::DIRECTIONAL, (-1., -1.)),
+        Kind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        } => (
+            light_kind::SPOT,
+            (inner_cone_angle.cos(), outer_cone_angle.cos()),
+        ),
+    };
+
+    Light {
+        position: transform.transform_point3(glam::Vec3::ZERO),
+        kind,
+        direction: transform
+            .transform_vector3(glam::Vec3::NEG_Z)
+            .normalize_or_zero(),
+        range: light.range().unwrap_or_default(),
+        intensity: glam::Vec3::from(light.color()) * light.intensity(),
+        cos_inner_cone,
+        cos_outer_cone,
+    }
+}
+
+// Flattens the hierarchy under the node with parents ahead of their children
+fn load_node(
+    node: &gltf::Node,
+    parent: Option<usize>,
+    nodes: &mut Vec<animation::Node>,
+    node_indices: &mut HashMap<usize, usize>,
+) {
+    let index = nodes.len();
+    let (translation, rotation, scale) = node.transform().decomposed();
+    nodes.push(animation::Node {
+        parent,
+        translation: translation.into(),
+        rotation: glam::Quat::from_array(rotation),
+        scale: scale.into(),
+    });
+    node_indices.insert(node.index(), index);
+
+    node.children()
+        .for_each(|child| load_node(&child, Some(index), nodes, node_indices));
+}
+
+// Morph target weights are not supported, nor are nodes outside of the loaded scene
+fn load_channel(
+    channel: &gltf::animation::Channel,
+    buffers: &[gltf::buffer::Data],
+    node_indices: &HashMap<usize, usize>,
+) -> Option<Channel> {
+    let node = *node_indices.get(&channel.target().node().index())?;
+    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let times = reader.read_inputs()?.collect::<Vec<_>>();
+    let (property, values) = match reader.read_outputs()? {
+        ReadOutputs::Translations(translations) => (
+            Property::Translation,
+            translations
+                .map(|translation| glam::Vec3::from(translation).extend(0.))
+                .collect(),
+        ),
+        ReadOutputs::Rotations(rotations) => (
+            Property::Rotation,
+            rotations.into_f32().map(glam::Vec4::from).collect(),
+        ),
+        ReadOutputs::Scales(scales) => (
+            Property::Scale,
+            scales
+                .map(|scale| glam::Vec3::from(scale).extend(0.))
+                .collect(),
+        ),
+        ReadOutputs::MorphTargetWeights(_) => return None,
+    };
+
+    let interpolation = match channel.sampler().interpolation() {
+        gltf::animation::Interpolation::Step => Interpolation::Step,
+        gltf::animation::Interpolation::Linear => Interpolation::Linear,
+        gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+    };
+
+    (!times.is_empty()).then_some(Channel {
+        node,
+        property,
+        interpolation,
+        times,
+        values,
+    })
+}
+
+// Joints outside of the loaded scene can't be posed, so neither can their skin
+fn load_skin(
+    skin: &gltf::Skin,
+    buffers: &[gltf::buffer::Data],
+    node_indices: &HashMap<usize, usize>,
+) -> Option<animation::Skin> {
+    let joints = skin
+        .joints()
+        .map(|joint| node_indices.get(&joint.index()).copied())
+        .collect::<Option<Vec<_>>>()?;
+
+    let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+    // identities when left out
+    let inverse_bind_matrices = reader.read_inverse_bind_matrices().map_or_else(
+        || vec![glam::Mat4::IDENTITY; joints.len()],
+        |matrices| {
+            matrices
+                .map(|matrix| glam::Mat4::from_cols_array_2d(&matrix))
+                .collect()
+        },
+    );
+
+    Some(animation::Skin {
+        joints,
+        inverse_bind_matrices,
+    })
+}
+
+// Keeps the loaded vertices of the mesh to be deformed by the skin. The primitives share their
+// vertices with every instance of the mesh, so only the first skin applied to them is kept
+fn add_skinned_primitives(
+    scene: &mut Scene,
+    mesh: &gltf::Mesh,
+    primitives: Range<usize>,
+    skin: usize,
+    buffers: &[gltf::buffer::Data],
+) -> bool {
+    if let Some(skinned) = scene
+        .animation
+        .skinned_primitives
+        .iter()
+        .find(|skinned| primitives.contains(&skinned.primitive))
+    {
+        if skinned.skin != skin {
+            eprintln!(
+                "Warning: mesh {} is skinned more than once, only its first skin is posed",
+                mesh.index()
+            );
+        }
+        return true;
+    }
+
+    let skinned_before = scene.animation.skinned_primitives.len();
+    let triangle_primitives = mesh
+        .primitives()
+        .filter(|primitive| has_triangles(primitive.mode()));
+    for (primitive, index) in triangle_primitives.zip(primitives) {
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let (Some(joints), Some(weights)) = (reader.read_joints(0), reader.read_weights(0)) else {
+            continue;
+        };
+
+        let info = &scene.info.primitive_infos[index];
+        let size = &scene.info.primitive_sizes[index];
+        let bind_vertices = scene.data.vertices[info.vertices_offset as usize..]
+            [..size.vertices_size as usize]
+            .to_vec();
+
+        scene.animation.skinned_primitives.push(SkinnedPrimitive {
+            primitive: index,
+            skin,
+            bind_vertices,
+            joints: joints.into_u16().collect(),
+            weights: weights.into_f32().map(glam::Vec4::from).collect(),
+        });
+    }
+
+    // without joints the mesh stays where its node puts it
+    scene.animation.skinned_primitives.len() > skinned_before
+}
+
+// Points and lines have no surface to render
+const fn has_triangles(mode: mesh::Mode) -> bool {
+    matches!(
+        mode,
+        mesh::Mode::Triangles | mesh::Mode::TriangleStrip | mesh::Mode::TriangleFan
+    )
+}
+
+// Unrolls strips and fans into a triangle list keeping their winding, without the degenerate
+// triangles that strips are often stitched together with. Lists are kept as they are
+fn triangulate(mode: mesh::Mode, indices: &[u32]) -> Vec<u32> {
+    let triangles = match mode {
+        mesh::Mode::TriangleStrip => indices
+            .windows(3)
+            .enumerate()
+            .map(|(i, tri)| {
+                if i % 2 == 0 {
+                    [tri[0], tri[1], tri[2]]
+                } else {
+                    [tri[0], tri[2], tri[1]]
+                }
+            })
+            .collect::<Vec<_>>(),
+        mesh::Mode::TriangleFan => indices
+            .windows(2)
+            .skip(1)
+            .map(|edge| [edge[0], edge[1], indices[0]])
+            .collect(),
+        _ => return indices.to_vec(),
+    };
+
+    triangles
+        .into_iter()
+        .filter(|&[a, b, c]| a != b && b != c && c != a)
+        .flatten()
+        .collect()
+}
+
+const fn wrap(mode: WrappingMode) -> Wrap {
+    match mode {
+        WrappingMode::Repeat => Wrap::Repeat,
+        WrappingMode::ClampToEdge => Wrap::ClampToEdge,
+        WrappingMode::MirroredRepeat => Wrap::MirroredRepeat,
+    }
+}
+
+trait Traversable {
+    fn traverse(self, transform: glam::Mat4, f: &mut impl FnMut(&gltf::Node<'_>, glam::Mat4));
 }
 
 impl Traversable for gltf::scene::Node<'_> {
-    fn traverse_meshes(
-        self,
-        transform: glam::Mat4,
-        f: &mut impl FnMut(&mesh::Mesh<'_>, glam::Mat4),
-    ) {
+    fn traverse(self, transform: glam::Mat4, f: &mut impl FnMut(&gltf::Node<'_>, glam::Mat4)) {
         let global_transform =
             transform * glam::Mat4::from_cols_array_2d(&self.transform().matrix());
-        if let Some(mesh) = self.mesh() {
-            f(&mesh, global_transform);
-        }
-        self.children().traverse_meshes(global_transform, f);
+        f(&self, global_transform);
+        self.children().traverse(global_transform, f);
     }
 }
 
 macro_rules! impl_traversable {
     ($t:ty) => {
         impl Traversable for $t {
-            fn traverse_meshes(
+            fn traverse(
                 self,
                 transform: glam::Mat4,
-                f: &mut impl FnMut(&mesh::Mesh<'_>, glam::Mat4),
+                f: &mut impl FnMut(&gltf::Node<'_>, glam::Mat4),
             ) {
-                self.for_each(|elem| elem.traverse_meshes(transform, f));
+                self.for_each(|elem| elem.traverse(transform, f));
             }
         }
     };
 }
 impl_traversable!(gltf::scene::iter::Nodes<'_>);
 impl_traversable!(gltf::scene::iter::Children<'_>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_alternate_winding() {
+        let indices = triangulate(mesh::Mode::TriangleStrip, &[0, 1, 2, 3, 4]);
+        assert_eq!(indices, [0, 1, 2, 1, 3, 2, 2, 3, 4]);
+    }
+
+    #[test]
+    fn strips_drop_degenerate_triangles() {
+        // two strips stitched together by repeating the vertices in between
+        let indices = triangulate(mesh::Mode::TriangleStrip, &[0, 1, 2, 3, 3, 4, 4, 5, 6, 7]);
+        assert_eq!(indices, [0, 1, 2, 1, 3, 2, 4, 5, 6, 5, 7, 6]);
+    }
+
+    #[test]
+    fn fans_share_the_first_vertex() {
+        let indices = triangulate(mesh::Mode::TriangleFan, &[0, 1, 2, 3]);
+        assert_eq!(indices, [1, 2, 0, 2, 3, 0]);
+    }
+}
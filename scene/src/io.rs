@@ -1,10 +1,13 @@
 use std::{
     fs::File,
     io::{BufReader, BufWriter},
-    path::Path,
+    path::{Path, PathBuf},
+    thread,
 };
 
-use super::Scene;
+use shared::inputs;
+
+use super::{gltf::Gltf, Scene};
 
 pub trait FileLoader {
     const SUPPORTED_EXTENSIONS: &'static [&'static str];
@@ -21,6 +24,8 @@ pub trait FileLoader {
 }
 
 const FILE_EXTENSION: &str = "tsnasset";
+// Exported cameras live next to the asset so that saving one does not rewrite the whole asset
+const CAMERA_FILE_EXTENSION: &str = "tsncamera";
 
 pub fn load(file: impl AsRef<Path>) -> Scene {
     firestorm::profile_fn!(scene_load);
@@ -32,7 +37,59 @@ pub fn load(file: impl AsRef<Path>) -> Scene {
     );
     let file = File::open(filepath).expect("Unable to open scene asset file");
     let reader = flate2::bufread::GzDecoder::new(BufReader::new(file));
-    rmp_serde::decode::from_read(reader).expect("Failed to load scene asset")
+    let mut scene: Scene =
+        rmp_serde::decode::from_read(reader).expect("Failed to load scene asset");
+
+    if let Some(camera) = load_camera(filepath) {
+        scene.camera = Some(camera);
+    }
+
+    scene
+}
+
+// A scene being loaded on a worker thread, see `load_async`
+pub struct Loading {
+    worker: Option<thread::JoinHandle<Scene>>,
+}
+
+impl Loading {
+    // The scene once the worker is done with it, which is only handed out once
+    pub fn poll(&mut self) -> Option<Scene> {
+        if !self.worker.as_ref()?.is_finished() {
+            return None;
+        }
+        self.worker.take().map(Self::join)
+    }
+
+    pub fn wait(mut self) -> Scene {
+        Self::join(self.worker.take().expect("Scene was already loaded"))
+    }
+
+    // panics while loading are raised on the thread waiting for the scene
+    fn join(worker: thread::JoinHandle<Scene>) -> Scene {
+        worker
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+// Parses glTF files or loads preprocessed assets without blocking the calling thread, uploading
+// the scene is left to the caller
+pub fn load_async(file: impl Into<PathBuf>) -> Loading {
+    firestorm::profile_fn!(scene_load_async);
+
+    let file = file.into();
+    let worker = thread::spawn(move || {
+        if Gltf::can_load(&file) {
+            Gltf::load(&file)
+        } else {
+            load(&file)
+        }
+    });
+
+    Loading {
+        worker: Some(worker),
+    }
 }
 
 pub fn save(scene: &Scene, file: impl AsRef<Path>) {
@@ -43,3 +100,16 @@ pub fn save(scene: &Scene, file: impl AsRef<Path>) {
     rmp_serde::encode::write(&mut writer, &scene).expect("Failed to save processed asset");
     println!("Asset processed and saved to {}", output_filename.display());
 }
+
+fn load_camera(file: impl AsRef<Path>) -> Option<inputs::Camera> {
+    let file = File::open(file.as_ref().with_extension(CAMERA_FILE_EXTENSION)).ok()?;
+    Some(rmp_serde::decode::from_read(BufReader::new(file)).expect("Failed to load camera"))
+}
+
+pub fn save_camera(camera: &inputs::Camera, file: impl AsRef<Path>) {
+    let output_filename = file.as_ref().with_extension(CAMERA_FILE_EXTENSION);
+    let output_file = File::create(&output_filename).expect("Unable to open file for writing");
+    rmp_serde::encode::write(&mut BufWriter::new(output_file), camera)
+        .expect("Failed to save camera");
+    println!("Camera saved to {}", output_filename.display());
+}
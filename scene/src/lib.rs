@@ -1,5 +1,11 @@
+pub mod animation;
+pub mod builder;
 pub mod gltf;
 pub mod io;
+pub mod reference;
+mod weld;
+
+use std::ops::Range;
 
 use serde::{Deserialize, Serialize};
 
@@ -9,6 +15,11 @@ pub use shared::scene::*;
 pub struct Scene {
     pub data: Data,
     pub info: Info,
+    // viewpoint to start from instead of one framing the bounding box
+    #[serde(default)]
+    pub camera: Option<shared::inputs::Camera>,
+    #[serde(default)]
+    pub animation: animation::Animation,
 }
 
 #[derive(Default, Deserialize, Serialize)]
@@ -17,15 +28,24 @@ pub struct Data {
     pub vertices: Vec<Vertex>,
     pub materials: Vec<Material>,
     pub images: Vec<Image>,
+    #[serde(default)]
+    pub lights: Vec<Light>,
 }
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct Info {
     pub primitive_infos: Vec<PrimitiveInfo>,
     pub primitive_sizes: Vec<PrimitiveSize>,
+    // whether each primitive can skip alpha testing during traversal
+    pub primitive_opacities: Vec<bool>,
+    pub meshes: Vec<Mesh>,
     pub instances: Vec<Instance>,
     pub textures: Vec<TextureInfo>,
     pub bounding_box: BoundingBox,
+    // name of the node of each of `Data::lights`, empty if it has none. Missing in scenes saved
+    // before lights were named
+    #[serde(default)]
+    pub light_names: Vec<String>,
 }
 
 #[derive(Default, Deserialize, Serialize)]
@@ -39,15 +59,43 @@ pub struct PrimitiveSize {
     pub vertices_size: u32,
 }
 
+// A group of primitives that is built into a single BLAS and shared by all its instances
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Mesh {
+    pub primitives: Range<usize>,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Instance {
-    pub primitive_index: usize,
+    // index into `Info::meshes`
+    pub blas_index: usize,
     pub transform: glam::Mat4,
+    // the kinds of rays that hit the instance, see `ray_mask`
+    #[serde(default = "Instance::visible_to_all")]
+    pub visibility_mask: u8,
+    // replaces the material of every primitive of the mesh, as an index into `Data::materials`
+    #[serde(default)]
+    pub material: Option<u32>,
 }
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct TextureInfo {
     pub image_index: u32,
+    // color textures are sRGB encoded, data textures (eg. metallic-roughness) are linear
+    pub srgb: bool,
+    #[serde(default)]
+    pub wrap_s: Wrap,
+    #[serde(default)]
+    pub wrap_t: Wrap,
+}
+
+// How texture coordinates outside of [0, 1] are handled along each axis
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    ClampToEdge,
+    MirroredRepeat,
 }
 
 #[derive(Clone, Copy, Deserialize, Serialize)]
@@ -56,6 +104,180 @@ pub struct BoundingBox {
     pub max: glam::Vec3,
 }
 
+impl Scene {
+    // Appends the other scene, rebasing every index into the shared arrays. The camera of this scene
+    // is kept if it has one
+    pub fn merge(&mut self, other: Self) {
+        let vertices_offset = self.data.vertices.len() as u32;
+        let indices_offset = self.data.indices.len() as u32;
+        let materials_offset = self.data.materials.len() as u32;
+        let images_offset = self.data.images.len() as u32;
+        let textures_offset = self.info.textures.len() as i32;
+        let primitives_offset = self.info.primitive_infos.len();
+        let meshes_offset = self.info.meshes.len();
+        let instances_offset = self.info.instances.len();
+        let lights_offset = self.data.lights.len();
+
+        let Self {
+            data,
+            info,
+            camera,
+            animation,
+        } = other;
+
+        self.data.indices.extend(data.indices);
+        self.data.vertices.extend(data.vertices);
+        self.data.images.extend(data.images);
+        self.data.lights.extend(data.lights);
+        // padded so that each name stays with its light
+        self.info.light_names.resize(lights_offset, String::new());
+        self.info.light_names.extend(info.light_names);
+        self.info
+            .light_names
+            .resize(self.data.lights.len(), String::new());
+        self.data
+            .materials
+            .extend(data.materials.into_iter().map(|material| {
+                // negative texture indices mean no texture
+                let rebase = |texture: i32| {
+                    if texture < 0 {
+                        texture
+                    } else {
+                        texture + textures_offset
+                    }
+                };
+                Material {
+                    color_texture: rebase(material.color_texture),
+                    emittance_texture: rebase(material.emittance_texture),
+                    metallic_roughness_texture: rebase(material.metallic_roughness_texture),
+                    ..material
+                }
+            }));
+
+        self.info
+            .primitive_infos
+            .extend(
+                info.primitive_infos
+                    .into_iter()
+                    .map(|primitive| PrimitiveInfo {
+                        indices_offset: primitive.indices_offset + indices_offset,
+                        vertices_offset: primitive.vertices_offset + vertices_offset,
+                        material: primitive.material + materials_offset,
+                    }),
+            );
+        self.info.primitive_sizes.extend(info.primitive_sizes);
+        self.info
+            .primitive_opacities
+            .extend(info.primitive_opacities);
+        self.info
+            .meshes
+            .extend(info.meshes.into_iter().map(|mesh| Mesh {
+                primitives: mesh.primitives.start + primitives_offset
+                    ..mesh.primitives.end + primitives_offset,
+            }));
+        self.info
+            .instances
+            .extend(info.instances.into_iter().map(|instance| {
+                Instance {
+                    blas_index: instance.blas_index + meshes_offset,
+                    material: instance
+                        .material
+                        .map(|material| material + materials_offset),
+                    ..instance
+                }
+            }));
+        self.info
+            .textures
+            .extend(info.textures.into_iter().map(|texture| TextureInfo {
+                image_index: texture.image_index + images_offset,
+                ..texture
+            }));
+        self.info.bounding_box = self.info.bounding_box.union(info.bounding_box);
+
+        self.camera = self.camera.or(camera);
+        self.animation
+            .merge(animation, instances_offset, primitives_offset);
+    }
+
+    pub fn sample_animation(&self, time: f32) -> animation::Pose {
+        self.animation.sample(time)
+    }
+
+    // Moves the instances and overwrites the vertices of the posed primitives in place
+    pub fn apply_pose(&mut self, pose: &animation::Pose) {
+        for &(instance, transform) in &pose.instance_transforms {
+            self.info.instances[instance].transform = transform;
+        }
+        for (primitive, vertices) in &pose.primitive_vertices {
+            let offset = self.info.primitive_infos[*primitive].vertices_offset as usize;
+            self.data.vertices[offset..offset + vertices.len()].copy_from_slice(vertices);
+        }
+    }
+
+    // Appends a square quad of the given edge length facing +Y, centered under the bounding box.
+    // Texture coordinates are in world units so that textures tile across the plane.
+    pub fn add_ground_plane(&mut self, size: f32, material: Material) {
+        let bounds = self.info.bounding_box;
+        let center = if bounds.min.cmple(bounds.max).all() {
+            glam::vec3(bounds.center().x, bounds.min.y, bounds.center().z)
+        } else {
+            glam::Vec3::ZERO
+        };
+        let transform = glam::Mat4::from_translation(center);
+
+        let half_size = size / 2.;
+        let corners = [[-1., -1.], [1., -1.], [1., 1.], [-1., 1.]];
+        let vertices = corners.map(|[x, z]| {
+            Vertex::new(
+                &[x * half_size, 0., z * half_size],
+                &[0., 1., 0.],
+                &[(x + 1.) * half_size, (z + 1.) * half_size],
+                &[0., 0.],
+                &[1.; 4],
+            )
+        });
+        // counter-clockwise when seen from above
+        let indices = [0, 2, 1, 0, 3, 2];
+
+        let is_opaque = material.alpha_mode != alpha_mode::MASK;
+        self.data.materials.push(material);
+
+        let primitive = self.info.primitive_infos.len();
+        self.info.primitive_infos.push(PrimitiveInfo {
+            indices_offset: self.data.indices.len() as _,
+            vertices_offset: self.data.vertices.len() as _,
+            material: self.data.materials.len() as u32 - 1,
+        });
+        self.info.primitive_sizes.push(PrimitiveSize {
+            indices_size: indices.len() as _,
+            vertices_size: vertices.len() as _,
+        });
+        self.info.primitive_opacities.push(is_opaque);
+        self.data.indices.extend(indices);
+        self.data.vertices.extend(vertices);
+
+        self.info.meshes.push(Mesh {
+            primitives: primitive..primitive + 1,
+        });
+        self.info.instances.push(Instance {
+            blas_index: self.info.meshes.len() - 1,
+            transform,
+            visibility_mask: ray_mask::ALL,
+            material: None,
+        });
+
+        let plane_bounds =
+            BoundingBox::new([-half_size, 0., -half_size], [half_size, 0., half_size]);
+        self.info.bounding_box = bounds.union(plane_bounds.transform(transform));
+    }
+}
+
+impl Instance {
+    const fn visible_to_all() -> u8 {
+        ray_mask::ALL
+    }
+}
+
 impl PrimitiveSize {
     pub const fn count(&self) -> u32 {
         self.indices_size / 3
@@ -70,11 +292,17 @@ impl BoundingBox {
         }
     }
 
+    // Bounds of all eight transformed corners, the two extremes alone are not enough under rotation
     #[must_use]
     pub fn transform(self, transform: glam::Mat4) -> Self {
-        let a = (transform * self.min.extend(1.0)).truncate();
-        let b = (transform * self.max.extend(1.0)).truncate();
-        Self::new(a.min(b), a.max(b))
+        (0..8)
+            .map(|corner| {
+                let select = glam::BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+                transform.transform_point3(glam::Vec3::select(select, self.max, self.min))
+            })
+            .fold(Self::default(), |bounds, point| {
+                Self::new(bounds.min.min(point), bounds.max.max(point))
+            })
     }
 
     #[must_use]
@@ -96,3 +324,199 @@ impl Default for BoundingBox {
         Self::new(glam::Vec3::INFINITY, glam::Vec3::NEG_INFINITY)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> BoundingBox {
+        BoundingBox::new([-1., -1., -1.], [1., 1., 1.])
+    }
+
+    fn assert_bounds_eq(bounds: BoundingBox, min: glam::Vec3, max: glam::Vec3) {
+        assert!(bounds.min.abs_diff_eq(min, 1e-5), "{} != {min}", bounds.min);
+        assert!(bounds.max.abs_diff_eq(max, 1e-5), "{} != {max}", bounds.max);
+    }
+
+    #[test]
+    fn transform_translates() {
+        let bounds = unit_box().transform(glam::Mat4::from_translation(glam::vec3(1., 2., 3.)));
+        assert_bounds_eq(bounds, glam::vec3(0., 1., 2.), glam::vec3(2., 3., 4.));
+    }
+
+    #[test]
+    fn transform_scales() {
+        let bounds = unit_box().transform(glam::Mat4::from_scale(glam::vec3(2., -3., 0.5)));
+        assert_bounds_eq(bounds, glam::vec3(-2., -3., -0.5), glam::vec3(2., 3., 0.5));
+    }
+
+    #[test]
+    fn transform_covers_rotated_corners() {
+        let rotation = glam::Mat4::from_rotation_z(std::f32::consts::FRAC_PI_4);
+        let bounds = unit_box().transform(rotation);
+        let extent = std::f32::consts::SQRT_2;
+        assert_bounds_eq(
+            bounds,
+            glam::vec3(-extent, -extent, -1.),
+            glam::vec3(extent, extent, 1.),
+        );
+    }
+
+    #[test]
+    fn transform_of_off_center_box_rotates_about_origin() {
+        let bounds = BoundingBox::new([1., 0., 0.], [2., 1., 1.])
+            .transform(glam::Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2));
+        assert_bounds_eq(bounds, glam::vec3(0., 0., -2.), glam::vec3(1., 1., -1.));
+    }
+
+    #[test]
+    fn union_with_default_is_identity() {
+        let bounds = BoundingBox::default().union(unit_box());
+        assert_bounds_eq(bounds, unit_box().min, unit_box().max);
+
+        let bounds = unit_box().union(BoundingBox::default());
+        assert_bounds_eq(bounds, unit_box().min, unit_box().max);
+    }
+
+    #[test]
+    fn union_encloses_both() {
+        let other = BoundingBox::new([0., 2., -3.], [4., 5., 0.]);
+        let bounds = unit_box().union(other);
+        assert_bounds_eq(bounds, glam::vec3(-1., -1., -3.), glam::vec3(4., 5., 1.));
+        assert_eq!(bounds.center(), glam::vec3(1.5, 2., -1.));
+        assert_eq!(bounds.size(), glam::vec3(5., 6., 4.));
+    }
+
+    #[test]
+    fn ground_plane_sits_under_bounding_box() {
+        let mut scene = Scene::default();
+        scene.info.bounding_box = BoundingBox::new([1., 2., 3.], [3., 4., 5.]);
+        scene.add_ground_plane(10., Material::default());
+
+        assert_eq!(scene.data.materials.len(), 1);
+        assert_eq!(scene.info.primitive_infos.len(), 1);
+        assert_eq!(scene.info.primitive_sizes[0].count(), 2);
+        assert_eq!(scene.info.meshes[0].primitives, 0..1);
+        assert_eq!(scene.info.instances[0].blas_index, 0);
+        assert_bounds_eq(
+            scene.info.bounding_box,
+            glam::vec3(-3., 2., -1.),
+            glam::vec3(7., 4., 9.),
+        );
+
+        let instance = &scene.info.instances[0];
+        let info = &scene.info.primitive_infos[0];
+        let position = |index: u32| {
+            let vertex = &scene.data.vertices[(info.vertices_offset + index) as usize];
+            instance
+                .transform
+                .transform_point3(vertex.position.truncate())
+        };
+        let tri = &scene.data.indices[..3];
+        let (v0, v1, v2) = (position(tri[0]), position(tri[1]), position(tri[2]));
+        assert!((v1 - v0)
+            .cross(v2 - v0)
+            .normalize()
+            .abs_diff_eq(glam::Vec3::Y, 1e-5));
+    }
+
+    fn textured_plane(size: f32) -> Scene {
+        let mut scene = Scene::default();
+        scene.data.images.push(Image::default());
+        scene.info.textures.push(TextureInfo::default());
+        scene.add_ground_plane(
+            size,
+            Material {
+                color_texture: 0,
+                emittance_texture: -1,
+                metallic_roughness_texture: -1,
+                ..Default::default()
+            },
+        );
+        scene
+    }
+
+    #[test]
+    fn merge_rebases_indices() {
+        let mut scene = textured_plane(2.);
+        let mut other = textured_plane(4.);
+        other.info.instances[0].material = Some(0);
+        scene.merge(other);
+
+        assert_eq!(scene.data.vertices.len(), 8);
+        assert_eq!(scene.data.indices.len(), 12);
+        assert_eq!(scene.info.meshes[1].primitives, 1..2);
+        assert_eq!(scene.info.instances[1].blas_index, 1);
+        assert_eq!(scene.info.instances[1].material, Some(1));
+        assert_eq!(scene.info.textures[1].image_index, 1);
+        assert_eq!(scene.info.primitive_opacities.len(), 2);
+
+        let info = &scene.info.primitive_infos[1];
+        assert_eq!(info.indices_offset, 6);
+        assert_eq!(info.vertices_offset, 4);
+        assert_eq!(info.material, 1);
+
+        let material = &scene.data.materials[1];
+        assert_eq!(material.color_texture, 1);
+        assert_eq!(material.emittance_texture, -1);
+        assert_eq!(material.metallic_roughness_texture, -1);
+
+        assert_bounds_eq(
+            scene.info.bounding_box,
+            glam::vec3(-2., 0., -2.),
+            glam::vec3(2., 0., 2.),
+        );
+    }
+
+    #[test]
+    fn merge_keeps_light_names_aligned() {
+        let mut scene = Scene::default();
+        scene.data.lights.push(Light::default());
+        let mut other = Scene::default();
+        other
+            .data
+            .lights
+            .extend([Light::default(), Light::default()]);
+        other.info.light_names.push("key".to_owned());
+
+        scene.merge(other);
+        assert_eq!(scene.info.light_names, ["", "key", ""]);
+    }
+
+    #[test]
+    fn merge_keeps_own_camera() {
+        let camera = |frame| shared::inputs::Camera {
+            view: shared::inputs::Transform::new(glam::Mat4::from_translation(
+                glam::Vec3::X * frame,
+            )),
+            ..Default::default()
+        };
+
+        let mut scene = Scene::default();
+        scene.merge(Scene {
+            camera: Some(camera(1.)),
+            ..Default::default()
+        });
+        assert!(scene.camera.is_some());
+
+        scene.merge(Scene {
+            camera: Some(camera(2.)),
+            ..Default::default()
+        });
+        assert_eq!(
+            scene.camera.unwrap().view.forward,
+            glam::Mat4::from_translation(glam::Vec3::X)
+        );
+    }
+
+    #[test]
+    fn ground_plane_of_empty_scene_is_at_origin() {
+        let mut scene = Scene::default();
+        scene.add_ground_plane(2., Material::default());
+        assert_bounds_eq(
+            scene.info.bounding_box,
+            glam::vec3(-1., 0., -1.),
+            glam::vec3(1., 0., 1.),
+        );
+    }
+}
@@ -0,0 +1,590 @@
+// Single threaded CPU counterpart of the path tracer in shaders/pathtracer.common.glsl, for checking
+// the shaders against. It follows the same sampling decisions and random number stream as the device
+// with one sample per frame, including the light picked for next event estimation at every bounce.
+// Textures are not sampled, so every surface is opaque and uses its constant material factors, and
+// rays are tested against every triangle.
+
+use shared::{bsdf, inputs, lights, rng::Rng, tonemap::luminance};
+
+use crate::{BvhTriangle, Scene};
+
+mod conf {
+    // same as shaders/ray.common.glsl
+    pub const MIN_BOUNCES: u32 = 3;
+    pub const MAX_BOUNCES: u32 = 8;
+    // same as shaders/pathtracer.common.glsl
+    pub const PIXEL_STRATA: u32 = 4;
+    pub const ROUGH_BOUNCE_ROUGHNESS: f32 = 0.3;
+}
+
+pub struct Settings {
+    pub resolution: (u32, u32),
+    pub samples: u32,
+    pub background_color: glam::Vec3,
+    pub ray_epsilon: f32,
+    // one of `bsdf::sampling_strategy`
+    pub sampling_strategy: u32,
+    // parallel primary rays, for a camera with an orthographic projection
+    pub orthographic: bool,
+    // bounces a path may take after its first rough one, `u32::MAX` for no cap
+    pub max_bounces_after_rough: u32,
+}
+
+struct Ray {
+    origin: glam::Vec3,
+    direction: glam::Vec3,
+}
+
+struct Hit {
+    position: glam::Vec3,
+    normal: glam::Vec3,
+    color: glam::Vec3,
+    material: u32,
+    front_face: bool,
+}
+
+// Averaged radiance of every pixel, row by row
+pub fn render(scene: &Scene, camera: &inputs::Camera, settings: &Settings) -> Vec<glam::Vec3> {
+    firestorm::profile_fn!(render);
+
+    let triangles = world_triangles(scene);
+    let (width, height) = settings.resolution;
+
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| glam::uvec2(x, y)))
+        .map(|pixel| {
+            let radiance = (0..settings.samples)
+                .map(|sample| {
+                    let mut rng = Rng::new(pixel, sample, 0);
+                    trace_sample(scene, &triangles, camera, settings, pixel, &mut rng, sample)
+                })
+                .sum::<glam::Vec3>();
+            radiance / settings.samples.max(1) as f32
+        })
+        .collect()
+}
+
+fn world_triangles(scene: &Scene) -> Vec<BvhTriangle> {
+    let mut triangles = Vec::new();
+
+    for (instance_index, instance) in scene.info.instances.iter().enumerate() {
+        for primitive in scene.info.meshes[instance.blas_index].primitives.clone() {
+            let info = &scene.info.primitive_infos[primitive];
+            let size = &scene.info.primitive_sizes[primitive];
+
+            let indices =
+                &scene.data.indices[info.indices_offset as usize..][..size.indices_size as usize];
+            let position = |index: u32| {
+                let vertex = &scene.data.vertices[(info.vertices_offset + index) as usize];
+                instance
+                    .transform
+                    .transform_point3(vertex.position.truncate())
+            };
+
+            triangles.extend(
+                indices
+                    .chunks_exact(3)
+                    .zip(0..)
+                    .map(|(tri, triangle)| BvhTriangle {
+                        v0: position(tri[0]),
+                        instance: instance_index as _,
+                        v1: position(tri[1]),
+                        primitive: primitive as _,
+                        v2: position(tri[2]),
+                        triangle,
+                    }),
+            );
+        }
+    }
+
+    triangles
+}
+
+// Möller-Trumbore as in shaders/pathtracer.comp.glsl, returns the distance and the barycentrics of
+// v1 and v2
+fn intersect_triangle(
+    tri: &BvhTriangle,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, glam::Vec2)> {
+    let (e1, e2) = (tri.v1 - tri.v0, tri.v2 - tri.v0);
+    let pvec = ray.direction.cross(e2);
+    let det = e1.dot(pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1. / det;
+
+    let tvec = ray.origin - tri.v0;
+    let u = tvec.dot(pvec) * inv_det;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+
+    let qvec = tvec.cross(e1);
+    let v = ray.direction.dot(qvec) * inv_det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let t = e2.dot(qvec) * inv_det;
+    (t > t_min && t < t_max).then_some((t, glam::vec2(u, v)))
+}
+
+fn trace_ray(scene: &Scene, triangles: &[BvhTriangle], ray: &Ray, ray_epsilon: f32) -> Option<Hit> {
+    let mut closest = None;
+    let mut t_max = f32::MAX;
+    for tri in triangles {
+        if let Some((t, uv)) = intersect_triangle(tri, ray, ray_epsilon, t_max) {
+            closest = Some((tri, uv));
+            t_max = t;
+        }
+    }
+    let (tri, uv) = closest?;
+
+    let info = &scene.info.primitive_infos[tri.primitive as usize];
+    let transform = scene.info.instances[tri.instance as usize].transform;
+    let indices = &scene.data.indices[(info.indices_offset + 3 * tri.triangle) as usize..][..3];
+    let vertex = |index: u32| &scene.data.vertices[(info.vertices_offset + index) as usize];
+
+    let bary = glam::vec3(1. - uv.x - uv.y, uv.x, uv.y);
+    let interpolate = |attribute: fn(&crate::Vertex) -> glam::Vec3| {
+        attribute(vertex(indices[0])) * bary.x
+            + attribute(vertex(indices[1])) * bary.y
+            + attribute(vertex(indices[2])) * bary.z
+    };
+    let position = interpolate(|v| v.position.truncate());
+    let normal = interpolate(|v| v.normal.truncate()).normalize();
+
+    Some(Hit {
+        position: transform.transform_point3(position),
+        normal: transform.transform_vector3(normal).normalize(),
+        color: interpolate(|v| v.color.truncate()),
+        material: info.material,
+        front_face: (tri.v1 - tri.v0).cross(tri.v2 - tri.v0).dot(ray.direction) < 0.,
+    })
+}
+
+// Whether anything is hit before the distance, like the shadow rays of the device
+fn occluded(triangles: &[BvhTriangle], ray: &Ray, ray_epsilon: f32, distance: f32) -> bool {
+    triangles
+        .iter()
+        .any(|tri| intersect_triangle(tri, ray, ray_epsilon, distance).is_some())
+}
+
+// Light reflected towards wo straight from one of the punctual lights picked at random, as in
+// `trace_direct_light` of shaders/pathtracer.common.glsl
+#[allow(clippy::too_many_arguments, clippy::cast_sign_loss)]
+fn trace_direct_light(
+    scene: &Scene,
+    triangles: &[BvhTriangle],
+    rng: &mut Rng,
+    material: &bsdf::MaterialHit,
+    hit: &Hit,
+    wo: glam::Vec3,
+    n: glam::Vec3,
+    ray_epsilon: f32,
+) -> glam::Vec3 {
+    let lights = &scene.data.lights;
+    if lights.is_empty() {
+        return glam::Vec3::ZERO;
+    }
+
+    let num_lights = lights.len() as u32;
+    let index = ((rng.next_float() * num_lights as f32) as u32).min(num_lights - 1);
+    let incident = lights::sample_light(&lights[index as usize], hit.position);
+
+    let reflected = bsdf::bsdf_eval(material, wo, incident.direction, n) * incident.irradiance;
+    if luminance(reflected) == 0. {
+        return glam::Vec3::ZERO;
+    }
+
+    let shadow_ray = Ray {
+        origin: offset_ray_origin(hit.position, n, incident.direction, ray_epsilon),
+        direction: incident.direction,
+    };
+    if occluded(triangles, &shadow_ray, ray_epsilon, incident.distance) {
+        glam::Vec3::ZERO
+    } else {
+        reflected * num_lights as f32
+    }
+}
+
+fn stratified_pixel_offset(rng: &mut Rng, sample_number: u32) -> glam::Vec2 {
+    let stratum = sample_number % (conf::PIXEL_STRATA * conf::PIXEL_STRATA);
+    let cell = glam::uvec2(stratum % conf::PIXEL_STRATA, stratum / conf::PIXEL_STRATA).as_vec2();
+    (cell + next_vec2(rng)) / conf::PIXEL_STRATA as f32
+}
+
+fn next_vec2(rng: &mut Rng) -> glam::Vec2 {
+    let x = rng.next_float();
+    glam::vec2(x, rng.next_float())
+}
+
+fn offset_ray_origin(
+    position: glam::Vec3,
+    n: glam::Vec3,
+    direction: glam::Vec3,
+    ray_epsilon: f32,
+) -> glam::Vec3 {
+    position + if direction.dot(n) < 0. { -n } else { n } * ray_epsilon
+}
+
+// exact material factors select the perfect mirror path like on the device
+#[allow(clippy::float_cmp, clippy::too_many_lines)]
+fn trace_sample(
+    scene: &Scene,
+    triangles: &[BvhTriangle],
+    camera: &inputs::Camera,
+    settings: &Settings,
+    pixel_index: glam::UVec2,
+    rng: &mut Rng,
+    sample_number: u32,
+) -> glam::Vec3 {
+    let pixel = pixel_index.as_vec2() + stratified_pixel_offset(rng, sample_number);
+    let resolution = glam::uvec2(settings.resolution.0, settings.resolution.1).as_vec2();
+    let coords = 2. * (pixel / resolution) - 1.;
+
+    let mut ray = if settings.orthographic {
+        let origin = camera.proj.inverse.project_point3(coords.extend(0.));
+        Ray {
+            origin: camera.view.inverse.transform_point3(origin),
+            direction: camera.view.inverse.transform_vector3(glam::Vec3::NEG_Z),
+        }
+    } else {
+        let origin = camera.view.inverse.transform_point3(glam::Vec3::ZERO);
+        let target = camera.proj.inverse * coords.extend(1.).extend(1.);
+        let direction = camera
+            .view
+            .inverse
+            .transform_vector3(target.truncate().normalize());
+        Ray { origin, direction }
+    };
+
+    let mut radiance = glam::Vec3::ZERO;
+    let mut throughput = glam::Vec3::ONE;
+
+    // depth of the first diffuse or rough glossy bounce, the path is cut short after it
+    let mut rough_depth = None;
+
+    for depth in 0..conf::MAX_BOUNCES {
+        rng.begin_bounce(depth);
+        let Some(hit) = trace_ray(scene, triangles, &ray, settings.ray_epsilon) else {
+            radiance += throughput * settings.background_color;
+            break;
+        };
+
+        let hit_material = &scene.data.materials[hit.material as usize];
+
+        // backfaces of single-sided opaque surfaces absorb all light
+        if !hit.front_face && hit_material.double_sided == 0 && hit_material.transmission == 0. {
+            break;
+        }
+
+        let wo = -ray.direction;
+        let n = if hit.normal.dot(wo) < 0. {
+            -hit.normal
+        } else {
+            hit.normal
+        };
+
+        let material = bsdf::MaterialHit {
+            base_color: hit_material.color * hit.color,
+            metallic: hit_material.metallic,
+            emittance: hit_material.emittance,
+            roughness: hit_material.roughness,
+            transmission: hit_material.transmission,
+            ior: hit_material.ior,
+        };
+
+        radiance += throughput * material.emittance;
+
+        // Don't need to sample BSDF on last bounce
+        if depth == conf::MAX_BOUNCES - 1 {
+            break;
+        }
+
+        // the transmitted fraction is accounted for by following the refracted path instead
+        radiance += throughput
+            * (1. - material.transmission)
+            * trace_direct_light(
+                scene,
+                triangles,
+                rng,
+                &material,
+                &hit,
+                wo,
+                n,
+                settings.ray_epsilon,
+            );
+
+        // Biased, but long chains after a rough bounce are where most fireflies come from
+        if rough_depth
+            .is_some_and(|rough_depth| depth - rough_depth > settings.max_bounces_after_rough)
+        {
+            break;
+        }
+
+        // Russian Roulette
+        if depth > conf::MIN_BOUNCES {
+            let p_rr = luminance(throughput).min(0.95);
+            if p_rr < rng.next_float() {
+                break;
+            }
+            throughput /= p_rr;
+        }
+
+        // Transmission
+        if material.transmission > 0. && rng.next_float() < material.transmission {
+            let (wi, weight) =
+                bsdf::sample_transmission(&material, hit.front_face, wo, n, rng.next_float());
+            throughput *= weight;
+
+            ray.origin = offset_ray_origin(hit.position, n, wi, settings.ray_epsilon);
+            ray.direction = wi;
+            continue;
+        }
+
+        // BSDF evaluation
+        let mut is_specular = material.metallic == 1. && material.roughness == 0.;
+        if !is_specular {
+            let p_spec = bsdf::specular_probability(&material, wo, n);
+
+            if rng.next_float() < p_spec {
+                is_specular = true;
+                throughput /= p_spec;
+            } else {
+                throughput /= 1. - p_spec;
+            }
+        }
+
+        // Importance sample the BSDF
+        let Some((wi, weight)) = bsdf::bsdf_sample(
+            &material,
+            is_specular,
+            settings.sampling_strategy,
+            wo,
+            n,
+            next_vec2(rng),
+        ) else {
+            break;
+        };
+
+        throughput *= weight;
+        if rough_depth.is_none()
+            && (!is_specular || material.roughness > conf::ROUGH_BOUNCE_ROUGHNESS)
+        {
+            rough_depth = Some(depth);
+        }
+
+        ray.origin = offset_ray_origin(hit.position, n, wi, settings.ray_epsilon);
+        ray.direction = wi;
+    }
+
+    radiance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Material;
+
+    const BACKGROUND: f32 = 0.8;
+
+    // Camera above the origin looking straight down with a 90 degree field of view
+    fn looking_down() -> inputs::Camera {
+        inputs::Camera {
+            view: inputs::Transform::new(glam::Mat4::look_at_rh(
+                glam::Vec3::Y,
+                glam::Vec3::ZERO,
+                glam::Vec3::NEG_Z,
+            )),
+            proj: inputs::Transform::proj(glam::Mat4::perspective_rh(
+                core::f32::consts::FRAC_PI_2,
+                1.,
+                0.1,
+                100.,
+            )),
+        }
+    }
+
+    fn mean_radiance(material: Material, ground_size: f32) -> glam::Vec3 {
+        let mut scene = Scene::default();
+        scene.add_ground_plane(ground_size, material);
+
+        let settings = Settings {
+            resolution: (8, 8),
+            samples: 16,
+            background_color: glam::Vec3::splat(BACKGROUND),
+            ray_epsilon: 1e-4,
+            sampling_strategy: bsdf::sampling_strategy::COSINE,
+            orthographic: false,
+            max_bounces_after_rough: u32::MAX,
+        };
+        let pixels = render(&scene, &looking_down(), &settings);
+        pixels.iter().sum::<glam::Vec3>() / pixels.len() as f32
+    }
+
+    #[test]
+    fn empty_scene_shows_background() {
+        let settings = Settings {
+            resolution: (2, 2),
+            samples: 1,
+            background_color: glam::vec3(0.1, 0.2, 0.3),
+            ray_epsilon: 1e-4,
+            sampling_strategy: bsdf::sampling_strategy::COSINE,
+            orthographic: false,
+            max_bounces_after_rough: u32::MAX,
+        };
+        for pixel in render(&Scene::default(), &looking_down(), &settings) {
+            assert_eq!(pixel, settings.background_color);
+        }
+    }
+
+    #[test]
+    fn black_emitter_returns_its_emittance() {
+        // a black metal reflects nothing, so only the emission is seen
+        let material = Material {
+            emittance: glam::Vec3::splat(2.),
+            metallic: 1.,
+            ..Default::default()
+        };
+        let mean = mean_radiance(material, 100.);
+        assert!(mean.abs_diff_eq(glam::Vec3::splat(2.), 1e-5), "{mean}");
+    }
+
+    #[test]
+    fn perfect_mirror_reflects_background() {
+        let material = Material {
+            color: glam::Vec3::ONE,
+            metallic: 1.,
+            ..Default::default()
+        };
+        let mean = mean_radiance(material, 100.);
+        assert!(
+            mean.abs_diff_eq(glam::Vec3::splat(BACKGROUND), 1e-4),
+            "{mean}"
+        );
+    }
+
+    #[test]
+    fn vertex_colors_tint_the_base_color() {
+        let mut scene = Scene::default();
+        let material = Material {
+            color: glam::Vec3::ONE,
+            metallic: 1.,
+            ..Default::default()
+        };
+        scene.add_ground_plane(100., material);
+        for vertex in &mut scene.data.vertices {
+            vertex.color = glam::vec4(1., 0.5, 0., 1.);
+        }
+
+        let settings = Settings {
+            resolution: (4, 4),
+            samples: 4,
+            background_color: glam::Vec3::splat(BACKGROUND),
+            ray_epsilon: 1e-4,
+            sampling_strategy: bsdf::sampling_strategy::COSINE,
+            orthographic: false,
+            max_bounces_after_rough: u32::MAX,
+        };
+        let pixels = render(&scene, &looking_down(), &settings);
+        let mean = pixels.iter().sum::<glam::Vec3>() / pixels.len() as f32;
+        // up to the Fresnel brightening towards the edges of the view
+        let expected = glam::vec3(1., 0.5, 0.) * BACKGROUND;
+        assert!(mean.abs_diff_eq(expected, 5e-3), "{mean}");
+    }
+
+    #[test]
+    fn white_furnace_conserves_energy() {
+        // a white dielectric under a uniform sky may lose a little energy but must never create any
+        let material = Material {
+            color: glam::Vec3::ONE,
+            roughness: 1.,
+            ior: 1.5,
+            ..Default::default()
+        };
+        let mean = mean_radiance(material, 1000.);
+        assert!(mean.max_element() <= BACKGROUND * 1.02, "{mean}");
+        assert!(mean.min_element() >= BACKGROUND * 0.85, "{mean}");
+    }
+
+    #[test]
+    fn orthographic_view_does_not_shrink_with_distance() {
+        let mut scene = Scene::default();
+        let material = Material {
+            emittance: glam::Vec3::splat(2.),
+            metallic: 1.,
+            ..Default::default()
+        };
+        scene.add_ground_plane(1.2, material);
+
+        // high above the plane, it still fills the middle of a view 2 units across
+        let camera = inputs::Camera {
+            view: inputs::Transform::new(glam::Mat4::look_at_rh(
+                glam::Vec3::Y * 10.,
+                glam::Vec3::ZERO,
+                glam::Vec3::NEG_Z,
+            )),
+            proj: inputs::Transform::proj(glam::Mat4::orthographic_rh(-1., 1., -1., 1., 0., 100.)),
+        };
+        let settings = Settings {
+            resolution: (8, 8),
+            samples: 4,
+            background_color: glam::Vec3::splat(BACKGROUND),
+            ray_epsilon: 1e-4,
+            sampling_strategy: bsdf::sampling_strategy::COSINE,
+            orthographic: true,
+            max_bounces_after_rough: u32::MAX,
+        };
+        let pixels = render(&scene, &camera, &settings);
+
+        let pixel = |x: usize, y: usize| pixels[y * 8 + x];
+        assert_eq!(pixel(0, 0), settings.background_color);
+        assert_eq!(pixel(7, 7), settings.background_color);
+        for xy in 2..6 {
+            assert!(pixel(xy, xy).abs_diff_eq(glam::Vec3::splat(2.), 1e-5));
+        }
+    }
+
+    #[test]
+    fn point_light_lights_the_ground_below_it() {
+        // brightness in the middle of the view of a diffuse plane lit only by a point light
+        let lit_from = |height: f32| {
+            let mut scene = Scene::default();
+            let material = Material {
+                color: glam::Vec3::ONE,
+                roughness: 1.,
+                ..Default::default()
+            };
+            scene.add_ground_plane(100., material);
+            scene.data.lights.push(shared::scene::Light {
+                position: glam::Vec3::Y * height,
+                kind: shared::scene::light_kind::POINT,
+                intensity: glam::Vec3::ONE,
+                ..Default::default()
+            });
+
+            let settings = Settings {
+                resolution: (32, 32),
+                samples: 4,
+                background_color: glam::Vec3::ZERO,
+                ray_epsilon: 1e-4,
+                sampling_strategy: bsdf::sampling_strategy::COSINE,
+                orthographic: false,
+                max_bounces_after_rough: u32::MAX,
+            };
+            let pixels = render(&scene, &looking_down(), &settings);
+            pixels[16 * 32 + 16]
+        };
+
+        let near = lit_from(0.5);
+        let far = lit_from(1.);
+        assert!(far.min_element() > 0., "{far}");
+        // the ground sees nothing but the light, so halving its distance quadruples the radiance
+        let ratio = near / far;
+        assert!(ratio.abs_diff_eq(glam::Vec3::splat(4.), 0.1), "{ratio}");
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use crate::{PrimitiveInfo, PrimitiveSize, Scene, Vertex};
+
+impl Scene {
+    // Merges the vertices of each primitive that are identical in every attribute and drops the
+    // ones no triangle refers to, the triangles themselves stay as they were. Vertices are laid out
+    // in the order the indices first refer to them, and the bind pose of skinned primitives follows
+    // along. Returns the number of vertices removed
+    pub fn weld_vertices(&mut self) -> usize {
+        firestorm::profile_method!(weld_vertices);
+
+        let num_vertices = self.data.vertices.len();
+
+        let mut vertices = Vec::with_capacity(num_vertices);
+        let mut indices = Vec::with_capacity(self.data.indices.len());
+
+        let mut skinned_primitives = self
+            .animation
+            .skinned_primitives
+            .iter_mut()
+            .map(|skinned| (skinned.primitive, skinned))
+            .collect::<HashMap<_, _>>();
+
+        for (primitive, (info, size)) in self
+            .info
+            .primitive_infos
+            .iter_mut()
+            .zip(&mut self.info.primitive_sizes)
+            .enumerate()
+        {
+            let primitive_vertices =
+                &self.data.vertices[info.vertices_offset as usize..][..size.vertices_size as usize];
+            let primitive_indices =
+                &self.data.indices[info.indices_offset as usize..][..size.indices_size as usize];
+
+            let vertices_offset = vertices.len() as u32;
+            let indices_offset = indices.len() as u32;
+
+            let skinned = skinned_primitives.remove(&primitive);
+
+            // compared bit for bit, so that welding never changes an attribute. Vertices bound to the
+            // joints differently are kept apart too
+            let mut welded = HashMap::new();
+            // the vertex each welded one was first referred to as, within the primitive
+            let mut sources = Vec::new();
+            indices.extend(primitive_indices.iter().map(|&index| {
+                let vertex = primitive_vertices[index as usize];
+                let binding = skinned.as_ref().map(|skinned| {
+                    (
+                        skinned.joints[index as usize],
+                        skinned.weights[index as usize].to_array().map(f32::to_bits),
+                    )
+                });
+                *welded
+                    .entry((bytemuck::cast::<Vertex, [u32; 16]>(vertex), binding))
+                    .or_insert_with(|| {
+                        vertices.push(vertex);
+                        sources.push(index as usize);
+                        vertices.len() as u32 - 1 - vertices_offset
+                    })
+            }));
+
+            if let Some(skinned) = skinned {
+                skinned.bind_vertices = sources
+                    .iter()
+                    .map(|&source| skinned.bind_vertices[source])
+                    .collect();
+                skinned.joints = sources
+                    .iter()
+                    .map(|&source| skinned.joints[source])
+                    .collect();
+                skinned.weights = sources
+                    .iter()
+                    .map(|&source| skinned.weights[source])
+                    .collect();
+            }
+
+            *info = PrimitiveInfo {
+                indices_offset,
+                vertices_offset,
+                ..*info
+            };
+            *size = PrimitiveSize {
+                indices_size: size.indices_size,
+                vertices_size: vertices.len() as u32 - vertices_offset,
+            };
+        }
+
+        self.data.vertices = vertices;
+        self.data.indices = indices;
+
+        num_vertices - self.data.vertices.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{animation::SkinnedPrimitive, builder::SceneBuilder, Material, Scene, Vertex};
+
+    fn vertex(position: [f32; 3], tex_coord: [f32; 2]) -> Vertex {
+        Vertex::new(&position, &[0., 0., 1.], &tex_coord, &[0.; 2], &[1.; 4])
+    }
+
+    // every triangle with the bits of its vertices looked up, in the order they are drawn
+    fn triangles(scene: &Scene) -> Vec<[[u32; 16]; 3]> {
+        scene
+            .info
+            .primitive_infos
+            .iter()
+            .zip(&scene.info.primitive_sizes)
+            .flat_map(|(info, size)| {
+                let indices = &scene.data.indices[info.indices_offset as usize..]
+                    [..size.indices_size as usize];
+                indices.chunks_exact(3).map(|triangle| {
+                    [0, 1, 2].map(|corner| {
+                        let index = info.vertices_offset + triangle[corner];
+                        bytemuck::cast(scene.data.vertices[index as usize])
+                    })
+                })
+            })
+            .collect()
+    }
+
+    fn untextured() -> Material {
+        Material {
+            color_texture: -1,
+            emittance_texture: -1,
+            metallic_roughness_texture: -1,
+            ..Default::default()
+        }
+    }
+
+    // a quad as two triangles that repeat the vertices of their shared edge
+    fn quad() -> [Vertex; 6] {
+        [
+            vertex([0., 0., 0.], [0., 0.]),
+            vertex([1., 0., 0.], [1., 0.]),
+            vertex([1., 1., 0.], [1., 1.]),
+            vertex([0., 0., 0.], [0., 0.]),
+            vertex([1., 1., 0.], [1., 1.]),
+            vertex([0., 1., 0.], [0., 1.]),
+        ]
+    }
+
+    #[test]
+    fn welding_keeps_the_geometry() {
+        let mut builder = SceneBuilder::new();
+        let material = builder.add_material(untextured());
+        builder.add_primitive(quad(), 0..6, material);
+        // the same position with another uv is a seam and must not be merged, the last vertex is
+        // never referred to
+        let seam = [
+            vertex([0., 0., 1.], [0., 0.]),
+            vertex([1., 0., 1.], [1., 0.]),
+            vertex([0., 1., 1.], [0., 1.]),
+            vertex([0., 0., 1.], [1., 1.]),
+            vertex([2., 2., 2.], [0., 0.]),
+        ];
+        builder.add_primitive(seam, [0, 1, 2, 3, 1, 2], material);
+        let mut scene = builder.build().unwrap();
+
+        let before = triangles(&scene);
+        assert_eq!(scene.weld_vertices(), 3);
+        assert_eq!(triangles(&scene), before);
+
+        assert_eq!(scene.data.vertices.len(), 8);
+        assert_eq!(scene.info.primitive_sizes[0].vertices_size, 4);
+        assert_eq!(scene.info.primitive_infos[1].vertices_offset, 4);
+        assert_eq!(scene.info.primitive_sizes[1].vertices_size, 4);
+        assert_eq!(&scene.data.indices[..6], [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn welding_keeps_skins_in_step() {
+        let mut builder = SceneBuilder::new();
+        let material = builder.add_material(untextured());
+        builder.add_primitive(quad(), 0..6, material);
+        let mut scene = builder.build().unwrap();
+
+        // the first corner is repeated but bound to another joint the second time, so it stays
+        let joints = [0, 1, 2, 3, 2, 5].map(|joint| [joint, 0, 0, 0]);
+        scene.animation.skinned_primitives.push(SkinnedPrimitive {
+            primitive: 0,
+            skin: 0,
+            bind_vertices: quad().to_vec(),
+            joints: joints.to_vec(),
+            weights: vec![glam::Vec4::X; 6],
+        });
+
+        assert_eq!(scene.weld_vertices(), 1);
+
+        let size = scene.info.primitive_sizes[0].vertices_size as usize;
+        let skinned = &scene.animation.skinned_primitives[0];
+        assert_eq!(size, 5);
+        assert_eq!(skinned.bind_vertices.len(), size);
+        assert_eq!(skinned.joints.len(), size);
+        assert_eq!(skinned.weights.len(), size);
+        assert_eq!(
+            bytemuck::cast_slice::<Vertex, u32>(&skinned.bind_vertices),
+            bytemuck::cast_slice::<Vertex, u32>(&scene.data.vertices[..size]),
+        );
+        assert_eq!(
+            skinned
+                .joints
+                .iter()
+                .map(|joints| joints[0])
+                .collect::<Vec<_>>(),
+            [0, 1, 2, 3, 5]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+#version 460
+
+#include "inputs.h.glsl"
+
+// must match the renderer
+const uint MODE_PREFILTER = 0;
+const uint MODE_DOWNSAMPLE = 1;
+const uint MODE_UPSAMPLE = 2;
+const uint MODE_COMPOSITE = 3;
+
+// width of the transition around the threshold, relative to it
+const float KNEE = 0.5;
+
+layout(local_size_x=16, local_size_y=16) in;
+
+layout(push_constant) uniform _PushConstants { BloomConstants constants; };
+
+layout(set=0, binding=0, INTERMEDIATE_FORMAT) uniform readonly image2D input_image;
+layout(set=0, binding=1, INTERMEDIATE_FORMAT) uniform image2D output_image;
+
+
+vec3 load(ivec2 pixel) {
+  return imageLoad(input_image, clamp(pixel, ivec2(0), imageSize(input_image) - 1)).xyz;
+}
+
+
+// Average of the 2x2 input texels under an output texel
+vec3 downsample(ivec2 pixel) {
+  const ivec2 base = 2 * pixel;
+  return 0.25 * (load(base) + load(base + ivec2(1, 0)) + load(base + ivec2(0, 1)) + load(base + ivec2(1, 1)));
+}
+
+
+// Bilinear sample of the input at the center of an output texel
+vec3 upsample(ivec2 pixel, ivec2 output_size) {
+  const vec2 position = (vec2(pixel) + 0.5) * vec2(imageSize(input_image)) / vec2(output_size) - 0.5;
+  const ivec2 base = ivec2(floor(position));
+  const vec2 f = fract(position);
+  return mix(mix(load(base), load(base + ivec2(1, 0)), f.x),
+             mix(load(base + ivec2(0, 1)), load(base + ivec2(1, 1)), f.x), f.y);
+}
+
+
+// Soft threshold on the brightest channel so that bloom fades in instead of popping
+vec3 prefilter(vec3 color) {
+  const float brightness = max(color.r, max(color.g, color.b));
+  const float knee = constants.threshold * KNEE;
+  float soft = clamp(brightness - constants.threshold + knee, 0, 2 * knee);
+  soft = soft * soft / (4 * knee + 1e-5);
+  const float contribution = max(soft, brightness - constants.threshold) / max(brightness, 1e-5);
+  return color * contribution;
+}
+
+
+void main() {
+  const ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  const ivec2 size = imageSize(output_image);
+  if (any(greaterThanEqual(pixel, size))) return;
+
+  vec3 color;
+  switch (constants.mode) {
+    case MODE_PREFILTER: color = prefilter(downsample(pixel)); break;
+    case MODE_DOWNSAMPLE: color = downsample(pixel); break;
+    case MODE_UPSAMPLE: color = imageLoad(output_image, pixel).xyz + upsample(pixel, size); break;
+    case MODE_COMPOSITE: color = imageLoad(output_image, pixel).xyz + constants.intensity * upsample(pixel, size); break;
+  }
+
+  imageStore(output_image, pixel, vec4(color, 1));
+}
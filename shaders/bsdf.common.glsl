@@ -4,13 +4,22 @@
 #include "globals.common.glsl"
 #include "scene.h.glsl"
 
+// mirrored on the host in shared/src/bsdf.rs for the CPU reference path tracer
+
 const float MIN_DIELECTRICS_F0 = 0.04;
+const float MIN_EVAL_ALPHA = 0.05;
+
+// how the diffuse lobe picks directions, uniform moves the cosine into the weight instead
+const uint SAMPLING_STRATEGY_COSINE = 0;
+const uint SAMPLING_STRATEGY_UNIFORM = 1;
 
 struct MaterialHit {
   vec3 base_color;
   float metallic;
   vec3 emittance;
   float roughness;
+  float transmission;
+  float ior;
 };
 
 float clamp_unit_nonzero(float value) {
@@ -46,11 +55,28 @@ vec3 eval_fresnel(vec3 f0, float n_dot_s) {
   return f0 + (shadowed_f90(f0) - f0) * pow(1 - n_dot_s, 5);
 }
 
+// Unpolarized Fresnel reflectance for a dielectric interface, eta = n_incident / n_transmitted
+float eval_dielectric_fresnel(float cos_i, float eta) {
+  const float sin_t_sq = eta * eta * (1 - cos_i * cos_i);
+  if (sin_t_sq >= 1) return 1;  // total internal reflection
+
+  const float cos_t = sqrt(1 - sin_t_sq);
+  const float r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  const float r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  return 0.5 * (r_s * r_s + r_p * r_p);
+}
+
 // GGX
 float smith_g1(float alpha_sq, float n_dot_s_sq) {
   return 2 / (sqrt((alpha_sq * (1 - n_dot_s_sq) + n_dot_s_sq) / n_dot_s_sq) + 1);
 }
 
+// GGX normal distribution
+float ggx_distribution(float alpha_sq, float n_dot_h) {
+  const float d = n_dot_h * n_dot_h * (alpha_sq - 1) + 1;
+  return alpha_sq / (PI * d * d);
+}
+
 // GGX VNDF height correlated
 float specular_sample_weight(float alpha_sq, float n_dot_l_sq, float n_dot_wo_sq) {
   const float g1wo = smith_g1(alpha_sq, n_dot_wo_sq);
@@ -108,9 +134,24 @@ float specular_probability(MaterialHit material, vec3 wo, vec3 n) {
   return clamp(p, 0.1, 0.9);
 }
 
-bool bsdf_sample(MaterialHit material, bool is_specular, vec3 wo, vec3 n, vec2 r,
-                 out vec3 wi, out vec3 weight) {
-  if (dot(n, wo) <= 0) false;
+// Smooth dielectric, stochastically reflects or refracts based on the Fresnel reflectance
+vec3 sample_transmission(MaterialHit material, bool entering, vec3 wo, vec3 n, float r,
+                         out vec3 weight) {
+  const float eta = entering ? 1 / material.ior : material.ior;
+  const float fresnel = eval_dielectric_fresnel(clamp_unit(dot(n, wo)), eta);
+
+  if (r < fresnel) {
+    weight = vec3(1);
+    return reflect(-wo, n);
+  }
+
+  weight = material.base_color;
+  return refract(-wo, n, eta);
+}
+
+bool bsdf_sample(MaterialHit material, bool is_specular, uint sampling_strategy, vec3 wo, vec3 n,
+                 vec2 r, out vec3 wi, out vec3 weight) {
+  if (dot(n, wo) <= 0) return false;
 
   const vec4 frame = quat_frame(n);
   wo = quat_rotate(frame, wo);
@@ -121,12 +162,18 @@ bool bsdf_sample(MaterialHit material, bool is_specular, vec3 wo, vec3 n, vec2 r
   if (is_specular) {
     wi = sample_specular_microfacet(wo, alpha, specular_f0, r, weight);
   } else {
-    wi = sample_hemisphere(r);
-
     const vec3 h = sample_specular_half_vector(wo, alpha, r);
     const float wo_dot_h = clamp_unit_nonzero(dot(wo, h));
     weight = base_color_to_diffuse_reflectance(material.base_color, material.metallic)
               * (vec3(1) - eval_fresnel(specular_f0, wo_dot_h));
+
+    if (sampling_strategy == SAMPLING_STRATEGY_UNIFORM) {
+      // cosine over the uniform pdf of 1 / 2pi, relative to the cosine weighted pdf
+      wi = sample_uniform_hemisphere(r);
+      weight *= 2 * wi.z;
+    } else {
+      wi = sample_hemisphere(r);
+    }
   }
 
   if (luminance(weight) == 0) return false;
@@ -136,4 +183,27 @@ bool bsdf_sample(MaterialHit material, bool is_specular, vec3 wo, vec3 n, vec2 r
   return dot(n, wi) > 0;
 }
 
+// BSDF times the cosine term for a given light direction, for shading without sampling. Smooth
+// surfaces are treated as slightly rough since a perfect mirror never reflects a single direction
+vec3 bsdf_eval(MaterialHit material, vec3 wo, vec3 wi, vec3 n) {
+  const float n_dot_l = dot(n, wi);
+  const float n_dot_wo = dot(n, wo);
+  if (n_dot_l <= 0 || n_dot_wo <= 0) return vec3(0);
+
+  const vec3 h = normalize(wo + wi);
+  const float alpha = max(material.roughness * material.roughness, MIN_EVAL_ALPHA);
+  const float alpha_sq = alpha * alpha;
+  const vec3 specular_f0 = base_color_to_specular_f0(material.base_color, material.metallic);
+  const vec3 F = eval_fresnel(specular_f0, clamp_unit_nonzero(dot(wo, h)));
+
+  const vec3 diffuse = base_color_to_diffuse_reflectance(material.base_color, material.metallic)
+                     * (vec3(1) - F) / PI;
+
+  const float D = ggx_distribution(alpha_sq, clamp_unit(dot(n, h)));
+  const float G = smith_g1(alpha_sq, n_dot_l * n_dot_l) * smith_g1(alpha_sq, n_dot_wo * n_dot_wo);
+  const vec3 specular = F * D * G / (4 * n_dot_l * n_dot_wo);
+
+  return (diffuse + specular) * n_dot_l;
+}
+
 #endif
@@ -0,0 +1,62 @@
+#version 460
+
+#include "inputs.h.glsl"
+
+// edge-avoiding a-trous wavelet filter (Dammertz et al. 2010)
+const int KERNEL_RADIUS = 2;
+const float KERNEL[KERNEL_RADIUS + 1] = float[](3. / 8., 1. / 4., 1. / 16.);
+
+const float SIGMA_COLOR = 1;
+const float SIGMA_NORMAL = 128;
+const float SIGMA_ALBEDO = 0.1;
+
+layout(local_size_x=16, local_size_y=16) in;
+
+layout(push_constant) uniform _PushConstants { DenoiseConstants constants; };
+
+layout(set=0, binding=0, INTERMEDIATE_FORMAT) uniform readonly image2D input_image;
+layout(set=0, binding=1, INTERMEDIATE_FORMAT) uniform writeonly image2D output_image;
+layout(set=0, binding=2, INTERMEDIATE_FORMAT) uniform readonly image2D albedo_image;
+layout(set=0, binding=3, INTERMEDIATE_FORMAT) uniform readonly image2D normal_image;
+
+
+void main() {
+  const ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  const ivec2 size = imageSize(input_image);
+  if (any(greaterThanEqual(pixel, size))) return;
+
+  const vec3 color = imageLoad(input_image, pixel).xyz;
+  const vec3 albedo = imageLoad(albedo_image, pixel).xyz;
+  const vec3 normal = imageLoad(normal_image, pixel).xyz;
+
+  // coarser levels have already been smoothed so tighten the color edge stop
+  const float sigma_color = SIGMA_COLOR / float(constants.step_size);
+
+  // the center tap always contributes fully so the weights never sum to zero
+  vec3 sum = color * KERNEL[0] * KERNEL[0];
+  float weight_sum = KERNEL[0] * KERNEL[0];
+  for (int y = -KERNEL_RADIUS; y <= KERNEL_RADIUS; ++y) {
+    for (int x = -KERNEL_RADIUS; x <= KERNEL_RADIUS; ++x) {
+      if (x == 0 && y == 0) continue;
+
+      const ivec2 tap = clamp(pixel + ivec2(x, y) * int(constants.step_size), ivec2(0), size - 1);
+
+      const vec3 tap_color = imageLoad(input_image, tap).xyz;
+      const vec3 tap_albedo = imageLoad(albedo_image, tap).xyz;
+      const vec3 tap_normal = imageLoad(normal_image, tap).xyz;
+
+      const vec3 d_color = tap_color - color;
+      const vec3 d_albedo = tap_albedo - albedo;
+
+      const float w_color = exp(-dot(d_color, d_color) / sigma_color);
+      const float w_albedo = exp(-dot(d_albedo, d_albedo) / SIGMA_ALBEDO);
+      const float w_normal = pow(max(dot(tap_normal, normal), 0), SIGMA_NORMAL);
+
+      const float w = KERNEL[abs(x)] * KERNEL[abs(y)] * w_color * w_albedo * w_normal;
+      sum += tap_color * w;
+      weight_sum += w;
+    }
+  }
+
+  imageStore(output_image, pixel, vec4(sum / weight_sum, 1));
+}
@@ -0,0 +1,41 @@
+#version 460
+
+#include "inputs.h.glsl"
+
+// must match the renderer, the first bin collects (near) black pixels
+const uint NUM_BINS = 64;
+const float MIN_LUMINANCE = 1e-4;
+
+layout(local_size_x=16, local_size_y=16) in;
+
+layout(push_constant) uniform _PushConstants { ExposureConstants constants; };
+
+layout(set=0, binding=0, INTERMEDIATE_FORMAT) uniform readonly image2D input_image;
+layout(set=0, binding=1) buffer _Histogram { uint bins[NUM_BINS]; };
+
+shared uint local_bins[NUM_BINS];
+
+
+uint bin_of(vec3 color) {
+  const float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+  if (luminance < MIN_LUMINANCE) return 0;
+
+  const float t = clamp((log2(luminance) - constants.min_log_luminance) / constants.log_luminance_range, 0, 1);
+  return min(uint(t * (NUM_BINS - 1)) + 1, NUM_BINS - 1);
+}
+
+
+// Bins are counted per workgroup in shared memory first to keep contention on the global histogram low
+void main() {
+  const uint local_index = gl_LocalInvocationIndex;
+  if (local_index < NUM_BINS) local_bins[local_index] = 0;
+  barrier();
+
+  const ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  if (all(lessThan(pixel, imageSize(input_image)))) {
+    atomicAdd(local_bins[bin_of(imageLoad(input_image, pixel).xyz)], 1);
+  }
+  barrier();
+
+  if (local_index < NUM_BINS) atomicAdd(bins[local_index], local_bins[local_index]);
+}
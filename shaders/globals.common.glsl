@@ -4,6 +4,35 @@
 const float PI = 3.1415926535897932384626433832795;
 const float FLOAT_MAX = 3.402823466e+38f;
 
+// Material::alpha_mode values, mirrored from shared::scene::alpha_mode
+const uint ALPHA_MODE_OPAQUE = 0;
+const uint ALPHA_MODE_MASK = 1;
+const uint ALPHA_MODE_BLEND = 2;
+
+// Instance::visibility_mask bits that rays are traced with, mirrored from shared::scene::ray_mask
+const uint RAY_MASK_CAMERA = 1 << 0;
+const uint RAY_MASK_SHADOW = 1 << 1;
+const uint RAY_MASK_INDIRECT = 1 << 2;
+
+// instances without a material override, mirrored from shared::scene::NO_MATERIAL_OVERRIDE
+const uint NO_MATERIAL_OVERRIDE = 0xffffffff;
+
+// Light::kind values, mirrored from shared::scene::light_kind
+const uint LIGHT_KIND_POINT = 0;
+const uint LIGHT_KIND_DIRECTIONAL = 1;
+const uint LIGHT_KIND_SPOT = 2;
+
+// Material::*_tex_coord picks one of the two uv sets packed into Vertex::tex_coords
+vec2 tex_coord_set(vec4 tex_coords, uint set) {
+  return set == 0 ? tex_coords.xy : tex_coords.zw;
+}
+
+// Inverse transpose of the linear part of a transform, keeps normals perpendicular to surfaces under
+// non-uniform scaling. Mirrors shared::inputs::Transform::normal_matrix
+mat3 normal_matrix(mat3 linear) {
+  return transpose(inverse(linear));
+}
+
 vec3 barycentrics(vec2 uv) {
   return vec3(1 - uv.x - uv.y, uv);
 }
@@ -29,4 +58,11 @@ vec3 sample_hemisphere(vec2 r) {
   return vec3(uv * sqrt(r.x), sqrt(1 - r.x));
 }
 
+vec3 sample_uniform_hemisphere(vec2 r) {
+  r.y *= 2 * PI;
+  const vec2 uv = vec2(cos(r.y), sin(r.y));
+  const float z = 1 - r.x;
+  return vec3(uv * sqrt(1 - z * z), z);
+}
+
 #endif
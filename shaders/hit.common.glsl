@@ -0,0 +1,63 @@
+#ifndef HIT_COMMON_GLSL_
+#define HIT_COMMON_GLSL_
+
+#include "ray.common.glsl"
+#include "scene.h.glsl"
+
+layout(buffer_reference, scalar) buffer Vertices { Vertex v[]; };
+layout(buffer_reference, scalar) buffer Indices { uvec3 i[]; };
+layout(buffer_reference, scalar) buffer Primitives { PrimitiveInfo p[]; };
+layout(buffer_reference, scalar) buffer InstanceMaterials { uint m[]; };
+
+
+uvec3 triangle_indices(SceneDesc scene_desc, PrimitiveInfo primitive, uint triangle) {
+  Indices indices = Indices(scene_desc.indices_address);
+  return indices.i[primitive.indices_offset / 3 + triangle] + primitive.vertices_offset;
+}
+
+
+// The material the instance draws its primitives with in place of their own, if it has one
+uint instance_material(SceneDesc scene_desc, uint instance, uint material) {
+  InstanceMaterials instance_materials = InstanceMaterials(scene_desc.instance_materials_address);
+  const uint override_material = instance_materials.m[instance];
+  return override_material == NO_MATERIAL_OVERRIDE ? material : override_material;
+}
+
+
+vec4 triangle_tex_coords(SceneDesc scene_desc, PrimitiveInfo primitive, uint triangle, vec3 bary) {
+  Vertices vertices = Vertices(scene_desc.vertices_address);
+
+  const uvec3 idx = triangle_indices(scene_desc, primitive, triangle);
+  return vertices.v[idx.x].tex_coords * bary.x
+       + vertices.v[idx.y].tex_coords * bary.y
+       + vertices.v[idx.z].tex_coords * bary.z;
+}
+
+
+// Interpolates the vertex attributes at the hit point and moves them into world space
+HitInfo triangle_hit(SceneDesc scene_desc, uint instance, uint primitive_index, uint triangle, vec3 bary,
+                     mat4x3 object_to_world, mat3 normal_to_world, float t, bool front_face) {
+  Vertices vertices = Vertices(scene_desc.vertices_address);
+  Primitives primitives = Primitives(scene_desc.primitives_address);
+
+  const PrimitiveInfo primitive = primitives.p[primitive_index];
+  const uvec3 idx = triangle_indices(scene_desc, primitive, triangle);
+  const Vertex v0 = vertices.v[idx.x], v1 = vertices.v[idx.y], v2 = vertices.v[idx.z];
+
+  HitInfo hit;
+  const vec3 position = v0.position.xyz * bary.x + v1.position.xyz * bary.y + v2.position.xyz * bary.z;
+  hit.position = vec4(object_to_world * vec4(position, 1), 0);
+  const vec3 normal = normalize(v0.normal.xyz * bary.x + v1.normal.xyz * bary.y + v2.normal.xyz * bary.z);
+  hit.normal = vec4(normalize(normal_to_world * normal), 0);
+  hit.tex_coords = v0.tex_coords * bary.x + v1.tex_coords * bary.y + v2.tex_coords * bary.z;
+  hit.color = v0.color * bary.x + v1.color * bary.y + v2.color * bary.z;
+  hit.t = t;
+  hit.material = instance_material(scene_desc, instance, primitive.material);
+  hit.instance = instance;
+  hit.primitive = primitive_index;
+  hit.front_face = front_face;
+  hit.hit = true;
+  return hit;
+}
+
+#endif
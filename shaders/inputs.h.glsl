@@ -1,5 +1,8 @@
 // AUTO-GENERATED: do not edit
 
+#ifndef INPUTS_H_GLSL_
+#define INPUTS_H_GLSL_
+
 struct Transform {
   mat4 forward;
   mat4 inverse;
@@ -12,14 +15,81 @@ struct Camera {
 
 struct Uniforms {
   Camera camera;
+  vec3 background_color;
+  uint orthographic;
+  Camera previous_camera;
+  vec2 jitter;
+  float time;
+  float delta_time;
+  float env_intensity;
+  uint pad;
+  float shutter_time;
+  float eye_separation;
+  Camera shutter_camera;
 };
 
-struct RasterizerConstants {
+struct RasterizerInstance {
   mat4 model_transform;
-  uint material_index;
-  vec3 pad;
+  mat4 previous_model_transform;
+};
+
+struct RasterizerConstants {
+  uint64_t instances_address;
+  uint material;
+  float eye;
+  float ao_radius;
+  float ray_epsilon;
 };
 
 struct PathtracerConstants {
   uint frame;
+  uint debug_view;
+  uint samples_per_frame;
+  uint mode;
+  float ao_radius;
+  float ray_epsilon;
+  uvec2 crop_offset;
+  uvec2 crop_extent;
+  uint64_t pick_address;
+  uint realtime;
+  uint max_bounces_after_rough;
+  uint sampling_strategy;
+  float max_sample_luminance;
+};
+
+struct PickResult {
+  vec3 position;
+  uint hit;
+  uint instance;
+  uint primitive;
+  uint material;
+  uint pad;
+};
+
+struct DenoiseConstants {
+  uint step_size;
 };
+
+struct TonemapConstants {
+  uint mode;
+  float exposure;
+  uint encoding;
+  float paper_white;
+};
+
+struct BloomConstants {
+  uint mode;
+  float threshold;
+  float intensity;
+};
+
+struct ExposureConstants {
+  float min_log_luminance;
+  float log_luminance_range;
+};
+
+struct TaaConstants {
+  float history_weight;
+};
+
+#endif
@@ -0,0 +1,58 @@
+#ifndef LIGHTS_COMMON_GLSL_
+#define LIGHTS_COMMON_GLSL_
+
+#include "globals.common.glsl"
+#include "scene.h.glsl"
+
+// mirrored on the host in shared/src/lights.rs for the CPU reference path tracer
+
+// keeps the irradiance finite at the position of point and spot lights
+const float MIN_LIGHT_DISTANCE = 1e-3;
+
+layout(buffer_reference, scalar) buffer Lights { Light l[]; };
+
+struct LightSample {
+  // towards the light
+  vec3 direction;
+  float distance;
+  // onto a surface facing the light
+  vec3 irradiance;
+};
+
+
+// Falls off smoothly from the inner to the outer cone, as in the KHR_lights_punctual reference
+float spot_attenuation(Light light, vec3 direction) {
+  const float scale = 1 / max(light.cos_inner_cone - light.cos_outer_cone, 1e-3);
+  const float t = clamp((dot(light.direction, direction) - light.cos_outer_cone) * scale, 0, 1);
+  return t * t;
+}
+
+
+// Inverse square falloff that reaches zero at the range of the light, if it has one
+float range_attenuation(Light light, float distance) {
+  if (light.range <= 0) return 1;
+  const float r = distance / light.range;
+  const float window = clamp(1 - r * r * r * r, 0, 1);
+  return window * window;
+}
+
+
+LightSample sample_light(Light light, vec3 position) {
+  LightSample incident;
+  if (light.kind == LIGHT_KIND_DIRECTIONAL) {
+    incident.direction = -light.direction;
+    incident.distance = FLOAT_MAX;
+    incident.irradiance = light.intensity;
+    return incident;
+  }
+
+  const vec3 to_light = light.position - position;
+  incident.distance = max(length(to_light), MIN_LIGHT_DISTANCE);
+  incident.direction = to_light / incident.distance;
+  const float falloff = range_attenuation(light, incident.distance) / (incident.distance * incident.distance);
+  incident.irradiance = light.intensity * falloff;
+  if (light.kind == LIGHT_KIND_SPOT) incident.irradiance *= spot_attenuation(light, -incident.direction);
+  return incident;
+}
+
+#endif
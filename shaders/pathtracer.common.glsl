@@ -0,0 +1,368 @@
+#ifndef PATHTRACER_COMMON_GLSL_
+#define PATHTRACER_COMMON_GLSL_
+
+#include "inputs.h.glsl"
+#include "ray.common.glsl"
+#include "bsdf.common.glsl"
+#include "lights.common.glsl"
+
+const uint DEBUG_VIEW_FINAL = 0;
+const uint DEBUG_VIEW_ALBEDO = 1;
+const uint DEBUG_VIEW_NORMAL = 2;
+const uint DEBUG_VIEW_DEPTH = 3;
+const uint DEBUG_VIEW_BOUNCES = 4;
+// T_MAX is unbounded so the depth view is normalized against a fixed range instead
+const float DEBUG_DEPTH_RANGE = 100;
+
+const uint RENDER_MODE_PATH_TRACING = 0;
+const uint RENDER_MODE_AMBIENT_OCCLUSION = 1;
+// occlusion rays per sample
+const uint AO_RAYS = 4;
+
+// specular bounces off rougher surfaces than this count towards the bounce limit after rough bounces
+const float ROUGH_BOUNCE_ROUGHNESS = 0.3;
+
+// subpixel strata per axis, cycled through by consecutive samples
+const uint PIXEL_STRATA = 4;
+
+layout(push_constant) uniform _PushConstants { PathtracerConstants constants; };
+
+layout(set=0, binding=0) uniform _Uniforms { Uniforms uniforms; };
+layout(set=0, binding=1) uniform _SceneDesc { SceneDesc scene_desc; };
+layout(set=0, binding=3, INTERMEDIATE_FORMAT) uniform image2D output_image;
+layout(set=0, binding=4, INTERMEDIATE_FORMAT) uniform image2D albedo_image;
+layout(set=0, binding=5, INTERMEDIATE_FORMAT) uniform image2D normal_image;
+layout(set=0, binding=6, INTERMEDIATE_FORMAT) uniform image2D moments_image;
+layout(set=0, binding=7, INTERMEDIATE_FORMAT) uniform image2D reference_image;
+layout(set=0, binding=8) uniform sampler2D[] textures;
+
+layout(buffer_reference, scalar) buffer Materials { Material m[]; };
+layout(buffer_reference, scalar) buffer PickResults { PickResult r; };
+
+// provided by the including shader, fills in the global payload. Only instances that share a bit
+// with the cull mask (one of RAY_MASK_*) are hit
+void trace_ray(Ray ray, float t_max, uint cull_mask);
+
+
+MaterialHit material_info_at_hit(Material material, vec4 tex_coords, vec4 vertex_color) {
+  MaterialHit info;
+  info.base_color = material.color * vertex_color.rgb;
+  if (material.color_texture > -1) {
+    info.base_color *= texture(textures[material.color_texture], tex_coord_set(tex_coords, material.color_tex_coord)).xyz;
+  }
+  info.emittance = material.emittance;
+  if (material.emittance_texture > -1) {
+    info.emittance *= texture(textures[material.emittance_texture], tex_coord_set(tex_coords, material.emittance_tex_coord)).xyz;
+  }
+  info.metallic = material.metallic;
+  info.roughness = material.roughness;
+  info.transmission = material.transmission;
+  info.ior = material.ior;
+  if (material.metallic_roughness_texture > -1) {
+    const vec2 coords = tex_coord_set(tex_coords, material.metallic_roughness_tex_coord);
+    vec2 metallic_roughness = texture(textures[material.metallic_roughness_texture], coords).yz;
+    info.metallic *= metallic_roughness.y;
+    info.roughness *= metallic_roughness.x;
+  }
+  return info;
+}
+
+
+// Jittered offset within one cell of the subpixel grid, all cells are visited every PIXEL_STRATA^2 samples
+vec2 stratified_pixel_offset(inout Rng rng, uint sample_number) {
+  const uint stratum = sample_number % (PIXEL_STRATA * PIXEL_STRATA);
+  const vec2 cell = vec2(stratum % PIXEL_STRATA, stratum / PIXEL_STRATA);
+  return (cell + rng_vec2(rng)) / float(PIXEL_STRATA);
+}
+
+
+// Moves the start of a bounce off the surface, to the side that the new direction leaves through, so
+// that the ray does not hit the surface it starts from
+vec4 offset_ray_origin(vec4 position, vec3 n, vec3 direction) {
+  const vec3 offset = (dot(direction, n) < 0 ? -n : n) * constants.ray_epsilon;
+  return position + vec4(offset, 0);
+}
+
+
+// Radiance of rays that escape the scene, the same from every direction
+vec3 environment_radiance() {
+  return uniforms.background_color * uniforms.env_intensity;
+}
+
+
+// Light reflected towards wo straight from one of the punctual lights, picked at random. BSDF sampled
+// rays can never hit them, so this is the only way their light is gathered. The payload of the hit
+// is left as it was
+vec3 trace_direct_light(inout Rng rng, MaterialHit material, vec3 wo, vec3 n) {
+  const uint num_lights = scene_desc.num_lights;
+  if (num_lights == 0) return vec3(0);
+
+  const uint index = min(uint(rng_float(rng) * float(num_lights)), num_lights - 1);
+  const Light light = Lights(scene_desc.lights_address).l[index];
+  const HitInfo hit = payload;
+  const LightSample incident = sample_light(light, hit.position.xyz);
+
+  const vec3 reflected = bsdf_eval(material, wo, incident.direction, n) * incident.irradiance;
+  if (luminance(reflected) == 0) return vec3(0);
+
+  const vec4 origin = offset_ray_origin(hit.position, n, incident.direction);
+  trace_ray(Ray(origin, vec4(incident.direction, 0)), incident.distance, RAY_MASK_SHADOW);
+  const bool occluded = payload.hit;
+  payload = hit;
+
+  return occluded ? vec3(0) : reflected * float(num_lights);
+}
+
+
+// Unoccluded fraction of cosine weighted rays from the primary hit, blockers beyond ao_radius are ignored
+vec3 trace_ambient_occlusion(inout Rng rng, Ray ray, out vec3 albedo, out vec3 normal) {
+  albedo = uniforms.background_color;
+  normal = vec3(0);
+
+  rng_begin_bounce(rng, 0);
+  trace_ray(ray, T_MAX, RAY_MASK_CAMERA);
+  if (!payload.hit) return environment_radiance();
+
+  vec3 n = payload.normal.xyz;
+  if (dot(n, ray.direction.xyz) > 0) n = -n;
+  albedo = vec3(1);
+  normal = n;
+
+  const vec4 origin = offset_ray_origin(payload.position, n, n);
+  const vec4 to_world = quat_invert_rotation(quat_frame(n));
+
+  uint unoccluded = 0;
+  for (uint i = 0; i < AO_RAYS; ++i) {
+    const vec3 direction = normalize(quat_rotate(to_world, sample_hemisphere(rng_vec2(rng))));
+    trace_ray(Ray(origin, vec4(direction, 0)), constants.ao_radius, RAY_MASK_SHADOW);
+    if (!payload.hit) ++unoccluded;
+  }
+
+  return vec3(float(unoccluded) / float(AO_RAYS));
+}
+
+
+// The eye offset moves the origin sideways in view space, for stereo
+Ray camera_ray(Camera camera, vec2 coords, float eye_offset) {
+  if (uniforms.orthographic != 0) {
+    // parallel rays from the near plane
+    const vec4 origin = camera.proj.inverse * vec4(coords, 0, 1) + vec4(eye_offset, 0, 0, 0);
+    return Ray(camera.view.inverse * origin, camera.view.inverse * vec4(0, 0, -1, 0));
+  }
+
+  const vec4 origin = camera.view.inverse * vec4(eye_offset, 0, 0, 1);
+  const vec4 target = camera.proj.inverse * vec4(coords, 1, 1);
+  const vec4 direction = camera.view.inverse * vec4(normalize(target.xyz), 0);
+  return Ray(origin, direction);
+}
+
+// Camera ray through a point of the image, in pixels. With motion blur, the ray is taken at a point
+// in time between the shutter camera (1) and the current one (0). In stereo each half of the image
+// is seen by one eye through the middle half of the camera's view, so that it keeps its aspect ratio
+Ray primary_ray(vec2 pixel, vec2 resolution, float time) {
+  float eye_offset = 0;
+  if (uniforms.eye_separation != 0) {
+    resolution.x /= 2;
+    eye_offset = (pixel.x < resolution.x ? -0.5 : 0.5) * uniforms.eye_separation;
+    pixel.x = mod(pixel.x, resolution.x);
+  }
+
+  vec2 coords = 2 * (pixel / resolution) - 1;
+  if (uniforms.eye_separation != 0) coords.x /= 2;
+
+  const Ray ray = camera_ray(uniforms.camera, coords, eye_offset);
+  if (uniforms.shutter_time == 0) return ray;
+
+  const Ray shutter_ray = camera_ray(uniforms.shutter_camera, coords, eye_offset);
+  const float t = time * uniforms.shutter_time;
+  return Ray(mix(ray.origin, shutter_ray.origin, t), vec4(normalize(mix(ray.direction.xyz, shutter_ray.direction.xyz, t)), 0));
+}
+
+
+// Traces a single path through the pixel and returns the radiance (or the selected debug quantity)
+vec3 trace_sample(uvec2 pixel_index, uvec2 dims, inout Rng rng, uint sample_number, out vec3 albedo, out vec3 normal) {
+  Materials materials = Materials(scene_desc.materials_address);
+
+  // anti-aliased pixel
+  const vec2 pixel = vec2(pixel_index) + stratified_pixel_offset(rng, sample_number);
+  // drawn only with motion blur, so that the rest of the path samples the same without it
+  const float time = uniforms.shutter_time == 0 ? 0 : rng_float(rng);
+  Ray ray = primary_ray(pixel, vec2(dims), time);
+
+  if (constants.mode == RENDER_MODE_AMBIENT_OCCLUSION) return trace_ambient_occlusion(rng, ray, albedo, normal);
+
+  vec3 radiance = vec3(0);
+  vec3 throughput = vec3(1);
+
+  // first hit auxiliary features used to guide the denoiser
+  albedo = uniforms.background_color;
+  normal = vec3(0);
+  float hit_distance = T_MAX;
+
+  // depth of the first diffuse or rough glossy bounce, the path is cut short after it
+  int rough_depth = -1;
+
+  int depth = 0;
+  for (; depth < MAX_BOUNCES; ++depth) {
+    rng_begin_bounce(rng, depth);
+    trace_ray(ray, T_MAX, depth == 0 ? RAY_MASK_CAMERA : RAY_MASK_INDIRECT);
+
+    if (!payload.hit) {
+      radiance += throughput * environment_radiance();
+      break;
+    }
+
+    const Material hit_material = materials.m[payload.material];
+
+    // backfaces of single-sided opaque surfaces absorb all light
+    if (!payload.front_face && hit_material.double_sided == 0 && hit_material.transmission == 0) break;
+
+    const vec3 wo = -ray.direction.xyz;
+    vec3 n = payload.normal.xyz;
+    if (dot(n, wo) < 0) n = -n;
+
+    const MaterialHit material = material_info_at_hit(hit_material, payload.tex_coords, payload.color);
+
+    if (depth == 0) {
+      albedo = material.base_color;
+      normal = n;
+      hit_distance = payload.t;
+    }
+
+    radiance += throughput * material.emittance;
+
+    // Don't need to sample BSDF on last bounce
+    if (depth == MAX_BOUNCES - 1) break;
+
+    // the transmitted fraction is accounted for by following the refracted path instead
+    radiance += throughput * (1 - material.transmission) * trace_direct_light(rng, material, wo, n);
+
+    // Biased, but long chains after a rough bounce are where most fireflies come from
+    if (rough_depth >= 0 && uint(depth - rough_depth) > constants.max_bounces_after_rough) break;
+
+    // Russian Roulette
+    if (depth > MIN_BOUNCES) {
+      float p_rr = min(0.95, luminance(throughput));
+      if (p_rr < rng_float(rng)) break;
+      else throughput /= p_rr;
+    }
+
+    // Transmission
+    if (material.transmission > 0 && rng_float(rng) < material.transmission) {
+      vec3 weight;
+      const vec3 wi = sample_transmission(material, payload.front_face, wo, n, rng_float(rng), weight);
+      throughput *= weight;
+
+      ray.origin = offset_ray_origin(payload.position, n, wi);
+      ray.direction = vec4(wi, 0);
+      continue;
+    }
+
+    // BSDF evaluation
+    bool is_specular = material.metallic == 1 && material.roughness == 0;
+    if (!is_specular) {
+      float p_spec = specular_probability(material, wo, n);
+
+      if (rng_float(rng) < p_spec) {
+        is_specular = true;
+        throughput /= p_spec;
+      } else {
+        throughput /= 1 - p_spec;
+      }
+    }
+
+    // Importance sample the BSDF
+    vec3 wi, weight;
+    if (!bsdf_sample(material, is_specular, constants.sampling_strategy, wo, n, rng_vec2(rng), wi, weight)) break;
+
+    throughput *= weight;
+    if (rough_depth < 0 && (!is_specular || material.roughness > ROUGH_BOUNCE_ROUGHNESS)) rough_depth = depth;
+
+    ray.origin = offset_ray_origin(payload.position, n, wi);
+    ray.direction = vec4(wi, 0);
+  }
+
+  switch (constants.debug_view) {
+    case DEBUG_VIEW_ALBEDO: radiance = albedo; break;
+    case DEBUG_VIEW_NORMAL: radiance = normal * 0.5 + 0.5; break;
+    case DEBUG_VIEW_DEPTH: radiance = vec3(min(hit_distance / DEBUG_DEPTH_RANGE, 1)); break;
+    // averaged over the accumulated samples in place of radiance, accumulation restarts when switching views
+    case DEBUG_VIEW_BOUNCES: radiance = vec3(float(depth) / float(MAX_BOUNCES - 1)); break;
+    default: break;
+  }
+
+  return radiance;
+}
+
+
+// Records the first hit of the ray through the center of the pixel, the images are left alone
+void pick_pixel(uvec2 pixel_index, uvec2 dims) {
+  trace_ray(primary_ray(vec2(pixel_index) + 0.5, vec2(dims), 0), T_MAX, RAY_MASK_CAMERA);
+
+  PickResult result;
+  result.position = payload.position.xyz;
+  result.hit = payload.hit ? 1 : 0;
+  result.instance = payload.instance;
+  result.primitive = payload.primitive;
+  result.material = payload.material;
+  result.pad = 0;
+  PickResults(constants.pick_address).r = result;
+}
+
+
+// Accumulates this frame's samples for the pixel into the output and auxiliary images
+void pathtrace_pixel(uvec2 pixel_index, uvec2 dims) {
+  if (constants.pick_address != 0) {
+    pick_pixel(pixel_index, dims);
+    return;
+  }
+
+  const uint frame_num = constants.frame;
+
+  vec3 radiance = vec3(0);
+  // unbiased, unlike the firefly clamped radiance that is shown
+  vec3 reference = vec3(0);
+  vec3 albedo = vec3(0);
+  vec3 normal = vec3(0);
+  for (uint sample_index = 0; sample_index < constants.samples_per_frame; ++sample_index) {
+    Rng rng = rng_init(pixel_index, frame_num, sample_index);
+
+    vec3 sample_albedo, sample_normal;
+    const uint sample_number = frame_num * constants.samples_per_frame + sample_index;
+    const vec3 sample_radiance = trace_sample(pixel_index, dims, rng, sample_number, sample_albedo, sample_normal);
+    reference += sample_radiance;
+    const float sample_luminance = luminance(sample_radiance);
+    radiance += constants.max_sample_luminance > 0 && sample_luminance > constants.max_sample_luminance
+                ? sample_radiance * (constants.max_sample_luminance / sample_luminance)
+                : sample_radiance;
+    albedo += sample_albedo;
+    normal += sample_normal;
+  }
+  const float inv_samples = 1 / float(constants.samples_per_frame);
+  radiance *= inv_samples;
+  reference *= inv_samples;
+  albedo *= inv_samples;
+  normal *= inv_samples;
+
+  // accumulation is reset by clearing the output image on the host side
+  const ivec2 out_pixel = ivec2(pixel_index);
+  const float w = constants.realtime != 0 ? 1 : 1 / float(frame_num + 1);
+  const vec3 old_color = imageLoad(output_image, out_pixel).xyz;
+  imageStore(output_image, out_pixel, vec4(mix(old_color, radiance, w), 1));
+
+  const vec3 old_reference = imageLoad(reference_image, out_pixel).xyz;
+  imageStore(reference_image, out_pixel, vec4(mix(old_reference, reference, w), 1));
+
+  const vec3 old_albedo = imageLoad(albedo_image, out_pixel).xyz;
+  imageStore(albedo_image, out_pixel, vec4(mix(old_albedo, albedo, w), 1));
+
+  const vec3 old_normal = imageLoad(normal_image, out_pixel).xyz;
+  imageStore(normal_image, out_pixel, vec4(mix(old_normal, normal, w), 0));
+
+  // the spread of the frames' luminance tells how far the accumulated image is from converging
+  const float lum = luminance(radiance);
+  const vec2 old_moments = imageLoad(moments_image, out_pixel).xy;
+  imageStore(moments_image, out_pixel, vec4(mix(old_moments, vec2(lum, lum * lum), w), 0, 1));
+}
+
+#endif
@@ -0,0 +1,144 @@
+#version 460
+#extension GL_EXT_buffer_reference2 : require
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+// Fallback for devices without hardware ray tracing, rays traverse a BVH built on the host
+
+layout(local_size_x=8, local_size_y=8) in;
+
+#include "ray.common.glsl"
+
+HitInfo payload;
+
+#include "pathtracer.common.glsl"
+#include "hit.common.glsl"
+
+layout(buffer_reference, scalar) buffer BvhNodes { BvhNode n[]; };
+layout(buffer_reference, scalar) buffer BvhTriangles { BvhTriangle t[]; };
+layout(buffer_reference, scalar) buffer Transforms { mat4 m[]; };
+layout(buffer_reference, scalar) buffer Masks { uint m[]; };
+
+// comfortably deeper than the median split hierarchy gets
+const uint STACK_SIZE = 64;
+
+
+// Distance to where the ray enters the box, or T_MAX if it misses before t_max
+float intersect_aabb(vec3 aabb_min, vec3 aabb_max, vec3 origin, vec3 inv_direction, float t_max) {
+  const vec3 t0 = (aabb_min - origin) * inv_direction;
+  const vec3 t1 = (aabb_max - origin) * inv_direction;
+  const vec3 t_min = min(t0, t1), t_max3 = max(t0, t1);
+  const float t_near = max(max(t_min.x, t_min.y), max(t_min.z, constants.ray_epsilon));
+  const float t_far = min(min(t_max3.x, t_max3.y), min(t_max3.z, t_max));
+  return t_near <= t_far ? t_near : T_MAX;
+}
+
+
+// Möller-Trumbore, uv are the barycentrics of v1 and v2
+bool intersect_triangle(BvhTriangle tri, Ray ray, float t_max, out float t, out vec2 uv) {
+  t = T_MAX;
+  uv = vec2(0);
+
+  const vec3 e1 = tri.v1 - tri.v0, e2 = tri.v2 - tri.v0;
+  const vec3 p = cross(ray.direction.xyz, e2);
+  const float det = dot(e1, p);
+  if (abs(det) < 1e-12) return false;
+  const float inv_det = 1 / det;
+
+  const vec3 s = ray.origin.xyz - tri.v0;
+  uv.x = dot(s, p) * inv_det;
+  if (uv.x < 0 || uv.x > 1) return false;
+
+  const vec3 q = cross(s, e1);
+  uv.y = dot(ray.direction.xyz, q) * inv_det;
+  if (uv.y < 0 || uv.x + uv.y > 1) return false;
+
+  t = dot(e2, q) * inv_det;
+  return t > constants.ray_epsilon && t < t_max;
+}
+
+
+// Counterpart of the any-hit shader
+bool passes_alpha_test(uint instance, uint primitive_index, uint triangle, vec2 uv) {
+  Primitives primitives = Primitives(scene_desc.primitives_address);
+  Materials materials = Materials(scene_desc.materials_address);
+
+  const PrimitiveInfo primitive = primitives.p[primitive_index];
+  const Material material = materials.m[instance_material(scene_desc, instance, primitive.material)];
+  if (material.alpha_mode != ALPHA_MODE_MASK || material.color_texture < 0) return true;
+
+  const vec4 tex_coords = triangle_tex_coords(scene_desc, primitive, triangle, barycentrics(uv));
+  const vec2 coords = tex_coord_set(tex_coords, material.color_tex_coord);
+  return textureLod(textures[material.color_texture], coords, 0).a >= material.alpha_cutoff;
+}
+
+
+void trace_ray(Ray ray, float t_max, uint cull_mask) {
+  BvhNodes nodes = BvhNodes(scene_desc.bvh_nodes_address);
+  BvhTriangles triangles = BvhTriangles(scene_desc.bvh_triangles_address);
+  Transforms transforms = Transforms(scene_desc.instance_transforms_address);
+  Masks masks = Masks(scene_desc.instance_masks_address);
+
+  const vec3 inv_direction = 1 / ray.direction.xyz;
+
+  float closest_t = t_max;
+  uint closest = 0;
+  vec2 closest_uv = vec2(0);
+  bool hit = false;
+
+  uint stack[STACK_SIZE];
+  uint stack_size = 0;
+  stack[stack_size++] = 0;
+
+  while (stack_size > 0) {
+    const BvhNode node = nodes.n[stack[--stack_size]];
+    if (intersect_aabb(node.aabb_min, node.aabb_max, ray.origin.xyz, inv_direction, closest_t) == T_MAX) continue;
+
+    if (node.count > 0) {
+      for (uint i = node.left_or_first; i < node.left_or_first + node.count; ++i) {
+        const BvhTriangle tri = triangles.t[i];
+        if ((masks.m[tri.instance] & cull_mask) == 0) continue;
+        float t;
+        vec2 uv;
+        if (intersect_triangle(tri, ray, closest_t, t, uv) && passes_alpha_test(tri.instance, tri.primitive, tri.triangle, uv)) {
+          closest_t = t;
+          closest = i;
+          closest_uv = uv;
+          hit = true;
+        }
+      }
+      continue;
+    }
+
+    // the nearer child is pushed last so it is visited first
+    const uint left = node.left_or_first, right = left + 1;
+    const float t_left = intersect_aabb(nodes.n[left].aabb_min, nodes.n[left].aabb_max,
+                                        ray.origin.xyz, inv_direction, closest_t);
+    const float t_right = intersect_aabb(nodes.n[right].aabb_min, nodes.n[right].aabb_max,
+                                         ray.origin.xyz, inv_direction, closest_t);
+    const bool left_first = t_left <= t_right;
+    const float t_far = left_first ? t_right : t_left;
+    if (t_far < T_MAX && stack_size < STACK_SIZE) stack[stack_size++] = left_first ? right : left;
+    if (min(t_left, t_right) < T_MAX && stack_size < STACK_SIZE) stack[stack_size++] = left_first ? left : right;
+  }
+
+  if (!hit) {
+    payload.hit = false;
+    return;
+  }
+
+  const BvhTriangle tri = triangles.t[closest];
+  const bool front_face = dot(cross(tri.v1 - tri.v0, tri.v2 - tri.v0), ray.direction.xyz) < 0;
+  const mat4 object_to_world = transforms.m[tri.instance];
+  payload = triangle_hit(scene_desc, tri.instance, tri.primitive, tri.triangle, barycentrics(closest_uv),
+                         mat4x3(object_to_world), normal_matrix(mat3(object_to_world)), closest_t, front_face);
+}
+
+
+void main() {
+  // the dispatch only covers the crop region
+  if (any(greaterThanEqual(gl_GlobalInvocationID.xy, constants.crop_extent))) return;
+
+  pathtrace_pixel(gl_GlobalInvocationID.xy + constants.crop_offset, uvec2(imageSize(output_image)));
+}
@@ -0,0 +1,31 @@
+#version 460
+#extension GL_EXT_buffer_reference2 : require
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_ray_tracing : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+#include "hit.common.glsl"
+
+layout(set=0, binding=1) uniform _SceneDesc { SceneDesc scene_desc; };
+layout(set=0, binding=8) uniform sampler2D[] textures;
+
+layout(buffer_reference, scalar) buffer Materials { Material m[]; };
+
+hitAttributeEXT vec2 hit_uv;
+
+
+// Only invoked for non-opaque (alpha masked) geometry
+void main() {
+  Primitives primitives = Primitives(scene_desc.primitives_address);
+  Materials materials = Materials(scene_desc.materials_address);
+
+  const PrimitiveInfo primitive = primitives.p[gl_InstanceCustomIndexEXT + gl_GeometryIndexEXT];
+  const Material material = materials.m[instance_material(scene_desc, gl_InstanceID, primitive.material)];
+  if (material.alpha_mode != ALPHA_MODE_MASK || material.color_texture < 0) return;
+
+  const vec4 tex_coords = triangle_tex_coords(scene_desc, primitive, gl_PrimitiveID, barycentrics(hit_uv));
+  const vec2 uv = tex_coord_set(tex_coords, material.color_tex_coord);
+
+  if (textureLod(textures[material.color_texture], uv, 0).a < material.alpha_cutoff) ignoreIntersectionEXT;
+}
@@ -3,6 +3,13 @@
 
 struct Interface {
   vec4 tex_coords;
+  vec4 color;
+  // unjittered clip space positions of this and the last frame
+  vec4 clip_position;
+  vec4 previous_clip_position;
+  // world space, the normal is not normalized
+  vec3 position;
+  vec3 normal;
 };
 
 #endif
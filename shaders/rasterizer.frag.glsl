@@ -3,32 +3,123 @@
 #extension GL_EXT_nonuniform_qualifier : require
 #extension GL_EXT_scalar_block_layout : require
 #extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+#ifdef RAY_QUERY_AO
+#extension GL_EXT_ray_query : require
+#endif
 
+#include "bsdf.common.glsl"
+#include "globals.common.glsl"
 #include "inputs.h.glsl"
+#include "lights.common.glsl"
 #include "rasterizer.common.glsl"
+#include "rng.common.glsl"
 #include "scene.h.glsl"
 
-layout(push_constant) uniform _PushConstants { RasterizerConstants constants; };
-
+layout(set=0, binding=0) uniform _Uniforms { Uniforms uniforms; };
 layout(set=0, binding=1) uniform _SceneDesc { SceneDesc scene_desc; };
-layout(set=0, binding=4) uniform sampler2D[] textures;
+layout(set=0, binding=8) uniform sampler2D[] textures;
 
 layout(buffer_reference, scalar) buffer Materials { Material m[]; };
 
 layout(location=0) in _Interface { Interface in_data; };
+layout(location=6) flat in uint material_index;
 
 layout(location=0) out vec4 color;
+layout(location=1) out vec4 velocity;
+
+// The background as uniform ambient light, and a sun overhead in scenes without punctual lights, so
+// that the preview resembles the path traced image even in scenes without emitters. Nothing casts
+// shadows, only the ambient light is occluded with RAY_QUERY_AO
+const vec3 LIGHT_DIRECTION = normalize(vec3(0.3, 1, 0.2));
+const vec3 LIGHT_IRRADIANCE = vec3(2);
+
+#ifdef RAY_QUERY_AO
+layout(push_constant) uniform _PushConstants { RasterizerConstants constants; };
+layout(set=0, binding=2) uniform accelerationStructureEXT tlas;
+
+const uint AO_RAYS = 4;
+
+// Unoccluded fraction of cosine weighted rays leaving the surface, blockers beyond ao_radius are
+// ignored. Alpha masked surfaces occlude as though they were opaque. The noise changes with the
+// time, for temporal antialiasing to average it out
+float ambient_occlusion(vec3 position, vec3 n) {
+  Rng rng = rng_init(uvec2(gl_FragCoord.xy), floatBitsToUint(uniforms.time), 0);
+  const vec3 origin = position + n * constants.ray_epsilon;
+  const vec4 to_world = quat_invert_rotation(quat_frame(n));
+
+  uint unoccluded = 0;
+  for (uint i = 0; i < AO_RAYS; ++i) {
+    const vec3 direction = normalize(quat_rotate(to_world, sample_hemisphere(rng_vec2(rng))));
+    rayQueryEXT query;
+    rayQueryInitializeEXT(query, tlas, gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT,
+                          RAY_MASK_SHADOW, origin, 0, direction, constants.ao_radius);
+    while (rayQueryProceedEXT(query)) {}
+    if (rayQueryGetIntersectionTypeEXT(query, true) == gl_RayQueryCommittedIntersectionNoneEXT) {
+      ++unoccluded;
+    }
+  }
+
+  return float(unoccluded) / float(AO_RAYS);
+}
+#endif
 
 void main() {
   Materials materials = Materials(scene_desc.materials_address);
-  Material material = materials.m[constants.material_index];
-  vec3 diffuse = material.color;
+  Material material = materials.m[material_index];
+  vec3 diffuse = material.color * in_data.color.rgb;
   if (material.color_texture > -1) {
-    diffuse *= texture(textures[material.color_texture], in_data.tex_coords.xy).xyz;
+    const vec4 texel = texture(textures[material.color_texture], tex_coord_set(in_data.tex_coords, material.color_tex_coord));
+    if (material.alpha_mode == ALPHA_MODE_MASK && texel.a < material.alpha_cutoff) discard;
+    diffuse *= texel.xyz;
   }
   vec3 emittance = material.emittance;
   if (material.emittance_texture > -1) {
-    emittance *= texture(textures[material.emittance_texture], in_data.tex_coords.xy).xyz;
+    emittance *= texture(textures[material.emittance_texture], tex_coord_set(in_data.tex_coords, material.emittance_tex_coord)).xyz;
+  }
+
+  MaterialHit hit;
+  hit.base_color = diffuse;
+  hit.emittance = emittance;
+  hit.metallic = material.metallic;
+  hit.roughness = material.roughness;
+  if (material.metallic_roughness_texture > -1) {
+    const vec2 coords = tex_coord_set(in_data.tex_coords, material.metallic_roughness_tex_coord);
+    const vec2 metallic_roughness = texture(textures[material.metallic_roughness_texture], coords).yz;
+    hit.metallic *= metallic_roughness.y;
+    hit.roughness *= metallic_roughness.x;
   }
-  color = vec4(diffuse + emittance, 1);
+
+  const vec3 camera_position = uniforms.camera.view.inverse[3].xyz;
+  // towards the camera, which is the same everywhere for parallel projections
+  const vec3 wo = uniforms.orthographic != 0
+                ? normalize(uniforms.camera.view.inverse[2].xyz)
+                : normalize(camera_position - in_data.position);
+  vec3 n = normalize(in_data.normal);
+  if (dot(n, wo) < 0) n = -n;
+
+  const vec3 specular_f0 = base_color_to_specular_f0(hit.base_color, hit.metallic);
+  const vec3 ambient = base_color_to_diffuse_reflectance(hit.base_color, hit.metallic)
+                     + eval_fresnel(specular_f0, clamp_unit(dot(n, wo)));
+
+  vec3 ambient_radiance = ambient * uniforms.background_color * uniforms.env_intensity;
+#ifdef RAY_QUERY_AO
+  ambient_radiance *= ambient_occlusion(in_data.position, n);
+#endif
+  vec3 radiance = hit.emittance + ambient_radiance;
+  if (scene_desc.num_lights == 0) {
+    radiance += bsdf_eval(hit, wo, LIGHT_DIRECTION, n) * LIGHT_IRRADIANCE;
+  }
+  Lights lights = Lights(scene_desc.lights_address);
+  for (uint i = 0; i < scene_desc.num_lights; ++i) {
+    const LightSample incident = sample_light(lights.l[i], in_data.position);
+    radiance += bsdf_eval(hit, wo, incident.direction, n) * incident.irradiance;
+  }
+  color = vec4(radiance, 1);
+
+  // in uv units, matching how the resolve pass looks up the history
+  const vec2 ndc = in_data.clip_position.xy / in_data.clip_position.w;
+  const vec2 previous_ndc = in_data.previous_clip_position.xy / in_data.previous_clip_position.w;
+  velocity = vec4(0.5 * (ndc - previous_ndc), 0, 0);
+  // across the whole target rather than the half of an eye
+  if (uniforms.eye_separation != 0) velocity.x /= 2;
 }
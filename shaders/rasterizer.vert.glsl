@@ -1,5 +1,9 @@
 #version 460
+#extension GL_EXT_buffer_reference2 : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
 
+#include "globals.common.glsl"
 #include "inputs.h.glsl"
 #include "rasterizer.common.glsl"
 
@@ -7,15 +11,38 @@ layout(push_constant) uniform _PushConstants { RasterizerConstants constants; };
 
 layout(binding=0) uniform _Uniforms { Uniforms uniforms; };
 
+layout(buffer_reference, scalar) buffer Instances { RasterizerInstance i[]; };
+
 layout(location=0) in vec4 position;
 layout(location=1) in vec4 tex_coords;
+layout(location=2) in vec4 normal;
+layout(location=3) in vec4 color;
 
 layout(location=0) out _Interface { Interface out_data; };
+layout(location=6) flat out uint material_index;
 
 void main() {
-  gl_Position = uniforms.camera.proj.forward
-              * uniforms.camera.view.forward
-              * constants.model_transform
-              * position;
+  const RasterizerInstance instance = Instances(constants.instances_address).i[gl_InstanceIndex];
+
+  // in stereo each eye is drawn into half of the target, seeing the middle half of the camera's view
+  const vec4 eye_offset = vec4(constants.eye * uniforms.eye_separation / 2, 0, 0, 0);
+  const vec4 eye_scale = vec4(constants.eye == 0 ? 1 : 2, 1, 1, 1);
+
+  const vec4 world_position = instance.model_transform * position;
+  const vec4 clip_position = eye_scale
+                           * (uniforms.camera.proj.forward
+                           * (uniforms.camera.view.forward * world_position - eye_offset));
+  out_data.clip_position = clip_position;
+  out_data.previous_clip_position = eye_scale
+                                  * (uniforms.previous_camera.proj.forward
+                                  * (uniforms.previous_camera.view.forward
+                                  * instance.previous_model_transform
+                                  * position - eye_offset));
   out_data.tex_coords = tex_coords;
+  out_data.color = color;
+  out_data.position = world_position.xyz;
+  out_data.normal = normal_matrix(mat3(instance.model_transform)) * normal.xyz;
+  material_index = constants.material;
+
+  gl_Position = clip_position + vec4(uniforms.jitter * clip_position.w, 0, 0);
 }
@@ -6,8 +6,6 @@
 
 const uint MIN_BOUNCES = 3;
 const uint MAX_BOUNCES = 8;
-const uint RAY_FLAGS = gl_RayFlagsOpaqueEXT;
-const float T_MIN = 1e-4;
 const float T_MAX = FLOAT_MAX;
 
 struct Ray {
@@ -18,8 +16,14 @@ struct Ray {
 struct HitInfo {
   vec4 position;
   vec4 normal;
-  vec2 uv;
+  vec4 tex_coords;
+  vec4 color;
+  float t;
   uint material;
+  // with hardware ray tracing the instance indexes the TLAS, which leaves out hidden instances
+  uint instance;
+  uint primitive;
+  bool front_face;
   bool hit;
 };
 
@@ -1,32 +1,40 @@
 #ifndef RNG_COMMON_GLSL_
 #define RNG_COMMON_GLSL_
 
-// pcg4d
+// PCG32 (RXS-M-XS), mirrored on the host in shared/src/rng.rs
+const uint PCG_MULTIPLIER = 747796405u;
+const uint PCG_INCREMENT = 2891336453u;
+
 struct Rng {
-  uvec4 state;
+  uint state;
+  uint seed;
 };
 
-Rng rng_init(uvec2 pixel, uint frame) {
-  return Rng(uvec4(pixel, frame, 0));
+uint pcg_permute(uint state) {
+  const uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
 }
 
-uvec4 rng_uint4(inout Rng rng) {
-  uvec4 v = rng.state * 1664525u + 1013904223u;
-
-  v.x += v.y * v.w; 
-  v.y += v.z * v.x; 
-  v.z += v.x * v.y; 
-  v.w += v.y * v.z;
+uint pcg_hash(uint value) {
+  return pcg_permute(value * PCG_MULTIPLIER + PCG_INCREMENT);
+}
 
-  v ^= v >> 16u;
+Rng rng_init(uvec2 pixel, uint frame, uint sample_index) {
+  uint seed = pcg_hash(sample_index);
+  seed = pcg_hash(frame + seed);
+  seed = pcg_hash(pixel.y + seed);
+  seed = pcg_hash(pixel.x + seed);
+  return Rng(seed, seed);
+}
 
-  v.x += v.y * v.w; 
-  v.y += v.z * v.x; 
-  v.z += v.x * v.y; 
-  v.w += v.y * v.z;
+// Decorrelates the dimensions consumed at each bounce of a path
+void rng_begin_bounce(inout Rng rng, uint depth) {
+  rng.state = pcg_hash(rng.seed + depth);
+}
 
-  rng.state = v;
-  return v;
+uint rng_uint(inout Rng rng) {
+  rng.state = rng.state * PCG_MULTIPLIER + PCG_INCREMENT;
+  return pcg_permute(rng.state);
 }
 
 float uintToFloat(uint x) {
@@ -34,12 +42,11 @@ float uintToFloat(uint x) {
 }
 
 float rng_float(inout Rng rng) {
-  return uintToFloat(rng_uint4(rng).x);
+  return uintToFloat(rng_uint(rng));
 }
 
 vec2 rng_vec2(inout Rng rng) {
-  uvec4 v = rng_uint4(rng);
-  return vec2(uintToFloat(v.x), uintToFloat(v.y));
+  return vec2(rng_float(rng), rng_float(rng));
 }
 
 #endif
@@ -1,16 +1,29 @@
 // AUTO-GENERATED: do not edit
 
+#ifndef SCENE_H_GLSL_
+#define SCENE_H_GLSL_
+
 struct SceneDesc {
   uint64_t vertices_address;
   uint64_t indices_address;
   uint64_t materials_address;
   uint64_t primitives_address;
+  uint64_t bvh_nodes_address;
+  uint64_t bvh_triangles_address;
+  uint64_t instance_transforms_address;
+  uint64_t instance_masks_address;
+  uint64_t instance_materials_address;
+  uint64_t lights_address;
+  uint num_lights;
+  uint pad;
+  uvec2 pad2;
 };
 
 struct Vertex {
   vec4 position;
   vec4 normal;
   vec4 tex_coords;
+  vec4 color;
 };
 
 struct Material {
@@ -21,6 +34,15 @@ struct Material {
   float metallic;
   float roughness;
   int metallic_roughness_texture;
+  uint double_sided;
+  float transmission;
+  float ior;
+  uint alpha_mode;
+  float alpha_cutoff;
+  uint color_tex_coord;
+  uint emittance_tex_coord;
+  uint metallic_roughness_tex_coord;
+  uint pad;
 };
 
 struct PrimitiveInfo {
@@ -28,3 +50,31 @@ struct PrimitiveInfo {
   uint vertices_offset;
   uint material;
 };
+
+struct Light {
+  vec3 position;
+  uint kind;
+  vec3 direction;
+  float range;
+  vec3 intensity;
+  float cos_inner_cone;
+  float cos_outer_cone;
+};
+
+struct BvhNode {
+  vec3 aabb_min;
+  uint left_or_first;
+  vec3 aabb_max;
+  uint count;
+};
+
+struct BvhTriangle {
+  vec3 v0;
+  uint instance;
+  vec3 v1;
+  uint primitive;
+  vec3 v2;
+  uint triangle;
+};
+
+#endif
@@ -0,0 +1,62 @@
+#version 460
+
+#include "inputs.h.glsl"
+
+layout(local_size_x=16, local_size_y=16) in;
+
+layout(push_constant) uniform _PushConstants { TaaConstants constants; };
+
+layout(set=0, binding=0, INTERMEDIATE_FORMAT) uniform readonly image2D current_image;
+layout(set=0, binding=1, INTERMEDIATE_FORMAT) uniform readonly image2D velocity_image;
+layout(set=0, binding=2, INTERMEDIATE_FORMAT) uniform readonly image2D history_image;
+layout(set=0, binding=3, INTERMEDIATE_FORMAT) uniform writeonly image2D output_image;
+
+
+// Bilinearly filtered history at a (fractional) pixel position
+vec3 sample_history(vec2 position, ivec2 size) {
+  const vec2 base = floor(position - 0.5);
+  const vec2 f = position - 0.5 - base;
+  const ivec2 p = ivec2(base);
+
+  vec3 color = vec3(0);
+  for (int y = 0; y <= 1; ++y) {
+    for (int x = 0; x <= 1; ++x) {
+      const float w = (x == 0 ? 1 - f.x : f.x) * (y == 0 ? 1 - f.y : f.y);
+      color += w * imageLoad(history_image, clamp(p + ivec2(x, y), ivec2(0), size - 1)).xyz;
+    }
+  }
+  return color;
+}
+
+
+void main() {
+  const ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  const ivec2 size = imageSize(current_image);
+  if (any(greaterThanEqual(pixel, size))) return;
+
+  const vec3 current = imageLoad(current_image, pixel).xyz;
+
+  // the history is clamped to the current neighbourhood to reject disoccluded and changed pixels
+  vec3 neighbourhood_min = current;
+  vec3 neighbourhood_max = current;
+  for (int y = -1; y <= 1; ++y) {
+    for (int x = -1; x <= 1; ++x) {
+      const vec3 neighbour = imageLoad(current_image, clamp(pixel + ivec2(x, y), ivec2(0), size - 1)).xyz;
+      neighbourhood_min = min(neighbourhood_min, neighbour);
+      neighbourhood_max = max(neighbourhood_max, neighbour);
+    }
+  }
+
+  // velocity is the screen space motion since the last frame in uv units
+  const vec2 velocity = imageLoad(velocity_image, pixel).xy;
+  const vec2 previous_position = vec2(pixel) + 0.5 - velocity * vec2(size);
+
+  float history_weight = constants.history_weight;
+  if (any(lessThan(previous_position, vec2(0))) || any(greaterThanEqual(previous_position, vec2(size)))) {
+    history_weight = 0;
+  }
+
+  const vec3 history = clamp(sample_history(previous_position, size), neighbourhood_min, neighbourhood_max);
+
+  imageStore(output_image, pixel, vec4(mix(current, history, history_weight), 1));
+}
@@ -0,0 +1,93 @@
+#ifndef TONEMAP_COMMON_GLSL_
+#define TONEMAP_COMMON_GLSL_
+
+#include "inputs.h.glsl"
+
+// shared by the graphics and compute tonemap passes, mirrored on the host in shared/src/tonemap.rs
+
+const uint TONEMAP_MODE_STANDARD = 0;
+const uint TONEMAP_MODE_FALSE_COLOR = 1;
+const uint TONEMAP_MODE_HEATMAP = 2;
+
+const uint ENCODING_SDR = 0;
+const uint ENCODING_SCRGB = 1;
+const uint ENCODING_HDR10 = 2;
+const uint ENCODING_SRGB = 3;
+
+const float SCRGB_WHITE_NITS = 80;
+const float PQ_MAX_NITS = 10000;
+// from linear Rec. 709 to Rec. 2020 (ITU-R BT.2087), column major
+const mat3 REC709_TO_REC2020 = mat3(
+  0.627404, 0.069097, 0.016391,
+  0.329283, 0.919540, 0.088013,
+  0.043313, 0.011362, 0.895595
+);
+
+// upper luminance limit of each false color band, middle grey (0.18) lands in the green band
+const float FALSE_COLOR_LIMITS[] = float[](0.01, 0.05, 0.12, 0.25, 0.6, 1);
+// one more color than limits for everything that is clipped
+const vec3 FALSE_COLORS[] = vec3[](
+  vec3(0, 0, 1),
+  vec3(0, 0.5, 1),
+  vec3(0.4),
+  vec3(0, 1, 0),
+  vec3(0.8),
+  vec3(1, 1, 0),
+  vec3(1, 0, 0)
+);
+
+
+vec3 false_color(vec3 rgb) {
+  const float luminance = dot(rgb, vec3(0.2126, 0.7152, 0.0722));
+  uint band = 0;
+  while (band < FALSE_COLOR_LIMITS.length() && luminance >= FALSE_COLOR_LIMITS[band]) ++band;
+  return FALSE_COLORS[band];
+}
+
+
+vec3 heatmap(float t) {
+  const uint band = min(uint(max(t, 0) * FALSE_COLORS.length()), FALSE_COLORS.length() - 1);
+  return FALSE_COLORS[band];
+}
+
+
+// SMPTE ST 2084 inverse EOTF, from absolute luminance to a signal in [0, 1]
+vec3 pq_encode(vec3 nits) {
+  const float m1 = 0.1593017578125;
+  const float m2 = 78.84375;
+  const float c1 = 0.8359375;
+  const float c2 = 18.8515625;
+  const float c3 = 18.6875;
+
+  const vec3 y = pow(clamp(nits / PQ_MAX_NITS, 0, 1), vec3(m1));
+  return pow((c1 + c2 * y) / (1 + c3 * y), vec3(m2));
+}
+
+
+vec3 srgb_encode(vec3 rgb) {
+  rgb = clamp(rgb, 0, 1);
+  return mix(1.055 * pow(rgb, vec3(1 / 2.4)) - 0.055, 12.92 * rgb, lessThanEqual(rgb, vec3(0.0031308)));
+}
+
+
+// Linear output for the given linear radiance
+vec3 tonemap(vec3 rgb, TonemapConstants constants) {
+  // heatmap values are not radiance and are never exposed
+  if (constants.mode == TONEMAP_MODE_HEATMAP) return heatmap(rgb.r);
+
+  rgb *= constants.exposure;
+  if (constants.mode == TONEMAP_MODE_FALSE_COLOR) rgb = false_color(rgb);
+  return rgb;
+}
+
+
+vec3 encode_output(vec3 rgb, TonemapConstants constants) {
+  switch (constants.encoding) {
+    case ENCODING_SCRGB: return rgb * constants.paper_white / SCRGB_WHITE_NITS;
+    case ENCODING_HDR10: return pq_encode(REC709_TO_REC2020 * max(rgb, 0) * constants.paper_white);
+    case ENCODING_SRGB: return srgb_encode(rgb);
+    default: return rgb;
+  }
+}
+
+#endif
@@ -0,0 +1,21 @@
+#version 460
+
+#include "tonemap.common.glsl"
+
+// the output has no sRGB format to encode on write, SDR output is encoded by encode_output as well
+
+layout(local_size_x=16, local_size_y=16) in;
+
+layout(push_constant) uniform _PushConstants { TonemapConstants constants; };
+
+layout(set=0, binding=0, INTERMEDIATE_FORMAT) uniform readonly image2D input_image;
+layout(set=0, binding=1, rgba8) uniform writeonly image2D output_image;
+
+
+void main() {
+  const ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  if (any(greaterThanEqual(pixel, imageSize(output_image)))) return;
+
+  const vec3 color = imageLoad(input_image, pixel).rgb;
+  imageStore(output_image, pixel, vec4(encode_output(tonemap(color, constants), constants), 1));
+}
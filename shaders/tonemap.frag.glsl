@@ -1,6 +1,11 @@
 #version 460
 
-const float INV_GAMMA = 1.0f / 2.2f;
+#include "tonemap.common.glsl"
+
+// no gamma here, an sRGB swapchain format encodes SDR output on write, HDR output is encoded by
+// encode_output
+
+layout(push_constant) uniform _PushConstants { TonemapConstants constants; };
 
 layout(binding=0) uniform sampler2D tex;
 
@@ -8,6 +13,8 @@ layout(location=0) in vec2 uv;
 
 layout(location=0) out vec4 color;
 
+
 void main() {
-  color = pow(texture(tex, uv), vec4(INV_GAMMA));
+  color = texture(tex, uv);
+  color.rgb = encode_output(tonemap(color.rgb, constants), constants);
 }
@@ -14,7 +14,11 @@ struct GlslHeader {
 
 impl GlslHeader {
     fn glsl_definition(&self) -> String {
-        String::from("// AUTO-GENERATED: do not edit\n\n") + &self.definitions.join("\n")
+        let guard = format!("{}_H_GLSL_", self.name.to_uppercase());
+        format!(
+            "// AUTO-GENERATED: do not edit\n\n#ifndef {guard}\n#define {guard}\n\n{}\n#endif\n",
+            self.definitions.join("\n")
+        )
     }
 }
 
@@ -30,8 +34,15 @@ fn main() -> Result<()> {
                 inputs::Transform::glsl_struct_definition(),
                 inputs::Camera::glsl_struct_definition(),
                 inputs::Uniforms::glsl_struct_definition(),
+                inputs::RasterizerInstance::glsl_struct_definition(),
                 inputs::RasterizerConstants::glsl_struct_definition(),
                 inputs::PathtracerConstants::glsl_struct_definition(),
+                inputs::PickResult::glsl_struct_definition(),
+                inputs::DenoiseConstants::glsl_struct_definition(),
+                inputs::TonemapConstants::glsl_struct_definition(),
+                inputs::BloomConstants::glsl_struct_definition(),
+                inputs::ExposureConstants::glsl_struct_definition(),
+                inputs::TaaConstants::glsl_struct_definition(),
             ],
         },
         GlslHeader {
@@ -41,6 +52,9 @@ fn main() -> Result<()> {
                 scene::Vertex::glsl_struct_definition(),
                 scene::Material::glsl_struct_definition(),
                 scene::PrimitiveInfo::glsl_struct_definition(),
+                scene::Light::glsl_struct_definition(),
+                scene::BvhNode::glsl_struct_definition(),
+                scene::BvhTriangle::glsl_struct_definition(),
             ],
         },
     ];
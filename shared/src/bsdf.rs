@@ -0,0 +1,424 @@
+// Host mirror of shaders/bsdf.common.glsl and the sampling helpers in globals.common.glsl, keep them
+// in sync. Quaternions are kept as plain vectors (xyz imaginary, w real) like on the device.
+
+use core::f32::consts::PI;
+
+use crate::tonemap::luminance;
+
+const MIN_DIELECTRICS_F0: f32 = 0.04;
+const MIN_EVAL_ALPHA: f32 = 0.05;
+
+// Values of `PathtracerConstants::sampling_strategy`, how the diffuse lobe picks directions
+pub mod sampling_strategy {
+    pub const COSINE: u32 = 0;
+    // the cosine moves into the weight instead, converging to the same image with more noise
+    pub const UNIFORM: u32 = 1;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaterialHit {
+    pub base_color: glam::Vec3,
+    pub metallic: f32,
+    pub emittance: glam::Vec3,
+    pub roughness: f32,
+    pub transmission: f32,
+    pub ior: f32,
+}
+
+const fn clamp_unit_nonzero(value: f32) -> f32 {
+    value.clamp(0.00001, 1.)
+}
+
+const fn clamp_unit(value: f32) -> f32 {
+    value.clamp(0., 1.)
+}
+
+const fn clamp_pos(value: f32) -> f32 {
+    value.max(0.)
+}
+
+fn reflect(i: glam::Vec3, n: glam::Vec3) -> glam::Vec3 {
+    i - 2. * n.dot(i) * n
+}
+
+fn refract(i: glam::Vec3, n: glam::Vec3, eta: f32) -> glam::Vec3 {
+    let n_dot_i = n.dot(i);
+    let k = (eta * eta).mul_add(-n_dot_i.mul_add(-n_dot_i, 1.), 1.);
+    if k < 0. {
+        glam::Vec3::ZERO
+    } else {
+        eta * i - eta.mul_add(n_dot_i, k.sqrt()) * n
+    }
+}
+
+pub fn quat_frame(v: glam::Vec3) -> glam::Vec4 {
+    if v.z < -0.99999 {
+        return glam::Vec4::X;
+    }
+    glam::vec4(v.y, -v.x, 0., 1. + v.z).normalize()
+}
+
+pub fn quat_invert_rotation(q: glam::Vec4) -> glam::Vec4 {
+    (-q.truncate()).extend(q.w)
+}
+
+pub fn quat_rotate(q: glam::Vec4, v: glam::Vec3) -> glam::Vec3 {
+    let (xyz, w) = (q.truncate(), q.w);
+    2. * xyz.dot(v) * xyz + w.mul_add(w, -xyz.dot(xyz)) * v + 2. * w * xyz.cross(v)
+}
+
+// Cosine weighted around +Z
+pub fn sample_hemisphere(r: glam::Vec2) -> glam::Vec3 {
+    let phi = r.y * 2. * PI;
+    let uv = glam::vec2(phi.cos(), phi.sin());
+    (uv * r.x.sqrt()).extend((1. - r.x).sqrt())
+}
+
+// Uniform around +Z
+pub fn sample_uniform_hemisphere(r: glam::Vec2) -> glam::Vec3 {
+    let phi = r.y * 2. * PI;
+    let uv = glam::vec2(phi.cos(), phi.sin());
+    let z = 1. - r.x;
+    (uv * z.mul_add(-z, 1.).sqrt()).extend(z)
+}
+
+fn base_color_to_specular_f0(base_color: glam::Vec3, metallic: f32) -> glam::Vec3 {
+    glam::Vec3::splat(MIN_DIELECTRICS_F0).lerp(base_color, metallic)
+}
+
+fn base_color_to_diffuse_reflectance(base_color: glam::Vec3, metallic: f32) -> glam::Vec3 {
+    base_color * (1. - metallic)
+}
+
+fn shadowed_f90(f0: glam::Vec3) -> f32 {
+    (luminance(f0) / MIN_DIELECTRICS_F0).min(1.)
+}
+
+// Schlick's approximation
+fn eval_fresnel(f0: glam::Vec3, n_dot_s: f32) -> glam::Vec3 {
+    f0 + (shadowed_f90(f0) - f0) * (1. - n_dot_s).powi(5)
+}
+
+// Unpolarized Fresnel reflectance for a dielectric interface, eta = n_incident / n_transmitted
+fn eval_dielectric_fresnel(cos_i: f32, eta: f32) -> f32 {
+    let sin_t_sq = eta * eta * cos_i.mul_add(-cos_i, 1.);
+    if sin_t_sq >= 1. {
+        return 1.; // total internal reflection
+    }
+
+    let cos_t = (1. - sin_t_sq).sqrt();
+    let r_s = eta.mul_add(cos_i, -cos_t) / eta.mul_add(cos_i, cos_t);
+    let r_p = eta.mul_add(-cos_t, cos_i) / eta.mul_add(cos_t, cos_i);
+    0.5 * r_s.mul_add(r_s, r_p * r_p)
+}
+
+// GGX
+fn smith_g1(alpha_sq: f32, n_dot_s_sq: f32) -> f32 {
+    2. / ((alpha_sq.mul_add(1. - n_dot_s_sq, n_dot_s_sq) / n_dot_s_sq).sqrt() + 1.)
+}
+
+// GGX normal distribution
+fn ggx_distribution(alpha_sq: f32, n_dot_h: f32) -> f32 {
+    let d = (n_dot_h * n_dot_h).mul_add(alpha_sq - 1., 1.);
+    alpha_sq / (PI * d * d)
+}
+
+// GGX VNDF height correlated
+fn specular_sample_weight(alpha_sq: f32, n_dot_l_sq: f32, n_dot_wo_sq: f32) -> f32 {
+    let g1wo = smith_g1(alpha_sq, n_dot_wo_sq);
+    let g1l = smith_g1(alpha_sq, n_dot_l_sq);
+    g1l / g1wo.mul_add(-g1l, g1wo + g1l)
+}
+
+// GGX VNDF
+fn sample_specular_half_vector(wo: glam::Vec3, alpha: f32, uv: glam::Vec2) -> glam::Vec3 {
+    let vh = (alpha * wo.truncate()).extend(wo.z).normalize();
+
+    let len_sq = wo.truncate().length_squared();
+    let tv1 = if len_sq > 0. {
+        glam::vec3(-wo.y, wo.x, 0.) / len_sq.sqrt()
+    } else {
+        glam::Vec3::X
+    };
+    let tv2 = wo.cross(tv1);
+
+    let r = uv.x.sqrt();
+    let phi = 2. * PI * uv.y;
+    let t1 = r * phi.cos();
+    let t2 = r * phi.sin();
+    let s = 0.5 * (1. + wo.z);
+    let t2_min = t1.mul_add(-t1, 1.).sqrt();
+    let t2 = (t2 - t2_min).mul_add(s, t2_min);
+
+    let nh = t1 * tv1 + t2 * tv2 + t2.mul_add(-t2, t1.mul_add(-t1, 1.)).max(0.).sqrt() * vh;
+
+    (alpha * nh.truncate()).extend(nh.z.max(0.)).normalize()
+}
+
+fn sample_specular_microfacet(
+    wo: glam::Vec3,
+    alpha: f32,
+    specular_f0: glam::Vec3,
+    r: glam::Vec2,
+) -> (glam::Vec3, glam::Vec3) {
+    let h = if alpha == 0. {
+        glam::Vec3::Z
+    } else {
+        sample_specular_half_vector(wo, alpha, r)
+    };
+
+    let l = reflect(-wo, h);
+
+    let n = glam::Vec3::Z;
+    let h_dot_l = clamp_unit_nonzero(h.dot(l));
+    let n_dot_l = clamp_unit_nonzero(n.dot(l));
+    let n_dot_wo = clamp_unit_nonzero(n.dot(wo));
+
+    let fresnel = eval_fresnel(specular_f0, h_dot_l);
+
+    let weight =
+        fresnel * specular_sample_weight(alpha * alpha, n_dot_l * n_dot_l, n_dot_wo * n_dot_wo);
+
+    (l, weight)
+}
+
+pub fn specular_probability(material: &MaterialHit, wo: glam::Vec3, n: glam::Vec3) -> f32 {
+    let specular_f0 = luminance(base_color_to_specular_f0(
+        material.base_color,
+        material.metallic,
+    ));
+    let diffuse_reflectance = luminance(base_color_to_diffuse_reflectance(
+        material.base_color,
+        material.metallic,
+    ));
+
+    let specular = clamp_unit(luminance(eval_fresnel(
+        glam::Vec3::splat(specular_f0),
+        clamp_pos(wo.dot(n)),
+    )));
+    let diffuse = diffuse_reflectance * (1. - specular);
+
+    let p = specular / (specular + diffuse).max(0.0001);
+    p.clamp(0.1, 0.9)
+}
+
+// Smooth dielectric, stochastically reflects or refracts based on the Fresnel reflectance. Returns
+// the sampled direction along with its weight.
+pub fn sample_transmission(
+    material: &MaterialHit,
+    entering: bool,
+    wo: glam::Vec3,
+    n: glam::Vec3,
+    r: f32,
+) -> (glam::Vec3, glam::Vec3) {
+    let eta = if entering {
+        1. / material.ior
+    } else {
+        material.ior
+    };
+    let fresnel = eval_dielectric_fresnel(clamp_unit(n.dot(wo)), eta);
+
+    if r < fresnel {
+        return (reflect(-wo, n), glam::Vec3::ONE);
+    }
+
+    (refract(-wo, n, eta), material.base_color)
+}
+
+// The sampled direction along with its weight, or None if the path is absorbed
+pub fn bsdf_sample(
+    material: &MaterialHit,
+    is_specular: bool,
+    sampling_strategy: u32,
+    wo: glam::Vec3,
+    n: glam::Vec3,
+    r: glam::Vec2,
+) -> Option<(glam::Vec3, glam::Vec3)> {
+    if n.dot(wo) <= 0. {
+        return None;
+    }
+
+    let frame = quat_frame(n);
+    let wo = quat_rotate(frame, wo);
+
+    let alpha = material.roughness * material.roughness;
+    let specular_f0 = base_color_to_specular_f0(material.base_color, material.metallic);
+
+    let (wi, weight) = if is_specular {
+        sample_specular_microfacet(wo, alpha, specular_f0, r)
+    } else {
+        let h = sample_specular_half_vector(wo, alpha, r);
+        let wo_dot_h = clamp_unit_nonzero(wo.dot(h));
+        let weight = base_color_to_diffuse_reflectance(material.base_color, material.metallic)
+            * (glam::Vec3::ONE - eval_fresnel(specular_f0, wo_dot_h));
+
+        if sampling_strategy == sampling_strategy::UNIFORM {
+            // cosine over the uniform pdf of 1 / 2pi, relative to the cosine weighted pdf
+            let wi = sample_uniform_hemisphere(r);
+            (wi, weight * 2. * wi.z)
+        } else {
+            (sample_hemisphere(r), weight)
+        }
+    };
+
+    if luminance(weight) == 0. {
+        return None;
+    }
+
+    let wi = quat_rotate(quat_invert_rotation(frame), wi).normalize();
+
+    (n.dot(wi) > 0.).then_some((wi, weight))
+}
+
+// BSDF times the cosine term for a given light direction, for shading without sampling. Smooth
+// surfaces are treated as slightly rough since a perfect mirror never reflects a single direction
+pub fn bsdf_eval(
+    material: &MaterialHit,
+    wo: glam::Vec3,
+    wi: glam::Vec3,
+    n: glam::Vec3,
+) -> glam::Vec3 {
+    let n_dot_l = n.dot(wi);
+    let n_dot_wo = n.dot(wo);
+    if n_dot_l <= 0. || n_dot_wo <= 0. {
+        return glam::Vec3::ZERO;
+    }
+
+    let h = (wo + wi).normalize();
+    let alpha = (material.roughness * material.roughness).max(MIN_EVAL_ALPHA);
+    let alpha_sq = alpha * alpha;
+    let specular_f0 = base_color_to_specular_f0(material.base_color, material.metallic);
+    let fresnel = eval_fresnel(specular_f0, clamp_unit_nonzero(wo.dot(h)));
+
+    let diffuse = base_color_to_diffuse_reflectance(material.base_color, material.metallic)
+        * (glam::Vec3::ONE - fresnel)
+        / PI;
+
+    let d = ggx_distribution(alpha_sq, clamp_unit(n.dot(h)));
+    let g = smith_g1(alpha_sq, n_dot_l * n_dot_l) * smith_g1(alpha_sq, n_dot_wo * n_dot_wo);
+    let specular = fresnel * d * g / (4. * n_dot_l * n_dot_wo);
+
+    (diffuse + specular) * n_dot_l
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rotates_normal_to_z() {
+        for n in [
+            glam::Vec3::Z,
+            glam::Vec3::X,
+            glam::vec3(0.3, -0.5, 0.8).normalize(),
+            -glam::Vec3::Z,
+        ] {
+            let frame = quat_frame(n);
+            assert!(quat_rotate(frame, n).abs_diff_eq(glam::Vec3::Z, 1e-5));
+            let back = quat_rotate(quat_invert_rotation(frame), glam::Vec3::Z);
+            assert!(back.abs_diff_eq(n, 1e-5), "{back} != {n}");
+        }
+    }
+
+    #[test]
+    fn hemisphere_samples_are_unit_and_upward() {
+        for (x, y) in [(0., 0.), (0.5, 0.25), (0.99, 0.75), (0.1, 0.9)] {
+            let v = sample_hemisphere(glam::vec2(x, y));
+            assert!((v.length() - 1.).abs() < 1e-5);
+            assert!(v.z >= 0.);
+        }
+    }
+
+    #[test]
+    fn uniform_hemisphere_samples_are_unit_and_upward() {
+        for (x, y) in [(0., 0.), (0.5, 0.25), (0.99, 0.75), (0.1, 0.9)] {
+            let v = sample_uniform_hemisphere(glam::vec2(x, y));
+            assert!((v.length() - 1.).abs() < 1e-5);
+            assert!(v.z >= 0.);
+        }
+    }
+
+    #[test]
+    fn sampling_strategies_agree_on_diffuse_albedo() {
+        const STEPS: u32 = 256;
+
+        // smooth so that the Fresnel term of the diffuse weight is the same for every sample
+        let material = MaterialHit {
+            base_color: glam::vec3(0.8, 0.5, 0.2),
+            ..Default::default()
+        };
+        let n = glam::Vec3::Z;
+        let wo = glam::vec3(0.3, 0., 1.).normalize();
+
+        let albedo = |sampling_strategy| {
+            let mut sum = glam::Vec3::ZERO;
+            for i in 0..STEPS {
+                for j in 0..STEPS {
+                    let r = (glam::vec2(i as f32, j as f32) + 0.5) / STEPS as f32;
+                    if let Some((_, weight)) =
+                        bsdf_sample(&material, false, sampling_strategy, wo, n, r)
+                    {
+                        sum += weight;
+                    }
+                }
+            }
+            sum / (STEPS * STEPS) as f32
+        };
+
+        let cosine = albedo(sampling_strategy::COSINE);
+        let uniform = albedo(sampling_strategy::UNIFORM);
+        assert!(cosine.abs_diff_eq(uniform, 1e-3), "{cosine} != {uniform}");
+    }
+
+    #[test]
+    fn dielectric_fresnel_matches_reference() {
+        // normal incidence on glass, ((1 - 1.5) / (1 + 1.5))^2
+        assert!((eval_dielectric_fresnel(1., 1. / 1.5) - 0.04).abs() < 1e-6);
+        // past the critical angle going out of glass
+        assert!((eval_dielectric_fresnel(0.5, 1.5) - 1.).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn eval_matches_lobes() {
+        let n = glam::Vec3::Z;
+        let rough = MaterialHit {
+            base_color: glam::Vec3::ONE,
+            roughness: 1.,
+            ..Default::default()
+        };
+        // below the horizon on either side
+        assert_eq!(bsdf_eval(&rough, n, -n, n), glam::Vec3::ZERO);
+        assert_eq!(bsdf_eval(&rough, -n, n, n), glam::Vec3::ZERO);
+
+        // at normal incidence a white dielectric is mostly Lambertian
+        let f = bsdf_eval(&rough, n, n, n);
+        let expected = (1. - MIN_DIELECTRICS_F0) / PI + MIN_DIELECTRICS_F0 / (4. * PI);
+        assert!(f.abs_diff_eq(glam::Vec3::splat(expected), 1e-5), "{f}");
+
+        // a glossy metal reflects far more towards the mirror direction than away from it
+        let metal = MaterialHit {
+            base_color: glam::Vec3::ONE,
+            metallic: 1.,
+            roughness: 0.3,
+            ..Default::default()
+        };
+        let wo = glam::vec3(1., 0., 1.).normalize();
+        let mirror = bsdf_eval(&metal, wo, reflect(-wo, n), n);
+        let off = bsdf_eval(&metal, wo, glam::vec3(0.2, 0.5, 1.).normalize(), n);
+        assert!(mirror.x > 10. * off.x, "{mirror} {off}");
+    }
+
+    #[test]
+    fn refraction_bends_towards_the_normal() {
+        let n = glam::Vec3::Z;
+        let i = glam::vec3(1., 0., -1.).normalize();
+        let t = refract(i, n, 1. / 1.5);
+        assert!((t.length() - 1.).abs() < 1e-5);
+        // Snell's law, sin(t) = sin(i) / 1.5
+        assert!((t.x - i.x / 1.5).abs() < 1e-5);
+        assert_eq!(
+            refract(glam::vec3(1., 0., -0.1).normalize(), n, 1.5),
+            glam::Vec3::ZERO
+        );
+    }
+}
@@ -1,18 +1,19 @@
 use core::ops::{Div, Mul};
 
 use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
 
 use glsl::GlslStruct;
 
 #[repr(C)]
-#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+#[derive(Copy, Clone, Default, Deserialize, GlslStruct, Pod, Serialize, Zeroable)]
 pub struct Transform {
     pub forward: glam::Mat4,
     pub inverse: glam::Mat4,
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+#[derive(Copy, Clone, Default, Deserialize, GlslStruct, Pod, Serialize, Zeroable)]
 pub struct Camera {
     pub view: Transform,
     pub proj: Transform,
@@ -22,20 +23,125 @@ pub struct Camera {
 #[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
 pub struct Uniforms {
     pub camera: Camera,
+    // radiance of rays that escape the scene
+    pub background_color: glam::Vec3,
+    // primary rays are parallel and start across the image plane instead of at the camera
+    pub orthographic: u32,
+    // what the last frame was rendered with, for reprojecting it
+    pub previous_camera: Camera,
+    // subpixel offset of the rasterized projection in normalized device coordinates
+    pub jitter: glam::Vec2,
+    // seconds of animation, which only advance while time is running
+    pub time: f32,
+    pub delta_time: f32,
+    // scales the radiance of the environment, which is the background color
+    pub env_intensity: f32,
+    pub pad: u32,
+    // fraction of the move from the shutter camera to the current one that each path tracer sample
+    // picks a random point of, blurring the motion. No blur at 0
+    pub shutter_time: f32,
+    // distance between the eyes, which are rendered side by side with the left one on the left. No
+    // stereo at 0
+    pub eye_separation: f32,
+    // the camera before the last update, where the shutter opened
+    pub shutter_camera: Camera,
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
-pub struct RasterizerConstants {
+// Read by the rasterizer at gl_InstanceIndex, instances of a mesh are contiguous so that they are
+// drawn together
+pub struct RasterizerInstance {
     pub model_transform: glam::Mat4,
-    pub material_index: u32,
-    pub pad: glam::Vec3,
+    pub previous_model_transform: glam::Mat4,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct RasterizerConstants {
+    pub instances_address: u64,
+    pub material: u32,
+    // -1 for the left eye and 1 for the right one, 0 without stereo
+    pub eye: f32,
+    // for the ray traced ambient occlusion of builds with the `ray_query_ao` feature
+    pub ao_radius: f32,
+    pub ray_epsilon: f32,
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
 pub struct PathtracerConstants {
     pub frame: u32,
+    pub debug_view: u32,
+    pub samples_per_frame: u32,
+    pub mode: u32,
+    pub ao_radius: f32,
+    // hits closer than this to the ray origin are ignored, also the offset of bounces off surfaces
+    pub ray_epsilon: f32,
+    // region of the target that is traced, in pixels
+    pub crop_offset: glam::UVec2,
+    pub crop_extent: glam::UVec2,
+    // when set, only the first hit of the pixel's center is written to a `PickResult` here
+    pub pick_address: u64,
+    // each frame replaces the output instead of being accumulated into it
+    pub realtime: u32,
+    // bounces a path may take after its first rough one
+    pub max_bounces_after_rough: u32,
+    // one of `bsdf::sampling_strategy`
+    pub sampling_strategy: u32,
+    // samples brighter than this are scaled down to it in the shown image, the reference keeps them
+    // as they are. No clamping at 0
+    pub max_sample_luminance: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct PickResult {
+    pub position: glam::Vec3,
+    pub hit: u32,
+    pub instance: u32,
+    pub primitive: u32,
+    pub material: u32,
+    pub pad: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct DenoiseConstants {
+    pub step_size: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct TonemapConstants {
+    pub mode: u32,
+    // linear scale applied to the radiance before display
+    pub exposure: f32,
+    // how the surface expects the output to be encoded, see `tonemap::encoding`
+    pub encoding: u32,
+    // luminance in nits that an output of 1 is shown at on HDR surfaces
+    pub paper_white: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct BloomConstants {
+    pub mode: u32,
+    pub threshold: f32,
+    pub intensity: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct ExposureConstants {
+    pub min_log_luminance: f32,
+    pub log_luminance_range: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct TaaConstants {
+    pub history_weight: f32,
 }
 
 impl Transform {
@@ -50,6 +156,12 @@ impl Transform {
         mat.y_axis.y *= -1.;
         Self::new(mat)
     }
+
+    // Transforms normals so that they stay perpendicular to surfaces under non-uniform scaling, the
+    // transpose of the inverse's upper-left 3x3
+    pub fn normal_matrix(&self) -> glam::Mat3 {
+        glam::Mat3::from_mat4(self.inverse).transpose()
+    }
 }
 
 impl<T> Mul<T> for Transform
@@ -73,3 +185,113 @@ where
         self.inverse * rhs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glsl::{Glsl, Packing};
+
+    use super::*;
+
+    // a vec3 that the host aligns like std140 does
+    #[repr(C, align(16))]
+    struct AlignedVec3(glam::Vec3);
+
+    impl Glsl for AlignedVec3 {
+        const NAME: &'static str = "vec3";
+    }
+
+    #[repr(C)]
+    #[derive(GlslStruct)]
+    struct Vec3Float {
+        a: glam::Vec3,
+        b: f32,
+    }
+
+    #[repr(C)]
+    #[derive(GlslStruct)]
+    struct Vec3Vec3 {
+        a: glam::Vec3,
+        b: AlignedVec3,
+    }
+
+    #[repr(C)]
+    #[derive(GlslStruct)]
+    struct PackedVec3Vec3 {
+        a: glam::Vec3,
+        b: glam::Vec3,
+    }
+
+    #[test]
+    fn host_layouts_match_glsl() {
+        let results = [
+            Uniforms::validate_layout(Packing::Std140),
+            RasterizerInstance::validate_layout(Packing::Scalar),
+            RasterizerConstants::validate_layout(Packing::Std430),
+            PathtracerConstants::validate_layout(Packing::Std430),
+            PickResult::validate_layout(Packing::Scalar),
+            DenoiseConstants::validate_layout(Packing::Std430),
+            TonemapConstants::validate_layout(Packing::Std430),
+            BloomConstants::validate_layout(Packing::Std430),
+            ExposureConstants::validate_layout(Packing::Std430),
+            TaaConstants::validate_layout(Packing::Std430),
+        ];
+        for result in results {
+            result.unwrap_or_else(|e| panic!("{e}"));
+        }
+    }
+
+    #[test]
+    fn std140_float_fills_the_end_of_a_vec3() {
+        assert_eq!(
+            Vec3Float::glsl_struct_definition_std140(),
+            "struct Vec3Float {\n  vec3 a;\n  float b;\n};\n"
+        );
+    }
+
+    #[test]
+    fn std140_pads_between_vec3s() {
+        assert_eq!(
+            Vec3Vec3::glsl_struct_definition_std140(),
+            "struct Vec3Vec3 {\n  vec3 a;\n  uint _pad0;\n  vec3 b;\n};\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "`PackedVec3Vec3::b` at offset 12 cannot satisfy std140 alignment of 16"
+    )]
+    fn std140_rejects_a_misaligned_vec3() {
+        PackedVec3Vec3::glsl_struct_definition_std140();
+    }
+
+    #[test]
+    fn normal_matrix_is_inverse_transpose() {
+        let skewed = glam::Mat4::from_cols(
+            glam::vec4(2., 0.5, 0., 0.),
+            glam::vec4(0.3, 1., -0.7, 0.),
+            glam::vec4(0., 0.2, 4., 0.),
+            glam::vec4(1., 2., 3., 1.),
+        );
+        let transform = Transform::new(skewed);
+        let normal_matrix = transform.normal_matrix();
+
+        // brute force, cofactors over the determinant
+        let m = glam::Mat3::from_mat4(skewed);
+        let cofactor = |col: usize, row: usize| {
+            let minor = |c: usize, r: usize| m.col((col + c) % 3)[(row + r) % 3];
+            minor(1, 1) * minor(2, 2) - minor(2, 1) * minor(1, 2)
+        };
+        let expected = glam::Mat3::from_cols_array_2d(&[
+            [cofactor(0, 0), cofactor(0, 1), cofactor(0, 2)],
+            [cofactor(1, 0), cofactor(1, 1), cofactor(1, 2)],
+            [cofactor(2, 0), cofactor(2, 1), cofactor(2, 2)],
+        ]) * m.determinant().recip();
+        assert!(normal_matrix.abs_diff_eq(expected, 1e-5));
+
+        // normals stay perpendicular to the transformed tangents
+        let normal = normal_matrix * glam::Vec3::Z;
+        for tangent in [glam::Vec3::X, glam::Vec3::Y] {
+            assert!(normal.dot(m * tangent).abs() < 1e-5);
+        }
+    }
+}
@@ -1,2 +1,6 @@
+pub mod bsdf;
 pub mod inputs;
+pub mod lights;
+pub mod rng;
 pub mod scene;
+pub mod tonemap;
@@ -0,0 +1,85 @@
+// Host mirror of shaders/lights.common.glsl, keep the two in sync
+
+use crate::scene::{light_kind, Light};
+
+// keeps the irradiance finite at the position of point and spot lights
+const MIN_LIGHT_DISTANCE: f32 = 1e-3;
+
+pub struct LightSample {
+    // towards the light
+    pub direction: glam::Vec3,
+    pub distance: f32,
+    // onto a surface facing the light
+    pub irradiance: glam::Vec3,
+}
+
+// Falls off smoothly from the inner to the outer cone, as in the KHR_lights_punctual reference
+fn spot_attenuation(light: &Light, direction: glam::Vec3) -> f32 {
+    let scale = 1. / (light.cos_inner_cone - light.cos_outer_cone).max(1e-3);
+    let t = ((light.direction.dot(direction) - light.cos_outer_cone) * scale).clamp(0., 1.);
+    t * t
+}
+
+// Inverse square falloff that reaches zero at the range of the light, if it has one
+fn range_attenuation(light: &Light, distance: f32) -> f32 {
+    if light.range <= 0. {
+        return 1.;
+    }
+    let r = distance / light.range;
+    let window = (r * r * r).mul_add(-r, 1.).clamp(0., 1.);
+    window * window
+}
+
+pub fn sample_light(light: &Light, position: glam::Vec3) -> LightSample {
+    if light.kind == light_kind::DIRECTIONAL {
+        return LightSample {
+            direction: -light.direction,
+            distance: f32::MAX,
+            irradiance: light.intensity,
+        };
+    }
+
+    let to_light = light.position - position;
+    let distance = to_light.length().max(MIN_LIGHT_DISTANCE);
+    let direction = to_light / distance;
+    let falloff = range_attenuation(light, distance) / (distance * distance);
+    let mut irradiance = light.intensity * falloff;
+    if light.kind == light_kind::SPOT {
+        irradiance *= spot_attenuation(light, -direction);
+    }
+
+    LightSample {
+        direction,
+        distance,
+        irradiance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point_light(range: f32) -> Light {
+        Light {
+            position: glam::vec3(0., 2., 0.),
+            kind: light_kind::POINT,
+            range,
+            intensity: glam::Vec3::splat(8.),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn point_light_falls_off_with_the_inverse_square() {
+        let incident = sample_light(&point_light(0.), glam::Vec3::ZERO);
+        assert!(incident.direction.abs_diff_eq(glam::Vec3::Y, 1e-6));
+        assert!((incident.distance - 2.).abs() < 1e-6);
+        assert!(incident.irradiance.abs_diff_eq(glam::Vec3::splat(2.), 1e-6));
+    }
+
+    #[test]
+    fn nothing_reaches_past_the_range() {
+        let incident = sample_light(&point_light(1.5), glam::Vec3::ZERO);
+        assert_eq!(incident.irradiance, glam::Vec3::ZERO);
+    }
+}
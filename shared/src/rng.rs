@@ -0,0 +1,88 @@
+// Host mirror of the PCG32 sampler in shaders/rng.common.glsl, keep the two in sync
+
+const MULTIPLIER: u32 = 747_796_405;
+const INCREMENT: u32 = 2_891_336_453;
+
+#[derive(Clone, Copy)]
+pub struct Rng {
+    state: u32,
+    seed: u32,
+}
+
+const fn lcg_step(state: u32) -> u32 {
+    state.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT)
+}
+
+// RXS-M-XS output permutation
+const fn permute(state: u32) -> u32 {
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
+pub const fn pcg_hash(value: u32) -> u32 {
+    permute(lcg_step(value))
+}
+
+impl Rng {
+    pub const fn new(pixel: glam::UVec2, frame: u32, sample_index: u32) -> Self {
+        let seed = pcg_hash(sample_index);
+        let seed = pcg_hash(frame.wrapping_add(seed));
+        let seed = pcg_hash(pixel.y.wrapping_add(seed));
+        let seed = pcg_hash(pixel.x.wrapping_add(seed));
+        Self { state: seed, seed }
+    }
+
+    // Decorrelates the dimensions consumed at each bounce of a path
+    pub const fn begin_bounce(&mut self, depth: u32) {
+        self.state = pcg_hash(self.seed.wrapping_add(depth));
+    }
+
+    pub const fn next_u32(&mut self) -> u32 {
+        self.state = lcg_step(self.state);
+        permute(self.state)
+    }
+
+    pub fn next_float(&mut self) -> f32 {
+        f32::from_bits((self.next_u32() >> 9) | 0x3f80_0000) - 1.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcg_hash_matches_reference() {
+        assert_eq!(pcg_hash(0), 129_708_002);
+        assert_eq!(pcg_hash(1), 2_831_084_092);
+        assert_eq!(pcg_hash(2), 2_055_130_248);
+        assert_eq!(pcg_hash(0xdead_beef), 1_730_779_506);
+        assert_eq!(pcg_hash(u32::MAX), 3_861_530_882);
+    }
+
+    #[test]
+    fn stream_matches_reference() {
+        let mut rng = Rng::new(glam::UVec2::ZERO, 0, 0);
+        assert_eq!(rng.seed, 2_368_882_721);
+        assert_eq!(
+            [rng.next_u32(), rng.next_u32(), rng.next_u32()],
+            [920_646_579, 4_256_993_306, 1_054_128_691]
+        );
+
+        let mut rng = Rng::new(glam::UVec2::new(17, 42), 3, 1);
+        rng.begin_bounce(2);
+        assert_eq!(
+            [rng.next_u32(), rng.next_u32(), rng.next_u32()],
+            [273_313_184, 3_542_538_250, 2_005_492_814]
+        );
+    }
+
+    #[test]
+    fn floats_are_in_unit_interval() {
+        let mut rng = Rng::new(glam::UVec2::new(3, 5), 7, 0);
+        for _ in 0..1024 {
+            let x = rng.next_float();
+            assert!((0. ..1.).contains(&x));
+        }
+    }
+}
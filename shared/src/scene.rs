@@ -10,6 +10,19 @@ pub struct SceneDesc {
     pub indices_address: u64,
     pub materials_address: u64,
     pub primitives_address: u64,
+    // only set when tracing in software, see `BvhNode`
+    pub bvh_nodes_address: u64,
+    pub bvh_triangles_address: u64,
+    pub instance_transforms_address: u64,
+    // `Instance::visibility_mask` of each instance, widened to u32
+    pub instance_masks_address: u64,
+    // material override of each traced instance, `NO_MATERIAL_OVERRIDE` for those without one
+    pub instance_materials_address: u64,
+    pub lights_address: u64,
+    pub num_lights: u32,
+    pub pad: u32,
+    // rounds the struct up to the 16 byte alignment of std140 uniforms
+    pub pad2: glam::UVec2,
 }
 
 #[repr(C)]
@@ -17,7 +30,11 @@ pub struct SceneDesc {
 pub struct Vertex {
     pub position: glam::Vec4,
     pub normal: glam::Vec4,
+    // uv set 0 in xy and uv set 1 in zw
     pub tex_coords: glam::Vec4,
+    // linear RGBA multiplied into the base color, white in assets processed without it
+    #[serde(default = "Vertex::white")]
+    pub color: glam::Vec4,
 }
 
 #[repr(C)]
@@ -30,6 +47,86 @@ pub struct Material {
     pub metallic: f32,
     pub roughness: f32,
     pub metallic_roughness_texture: i32,
+    pub double_sided: u32,
+    pub transmission: f32,
+    pub ior: f32,
+    pub alpha_mode: u32,
+    pub alpha_cutoff: f32,
+    // uv set sampled by each texture
+    pub color_tex_coord: u32,
+    pub emittance_tex_coord: u32,
+    pub metallic_roughness_tex_coord: u32,
+    pub pad: u32,
+}
+
+// KHR_lights_punctual light in world space
+#[repr(C)]
+#[derive(Clone, Copy, Default, Deserialize, Serialize, GlslStruct, Pod, Zeroable)]
+pub struct Light {
+    pub position: glam::Vec3,
+    pub kind: u32,
+    // the way the light shines, for directional and spot lights
+    pub direction: glam::Vec3,
+    // distance at which the light falls off to zero, zero for an unlimited range
+    pub range: f32,
+    // color times intensity, in candela for point and spot lights and lux for directional ones
+    pub intensity: glam::Vec3,
+    // cosines of the cone angles of spot lights, full intensity inside the inner cone
+    pub cos_inner_cone: f32,
+    pub cos_outer_cone: f32,
+}
+
+// Node of the software BVH used by the compute path tracer when ray tracing is unavailable
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct BvhNode {
+    pub aabb_min: glam::Vec3,
+    // first triangle of a leaf, otherwise the left child (the right child directly follows it)
+    pub left_or_first: u32,
+    pub aabb_max: glam::Vec3,
+    // zero for interior nodes
+    pub count: u32,
+}
+
+// World space triangle along with what is needed to shade it like the closest hit shader
+#[repr(C)]
+#[derive(Copy, Clone, Default, GlslStruct, Pod, Zeroable)]
+pub struct BvhTriangle {
+    pub v0: glam::Vec3,
+    pub instance: u32,
+    pub v1: glam::Vec3,
+    pub primitive: u32,
+    pub v2: glam::Vec3,
+    // index of the triangle within its primitive
+    pub triangle: u32,
+}
+
+// Values of `Material::alpha_mode`, mirrored in globals.common.glsl
+pub mod alpha_mode {
+    pub const OPAQUE: u32 = 0;
+    pub const MASK: u32 = 1;
+    pub const BLEND: u32 = 2;
+}
+
+// Bits of `Instance::visibility_mask`, rays of each kind only hit instances that have their bit set.
+// Mirrored in globals.common.glsl
+pub mod ray_mask {
+    pub const CAMERA: u8 = 1 << 0;
+    // towards lights and for ambient occlusion
+    pub const SHADOW: u8 = 1 << 1;
+    // reflections and refractions, every bounce after the first hit
+    pub const INDIRECT: u8 = 1 << 2;
+    pub const ALL: u8 = 0xff;
+}
+
+// Traced instances that keep the materials of their primitives, mirrored in globals.common.glsl
+pub const NO_MATERIAL_OVERRIDE: u32 = u32::MAX;
+
+// Values of `Light::kind`, mirrored in globals.common.glsl
+pub mod light_kind {
+    pub const POINT: u32 = 0;
+    pub const DIRECTIONAL: u32 = 1;
+    pub const SPOT: u32 = 2;
 }
 
 #[repr(C)]
@@ -41,19 +138,70 @@ pub struct PrimitiveInfo {
 }
 
 impl Vertex {
-    pub fn new(position: &[f32], normal: &[f32], tex_coord0: &[f32], tex_coord1: &[f32]) -> Self {
+    pub fn new(
+        position: &[f32],
+        normal: &[f32],
+        tex_coord0: &[f32],
+        tex_coord1: &[f32],
+        color: &[f32],
+    ) -> Self {
         Self {
             position: glam::Vec3::from_slice(position).extend(1.0),
             normal: glam::Vec3::from_slice(normal).extend(1.0),
             tex_coords: glam::Vec4::new(tex_coord0[0], tex_coord0[1], tex_coord1[0], tex_coord1[1]),
+            color: glam::Vec4::from_slice(color),
         }
     }
+
+    const fn white() -> glam::Vec4 {
+        glam::Vec4::ONE
+    }
 }
 
-// (((position, normal), tex_coord0), tex_coord1)
-type RawData = ((([f32; 3], [f32; 3]), [f32; 2]), [f32; 2]);
+// ((((position, normal), tex_coord0), tex_coord1), color)
+type RawData = (((([f32; 3], [f32; 3]), [f32; 2]), [f32; 2]), [f32; 4]);
 impl From<RawData> for Vertex {
-    fn from((((position, normal), tex_coord0), tex_coord1): RawData) -> Self {
-        Self::new(&position, &normal, &tex_coord0, &tex_coord1)
+    fn from(((((position, normal), tex_coord0), tex_coord1), color): RawData) -> Self {
+        Self::new(&position, &normal, &tex_coord0, &tex_coord1, &color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glsl::Packing;
+
+    use super::*;
+
+    #[test]
+    fn host_layouts_match_glsl() {
+        let results = [
+            SceneDesc::validate_layout(Packing::Std140),
+            Vertex::validate_layout(Packing::Scalar),
+            Material::validate_layout(Packing::Scalar),
+            PrimitiveInfo::validate_layout(Packing::Scalar),
+            Light::validate_layout(Packing::Scalar),
+            BvhNode::validate_layout(Packing::Scalar),
+            BvhTriangle::validate_layout(Packing::Scalar),
+        ];
+        for result in results {
+            result.unwrap_or_else(|e| panic!("{e}"));
+        }
+    }
+
+    #[test]
+    fn vec3_padding_is_caught() {
+        #[repr(C)]
+        #[derive(GlslStruct)]
+        struct Padded {
+            position: glam::Vec3,
+            normal: glam::Vec3,
+        }
+
+        // std140 starts the second vec3 on the next 16 bytes, the host packs it right after
+        let error = Padded::validate_layout(Packing::Std140).unwrap_err();
+        assert_eq!(error.mismatches.len(), 1);
+        assert_eq!(error.mismatches[0].host_offset, 12);
+        assert_eq!(error.mismatches[0].glsl_offset, 16);
+        assert!(Padded::validate_layout(Packing::Scalar).is_ok());
     }
 }
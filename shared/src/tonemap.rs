@@ -0,0 +1,255 @@
+// Host mirror of the operators in shaders/tonemap.common.glsl, keep the two in sync. On the device an
+// sRGB swapchain format encodes SDR output, on the host `linear_to_srgb` does. HDR output and SDR
+// output to storage images are encoded by `encode_output` on both.
+
+use crate::inputs::TonemapConstants;
+
+// Values of `TonemapConstants::mode`
+pub mod mode {
+    pub const STANDARD: u32 = 0;
+    pub const FALSE_COLOR: u32 = 1;
+    pub const HEATMAP: u32 = 2;
+}
+
+// Values of `TonemapConstants::encoding`, one for each kind of surface
+pub mod encoding {
+    pub const SDR: u32 = 0;
+    // linear Rec. 709 primaries where 1 is 80 nits, values above it are brighter
+    pub const SCRGB: u32 = 1;
+    // Rec. 2020 primaries encoded with the PQ curve
+    pub const HDR10: u32 = 2;
+    // SDR encoded by the shader, storage images have no sRGB formats to do it on write
+    pub const SRGB: u32 = 3;
+}
+
+const SCRGB_WHITE_NITS: f32 = 80.;
+const PQ_MAX_NITS: f32 = 10_000.;
+// columns of the conversion from linear Rec. 709 to Rec. 2020 (ITU-R BT.2087)
+const REC709_TO_REC2020: glam::Mat3 = glam::Mat3::from_cols_array(&[
+    0.627_404, 0.069_097, 0.016_391, 0.329_283, 0.919_540, 0.088_013, 0.043_313, 0.011_362,
+    0.895_595,
+]);
+
+// upper luminance limit of each false color band, middle grey (0.18) lands in the green band
+const FALSE_COLOR_LIMITS: [f32; 6] = [0.01, 0.05, 0.12, 0.25, 0.6, 1.];
+// one more color than limits for everything that is clipped
+const FALSE_COLORS: [glam::Vec3; 7] = [
+    glam::Vec3::new(0., 0., 1.),
+    glam::Vec3::new(0., 0.5, 1.),
+    glam::Vec3::splat(0.4),
+    glam::Vec3::new(0., 1., 0.),
+    glam::Vec3::splat(0.8),
+    glam::Vec3::new(1., 1., 0.),
+    glam::Vec3::new(1., 0., 0.),
+];
+
+pub fn luminance(rgb: glam::Vec3) -> f32 {
+    rgb.dot(glam::vec3(0.2126, 0.7152, 0.0722))
+}
+
+pub fn false_color(rgb: glam::Vec3) -> glam::Vec3 {
+    let luminance = luminance(rgb);
+    let band = FALSE_COLOR_LIMITS
+        .iter()
+        .take_while(|&&limit| luminance >= limit)
+        .count();
+    FALSE_COLORS[band]
+}
+
+#[allow(clippy::cast_sign_loss)]
+pub fn heatmap(t: f32) -> glam::Vec3 {
+    let band = (t.max(0.) * FALSE_COLORS.len() as f32) as usize;
+    FALSE_COLORS[band.min(FALSE_COLORS.len() - 1)]
+}
+
+// Linear output for the given linear radiance
+pub fn tonemap(rgb: glam::Vec3, constants: &TonemapConstants) -> glam::Vec3 {
+    // heatmap values are not radiance and are never exposed
+    if constants.mode == mode::HEATMAP {
+        return heatmap(rgb.x);
+    }
+
+    let rgb = rgb * constants.exposure;
+
+    if constants.mode == mode::FALSE_COLOR {
+        false_color(rgb)
+    } else {
+        rgb
+    }
+}
+
+// Surface values for the linear output of `tonemap`, SDR is left to the swapchain format
+pub fn encode_output(rgb: glam::Vec3, constants: &TonemapConstants) -> glam::Vec3 {
+    match constants.encoding {
+        encoding::SRGB => rgb.to_array().map(srgb_encode).into(),
+        encoding::SCRGB => rgb * constants.paper_white / SCRGB_WHITE_NITS,
+        encoding::HDR10 => {
+            let nits = REC709_TO_REC2020 * rgb.max(glam::Vec3::ZERO) * constants.paper_white;
+            nits.to_array().map(pq_encode).into()
+        }
+        _ => rgb,
+    }
+}
+
+// SMPTE ST 2084 inverse EOTF, from absolute luminance to a signal in [0, 1]
+pub fn pq_encode(nits: f32) -> f32 {
+    const M1: f32 = 0.159_301_76;
+    const M2: f32 = 78.843_75;
+    const C1: f32 = 0.835_937_5;
+    const C2: f32 = 18.851_563;
+    const C3: f32 = 18.6875;
+
+    let y = (nits / PQ_MAX_NITS).clamp(0., 1.).powf(M1);
+    (C2.mul_add(y, C1) / C3.mul_add(y, 1.)).powf(M2)
+}
+
+#[allow(clippy::cast_sign_loss)]
+pub fn linear_to_srgb(value: f32) -> u8 {
+    (srgb_encode(value) * 255.).round() as u8
+}
+
+// The sRGB encoding of a linear value, clamped to [0, 1]
+pub fn srgb_encode(value: f32) -> f32 {
+    let value = value.clamp(0., 1.);
+    if value <= 0.003_130_8 {
+        12.92 * value
+    } else {
+        1.055f32.mul_add(value.powf(1. / 2.4), -0.055)
+    }
+}
+
+// Inverse of the sRGB encoding, for values in [0, 1]
+pub fn srgb_to_linear(encoded: f32) -> f32 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tonemap_to_srgb(rgb: [f32; 3], mode: u32, exposure: f32) -> [u8; 3] {
+        let constants = TonemapConstants {
+            mode,
+            exposure,
+            ..Default::default()
+        };
+        tonemap(glam::Vec3::from(rgb), &constants)
+            .to_array()
+            .map(linear_to_srgb)
+    }
+
+    #[test]
+    fn srgb_encoding_matches_reference() {
+        let encoded =
+            [0., 0.001, 0.003_130_8, 0.01, 0.18, 0.5, 0.7, 1., 4., -1.].map(linear_to_srgb);
+        assert_eq!(encoded, [0, 3, 10, 25, 118, 188, 218, 255, 255, 0]);
+    }
+
+    #[test]
+    fn srgb_decoding_inverts_encoding() {
+        for value in 0..=255u8 {
+            let decoded = srgb_to_linear(f32::from(value) / 255.);
+            assert_eq!(linear_to_srgb(decoded), value);
+        }
+    }
+
+    #[test]
+    fn standard_matches_reference() {
+        assert_eq!(
+            tonemap_to_srgb([0.18, 0.5, 0.7], mode::STANDARD, 1.),
+            [118, 188, 218]
+        );
+        assert_eq!(
+            tonemap_to_srgb([0.18, 0.25, 1.5], mode::STANDARD, 2.),
+            [162, 188, 255]
+        );
+        assert_eq!(
+            tonemap_to_srgb([0.01, 0., 0.001], mode::STANDARD, 0.5),
+            [16, 0, 2]
+        );
+    }
+
+    #[test]
+    fn false_color_bands_match_reference() {
+        let bands = [0., 0.02, 0.1, 0.18, 0.5, 0.8, 10.]
+            .map(|value| tonemap_to_srgb([value; 3], mode::FALSE_COLOR, 1.));
+        assert_eq!(
+            bands,
+            [
+                [0, 0, 255],
+                [0, 188, 255],
+                [170, 170, 170],
+                [0, 255, 0],
+                [231, 231, 231],
+                [255, 255, 0],
+                [255, 0, 0],
+            ]
+        );
+
+        // exposure is applied before banding
+        assert_eq!(
+            tonemap_to_srgb([0.09; 3], mode::FALSE_COLOR, 2.),
+            [0, 255, 0]
+        );
+    }
+
+    #[test]
+    fn pq_encoding_matches_reference() {
+        assert!(pq_encode(0.) < 1e-6);
+        assert!((pq_encode(100.) - 0.508).abs() < 1e-3);
+        assert!((pq_encode(1000.) - 0.752).abs() < 1e-3);
+        assert!((pq_encode(PQ_MAX_NITS) - 1.).abs() < 1e-6);
+        assert!((pq_encode(2. * PQ_MAX_NITS) - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn hdr_outputs_keep_white_at_paper_white() {
+        let constants = |encoding| TonemapConstants {
+            encoding,
+            paper_white: 203.,
+            ..Default::default()
+        };
+        let white = glam::Vec3::ONE;
+
+        let scrgb = encode_output(white, &constants(encoding::SCRGB));
+        assert!(scrgb.abs_diff_eq(glam::Vec3::splat(203. / 80.), 1e-5));
+
+        // the primaries change, white does not
+        let hdr10 = encode_output(white, &constants(encoding::HDR10));
+        assert!(hdr10.abs_diff_eq(glam::Vec3::splat(pq_encode(203.)), 1e-3));
+
+        assert_eq!(encode_output(white, &constants(encoding::SDR)), white);
+    }
+
+    #[test]
+    fn srgb_output_matches_the_swapchain_encoding() {
+        let constants = TonemapConstants {
+            encoding: encoding::SRGB,
+            ..Default::default()
+        };
+        for value in [-1., 0., 0.001, 0.18, 0.5, 1., 4.] {
+            let encoded = encode_output(glam::Vec3::splat(value), &constants);
+            assert!(
+                encoded
+                    .x
+                    .mul_add(255., -f32::from(linear_to_srgb(value)))
+                    .abs()
+                    <= 0.5
+            );
+        }
+    }
+
+    #[test]
+    fn heatmap_ignores_exposure_and_clamps() {
+        let heat = |t| tonemap_to_srgb([t, 0., 0.], mode::HEATMAP, 4.);
+        assert_eq!(heat(-1.), [0, 0, 255]);
+        assert_eq!(heat(0.), [0, 0, 255]);
+        assert_eq!(heat(0.5), [0, 255, 0]);
+        assert_eq!(heat(0.99), [255, 0, 0]);
+        assert_eq!(heat(2.), [255, 0, 0]);
+    }
+}